@@ -0,0 +1,205 @@
+//! 智能收藏夹模块
+//!
+//! 智能收藏夹是持久化保存的历史记录查询条件，例如“本周来自手机的链接”，
+//! UI可以将其显示为文件夹，打开时实时筛选历史记录。
+
+use crate::clipboard::{ClipboardContent, HistoryEntry};
+use serde::{Deserialize, Serialize};
+
+/// 内容类别，用于按类型筛选历史记录
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentKind {
+    /// 普通文本（不含链接）
+    Text,
+    /// 链接（以http://或https://开头的单行文本）
+    Url,
+    /// 图片
+    Image,
+    /// 文件路径列表
+    Files,
+}
+
+impl ContentKind {
+    /// 获取剪贴板内容的类别，空内容返回None
+    pub fn of(content: &ClipboardContent) -> Option<Self> {
+        match content {
+            ClipboardContent::Text(text) => {
+                let trimmed = text.trim();
+                let is_url = (trimmed.starts_with("http://") || trimmed.starts_with("https://"))
+                    && !trimmed.contains(char::is_whitespace);
+                Some(if is_url { Self::Url } else { Self::Text })
+            }
+            ClipboardContent::Image(_) => Some(Self::Image),
+            ClipboardContent::Files(_) => Some(Self::Files),
+            ClipboardContent::Empty => None,
+        }
+    }
+}
+
+/// 历史记录查询条件
+///
+/// 所有条件之间为“与”关系，未设置的条件不参与筛选。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionQuery {
+    /// 内容类别（任一匹配即可，为空表示不限）
+    #[serde(default)]
+    pub content_kinds: Vec<ContentKind>,
+    /// 必须同时包含的标签
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 仅收藏条目
+    #[serde(default)]
+    pub favorites_only: bool,
+    /// 文本包含的关键字（不区分大小写）
+    #[serde(default)]
+    pub text_contains: Option<String>,
+    /// 来源设备ID
+    #[serde(default)]
+    pub source_device_id: Option<String>,
+    /// 最大条目年龄（秒），例如 7 * 24 * 3600 表示最近一周
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+impl CollectionQuery {
+    /// 判断条目是否满足查询条件
+    ///
+    /// `now_ms` 为当前Unix时间戳（毫秒），与 `HistoryEntry::timestamp` 单位一致。
+    pub fn matches(&self, entry: &HistoryEntry, now_ms: u64) -> bool {
+        if !self.content_kinds.is_empty() {
+            match ContentKind::of(&entry.content) {
+                Some(kind) if self.content_kinds.contains(&kind) => {}
+                _ => return false,
+            }
+        }
+
+        if !self.tags.iter().all(|tag| entry.tags.contains(tag)) {
+            return false;
+        }
+
+        if self.favorites_only && !entry.is_favorite {
+            return false;
+        }
+
+        if let Some(keyword) = &self.text_contains {
+            let keyword = keyword.to_lowercase();
+            let found = match &entry.content {
+                ClipboardContent::Text(text) => text.to_lowercase().contains(&keyword),
+                ClipboardContent::Files(paths) => paths
+                    .iter()
+                    .any(|path| path.to_lowercase().contains(&keyword)),
+                _ => false,
+            };
+            if !found {
+                return false;
+            }
+        }
+
+        if let Some(device_id) = &self.source_device_id {
+            if entry.source_device_id.as_ref() != Some(device_id) {
+                return false;
+            }
+        }
+
+        if let Some(max_age_secs) = self.max_age_secs {
+            let oldest = now_ms.saturating_sub(max_age_secs.saturating_mul(1000));
+            if entry.timestamp < oldest {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// 智能收藏夹
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmartCollection {
+    /// 唯一标识符
+    pub id: String,
+    /// 显示名称
+    pub name: String,
+    /// 查询条件
+    pub query: CollectionQuery,
+    /// 创建时间（Unix时间戳，毫秒）
+    pub created_at: u64,
+}
+
+impl SmartCollection {
+    /// 创建新的智能收藏夹
+    pub fn new(name: &str, query: CollectionQuery) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            query,
+            created_at: now,
+        }
+    }
+}
+
+/// 标签统计信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagInfo {
+    /// 标签名称
+    pub name: String,
+    /// 使用该标签的条目数量
+    pub count: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_kind() {
+        let url = ClipboardContent::Text("https://example.com/a?b=c".to_string());
+        assert_eq!(ContentKind::of(&url), Some(ContentKind::Url));
+
+        let text = ClipboardContent::Text("see https://example.com".to_string());
+        assert_eq!(ContentKind::of(&text), Some(ContentKind::Text));
+
+        assert_eq!(ContentKind::of(&ClipboardContent::Empty), None);
+    }
+
+    #[test]
+    fn test_collection_query_matches() {
+        let now = 10 * 24 * 3600 * 1000;
+        let mut entry = HistoryEntry::new(ClipboardContent::Text("https://rust-lang.org".to_string()));
+        entry.timestamp = now - 3600 * 1000;
+        entry.source_device_id = Some("phone".to_string());
+        entry.add_tag("工作");
+
+        // 本周来自手机的链接
+        let query = CollectionQuery {
+            content_kinds: vec![ContentKind::Url],
+            source_device_id: Some("phone".to_string()),
+            max_age_secs: Some(7 * 24 * 3600),
+            ..Default::default()
+        };
+        assert!(query.matches(&entry, now));
+
+        // 超出时间范围
+        entry.timestamp = now - 8 * 24 * 3600 * 1000;
+        assert!(!query.matches(&entry, now));
+        entry.timestamp = now;
+
+        // 标签和关键字
+        let query = CollectionQuery {
+            tags: vec!["工作".to_string()],
+            text_contains: Some("RUST".to_string()),
+            ..Default::default()
+        };
+        assert!(query.matches(&entry, now));
+
+        let query = CollectionQuery {
+            favorites_only: true,
+            ..Default::default()
+        };
+        assert!(!query.matches(&entry, now));
+    }
+}
@@ -0,0 +1,34 @@
+//! 剪贴板历史记录变更事件
+
+use crate::clipboard::HistoryEntry;
+use serde::{Deserialize, Serialize};
+
+/// 历史记录变更事件回调函数类型
+pub type HistoryEventCallback = Box<dyn Fn(&HistoryEvent) + Send + Sync + 'static>;
+
+/// 订阅标识，用于取消订阅
+pub type SubscriptionId = u64;
+
+/// 历史记录变更事件
+///
+/// 序列化为 `{"type": "...", "data": ...}` 形式，便于前端按类型分发。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum HistoryEvent {
+    /// 新增条目（包括从回收站恢复的条目）
+    Added(HistoryEntry),
+    /// 条目被修改（收藏状态、标签等）
+    Updated(HistoryEntry),
+    /// 条目被删除（移入回收站）
+    Removed {
+        /// 条目ID
+        id: String,
+    },
+    /// 历史记录被清空
+    Cleared,
+    /// 条目因超出最大条数被移出
+    Evicted {
+        /// 条目ID
+        id: String,
+    },
+}
@@ -2,14 +2,38 @@
 //! 
 //! 提供剪贴板历史记录的存储和管理功能，支持查询历史记录、导出/导入历史记录等。
 
+use crate::clipboard::collections::{CollectionQuery, SmartCollection, TagInfo};
+use crate::clipboard::events::{HistoryEvent, HistoryEventCallback, SubscriptionId};
 use crate::clipboard::ClipboardContent;
 use crate::error::{Error, Result};
-use crate::storage;
+use crate::storage::{AsyncStorage, ClipboardRecord, CollectionRecord, Storage, WriteOp};
 use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 回收站条目的默认保留时间：30天
+pub const DEFAULT_PURGE_DELAY: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 长时间运行时定期清理回收站的间隔：1小时
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 删除标记回调函数类型
+pub type TombstoneCallback = Box<dyn Fn(Tombstone) + Send + Sync + 'static>;
+
+/// 删除标记
+///
+/// 用户删除条目后，将删除标记发送给持有相同内容的其他设备，
+/// 对方据此将本地对应条目移入回收站。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    /// 被删除内容的哈希
+    pub content_hash: String,
+    /// 删除时间（Unix时间戳，毫秒）
+    pub deleted_at: u64,
+}
 
 /// 历史记录条目，包含剪贴板内容和时间戳
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +48,12 @@ pub struct HistoryEntry {
     pub tags: Vec<String>,
     /// 是否标记为收藏
     pub is_favorite: bool,
+    /// 来源设备ID（本机复制的内容为None）
+    #[serde(default)]
+    pub source_device_id: Option<String>,
+    /// 移入回收站的时间（Unix时间戳，毫秒），None表示未删除
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
 }
 
 impl HistoryEntry {
@@ -40,8 +70,17 @@ impl HistoryEntry {
             timestamp: now,
             tags: Vec::new(),
             is_favorite: false,
+            source_device_id: None,
+            deleted_at: None,
         }
     }
+
+    /// 创建来自远程设备的历史记录条目
+    pub fn from_device(content: ClipboardContent, device_id: &str) -> Self {
+        let mut entry = Self::new(content);
+        entry.source_device_id = Some(device_id.to_string());
+        entry
+    }
     
     /// 添加标签
     pub fn add_tag(&mut self, tag: &str) {
@@ -59,37 +98,162 @@ impl HistoryEntry {
     pub fn toggle_favorite(&mut self) {
         self.is_favorite = !self.is_favorite;
     }
+    
+    /// 计算内容哈希（SHA-256十六进制），用于跨设备识别相同内容
+    pub fn content_hash(&self) -> String {
+        use sodiumoxide::crypto::hash::sha256;
+        
+        let mut data = Vec::new();
+        match &self.content {
+            ClipboardContent::Text(text) => {
+                data.push(0u8);
+                data.extend_from_slice(text.as_bytes());
+            }
+            ClipboardContent::Image(bytes) => {
+                data.push(1u8);
+                data.extend_from_slice(bytes);
+            }
+            ClipboardContent::Files(paths) => {
+                data.push(2u8);
+                for path in paths {
+                    data.extend_from_slice(path.as_bytes());
+                    data.push(0u8);
+                }
+            }
+            ClipboardContent::Empty => data.push(3u8),
+        }
+        
+        sha256::hash(&data)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
 }
 
+/// 变更事件订阅者
+type Subscriber = (SubscriptionId, Arc<HistoryEventCallback>);
+
 /// 剪贴板历史记录管理器
 pub struct ClipboardHistory {
     /// 历史记录列表
     entries: Arc<Mutex<VecDeque<HistoryEntry>>>,
     /// 最大历史记录条数
     max_entries: usize,
-    /// 是否启用持久化存储
-    persistence_enabled: bool,
+    /// 持久化存储，None表示只保存在内存中
+    storage: Option<AsyncStorage>,
+    /// 智能收藏夹
+    collections: Arc<Mutex<Vec<SmartCollection>>>,
+    /// 回收站（按删除时间降序）
+    trash: Arc<Mutex<Vec<HistoryEntry>>>,
+    /// 最近一次清空操作的时间戳，用于撤销
+    last_clear: Arc<Mutex<Option<u64>>>,
+    /// 回收站条目的保留时间
+    purge_delay: Duration,
+    /// 删除标记回调，设置后删除操作会生成删除标记
+    tombstone_callback: Arc<Mutex<Option<Arc<TombstoneCallback>>>>,
+    /// 变更事件订阅者
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    /// 下一个订阅标识
+    next_subscription_id: AtomicU64,
 }
 
 impl ClipboardHistory {
     /// 创建新的历史记录管理器
-    pub fn new(max_entries: usize, persistence_enabled: bool) -> Self {
+    ///
+    /// 提供存储时从中加载历史记录，之后的修改在后台批量写入。加载时会阻塞当前线程，
+    /// 在异步任务中需通过 `spawn_blocking` 创建。
+    pub fn new(max_entries: usize, storage: Option<AsyncStorage>) -> Self {
         let history = Self {
             entries: Arc::new(Mutex::new(VecDeque::with_capacity(max_entries))),
             max_entries,
-            persistence_enabled,
+            storage,
+            collections: Arc::new(Mutex::new(Vec::new())),
+            trash: Arc::new(Mutex::new(Vec::new())),
+            last_clear: Arc::new(Mutex::new(None)),
+            purge_delay: DEFAULT_PURGE_DELAY,
+            tombstone_callback: Arc::new(Mutex::new(None)),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            next_subscription_id: AtomicU64::new(1),
         };
         
         // 如果启用持久化存储，从数据库加载历史记录
-        if persistence_enabled {
+        if history.storage.is_some() {
             if let Err(e) = history.load_from_storage() {
                 warn!("从存储加载历史记录失败: {e:?}");
             }
+            if let Err(e) = history.load_collections_from_storage() {
+                warn!("从存储加载智能收藏夹失败: {e:?}");
+            }
         }
         
         history
     }
     
+    /// 设置回收站条目的保留时间，并立即清理已过期的条目
+    ///
+    /// 之后每次添加、删除和清空时也会清理过期条目，长时间空闲的进程需按
+    /// [`PURGE_INTERVAL`] 定期调用 [`ClipboardHistory::purge_expired`]。
+    pub fn with_purge_delay(mut self, purge_delay: Duration) -> Self {
+        self.purge_delay = purge_delay;
+        self.purge_expired_or_warn();
+        self
+    }
+    
+    /// 设置删除标记回调
+    ///
+    /// 设置后，`remove` 删除的条目会生成删除标记并通过回调发送给网络层，
+    /// 传播到持有相同内容的其他设备。传入None关闭传播。
+    pub fn set_tombstone_callback(&self, callback: Option<TombstoneCallback>) -> Result<()> {
+        let mut guard = self.tombstone_callback.lock().map_err(|e| 
+            Error::Other(format!("获取回调锁失败: {e:?}"))
+        )?;
+        *guard = callback.map(Arc::new);
+        Ok(())
+    }
+    
+    /// 订阅历史记录变更事件
+    ///
+    /// 回调在触发变更的线程上同步执行，且不持有任何内部锁，
+    /// 回调中可以安全地再次调用历史记录的查询接口。
+    pub fn subscribe(&self, callback: HistoryEventCallback) -> Result<SubscriptionId> {
+        let id = self.next_subscription_id.fetch_add(1, Ordering::Relaxed);
+        
+        let mut subscribers = self.subscribers.lock().map_err(|e| 
+            Error::Other(format!("获取订阅者锁失败: {e:?}"))
+        )?;
+        subscribers.push((id, Arc::new(callback)));
+        
+        Ok(id)
+    }
+    
+    /// 取消订阅，返回订阅是否存在
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        let mut subscribers = self.subscribers.lock().map_err(|e| 
+            Error::Other(format!("获取订阅者锁失败: {e:?}"))
+        )?;
+        
+        let initial_len = subscribers.len();
+        subscribers.retain(|(sub_id, _)| *sub_id != id);
+        
+        Ok(subscribers.len() < initial_len)
+    }
+    
+    /// 向所有订阅者发送事件
+    fn emit(&self, event: HistoryEvent) {
+        let callbacks: Vec<Arc<HistoryEventCallback>> = match self.subscribers.lock() {
+            Ok(subscribers) => subscribers.iter().map(|(_, cb)| cb.clone()).collect(),
+            Err(e) => {
+                error!("获取订阅者锁失败: {e:?}");
+                return;
+            }
+        };
+        
+        for callback in callbacks {
+            callback(&event);
+        }
+    }
+    
     /// 添加新的历史记录
     pub fn add(&self, content: ClipboardContent) -> Result<()> {
         // 忽略空内容
@@ -97,7 +261,22 @@ impl ClipboardHistory {
             return Ok(());
         }
         
-        let entry = HistoryEntry::new(content);
+        self.add_entry(HistoryEntry::new(content))
+    }
+    
+    /// 添加来自远程设备的历史记录
+    pub fn add_from_device(&self, content: ClipboardContent, device_id: &str) -> Result<()> {
+        // 忽略空内容
+        if matches!(content, ClipboardContent::Empty) {
+            return Ok(());
+        }
+        
+        self.add_entry(HistoryEntry::from_device(content, device_id))
+    }
+    
+    /// 添加历史记录条目
+    fn add_entry(&self, entry: HistoryEntry) -> Result<()> {
+        self.purge_expired_or_warn();
         
         // 获取锁并添加记录
         let mut entries = self.entries.lock().map_err(|e| 
@@ -112,17 +291,25 @@ impl ClipboardHistory {
             entries.push_front(entry.clone());
             
             // 如果超出最大条数，移除最旧的记录
+            let mut evicted = Vec::new();
             while entries.len() > self.max_entries {
-                entries.pop_back();
+                if let Some(old) = entries.pop_back() {
+                    evicted.push(old.id);
+                }
             }
+            drop(entries); // 释放锁后再保存和通知
             
             // 如果启用持久化，保存到存储
-            if self.persistence_enabled {
-                drop(entries); // 释放锁后再保存
+            if self.storage.is_some() {
                 if let Err(e) = self.save_entry(&entry) {
                     warn!("保存历史记录条目失败: {e:?}");
                 }
             }
+            
+            self.emit(HistoryEvent::Added(entry));
+            for id in evicted {
+                self.emit(HistoryEvent::Evicted { id });
+            }
         }
         
         Ok(())
@@ -175,47 +362,283 @@ impl ClipboardHistory {
             .cloned())
     }
     
-    /// 删除指定ID的历史记录
+    /// 将指定ID的历史记录移入回收站
     pub fn remove(&self, id: &str) -> Result<bool> {
+        self.purge_expired_or_warn();
+        
+        let entry = match self.soft_delete(id)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        
+        // 生成删除标记
+        let callback = self.tombstone_callback.lock().map_err(|e| 
+            Error::Other(format!("获取回调锁失败: {e:?}"))
+        )?.clone();
+        if let Some(callback) = callback {
+            callback(Tombstone {
+                content_hash: entry.content_hash(),
+                deleted_at: entry.deleted_at.unwrap_or_default(),
+            });
+        }
+        
+        Ok(true)
+    }
+    
+    /// 清空历史记录（移入回收站，可通过 `undo_clear` 撤销）
+    pub fn clear(&self) -> Result<()> {
+        self.purge_expired_or_warn();
+        let now = Self::now_millis();
+        
         let mut entries = self.entries.lock().map_err(|e| 
             Error::Other(format!("获取历史记录锁失败: {e:?}"))
         )?;
         
-        let initial_len = entries.len();
-        entries.retain(|entry| entry.id != id);
+        let mut cleared: Vec<HistoryEntry> = entries.drain(..).collect();
+        drop(entries);
         
-        let removed = entries.len() < initial_len;
+        for entry in cleared.iter_mut() {
+            entry.deleted_at = Some(now);
+        }
+        self.push_to_trash(cleared)?;
         
-        // 如果启用持久化且成功移除了记录，则从存储中也删除
-        if removed && self.persistence_enabled {
-            drop(entries); // 释放锁后再操作数据库
-            if let Err(e) = self.remove_from_storage(id) {
-                warn!("从存储中删除历史记录失败: {e:?}");
+        if let Ok(mut last_clear) = self.last_clear.lock() {
+            *last_clear = Some(now);
+        }
+        
+        // 如果启用持久化，标记存储中的记录
+        if self.storage.is_some() {
+            if let Err(e) = self.clear_storage(now) {
+                warn!("清空历史记录存储失败: {e:?}");
             }
         }
         
-        Ok(removed)
+        self.emit(HistoryEvent::Cleared);
+        Ok(())
     }
     
-    /// 清空历史记录
-    pub fn clear(&self) -> Result<()> {
+    /// 从回收站恢复指定ID的条目
+    pub fn restore(&self, id: &str) -> Result<bool> {
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        
+        let Some(pos) = trash.iter().position(|e| e.id == id) else {
+            return Ok(false);
+        };
+        let entry = trash.remove(pos);
+        drop(trash);
+        
+        self.restore_entries(vec![entry])?;
+        Ok(true)
+    }
+    
+    /// 撤销最近一次清空操作，返回恢复的条目数量
+    pub fn undo_clear(&self) -> Result<usize> {
+        let cleared_at = {
+            let mut last_clear = self.last_clear.lock().map_err(|e| 
+                Error::Other(format!("获取历史记录锁失败: {e:?}"))
+            )?;
+            match last_clear.take() {
+                Some(ts) => ts,
+                None => return Ok(0),
+            }
+        };
+        
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        
+        let (restored, kept): (Vec<_>, Vec<_>) = trash
+            .drain(..)
+            .partition(|e| e.deleted_at == Some(cleared_at));
+        *trash = kept;
+        drop(trash);
+        
+        let count = restored.len();
+        self.restore_entries(restored)?;
+        
+        // 恢复存储中未加载到内存的记录
+        self.persist(WriteOp::RestoreClipboardEntries { deleted_at: cleared_at });
+        
+        Ok(count)
+    }
+    
+    /// 获取回收站中的条目（按删除时间降序）
+    pub fn get_trash(&self) -> Result<Vec<HistoryEntry>> {
+        let trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        
+        Ok(trash.clone())
+    }
+    
+    /// 清空回收站，永久删除其中的条目
+    pub fn empty_trash(&self) -> Result<usize> {
+        self.purge(|_| true)
+    }
+    
+    /// 永久删除超过保留时间的回收站条目，返回删除数量
+    pub fn purge_expired(&self) -> Result<usize> {
+        let cutoff = Self::now_millis().saturating_sub(self.purge_delay.as_millis() as u64);
+        self.purge(|entry| entry.deleted_at.unwrap_or_default() <= cutoff)
+    }
+    
+    /// 清理过期的回收站条目，失败时只记录日志
+    ///
+    /// 在修改历史记录之前调用，刚移入回收站的条目不会被立即清理。
+    fn purge_expired_or_warn(&self) {
+        if let Err(e) = self.purge_expired() {
+            warn!("清理回收站失败: {e:?}");
+        }
+    }
+    
+    /// 应用来自其他设备的删除标记，返回移入回收站的条目数量
+    ///
+    /// 本地被删除的条目不会再次生成删除标记，避免在设备间循环传播。
+    pub fn apply_tombstone(&self, tombstone: &Tombstone) -> Result<usize> {
+        let ids: Vec<String> = {
+            let entries = self.entries.lock().map_err(|e| 
+                Error::Other(format!("获取历史记录锁失败: {e:?}"))
+            )?;
+            entries
+                .iter()
+                .filter(|e| e.timestamp <= tombstone.deleted_at)
+                .filter(|e| e.content_hash() == tombstone.content_hash)
+                .map(|e| e.id.clone())
+                .collect()
+        };
+        
+        let mut count = 0;
+        for id in ids {
+            if self.soft_delete(&id)?.is_some() {
+                count += 1;
+            }
+        }
+        
+        Ok(count)
+    }
+    
+    /// 将条目从历史记录移入回收站，返回被移动的条目
+    fn soft_delete(&self, id: &str) -> Result<Option<HistoryEntry>> {
         let mut entries = self.entries.lock().map_err(|e| 
             Error::Other(format!("获取历史记录锁失败: {e:?}"))
         )?;
         
-        entries.clear();
+        let Some(pos) = entries.iter().position(|e| e.id == id) else {
+            return Ok(None);
+        };
+        let mut entry = entries.remove(pos).expect("位置有效");
+        drop(entries);
         
-        // 如果启用持久化，清空存储中的记录
-        if self.persistence_enabled {
-            drop(entries); // 释放锁后再操作数据库
-            if let Err(e) = self.clear_storage() {
-                warn!("清空历史记录存储失败: {e:?}");
+        entry.deleted_at = Some(Self::now_millis());
+        self.push_to_trash(vec![entry.clone()])?;
+        
+        // 如果启用持久化，更新存储中的删除标记
+        if self.storage.is_some() {
+            if let Err(e) = self.update_entry(&entry) {
+                warn!("更新历史记录条目失败: {e:?}");
             }
         }
         
+        self.emit(HistoryEvent::Removed { id: entry.id.clone() });
+        Ok(Some(entry))
+    }
+    
+    /// 将条目放入回收站
+    fn push_to_trash(&self, removed: Vec<HistoryEntry>) -> Result<()> {
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        
+        trash.extend(removed);
+        trash.sort_by_key(|e| std::cmp::Reverse(e.deleted_at));
         Ok(())
     }
     
+    /// 将回收站中取出的条目放回历史记录
+    fn restore_entries(&self, restored: Vec<HistoryEntry>) -> Result<()> {
+        if restored.is_empty() {
+            return Ok(());
+        }
+        
+        let mut entries = self.entries.lock().map_err(|e| 
+            Error::Other(format!("获取历史记录锁失败: {e:?}"))
+        )?;
+        
+        let mut restored = restored;
+        for entry in restored.iter_mut() {
+            entry.deleted_at = None;
+        }
+        
+        entries.extend(restored.iter().cloned());
+        entries.make_contiguous().sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+        let mut evicted = Vec::new();
+        while entries.len() > self.max_entries {
+            if let Some(old) = entries.pop_back() {
+                evicted.push(old.id);
+            }
+        }
+        drop(entries);
+        
+        // 如果启用持久化，清除存储中的删除标记
+        if self.storage.is_some() {
+            for entry in &restored {
+                if let Err(e) = self.update_entry(entry) {
+                    warn!("更新历史记录条目失败: {e:?}");
+                }
+            }
+        }
+        
+        for entry in restored {
+            if !evicted.contains(&entry.id) {
+                self.emit(HistoryEvent::Added(entry));
+            }
+        }
+        for id in evicted {
+            self.emit(HistoryEvent::Evicted { id });
+        }
+        
+        Ok(())
+    }
+    
+    /// 永久删除回收站中满足条件的条目
+    fn purge<F>(&self, should_purge: F) -> Result<usize>
+    where
+        F: Fn(&HistoryEntry) -> bool,
+    {
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        
+        let (purged, kept): (Vec<_>, Vec<_>) = trash.drain(..).partition(|e| should_purge(e));
+        *trash = kept;
+        drop(trash);
+        
+        // 如果启用持久化，从存储中删除
+        if self.storage.is_some() {
+            for entry in &purged {
+                if let Err(e) = self.remove_from_storage(&entry.id) {
+                    warn!("从存储中删除历史记录失败: {e:?}");
+                }
+            }
+        }
+        
+        if !purged.is_empty() {
+            debug!("永久删除 {} 条回收站记录", purged.len());
+        }
+        
+        Ok(purged.len())
+    }
+    
+    /// 当前Unix时间戳（毫秒）
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+    
     /// 切换条目的收藏状态
     pub fn toggle_favorite(&self, id: &str) -> Result<bool> {
         let mut entries = self.entries.lock().map_err(|e| 
@@ -224,16 +647,17 @@ impl ClipboardHistory {
         
         if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
             entry.toggle_favorite();
+            let entry_clone = entry.clone();
+            drop(entries); // 释放锁后再操作数据库
             
             // 如果启用持久化，更新存储
-            if self.persistence_enabled {
-                let entry_clone = entry.clone();
-                drop(entries); // 释放锁后再操作数据库
+            if self.storage.is_some() {
                 if let Err(e) = self.update_entry(&entry_clone) {
                     warn!("更新历史记录条目失败: {e:?}");
                 }
             }
             
+            self.emit(HistoryEvent::Updated(entry_clone));
             Ok(true)
         } else {
             Ok(false)
@@ -248,16 +672,17 @@ impl ClipboardHistory {
         
         if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
             entry.add_tag(tag);
+            let entry_clone = entry.clone();
+            drop(entries); // 释放锁后再操作数据库
             
             // 如果启用持久化，更新存储
-            if self.persistence_enabled {
-                let entry_clone = entry.clone();
-                drop(entries); // 释放锁后再操作数据库
+            if self.storage.is_some() {
                 if let Err(e) = self.update_entry(&entry_clone) {
                     warn!("更新历史记录条目失败: {e:?}");
                 }
             }
             
+            self.emit(HistoryEvent::Updated(entry_clone));
             Ok(true)
         } else {
             Ok(false)
@@ -272,143 +697,364 @@ impl ClipboardHistory {
         
         if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
             entry.remove_tag(tag);
+            let entry_clone = entry.clone();
+            drop(entries); // 释放锁后再操作数据库
             
             // 如果启用持久化，更新存储
-            if self.persistence_enabled {
-                let entry_clone = entry.clone();
-                drop(entries); // 释放锁后再操作数据库
+            if self.storage.is_some() {
                 if let Err(e) = self.update_entry(&entry_clone) {
                     warn!("更新历史记录条目失败: {e:?}");
                 }
             }
             
+            self.emit(HistoryEvent::Updated(entry_clone));
             Ok(true)
         } else {
             Ok(false)
         }
     }
     
+    /// 列出所有标签及其使用次数，按次数降序、名称升序排列
+    ///
+    /// 启用持久化时统计存储中的全部记录，包括未加载到内存的条目。
+    pub fn list_tags(&self) -> Result<Vec<TagInfo>> {
+        let tag_lists: Vec<Vec<String>> = if self.storage.is_some() {
+            self.read_storage()?
+                .get_clipboard_tags()?
+                .into_iter()
+                .filter(|(_, _, deleted)| !deleted)
+                .map(|(_, tags, _)| tags)
+                .collect()
+        } else {
+            let entries = self.entries.lock().map_err(|e| 
+                Error::Other(format!("获取历史记录锁失败: {e:?}"))
+            )?;
+            entries.iter().map(|entry| entry.tags.clone()).collect()
+        };
+        
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for tag in tag_lists.into_iter().flatten() {
+            *counts.entry(tag).or_default() += 1;
+        }
+        
+        let mut tags: Vec<TagInfo> = counts
+            .into_iter()
+            .map(|(name, count)| TagInfo { name, count })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        
+        Ok(tags)
+    }
+    
+    /// 在所有条目中重命名标签
+    ///
+    /// 如果条目已经带有新标签，则两者合并为一个。返回受影响的条目数量。
+    pub fn rename_tag(&self, old_tag: &str, new_tag: &str) -> Result<usize> {
+        self.merge_tags(&[old_tag], new_tag)
+    }
+    
+    /// 将多个标签合并为目标标签，返回受影响的条目数量
+    pub fn merge_tags(&self, source_tags: &[&str], target_tag: &str) -> Result<usize> {
+        let target_tag = target_tag.trim();
+        if target_tag.is_empty() {
+            return Err(Error::InvalidArgument("标签名称不能为空".to_string()));
+        }
+        
+        self.update_tags(|tags| {
+            if !tags.iter().any(|t| source_tags.contains(&t.as_str()) && t != target_tag) {
+                return false;
+            }
+            tags.retain(|t| !source_tags.contains(&t.as_str()));
+            if !tags.iter().any(|t| t == target_tag) {
+                tags.push(target_tag.to_string());
+            }
+            true
+        })
+    }
+    
+    /// 从所有条目中删除标签，返回受影响的条目数量
+    pub fn delete_tag(&self, tag: &str) -> Result<usize> {
+        self.update_tags(|tags| {
+            if !tags.iter().any(|t| t == tag) {
+                return false;
+            }
+            tags.retain(|t| t != tag);
+            true
+        })
+    }
+    
+    /// 批量修改所有条目（包括回收站）的标签，`f` 返回true表示标签被修改
+    ///
+    /// 启用持久化时同时更新存储中未加载到内存的记录，返回值为存储中受影响的未删除条目数量。
+    fn update_tags<F>(&self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&mut Vec<String>) -> bool,
+    {
+        let stored_count = if self.storage.is_some() {
+            Some(self.update_tags_in_storage(&mut f)?)
+        } else {
+            None
+        };
+        
+        let mut entries = self.entries.lock().map_err(|e| 
+            Error::Other(format!("获取历史记录锁失败: {e:?}"))
+        )?;
+        let changed: Vec<HistoryEntry> = entries
+            .iter_mut()
+            .filter_map(|entry| if f(&mut entry.tags) { Some(entry.clone()) } else { None })
+            .collect();
+        drop(entries);
+        
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        for entry in trash.iter_mut() {
+            f(&mut entry.tags);
+        }
+        drop(trash);
+        
+        let count = stored_count.unwrap_or(changed.len());
+        for entry in changed {
+            self.emit(HistoryEvent::Updated(entry));
+        }
+        
+        Ok(count)
+    }
+    
+    /// 按查询条件筛选历史记录
+    pub fn query(&self, query: &CollectionQuery) -> Result<Vec<HistoryEntry>> {
+        let entries = self.entries.lock().map_err(|e| 
+            Error::Other(format!("获取历史记录锁失败: {e:?}"))
+        )?;
+        
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        
+        Ok(entries
+            .iter()
+            .filter(|entry| query.matches(entry, now))
+            .cloned()
+            .collect())
+    }
+    
+    /// 创建智能收藏夹
+    pub fn create_collection(&self, name: &str, query: CollectionQuery) -> Result<SmartCollection> {
+        if name.trim().is_empty() {
+            return Err(Error::InvalidArgument("收藏夹名称不能为空".to_string()));
+        }
+        
+        let collection = SmartCollection::new(name.trim(), query);
+        
+        let mut collections = self.collections.lock().map_err(|e| 
+            Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+        )?;
+        collections.push(collection.clone());
+        drop(collections);
+        
+        if self.storage.is_some() {
+            self.save_collection(&collection)?;
+        }
+        
+        Ok(collection)
+    }
+    
+    /// 获取所有智能收藏夹
+    pub fn list_collections(&self) -> Result<Vec<SmartCollection>> {
+        let collections = self.collections.lock().map_err(|e| 
+            Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+        )?;
+        
+        Ok(collections.clone())
+    }
+    
+    /// 更新智能收藏夹的名称和查询条件
+    pub fn update_collection(&self, id: &str, name: &str, query: CollectionQuery) -> Result<bool> {
+        if name.trim().is_empty() {
+            return Err(Error::InvalidArgument("收藏夹名称不能为空".to_string()));
+        }
+        
+        let mut collections = self.collections.lock().map_err(|e| 
+            Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+        )?;
+        
+        let Some(collection) = collections.iter_mut().find(|c| c.id == id) else {
+            return Ok(false);
+        };
+        collection.name = name.trim().to_string();
+        collection.query = query;
+        let collection = collection.clone();
+        drop(collections);
+        
+        if self.storage.is_some() {
+            self.save_collection(&collection)?;
+        }
+        
+        Ok(true)
+    }
+    
+    /// 删除智能收藏夹
+    pub fn delete_collection(&self, id: &str) -> Result<bool> {
+        let mut collections = self.collections.lock().map_err(|e| 
+            Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+        )?;
+        
+        let initial_len = collections.len();
+        collections.retain(|c| c.id != id);
+        let removed = collections.len() < initial_len;
+        drop(collections);
+        
+        if removed {
+            self.persist(WriteOp::DeleteCollection(id.to_string()));
+        }
+        
+        Ok(removed)
+    }
+    
+    /// 获取智能收藏夹中的历史记录
+    pub fn get_collection_entries(&self, id: &str) -> Result<Vec<HistoryEntry>> {
+        let query = {
+            let collections = self.collections.lock().map_err(|e| 
+                Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+            )?;
+            
+            match collections.iter().find(|c| c.id == id) {
+                Some(collection) => collection.query.clone(),
+                None => return Err(Error::InvalidArgument(format!("收藏夹不存在: {id}"))),
+            }
+        };
+        
+        self.query(&query)
+    }
+    
     // 以下是内部持久化存储相关方法
     
-    /// 从存储中加载历史记录
+    /// 提交写操作，未启用持久化时忽略
+    ///
+    /// 写操作进入存储的批量写入队列，不阻塞调用方。
+    fn persist(&self, op: WriteOp) {
+        if let Some(storage) = &self.storage {
+            storage.submit(op);
+        }
+    }
+    
+    /// 等待已提交的写操作完成，返回用于读取的存储
+    fn read_storage(&self) -> Result<Arc<Storage>> {
+        let storage = self.storage.as_ref().ok_or_else(|| 
+            Error::Storage("未启用历史记录持久化".to_string())
+        )?;
+        storage.wait_for_writes()?;
+        Ok(storage.storage())
+    }
+    
+    /// 从存储中加载智能收藏夹
+    fn load_collections_from_storage(&self) -> Result<()> {
+        let records = self.read_storage()?.get_collections()?;
+        
+        let mut collections = self.collections.lock().map_err(|e| 
+            Error::Other(format!("获取收藏夹锁失败: {e:?}"))
+        )?;
+        
+        collections.clear();
+        for record in records {
+            match serde_json::from_str::<CollectionQuery>(&record.query) {
+                Ok(query) => collections.push(SmartCollection {
+                    id: record.id,
+                    name: record.name,
+                    query,
+                    created_at: record.created_at,
+                }),
+                Err(e) => warn!("解析智能收藏夹查询失败: {e:?}"),
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// 保存智能收藏夹到存储
+    fn save_collection(&self, collection: &SmartCollection) -> Result<()> {
+        self.persist(WriteOp::SaveCollection(CollectionRecord {
+            id: collection.id.clone(),
+            name: collection.name.clone(),
+            query: serde_json::to_string(&collection.query)?,
+            created_at: collection.created_at,
+        }));
+        
+        Ok(())
+    }
+    
+    /// 从存储中加载历史记录和回收站
     fn load_from_storage(&self) -> Result<()> {
         debug!("从存储中加载历史记录");
-        let db = storage::get_connection()?;
-        
-        // 确保表存在
-        db.execute(
-            "CREATE TABLE IF NOT EXISTS clipboard_history (
-                id TEXT PRIMARY KEY,
-                content BLOB NOT NULL,
-                content_type INTEGER NOT NULL,
-                timestamp INTEGER NOT NULL,
-                tags TEXT,
-                is_favorite INTEGER NOT NULL
-            )",
-            [],
-        ).map_err(Error::Database)?;
-        
-        // 查询所有记录，按时间戳降序排列
-        let mut stmt = db.prepare(
-            "SELECT id, content, content_type, timestamp, tags, is_favorite 
-             FROM clipboard_history 
-             ORDER BY timestamp DESC
-             LIMIT ?",
-        ).map_err(Error::Database)?;
-        
-        let rows = stmt.query_map([self.max_entries], |row| {
-            let id: String = row.get(0)?;
-            let content_blob: Vec<u8> = row.get(1)?;
-            let content_type: i32 = row.get(2)?;
-            let timestamp: i64 = row.get(3)?;
-            let tags_str: Option<String> = row.get(4)?;
-            let is_favorite: bool = row.get(5)?;
-            
-            // 解析内容
-            let content = match content_type {
-                0 => {
-                    // 文本
-                    let text = String::from_utf8(content_blob)
-                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
-                            0, 
-                            rusqlite::types::Type::Blob, 
-                            Box::new(e)
-                        ))?;
-                    ClipboardContent::Text(text)
-                },
-                1 => {
-                    // 图片
-                    ClipboardContent::Image(content_blob)
-                },
-                2 => {
-                    // 文件路径
-                    let text = String::from_utf8(content_blob)
-                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
-                            0, 
-                            rusqlite::types::Type::Blob, 
-                            Box::new(e)
-                        ))?;
-                    let paths: Vec<String> = serde_json::from_str(&text)
-                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
-                            0, 
-                            rusqlite::types::Type::Blob, 
-                            Box::new(e)
-                        ))?;
-                    ClipboardContent::Files(paths)
-                },
-                _ => ClipboardContent::Empty
-            };
-            
-            // 解析标签
-            let tags = if let Some(tags_str) = tags_str {
-                serde_json::from_str(&tags_str).unwrap_or_default()
-            } else {
-                Vec::new()
-            };
-            
-            Ok(HistoryEntry {
-                id,
-                content,
-                timestamp: timestamp as u64,
-                tags,
-                is_favorite,
-            })
-        }).map_err(Error::Database)?;
+        let storage = self.read_storage()?;
+        let loaded = Self::decode_records(storage.get_clipboard_entries(self.max_entries)?);
+        let loaded_trash = Self::decode_records(storage.get_clipboard_trash()?);
         
-        // 添加到内存中的历史记录
         let mut entries = self.entries.lock().map_err(|e| 
             Error::Other(format!("获取历史记录锁失败: {e:?}"))
         )?;
-        
         entries.clear();
-        for row_result in rows {
-            if let Ok(entry) = row_result {
-                entries.push_back(entry);
-            }
-        }
+        entries.extend(loaded);
+        drop(entries);
+        
+        let mut trash = self.trash.lock().map_err(|e| 
+            Error::Other(format!("获取回收站锁失败: {e:?}"))
+        )?;
+        *trash = loaded_trash;
+        drop(trash);
+        
+        self.purge_expired()?;
         
         Ok(())
     }
     
+    /// 将存储中的记录转换为历史记录条目，跳过无法解析的记录
+    fn decode_records(records: Vec<ClipboardRecord>) -> Vec<HistoryEntry> {
+        records
+            .into_iter()
+            .filter_map(|record| {
+                let id = record.id.clone();
+                match Self::record_to_entry(record) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        warn!("解析历史记录 {id} 失败: {e:?}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+    
+    /// 将存储中的记录转换为历史记录条目
+    fn record_to_entry(record: ClipboardRecord) -> Result<HistoryEntry> {
+        let content = match record.content_type {
+            // 文本
+            0 => ClipboardContent::Text(String::from_utf8(record.content).map_err(|e| 
+                Error::Other(format!("解析文本内容失败: {e:?}"))
+            )?),
+            // 图片
+            1 => ClipboardContent::Image(record.content),
+            // 文件路径
+            2 => ClipboardContent::Files(serde_json::from_slice(&record.content)?),
+            _ => ClipboardContent::Empty,
+        };
+        
+        Ok(HistoryEntry {
+            id: record.id,
+            content,
+            timestamp: record.timestamp,
+            tags: record.tags,
+            is_favorite: record.is_favorite,
+            source_device_id: record.source_device_id,
+            deleted_at: record.deleted_at,
+        })
+    }
+    
     /// 保存单个历史记录条目到存储
     fn save_entry(&self, entry: &HistoryEntry) -> Result<()> {
-        let db = storage::get_connection()?;
-        
-        // 确保表存在
-        db.execute(
-            "CREATE TABLE IF NOT EXISTS clipboard_history (
-                id TEXT PRIMARY KEY,
-                content BLOB NOT NULL,
-                content_type INTEGER NOT NULL,
-                timestamp INTEGER NOT NULL,
-                tags TEXT,
-                is_favorite INTEGER NOT NULL
-            )",
-            [],
-        ).map_err(Error::Database)?;
-        
         // 准备内容和类型
-        let (content_blob, content_type) = match &entry.content {
+        let (content, content_type) = match &entry.content {
             ClipboardContent::Text(text) => {
                 (text.as_bytes().to_vec(), 0)
             },
@@ -419,32 +1065,23 @@ impl ClipboardHistory {
                 let json = serde_json::to_string(paths).map_err(|e| 
                     Error::Other(format!("序列化文件路径失败: {e:?}"))
                 )?;
-                (json.as_bytes().to_vec(), 2)
+                (json.into_bytes(), 2)
             },
             ClipboardContent::Empty => {
                 (Vec::new(), 3)
             },
         };
         
-        // 序列化标签
-        let tags_json = serde_json::to_string(&entry.tags).map_err(|e| 
-            Error::Other(format!("序列化标签失败: {e:?}"))
-        )?;
-        
-        // 插入或更新记录
-        db.execute(
-            "INSERT OR REPLACE INTO clipboard_history 
-             (id, content, content_type, timestamp, tags, is_favorite) 
-             VALUES (?, ?, ?, ?, ?, ?)",
-            rusqlite::params![
-                &entry.id,
-                content_blob,
-                content_type,
-                entry.timestamp as i64,
-                tags_json,
-                entry.is_favorite,
-            ],
-        ).map_err(Error::Database)?;
+        self.persist(WriteOp::SaveClipboardEntry(ClipboardRecord {
+            id: entry.id.clone(),
+            content,
+            content_type,
+            timestamp: entry.timestamp,
+            tags: entry.tags.clone(),
+            is_favorite: entry.is_favorite,
+            source_device_id: entry.source_device_id.clone(),
+            deleted_at: entry.deleted_at,
+        }));
         
         Ok(())
     }
@@ -457,25 +1094,34 @@ impl ClipboardHistory {
     
     /// 从存储中删除历史记录
     fn remove_from_storage(&self, id: &str) -> Result<()> {
-        let db = storage::get_connection()?;
-        
-        db.execute(
-            "DELETE FROM clipboard_history WHERE id = ?",
-            [id],
-        ).map_err(Error::Database)?;
-        
+        self.persist(WriteOp::DeleteClipboardEntry(id.to_string()));
         Ok(())
     }
     
-    /// 清空历史记录存储
-    fn clear_storage(&self) -> Result<()> {
-        let db = storage::get_connection()?;
-        
-        db.execute(
-            "DELETE FROM clipboard_history",
-            [],
-        ).map_err(Error::Database)?;
+    /// 修改存储中所有记录的标签，返回受影响的未删除记录数量
+    ///
+    /// 修改作为一组写操作提交，由存储合并到同一批次中执行。
+    fn update_tags_in_storage<F>(&self, f: &mut F) -> Result<usize>
+    where
+        F: FnMut(&mut Vec<String>) -> bool,
+    {
+        let mut count = 0;
+        for (id, mut tags, deleted) in self.read_storage()?.get_clipboard_tags()? {
+            if !f(&mut tags) {
+                continue;
+            }
+            self.persist(WriteOp::SetClipboardTags { id, tags });
+            if !deleted {
+                count += 1;
+            }
+        }
         
+        Ok(count)
+    }
+    
+    /// 将存储中所有未删除的记录标记为已删除
+    fn clear_storage(&self, deleted_at: u64) -> Result<()> {
+        self.persist(WriteOp::TrashClipboardEntries { deleted_at });
         Ok(())
     }
 }
@@ -484,6 +1130,13 @@ impl ClipboardHistory {
 mod tests {
     use super::*;
     
+    /// 打开内存数据库，返回的运行时需在测试期间保持存活以执行后台写入
+    fn open_storage() -> (tokio::runtime::Runtime, AsyncStorage) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let storage = runtime.block_on(AsyncStorage::open(":memory:")).unwrap();
+        (runtime, storage)
+    }
+    
     #[test]
     fn test_history_entry() {
         let content = ClipboardContent::Text("测试文本".to_string());
@@ -515,7 +1168,7 @@ mod tests {
     #[test]
     fn test_clipboard_history_in_memory() {
         // 创建不启用持久化的历史记录管理器
-        let history = ClipboardHistory::new(3, false);
+        let history = ClipboardHistory::new(3, None);
         
         // 添加记录
         history.add(ClipboardContent::Text("第一条".to_string())).unwrap();
@@ -566,4 +1219,272 @@ mod tests {
         let entries = history.get_all().unwrap();
         assert_eq!(entries.len(), 0);
     }
+    
+    #[test]
+    fn test_tag_management() {
+        let history = ClipboardHistory::new(10, None);
+        history.add(ClipboardContent::Text("一".to_string())).unwrap();
+        history.add(ClipboardContent::Text("二".to_string())).unwrap();
+        history.add(ClipboardContent::Text("三".to_string())).unwrap();
+        
+        let ids: Vec<String> = history.get_all().unwrap().iter().map(|e| e.id.clone()).collect();
+        history.add_tag(&ids[0], "work").unwrap();
+        history.add_tag(&ids[1], "work").unwrap();
+        history.add_tag(&ids[1], "job").unwrap();
+        history.add_tag(&ids[2], "todo").unwrap();
+        
+        let tags = history.list_tags().unwrap();
+        assert_eq!(tags[0], TagInfo { name: "work".to_string(), count: 2 });
+        assert_eq!(tags.len(), 3);
+        
+        // 合并：同时带有两个标签的条目只保留一个
+        assert_eq!(history.merge_tags(&["job", "work"], "工作").unwrap(), 2);
+        let tags = history.list_tags().unwrap();
+        assert_eq!(tags[0], TagInfo { name: "工作".to_string(), count: 2 });
+        assert_eq!(history.find_by_id(&ids[1]).unwrap().unwrap().tags, vec!["工作".to_string()]);
+        
+        // 重命名
+        assert_eq!(history.rename_tag("todo", "待办").unwrap(), 1);
+        assert_eq!(history.get_by_tag("待办").unwrap().len(), 1);
+        assert!(history.rename_tag("todo", " ").is_err());
+        
+        // 删除
+        assert_eq!(history.delete_tag("工作").unwrap(), 2);
+        assert_eq!(history.list_tags().unwrap().len(), 1);
+        
+        // 条目已带有目标标签时合并后不会重复
+        history.add_tag(&ids[2], "done").unwrap();
+        assert_eq!(history.rename_tag("待办", "done").unwrap(), 1);
+        assert_eq!(history.find_by_id(&ids[2]).unwrap().unwrap().tags, vec!["done".to_string()]);
+        assert_eq!(history.list_tags().unwrap(), vec![TagInfo { name: "done".to_string(), count: 1 }]);
+    }
+    
+    #[test]
+    fn test_smart_collections_in_memory() {
+        let history = ClipboardHistory::new(10, None);
+        history.add(ClipboardContent::Text("本地文本".to_string())).unwrap();
+        history.add_from_device(ClipboardContent::Text("https://example.com".to_string()), "phone").unwrap();
+        history.add_from_device(ClipboardContent::Text("手机文本".to_string()), "phone").unwrap();
+        
+        let collection = history.create_collection("本周来自手机的链接", CollectionQuery {
+            content_kinds: vec![crate::clipboard::ContentKind::Url],
+            source_device_id: Some("phone".to_string()),
+            max_age_secs: Some(7 * 24 * 3600),
+            ..Default::default()
+        }).unwrap();
+        
+        let entries = history.get_collection_entries(&collection.id).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].content, ClipboardContent::Text("https://example.com".to_string()));
+        
+        // 修改查询条件
+        let query = CollectionQuery {
+            source_device_id: Some("phone".to_string()),
+            ..Default::default()
+        };
+        assert!(history.update_collection(&collection.id, "来自手机", query).unwrap());
+        assert_eq!(history.get_collection_entries(&collection.id).unwrap().len(), 2);
+        assert_eq!(history.list_collections().unwrap()[0].name, "来自手机");
+        
+        assert!(history.delete_collection(&collection.id).unwrap());
+        assert!(history.list_collections().unwrap().is_empty());
+        assert!(history.get_collection_entries(&collection.id).is_err());
+    }
+    
+    #[test]
+    fn test_persisted_tags_and_collections() {
+        let (_runtime, storage) = open_storage();
+        
+        let history = ClipboardHistory::new(10, Some(storage.clone()));
+        history.add_from_device(ClipboardContent::Text("持久化测试".to_string()), "laptop").unwrap();
+        let entry = history.get_all().unwrap().into_iter()
+            .find(|e| e.content == ClipboardContent::Text("持久化测试".to_string()))
+            .unwrap();
+        history.add_tag(&entry.id, "persist-old").unwrap();
+        
+        // 超出内存容量、只存在于存储中的条目也会被统计和修改
+        let small = ClipboardHistory::new(1, Some(storage.clone()));
+        small.add(ClipboardContent::Text("仅在存储中".to_string())).unwrap();
+        let stored_only = small.get_all().unwrap()[0].id.clone();
+        small.add_tag(&stored_only, "persist-old").unwrap();
+        small.add(ClipboardContent::Text("挤出内存".to_string())).unwrap();
+        assert!(small.find_by_id(&stored_only).unwrap().is_none());
+        assert!(history.list_tags().unwrap().contains(&TagInfo { name: "persist-old".to_string(), count: 2 }));
+        
+        assert_eq!(history.rename_tag("persist-old", "persist-new").unwrap(), 2);
+        assert!(!history.list_tags().unwrap().iter().any(|t| t.name == "persist-old"));
+        let collection = history.create_collection("持久化收藏夹", CollectionQuery {
+            tags: vec!["persist-new".to_string()],
+            ..Default::default()
+        }).unwrap();
+        
+        // 重新加载后标签、来源设备和收藏夹都应保留
+        let reloaded = ClipboardHistory::new(10, Some(storage.clone()));
+        let loaded = reloaded.find_by_id(&entry.id).unwrap().unwrap();
+        assert_eq!(loaded.tags, vec!["persist-new".to_string()]);
+        assert_eq!(loaded.source_device_id, Some("laptop".to_string()));
+        assert!(reloaded.list_collections().unwrap().iter().any(|c| c.id == collection.id));
+        assert_eq!(reloaded.get_collection_entries(&collection.id).unwrap().len(), 2);
+    }
+    
+    #[test]
+    fn test_soft_delete_and_restore() {
+        let history = ClipboardHistory::new(10, None);
+        history.add(ClipboardContent::Text("一".to_string())).unwrap();
+        history.add(ClipboardContent::Text("二".to_string())).unwrap();
+        let id = history.get_all().unwrap()[0].id.clone();
+        
+        assert!(history.remove(&id).unwrap());
+        assert_eq!(history.get_all().unwrap().len(), 1);
+        let trash = history.get_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert!(trash[0].deleted_at.is_some());
+        
+        // 恢复后回到历史记录中
+        assert!(history.restore(&id).unwrap());
+        let restored = history.find_by_id(&id).unwrap().unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert_eq!(history.get_all().unwrap().len(), 2);
+        assert!(history.get_trash().unwrap().is_empty());
+        assert!(!history.restore(&id).unwrap());
+        
+        // 撤销清空
+        history.clear().unwrap();
+        assert!(history.get_all().unwrap().is_empty());
+        assert_eq!(history.get_trash().unwrap().len(), 2);
+        assert_eq!(history.undo_clear().unwrap(), 2);
+        assert_eq!(history.get_all().unwrap().len(), 2);
+        assert_eq!(history.undo_clear().unwrap(), 0);
+        
+        // 清空回收站后无法恢复
+        history.remove(&id).unwrap();
+        assert_eq!(history.empty_trash().unwrap(), 1);
+        assert!(!history.restore(&id).unwrap());
+    }
+    
+    #[test]
+    fn test_purge_delay() {
+        let history = ClipboardHistory::new(10, None).with_purge_delay(Duration::ZERO);
+        history.add(ClipboardContent::Text("过期".to_string())).unwrap();
+        let id = history.get_all().unwrap()[0].id.clone();
+        history.remove(&id).unwrap();
+        assert_eq!(history.purge_expired().unwrap(), 1);
+        assert!(history.get_trash().unwrap().is_empty());
+        
+        let history = ClipboardHistory::new(10, None);
+        history.add(ClipboardContent::Text("保留".to_string())).unwrap();
+        let id = history.get_all().unwrap()[0].id.clone();
+        history.remove(&id).unwrap();
+        assert_eq!(history.purge_expired().unwrap(), 0);
+        assert_eq!(history.get_trash().unwrap().len(), 1);
+        
+        // 运行中的实例在之后的修改时自动清理过期条目，无需重新创建
+        let history = ClipboardHistory::new(10, None).with_purge_delay(Duration::ZERO);
+        history.add(ClipboardContent::Text("一".to_string())).unwrap();
+        let id = history.get_all().unwrap()[0].id.clone();
+        history.remove(&id).unwrap();
+        assert_eq!(history.get_trash().unwrap().len(), 1);
+        history.add(ClipboardContent::Text("二".to_string())).unwrap();
+        assert!(history.get_trash().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_tombstone_propagation() {
+        let sender = ClipboardHistory::new(10, None);
+        let receiver = ClipboardHistory::new(10, None);
+        let content = ClipboardContent::Text("共享内容".to_string());
+        sender.add(content.clone()).unwrap();
+        receiver.add_from_device(content, "sender").unwrap();
+        receiver.add(ClipboardContent::Text("其他内容".to_string())).unwrap();
+        
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = sent.clone();
+        sender.set_tombstone_callback(Some(Box::new(move |tombstone| {
+            sent_clone.lock().unwrap().push(tombstone);
+        }))).unwrap();
+        
+        let id = sender.get_all().unwrap()[0].id.clone();
+        sender.remove(&id).unwrap();
+        let tombstones = sent.lock().unwrap().clone();
+        assert_eq!(tombstones.len(), 1);
+        
+        // 接收方只删除相同内容的条目，并且可以从回收站恢复
+        assert_eq!(receiver.apply_tombstone(&tombstones[0]).unwrap(), 1);
+        let remaining = receiver.get_all().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].content, ClipboardContent::Text("其他内容".to_string()));
+        assert_eq!(receiver.get_trash().unwrap().len(), 1);
+        
+        // 清空不会生成删除标记
+        sender.add(ClipboardContent::Text("再来一条".to_string())).unwrap();
+        sender.clear().unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_history_events() {
+        let history = ClipboardHistory::new(2, None);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        let sub_id = history.subscribe(Box::new(move |event| {
+            events_clone.lock().unwrap().push(event.clone());
+        })).unwrap();
+        
+        history.add(ClipboardContent::Text("一".to_string())).unwrap();
+        history.add(ClipboardContent::Text("二".to_string())).unwrap();
+        history.add(ClipboardContent::Text("三".to_string())).unwrap();
+        
+        let taken: Vec<HistoryEvent> = events.lock().unwrap().drain(..).collect();
+        assert_eq!(taken.len(), 4);
+        let first_id = match &taken[0] {
+            HistoryEvent::Added(entry) => entry.id.clone(),
+            other => panic!("意外的事件: {other:?}"),
+        };
+        assert!(matches!(&taken[3], HistoryEvent::Evicted { id } if *id == first_id));
+        
+        // 修改和删除
+        let id = history.get_all().unwrap()[0].id.clone();
+        history.toggle_favorite(&id).unwrap();
+        history.remove(&id).unwrap();
+        history.restore(&id).unwrap();
+        history.clear().unwrap();
+        
+        let taken: Vec<HistoryEvent> = events.lock().unwrap().drain(..).collect();
+        assert!(matches!(&taken[0], HistoryEvent::Updated(entry) if entry.id == id && entry.is_favorite));
+        assert!(matches!(&taken[1], HistoryEvent::Removed { id: removed } if *removed == id));
+        assert!(matches!(&taken[2], HistoryEvent::Added(entry) if entry.id == id));
+        assert!(matches!(&taken[3], HistoryEvent::Cleared));
+        assert_eq!(taken.len(), 4);
+        
+        // 序列化格式
+        let json = serde_json::to_string(&HistoryEvent::Removed { id: "x".to_string() }).unwrap();
+        assert_eq!(json, r#"{"type":"Removed","data":{"id":"x"}}"#);
+        
+        // 取消订阅后不再收到事件
+        assert!(history.unsubscribe(sub_id).unwrap());
+        assert!(!history.unsubscribe(sub_id).unwrap());
+        history.add(ClipboardContent::Text("四".to_string())).unwrap();
+        assert!(events.lock().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_persisted_trash() {
+        let (_runtime, storage) = open_storage();
+        
+        let history = ClipboardHistory::new(100, Some(storage.clone()));
+        history.add(ClipboardContent::Text("回收站持久化".to_string())).unwrap();
+        let id = history.get_all().unwrap().into_iter()
+            .find(|e| e.content == ClipboardContent::Text("回收站持久化".to_string()))
+            .unwrap()
+            .id;
+        history.remove(&id).unwrap();
+        
+        let reloaded = ClipboardHistory::new(100, Some(storage.clone()));
+        assert!(reloaded.find_by_id(&id).unwrap().is_none());
+        assert!(reloaded.get_trash().unwrap().iter().any(|e| e.id == id));
+        
+        assert!(reloaded.restore(&id).unwrap());
+        let reloaded = ClipboardHistory::new(100, Some(storage.clone()));
+        assert!(reloaded.find_by_id(&id).unwrap().is_some());
+    }
 }
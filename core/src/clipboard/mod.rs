@@ -3,6 +3,7 @@
 use crate::error::{Error, Result};
 use arboard;
 use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 use tokio::time::{self, Duration};
@@ -19,10 +20,20 @@ pub use mock_clipboard::{set_mock_clipboard, get_mock_clipboard, clear_mock_clip
 
 // 导入历史记录功能
 mod history;
-pub use history::{ClipboardHistory, HistoryEntry};
+/// 默认保留的历史记录条数
+pub const DEFAULT_MAX_HISTORY: usize = 100;
+pub use history::{ClipboardHistory, HistoryEntry, Tombstone, TombstoneCallback, DEFAULT_PURGE_DELAY, PURGE_INTERVAL};
+
+// 导入历史记录变更事件
+mod events;
+pub use events::{HistoryEvent, HistoryEventCallback, SubscriptionId};
+
+// 导入智能收藏夹功能
+mod collections;
+pub use collections::{CollectionQuery, ContentKind, SmartCollection, TagInfo};
 
 /// 剪贴板内容类型
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ClipboardContent {
     /// 文本内容
     Text(String),
@@ -77,10 +88,17 @@ impl ClipboardWatcher {
         })
     }
     
-    /// 创建带有历史记录功能的剪贴板监听器
-    pub fn with_history(max_history: usize, persistence_enabled: bool) -> Result<Self> {
+    /// 创建带有历史记录功能的剪贴板监听器，提供存储时持久化历史记录
+    pub fn with_history(max_history: usize, storage: Option<crate::storage::AsyncStorage>) -> Result<Self> {
+        let mut watcher = Self::new()?;
+        let history = Arc::new(ClipboardHistory::new(max_history, storage));
+        watcher.history = Some(history);
+        Ok(watcher)
+    }
+    
+    /// 创建使用已有历史记录实例的剪贴板监听器
+    pub fn with_shared_history(history: Arc<ClipboardHistory>) -> Result<Self> {
         let mut watcher = Self::new()?;
-        let history = Arc::new(ClipboardHistory::new(max_history, persistence_enabled));
         watcher.history = Some(history);
         Ok(watcher)
     }
@@ -0,0 +1,141 @@
+//! 配对设备的密钥指纹
+//!
+//! 指纹由双方的身份公钥（加密公钥和签名公钥）共同派生，与计算方向无关，
+//! 两台设备显示相同的安全码和表情序列。用户当面比对一致后即可将设备标记为已验证。
+
+use crate::error::{Error, Result};
+use crate::types::DeviceInfo;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::generichash;
+
+/// 指纹派生的域分隔标识
+const FINGERPRINT_DOMAIN: &[u8] = b"PasteAll-Fingerprint-v1";
+
+/// 单设备密钥标识派生的域分隔标识
+const KEY_ID_DOMAIN: &[u8] = b"PasteAll-KeyId-v1";
+
+/// 单设备密钥标识长度（字节）
+const KEY_ID_BYTES: usize = 16;
+
+/// 安全码分组数
+const SAFETY_NUMBER_GROUPS: usize = 12;
+
+/// 表情序列长度
+const EMOJI_COUNT: usize = 8;
+
+/// 表情字母表（64个易于区分的表情）
+const EMOJI_ALPHABET: [&str; 64] = [
+    "🐶", "🐱", "🦁", "🐎", "🦄", "🐷", "🐘", "🐰", "🐼", "🐓", "🐧", "🐢", "🐟", "🐙", "🦋", "🌷",
+    "🌳", "🌵", "🍄", "🌏", "🌙", "☁️", "🔥", "🍌", "🍎", "🍓", "🌽", "🍕", "🎂", "❤️", "😀", "🤖",
+    "🎩", "👓", "🔧", "🎅", "👍", "☂️", "⌛", "⏰", "🎁", "💡", "📕", "✏️", "📎", "✂️", "🔒", "🔑",
+    "🔨", "☎️", "🏁", "🚂", "🚲", "✈️", "🚀", "🏆", "⚽", "🎸", "🎺", "🔔", "⚓", "🎧", "📁", "📌",
+];
+
+/// 配对设备的密钥指纹
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// 60位数字安全码，每5位一组，以空格分隔
+    pub safety_number: String,
+    /// 表情序列
+    pub emoji: Vec<String>,
+}
+
+/// 计算本设备与远程设备之间的指纹
+pub fn compute_fingerprint(local: &DeviceInfo, remote: &DeviceInfo) -> Result<Fingerprint> {
+    let mut identities = [identity_bytes(local)?, identity_bytes(remote)?];
+    identities.sort();
+
+    let hash_error = |_| Error::Crypto("计算密钥指纹失败".to_string());
+    let mut state = generichash::State::new(Some(64), None).map_err(hash_error)?;
+    state.update(FINGERPRINT_DOMAIN).map_err(hash_error)?;
+    for identity in &identities {
+        state.update(identity).map_err(hash_error)?;
+    }
+    let digest = state.finalize().map_err(hash_error)?;
+    let digest = digest.as_ref();
+
+    // 每5字节生成一组5位数字
+    let safety_number = digest
+        .chunks_exact(5)
+        .take(SAFETY_NUMBER_GROUPS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let emoji = digest[digest.len() - EMOJI_COUNT..]
+        .iter()
+        .map(|b| EMOJI_ALPHABET[usize::from(*b) % EMOJI_ALPHABET.len()].to_string())
+        .collect();
+
+    Ok(Fingerprint { safety_number, emoji })
+}
+
+/// 计算单台设备身份公钥的短标识
+///
+/// 用于邀请码等只能携带少量数据的场合，接收方拿到完整公钥后重新计算并比对。
+pub fn key_id(device: &DeviceInfo) -> Result<String> {
+    let hash_error = |_| Error::Crypto("计算密钥标识失败".to_string());
+    let mut state = generichash::State::new(Some(KEY_ID_BYTES), None).map_err(hash_error)?;
+    state.update(KEY_ID_DOMAIN).map_err(hash_error)?;
+    state.update(&identity_bytes(device)?).map_err(hash_error)?;
+    let digest = state.finalize().map_err(hash_error)?;
+
+    Ok(base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD))
+}
+
+/// 拼接设备的加密公钥和签名公钥
+fn identity_bytes(device: &DeviceInfo) -> Result<Vec<u8>> {
+    if device.verify_key.is_empty() {
+        return Err(Error::Crypto(format!("设备 {} 没有签名公钥，无法计算指纹", device.id)));
+    }
+
+    let mut bytes = Vec::with_capacity(64);
+    for key in [&device.public_key, &device.verify_key] {
+        let decoded = base64::decode(key)
+            .map_err(|e| Error::Crypto(format!("解析设备 {} 的公钥失败: {e}", device.id)))?;
+        if decoded.len() != 32 {
+            return Err(Error::Crypto(format!("设备 {} 的公钥长度无效", device.id)));
+        }
+        bytes.extend_from_slice(&decoded);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+    use crate::types::DeviceType;
+
+    fn device(name: &str) -> DeviceInfo {
+        let (public_key, verify_key) = CryptoManager::new().get_public_keys();
+        let mut device = DeviceInfo::new(name, DeviceType::Desktop, &public_key);
+        device.verify_key = verify_key;
+        device
+    }
+
+    #[test]
+    fn test_fingerprint_symmetric() {
+        crate::crypto::init();
+        let laptop = device("笔记本");
+        let phone = device("手机");
+        let impostor = device("笔记本");
+
+        let on_laptop = compute_fingerprint(&laptop, &phone).unwrap();
+        let on_phone = compute_fingerprint(&phone, &laptop).unwrap();
+        assert_eq!(on_laptop, on_phone);
+        assert_eq!(on_laptop.safety_number.len(), 12 * 5 + 11);
+        assert_eq!(on_laptop.emoji.len(), EMOJI_COUNT);
+
+        // 中间人使用自己的密钥时指纹不一致
+        assert_ne!(compute_fingerprint(&impostor, &phone).unwrap(), on_phone);
+
+        let mut legacy = phone.clone();
+        legacy.verify_key.clear();
+        assert!(compute_fingerprint(&laptop, &legacy).is_err());
+    }
+}
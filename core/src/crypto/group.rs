@@ -0,0 +1,102 @@
+//! 同步组密钥
+//!
+//! 组内所有成员共享同一个对称密钥，内容只需加密一次。组密钥通过成员的身份公钥
+//! 加密分发，成员变化时生成新的组密钥。
+
+use super::CryptoManager;
+use crate::error::{Error, Result};
+use sodiumoxide::crypto::{generichash, secretbox};
+use std::fmt;
+
+/// 同步组密钥
+#[derive(Clone)]
+pub struct GroupKey(secretbox::Key);
+
+impl fmt::Debug for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("GroupKey(..)")
+    }
+}
+
+impl GroupKey {
+    /// 生成随机组密钥
+    pub fn generate() -> Self {
+        Self(secretbox::gen_key())
+    }
+
+    /// 从原始字节恢复组密钥
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        secretbox::Key::from_slice(bytes)
+            .map(Self)
+            .ok_or_else(|| Error::Crypto("组密钥长度无效".to_string()))
+    }
+
+    /// 获取原始字节
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// 组密钥的摘要，不泄露密钥本身，用于在同一版本的并发轮换中确定性地选出保留的密钥
+    pub fn digest(&self) -> Result<Vec<u8>> {
+        generichash::hash(self.as_bytes(), None, Some(b"PasteAll-GroupKey-v1"))
+            .map(|digest| digest.as_ref().to_vec())
+            .map_err(|_| Error::Crypto("计算组密钥摘要失败".to_string()))
+    }
+
+    /// 使用组密钥加密，返回 nonce || 密文
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let nonce = secretbox::gen_nonce();
+        let encrypted = secretbox::seal(data, &nonce, &self.0);
+
+        let mut result = Vec::with_capacity(secretbox::NONCEBYTES + encrypted.len());
+        result.extend_from_slice(nonce.as_ref());
+        result.extend_from_slice(&encrypted);
+        result
+    }
+
+    /// 使用组密钥解密
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < secretbox::NONCEBYTES + secretbox::MACBYTES {
+            return Err(Error::Crypto("组加密数据长度无效".to_string()));
+        }
+
+        let (nonce_bytes, encrypted) = sealed.split_at(secretbox::NONCEBYTES);
+        let nonce = secretbox::Nonce::from_slice(nonce_bytes)
+            .ok_or_else(|| Error::Crypto("组加密数据nonce无效".to_string()))?;
+
+        secretbox::open(encrypted, &nonce, &self.0)
+            .map_err(|_| Error::Crypto("组加密数据解密失败".to_string()))
+    }
+
+    /// 使用成员的身份公钥加密组密钥，用于分发给该成员
+    pub fn seal_for_member(&self, manager: &CryptoManager, member_public_key: &str) -> Result<Vec<u8>> {
+        manager.encrypt_with_public_key(member_public_key, self.as_bytes())
+    }
+
+    /// 使用本设备的身份私钥解密分发来的组密钥
+    pub fn open_from_member(manager: &CryptoManager, sealed: &[u8]) -> Result<Self> {
+        Self::from_bytes(&manager.decrypt_with_private_key(sealed)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_key_distribution() {
+        crate::crypto::init();
+        let sender = CryptoManager::new();
+        let member = CryptoManager::new();
+        let outsider = CryptoManager::new();
+
+        let key = GroupKey::generate();
+        let sealed = key.seal_for_member(&sender, &member.get_public_key_base64()).unwrap();
+        let received = GroupKey::open_from_member(&member, &sealed).unwrap();
+        assert!(GroupKey::open_from_member(&outsider, &sealed).is_err());
+
+        let ciphertext = key.seal(b"clipboard");
+        assert_eq!(received.open(&ciphertext).unwrap(), b"clipboard");
+        assert!(GroupKey::generate().open(&ciphertext).is_err());
+    }
+}
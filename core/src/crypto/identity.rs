@@ -0,0 +1,221 @@
+//! 设备身份密钥的持久化
+//!
+//! 身份密钥在首次启动时生成并保存在配置目录中，此后每次启动都加载同一组密钥，
+//! 保证 `DeviceInfo.public_key` 和已建立的配对在重启后依然有效。
+//! 密钥文件仅允许当前用户读写，可选使用口令加密保存。
+//!
+//! 文件权限只在Unix平台上设置和检查。其他平台（如Windows）不修改也不检查ACL，
+//! 密钥文件继承配置目录的访问控制；配置目录可能被其他用户读取时应使用口令加密保存。
+
+use super::{open_with_passphrase, seal_with_passphrase, CryptoManager, IdentityKeys};
+use crate::error::{Error, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 身份密钥文件格式版本
+const IDENTITY_FILE_VERSION: u32 = 1;
+
+/// 身份密钥文件内容
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+enum IdentityFile {
+    /// 明文保存（依赖文件权限保护）
+    Plain {
+        /// 格式版本
+        version: u32,
+        /// 身份密钥
+        keys: IdentityKeys,
+    },
+    /// 使用口令加密保存
+    Wrapped {
+        /// 格式版本
+        version: u32,
+        /// 加密后的身份密钥（Base64编码）
+        data: String,
+    },
+}
+
+/// 根据数据库路径获取身份密钥文件路径
+///
+/// 内存数据库没有对应的配置目录，返回None。
+pub fn identity_path(storage_path: &str) -> Option<PathBuf> {
+    if storage_path == ":memory:" {
+        return None;
+    }
+
+    Some(Path::new(storage_path).with_extension("identity"))
+}
+
+/// 加载身份密钥，文件不存在时返回None
+///
+/// 文件使用口令加密时必须提供口令；明文文件在提供口令时会被重新加密保存。
+pub fn load_identity(path: &Path, passphrase: Option<&str>) -> Result<Option<IdentityKeys>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    check_permissions(path)?;
+
+    let content = fs::read(path)?;
+    let file: IdentityFile = serde_json::from_slice(&content)?;
+
+    let keys = match file {
+        IdentityFile::Plain { keys, .. } => {
+            if let Some(passphrase) = passphrase {
+                info!("使用口令重新加密身份密钥文件");
+                save_identity(path, &keys, Some(passphrase))?;
+            }
+            keys
+        }
+        IdentityFile::Wrapped { data, .. } => {
+            let passphrase = passphrase.ok_or_else(|| {
+                Error::Authentication("身份密钥文件已加密，需要提供口令".to_string())
+            })?;
+            let sealed = base64::decode(&data)
+                .map_err(|e| Error::Crypto(format!("解析身份密钥文件失败: {e}")))?;
+            serde_json::from_slice(&open_with_passphrase(passphrase, &sealed)?)?
+        }
+    };
+
+    // 校验公私钥匹配
+    CryptoManager::from_identity(&keys)?;
+
+    Ok(Some(keys))
+}
+
+/// 保存身份密钥
+///
+/// 先写入临时文件再替换，避免写入中断导致密钥丢失。
+pub fn save_identity(path: &Path, keys: &IdentityKeys, passphrase: Option<&str>) -> Result<()> {
+    let file = match passphrase {
+        Some(passphrase) => {
+            let sealed = seal_with_passphrase(passphrase, &serde_json::to_vec(keys)?)?;
+            IdentityFile::Wrapped {
+                version: IDENTITY_FILE_VERSION,
+                data: base64::encode(sealed),
+            }
+        }
+        None => IdentityFile::Plain {
+            version: IDENTITY_FILE_VERSION,
+            keys: keys.clone(),
+        },
+    };
+    let content = serde_json::to_vec_pretty(&file)?;
+
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let temp_path = path.with_extension("identity.tmp");
+    let mut temp = open_private(&temp_path)?;
+    temp.write_all(&content)?;
+    temp.sync_all()?;
+    drop(temp);
+
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// 加载身份密钥，不存在时生成并保存新的密钥
+pub fn load_or_create_identity(path: &Path, passphrase: Option<&str>) -> Result<IdentityKeys> {
+    if let Some(keys) = load_identity(path, passphrase)? {
+        return Ok(keys);
+    }
+
+    info!("生成新的设备身份密钥: {}", path.display());
+    let keys = CryptoManager::new().export_identity();
+    save_identity(path, &keys, passphrase)?;
+
+    Ok(keys)
+}
+
+/// 创建仅当前用户可读写的文件
+#[cfg(unix)]
+fn open_private(path: &Path) -> Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    Ok(fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?)
+}
+
+/// 创建密钥文件
+///
+/// 非Unix平台不设置ACL，文件继承配置目录的访问控制，不保证仅当前用户可读写。
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> Result<fs::File> {
+    Ok(fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?)
+}
+
+/// 检查密钥文件权限，其他用户可访问时拒绝加载
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        warn!("身份密钥文件权限过于宽松: {} ({:o})", path.display(), mode & 0o777);
+        return Err(Error::Permission(format!(
+            "身份密钥文件 {} 的权限过于宽松，应仅允许当前用户读写",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// 检查密钥文件权限
+///
+/// 非Unix平台不检查ACL，总是允许加载；明文保存的密钥只受配置目录的访问控制保护。
+#[cfg(not(unix))]
+fn check_permissions(path: &Path) -> Result<()> {
+    debug!("当前平台不检查身份密钥文件的访问权限: {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_persistence() {
+        crate::crypto::init();
+        let dir = tempfile::tempdir().unwrap();
+        let path = identity_path(dir.path().join("pasteall.db").to_str().unwrap()).unwrap();
+        assert!(identity_path(":memory:").is_none());
+
+        let created = load_or_create_identity(&path, None).unwrap();
+        let loaded = load_or_create_identity(&path, None).unwrap();
+        assert_eq!(created, loaded);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(matches!(load_identity(&path, None), Err(Error::Permission(_))));
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        // 提供口令后重新加密保存，此后必须使用口令加载
+        assert_eq!(load_identity(&path, Some("口令")).unwrap(), Some(created.clone()));
+        assert!(!fs::read_to_string(&path).unwrap().contains(&created.signing_secret));
+        assert!(matches!(load_identity(&path, None), Err(Error::Authentication(_))));
+        assert!(load_identity(&path, Some("错误口令")).is_err());
+        assert_eq!(load_identity(&path, Some("口令")).unwrap(), Some(created));
+    }
+}
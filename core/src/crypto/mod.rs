@@ -1,19 +1,42 @@
 //! 加密与认证模块，提供端到端加密、密钥管理和安全认证功能
 
 use crate::error::{Error, Result};
+use crate::types::DeviceInfo;
 use log::error;
+use serde::{Deserialize, Serialize};
 use sodiumoxide::crypto::{
     box_::{self, PublicKey, SecretKey},
-    sealedbox,
+    pwhash::argon2id13,
+    scalarmult::curve25519,
+    sealedbox, secretbox,
     sign::{self, Signature},
 };
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
 
-// 全局密钥管理器单例
-static CRYPTO_MANAGER: OnceLock<CryptoManager> = OnceLock::new();
+mod fingerprint;
+mod group;
+mod identity;
+mod stream;
+
+pub use fingerprint::{compute_fingerprint, key_id, Fingerprint};
+pub use group::GroupKey;
+pub use identity::{identity_path, load_identity, load_or_create_identity, save_identity};
+pub use stream::{StreamDecryptor, StreamEncryptor, StreamKey, STREAM_ABYTES};
+
+/// 身份导出文件标识
+const IDENTITY_EXPORT_MAGIC: &[u8; 4] = b"PAID";
+
+/// 身份导出格式版本
+const IDENTITY_EXPORT_VERSION: u8 = 2;
+
+// 全局密钥管理器单例，密钥轮换时整体替换
+static CRYPTO_MANAGER: RwLock<Option<Arc<CryptoManager>>> = RwLock::new(None);
 
 /// 初始化加密模块
+///
+/// 未加载身份密钥时生成临时密钥，重启后失效，仅适用于测试和内存配置。
 pub fn init() {
     if let Err(e) = sodiumoxide::init() {
         error!("初始化sodiumoxide失败: {e:?}");
@@ -21,12 +44,135 @@ pub fn init() {
     }
     
     // 初始化全局密钥管理器
-    let _ = CRYPTO_MANAGER.get_or_init(|| CryptoManager::new());
+    let mut manager = CRYPTO_MANAGER.write().unwrap_or_else(|e| e.into_inner());
+    if manager.is_none() {
+        *manager = Some(Arc::new(CryptoManager::new()));
+    }
+}
+
+/// 使用已有的身份密钥初始化加密模块
+///
+/// 用于从备份恢复或加载已保存的身份。若加密模块已使用其他密钥初始化则返回错误。
+pub fn init_with_identity(identity: &IdentityKeys) -> Result<()> {
+    if let Err(e) = sodiumoxide::init() {
+        error!("初始化sodiumoxide失败: {e:?}");
+        return Err(Error::Crypto("初始化加密库失败".to_string()));
+    }
+
+    let new_manager = CryptoManager::from_identity(identity)?;
+    let mut manager = CRYPTO_MANAGER.write().unwrap_or_else(|e| e.into_inner());
+    match &*manager {
+        None => *manager = Some(Arc::new(new_manager)),
+        Some(current) if current.get_public_keys() != new_manager.get_public_keys() => {
+            return Err(Error::Crypto("加密模块已使用其他身份密钥初始化".to_string()));
+        }
+        Some(_) => {}
+    }
+
+    Ok(())
+}
+
+/// 从配置目录加载身份密钥并初始化加密模块
+///
+/// 身份密钥文件不存在时生成并保存；`path` 为None时（内存配置）使用临时密钥。
+pub fn init_from_profile(path: Option<&Path>, passphrase: Option<&str>) -> Result<()> {
+    match path {
+        Some(path) => {
+            if let Err(e) = sodiumoxide::init() {
+                error!("初始化sodiumoxide失败: {e:?}");
+                return Err(Error::Crypto("初始化加密库失败".to_string()));
+            }
+            init_with_identity(&load_or_create_identity(path, passphrase)?)
+        }
+        None => {
+            init();
+            Ok(())
+        }
+    }
+}
+
+/// 轮换设备身份密钥
+///
+/// 生成新的加密和签名密钥对，保存到配置目录后替换当前密钥。已缓存的共享密钥
+/// 使用新私钥重新计算；已配对设备需要获取新公钥后才能继续通信。
+pub fn rotate_identity(path: Option<&Path>, passphrase: Option<&str>) -> Result<IdentityKeys> {
+    let rotated = prepare_rotation(path, passphrase)?;
+    let identity = rotated.export_identity();
+    activate_identity(rotated);
+
+    Ok(identity)
 }
 
-/// 获取全局密钥管理器引用
-fn get_crypto_manager() -> &'static CryptoManager {
-    CRYPTO_MANAGER.get().expect("加密模块未初始化")
+/// 生成轮换后的身份密钥并保存到配置目录，当前密钥保持不变
+///
+/// 调用方可在新密钥生效前使用旧密钥通知已配对设备，再通过 [`activate_identity`] 替换当前密钥。
+pub fn prepare_rotation(path: Option<&Path>, passphrase: Option<&str>) -> Result<CryptoManager> {
+    let rotated = get_crypto_manager().rotated()?;
+
+    // 先持久化，保证重启后不会回退到旧密钥
+    if let Some(path) = path {
+        save_identity(path, &rotated.export_identity(), passphrase)?;
+    }
+
+    Ok(rotated)
+}
+
+/// 使用新的密钥管理器替换当前密钥
+pub fn activate_identity(rotated: CryptoManager) {
+    let mut manager = CRYPTO_MANAGER.write().unwrap_or_else(|e| e.into_inner());
+    *manager = Some(Arc::new(rotated));
+}
+
+/// 导入从旧设备迁移的身份密钥
+///
+/// 解密后按 [`CryptoManager::import_encrypted`] 校验密钥与 `recorded_keys` 中该设备的记录一致，
+/// 保存到配置目录并替换当前密钥，返回旧设备的ID。
+/// 已配对设备无需重新配对，但共享密钥需由调用方重新计算。
+pub fn import_identity<F>(
+    path: Option<&Path>,
+    file_passphrase: Option<&str>,
+    data: &[u8],
+    passphrase: &str,
+    recorded_keys: F,
+) -> Result<(String, IdentityKeys)>
+where
+    F: FnOnce(&str) -> Result<Option<(String, String)>>,
+{
+    if let Err(e) = sodiumoxide::init() {
+        error!("初始化sodiumoxide失败: {e:?}");
+        return Err(Error::Crypto("初始化加密库失败".to_string()));
+    }
+
+    let (device_id, imported) = CryptoManager::import_encrypted(data, passphrase, recorded_keys)?;
+    let identity = imported.export_identity();
+    if let Some(path) = path {
+        save_identity(path, &identity, file_passphrase)?;
+    }
+
+    let mut manager = CRYPTO_MANAGER.write().unwrap_or_else(|e| e.into_inner());
+    *manager = Some(Arc::new(imported));
+
+    Ok((device_id, identity))
+}
+
+/// 获取全局密钥管理器
+///
+/// 与内部使用的 `get_crypto_manager` 不同，未初始化时返回错误而不是panic。
+pub fn manager() -> Result<Arc<CryptoManager>> {
+    CRYPTO_MANAGER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| Error::Crypto("加密模块未初始化".to_string()))
+}
+
+/// 获取全局密钥管理器
+fn get_crypto_manager() -> Arc<CryptoManager> {
+    CRYPTO_MANAGER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .expect("加密模块未初始化")
 }
 
 /// 获取公钥（Base64编码）
@@ -34,9 +180,15 @@ pub fn get_public_key() -> Result<String> {
     Ok(get_crypto_manager().get_public_key_base64())
 }
 
-/// 签名数据
-pub fn sign(data: &str, _public_key: &str) -> Result<String> {
-    // 在实际应用中，需要验证public_key与当前设备匹配
+/// 获取签名验证公钥（Ed25519，Base64编码）
+pub fn get_verify_key() -> Result<String> {
+    Ok(get_crypto_manager().get_signing_key_base64())
+}
+
+/// 使用本设备的签名私钥签名数据
+///
+/// 签名私钥只保存在全局密钥管理器中，调用方无法指定其他密钥。
+pub fn sign(data: &str) -> Result<String> {
     let signature = get_crypto_manager().sign(data.as_bytes());
     Ok(base64::encode(&signature))
 }
@@ -49,6 +201,21 @@ pub fn verify_signature(data: &str, signature: &str, public_key: &str) -> Result
     get_crypto_manager().verify(&signature_bytes, data.as_bytes(), public_key)
 }
 
+/// 使用设备记录中的签名公钥验证签名
+///
+/// 设备没有签名公钥或签名无效时返回认证错误。
+pub fn verify_device_signature(device: &DeviceInfo, data: &str, signature: &str) -> Result<()> {
+    if device.verify_key.is_empty() {
+        return Err(Error::Authentication(format!("设备 {} 没有签名公钥", device.id)));
+    }
+
+    if !verify_signature(data, signature, &device.verify_key)? {
+        return Err(Error::Authentication(format!("设备 {} 的签名验证失败", device.id)));
+    }
+
+    Ok(())
+}
+
 /// 加密数据
 pub fn encrypt(device_id: &str, data: &[u8]) -> Result<Vec<u8>> {
     get_crypto_manager().encrypt(device_id, data)
@@ -65,6 +232,82 @@ pub fn generate_shared_key(device_id: &str, remote_public_key_base64: &str) -> R
     get_crypto_manager().generate_shared_key(device_id, &remote_public_key)
 }
 
+/// 删除与设备的共享密钥
+pub fn remove_shared_key(device_id: &str) -> Result<()> {
+    get_crypto_manager().remove_shared_key(device_id)
+}
+
+/// 计算X25519密钥协商结果
+///
+/// 对方公钥为低阶点时返回错误，防止协商出可预测的共享秘密。
+pub fn x25519(secret_key: &SecretKey, public_key: &PublicKey) -> Result<[u8; 32]> {
+    let scalar = curve25519::Scalar(secret_key.0);
+    let point = curve25519::GroupElement(public_key.0);
+    curve25519::scalarmult(&scalar, &point)
+        .map(|shared| shared.0)
+        .map_err(|_| Error::Crypto("无效的对方公钥".to_string()))
+}
+
+/// 导出当前设备的身份密钥
+pub fn export_identity() -> Result<IdentityKeys> {
+    Ok(get_crypto_manager().export_identity())
+}
+
+/// 导出使用口令加密的身份密钥，用于迁移到新设备
+pub fn export_identity_encrypted(passphrase: &str, device_id: &str) -> Result<Vec<u8>> {
+    get_crypto_manager().export_encrypted(passphrase, device_id)
+}
+
+/// 使用口令加密数据
+///
+/// 密钥由Argon2id从口令派生，输出格式为 `盐 || nonce || 密文`。
+pub fn seal_with_passphrase(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let salt = argon2id13::gen_salt();
+    let key = derive_passphrase_key(passphrase, &salt)?;
+    let nonce = secretbox::gen_nonce();
+    let encrypted = secretbox::seal(data, &nonce, &key);
+
+    let mut result = Vec::with_capacity(argon2id13::SALTBYTES + secretbox::NONCEBYTES + encrypted.len());
+    result.extend_from_slice(salt.as_ref());
+    result.extend_from_slice(nonce.as_ref());
+    result.extend_from_slice(&encrypted);
+
+    Ok(result)
+}
+
+/// 使用口令解密 [`seal_with_passphrase`] 生成的数据
+pub fn open_with_passphrase(passphrase: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < argon2id13::SALTBYTES + secretbox::NONCEBYTES + secretbox::MACBYTES {
+        return Err(Error::Crypto("加密数据太短".to_string()));
+    }
+
+    let (salt_bytes, rest) = sealed.split_at(argon2id13::SALTBYTES);
+    let (nonce_bytes, encrypted) = rest.split_at(secretbox::NONCEBYTES);
+    let salt = argon2id13::Salt::from_slice(salt_bytes)
+        .ok_or_else(|| Error::Crypto("无效的盐值".to_string()))?;
+    let nonce = secretbox::Nonce::from_slice(nonce_bytes)
+        .ok_or_else(|| Error::Crypto("无效的nonce".to_string()))?;
+
+    let key = derive_passphrase_key(passphrase, &salt)?;
+    secretbox::open(encrypted, &nonce, &key)
+        .map_err(|_| Error::Crypto("口令错误或数据已损坏".to_string()))
+}
+
+/// 从口令派生对称密钥
+fn derive_passphrase_key(passphrase: &str, salt: &argon2id13::Salt) -> Result<secretbox::Key> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    argon2id13::derive_key(
+        &mut key.0,
+        passphrase.as_bytes(),
+        salt,
+        argon2id13::OPSLIMIT_INTERACTIVE,
+        argon2id13::MEMLIMIT_INTERACTIVE,
+    )
+    .map_err(|_| Error::Crypto("派生口令密钥失败".to_string()))?;
+
+    Ok(key)
+}
+
 /// 密钥对
 #[derive(Debug, Clone)]
 pub struct KeyPair {
@@ -174,6 +417,53 @@ impl SignKeyPair {
     }
 }
 
+/// 设备身份密钥（Base64编码）
+///
+/// 包含私钥，仅用于持久化和备份，不应通过网络发送。
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityKeys {
+    /// 加密公钥
+    pub encryption_public: String,
+    /// 加密私钥
+    pub encryption_secret: String,
+    /// 签名公钥
+    pub signing_public: String,
+    /// 签名私钥
+    pub signing_secret: String,
+}
+
+impl std::fmt::Debug for IdentityKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentityKeys")
+            .field("encryption_public", &self.encryption_public)
+            .field("signing_public", &self.signing_public)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 迁移导出的身份，记录所属设备及其公钥
+#[derive(Serialize, Deserialize)]
+struct IdentityExport {
+    /// 设备ID
+    device_id: String,
+    /// 加密公钥（Base64编码）
+    public_key: String,
+    /// 签名公钥（Base64编码）
+    verify_key: String,
+    /// 身份密钥
+    identity: IdentityKeys,
+}
+
+/// 解码定长的Base64密钥
+fn decode_key<const N: usize>(base64_str: &str, name: &str) -> Result<[u8; N]> {
+    let bytes = base64::decode(base64_str)
+        .map_err(|e| Error::Crypto(format!("解析{name}Base64编码失败: {e}")))?;
+
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        Error::Crypto(format!("{name}长度不正确，期望 {N} 字节，实际 {} 字节", bytes.len()))
+    })
+}
+
 /// 加密管理器
 pub struct CryptoManager {
     /// 加密密钥对
@@ -214,6 +504,145 @@ impl CryptoManager {
         }
     }
 
+    /// 从身份密钥创建加密管理器
+    pub fn from_identity(identity: &IdentityKeys) -> Result<Self> {
+        let manager = Self::with_keys(
+            PublicKey(decode_key(&identity.encryption_public, "加密公钥")?),
+            SecretKey(decode_key(&identity.encryption_secret, "加密私钥")?),
+            sign::PublicKey(decode_key(&identity.signing_public, "签名公钥")?),
+            sign::SecretKey(decode_key(&identity.signing_secret, "签名私钥")?),
+        );
+
+        // 检查公私钥是否匹配
+        if manager.encryption_keypair.secret_key.public_key() != manager.encryption_keypair.public_key {
+            return Err(Error::Crypto("加密公钥与私钥不匹配".to_string()));
+        }
+        if manager.signing_keypair.secret_key.public_key() != manager.signing_keypair.public_key {
+            return Err(Error::Crypto("签名公钥与私钥不匹配".to_string()));
+        }
+
+        Ok(manager)
+    }
+
+    /// 生成使用新身份密钥的加密管理器
+    ///
+    /// 保留已知设备的远程公钥，共享密钥改用新私钥计算。
+    pub fn rotated(&self) -> Result<Self> {
+        let rotated = Self::new();
+
+        let keys = match self.shared_keys.lock() {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("获取共享密钥锁失败: {e:?}");
+                return Err(Error::Crypto("获取共享密钥锁失败".to_string()));
+            }
+        };
+        for (device_id, (remote_public_key, _)) in keys.iter() {
+            rotated.generate_shared_key(device_id, remote_public_key)?;
+        }
+
+        Ok(rotated)
+    }
+
+    /// 导出使用口令加密的身份密钥
+    ///
+    /// 加密密钥由Argon2id从口令派生，格式为 `PAID || 版本 || 加密的身份密钥`，
+    /// 其中同时记录设备ID和公钥，导入时据此校验。
+    pub fn export_encrypted(&self, passphrase: &str, device_id: &str) -> Result<Vec<u8>> {
+        if passphrase.is_empty() {
+            return Err(Error::InvalidArgument("导出口令不能为空".to_string()));
+        }
+
+        let export = IdentityExport {
+            device_id: device_id.to_string(),
+            public_key: self.get_public_key_base64(),
+            verify_key: self.get_signing_key_base64(),
+            identity: self.export_identity(),
+        };
+        let sealed = seal_with_passphrase(passphrase, &serde_json::to_vec(&export)?)?;
+
+        let mut result = Vec::with_capacity(IDENTITY_EXPORT_MAGIC.len() + 1 + sealed.len());
+        result.extend_from_slice(IDENTITY_EXPORT_MAGIC);
+        result.push(IDENTITY_EXPORT_VERSION);
+        result.extend_from_slice(&sealed);
+
+        Ok(result)
+    }
+
+    /// 导入 [`CryptoManager::export_encrypted`] 导出的身份密钥，返回所属设备ID
+    ///
+    /// `recorded_keys` 返回本机记录的该设备公钥 (加密公钥, 签名公钥)，例如配对时固定的公钥。
+    /// 存在记录时密钥必须与之一致，防止以其他设备的密钥冒用设备ID；没有记录时无从校验。
+    pub fn import_encrypted<F>(data: &[u8], passphrase: &str, recorded_keys: F) -> Result<(String, Self)>
+    where
+        F: FnOnce(&str) -> Result<Option<(String, String)>>,
+    {
+        let header_len = IDENTITY_EXPORT_MAGIC.len() + 1;
+        if data.len() < header_len || &data[..IDENTITY_EXPORT_MAGIC.len()] != IDENTITY_EXPORT_MAGIC {
+            return Err(Error::InvalidArgument("不是有效的身份导出文件".to_string()));
+        }
+        if data[IDENTITY_EXPORT_MAGIC.len()] != IDENTITY_EXPORT_VERSION {
+            return Err(Error::InvalidArgument(format!(
+                "不支持的身份导出版本: {}",
+                data[IDENTITY_EXPORT_MAGIC.len()]
+            )));
+        }
+
+        let payload = open_with_passphrase(passphrase, &data[header_len..])?;
+        let export: IdentityExport = serde_json::from_slice(&payload)?;
+        if export.device_id.is_empty() {
+            return Err(Error::InvalidArgument("身份导出文件缺少设备ID".to_string()));
+        }
+        let manager = Self::from_identity(&export.identity)?;
+
+        if manager.get_public_key_base64() != export.public_key {
+            return Err(Error::Authentication(format!(
+                "导入的加密公钥与设备 {} 的记录不一致",
+                export.device_id
+            )));
+        }
+        if manager.get_signing_key_base64() != export.verify_key {
+            return Err(Error::Authentication(format!(
+                "导入的签名公钥与设备 {} 的记录不一致",
+                export.device_id
+            )));
+        }
+
+        if let Some((public_key, verify_key)) = recorded_keys(&export.device_id)? {
+            // 旧版本设备没有记录签名公钥
+            if manager.get_public_key_base64() != public_key
+                || (!verify_key.is_empty() && manager.get_signing_key_base64() != verify_key)
+            {
+                return Err(Error::Authentication(format!(
+                    "导入的密钥与本机记录的设备 {} 的公钥不一致",
+                    export.device_id
+                )));
+            }
+        }
+
+        Ok((export.device_id, manager))
+    }
+
+    /// 导出身份密钥
+    pub fn export_identity(&self) -> IdentityKeys {
+        IdentityKeys {
+            encryption_public: self.encryption_keypair.public_key_base64(),
+            encryption_secret: base64::encode(self.encryption_keypair.secret_key.as_ref()),
+            signing_public: self.signing_keypair.public_key_base64(),
+            signing_secret: base64::encode(self.signing_keypair.secret_key.as_ref()),
+        }
+    }
+
+    /// 获取加密公钥
+    pub fn public_key(&self) -> PublicKey {
+        self.encryption_keypair.public_key
+    }
+
+    /// 使用本设备的加密私钥与对方公钥进行密钥协商
+    pub fn key_agreement(&self, remote_public_key: &PublicKey) -> Result<[u8; 32]> {
+        x25519(&self.encryption_keypair.secret_key, remote_public_key)
+    }
+
     /// 获取加密公钥的Base64编码
     pub fn get_public_key_base64(&self) -> String {
         self.encryption_keypair.public_key_base64()
@@ -255,6 +684,20 @@ impl CryptoManager {
         Ok(())
     }
 
+    /// 删除与设备的共享密钥
+    pub fn remove_shared_key(&self, device_id: &str) -> Result<()> {
+        let mut keys = match self.shared_keys.lock() {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("获取共享密钥锁失败: {e:?}");
+                return Err(Error::Crypto("获取共享密钥锁失败".to_string()));
+            }
+        };
+
+        keys.remove(device_id);
+        Ok(())
+    }
+
     /// 计算与远程设备的共享密钥（向后兼容）
     pub fn compute_shared_key(&self, device_id: &str, remote_public_key: &PublicKey) -> Result<()> {
         self.generate_shared_key(device_id, remote_public_key)
@@ -417,4 +860,117 @@ mod tests {
         assert!(!enc_key.is_empty());
         assert!(!sign_key.is_empty());
     }
+
+    #[test]
+    fn test_device_signature() {
+        init();
+        let mut device = DeviceInfo::new("本机", crate::types::DeviceType::Desktop, &get_public_key().unwrap());
+        device.verify_key = get_verify_key().unwrap();
+
+        let signature = sign("payload").unwrap();
+        assert!(verify_device_signature(&device, "payload", &signature).is_ok());
+        assert!(verify_device_signature(&device, "tampered", &signature).is_err());
+
+        // 使用其他设备的签名公钥验证失败
+        device.verify_key = CryptoManager::new().get_signing_key_base64();
+        assert!(matches!(
+            verify_device_signature(&device, "payload", &signature),
+            Err(Error::Authentication(_))
+        ));
+
+        device.verify_key.clear();
+        assert!(verify_device_signature(&device, "payload", &signature).is_err());
+    }
+
+    #[test]
+    fn test_identity_roundtrip() {
+        init();
+        let manager = CryptoManager::new();
+        let identity = manager.export_identity();
+
+        let restored = CryptoManager::from_identity(&identity).unwrap();
+        assert_eq!(restored.get_public_keys(), manager.get_public_keys());
+        assert!(!format!("{identity:?}").contains(&identity.signing_secret));
+
+        // 公私钥不匹配时拒绝加载
+        let mut mismatched = identity.clone();
+        mismatched.encryption_public = CryptoManager::new().get_public_key_base64();
+        assert!(CryptoManager::from_identity(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_identity_export() {
+        init();
+        let manager = CryptoManager::new();
+
+        let exported = manager.export_encrypted("迁移口令", "old-device").unwrap();
+        assert_eq!(&exported[..4], IDENTITY_EXPORT_MAGIC);
+        assert!(manager.export_encrypted("", "old-device").is_err());
+
+        let (device_id, imported) = CryptoManager::import_encrypted(&exported, "迁移口令", |_| Ok(None)).unwrap();
+        assert_eq!(device_id, "old-device");
+        assert_eq!(imported.export_identity(), manager.export_identity());
+        assert!(CryptoManager::import_encrypted(&exported, "错误口令", |_| Ok(None)).is_err());
+
+        // 与本机记录的设备公钥一致时才能导入
+        let recorded = |device_id: &str| {
+            assert_eq!(device_id, "old-device");
+            Ok(Some(manager.get_public_keys()))
+        };
+        assert!(CryptoManager::import_encrypted(&exported, "迁移口令", recorded).is_ok());
+        let other = || Ok(Some(CryptoManager::new().get_public_keys()));
+        assert!(matches!(
+            CryptoManager::import_encrypted(&exported, "迁移口令", |_| other()),
+            Err(Error::Authentication(_))
+        ));
+
+        // 记录的公钥与密钥不一致时拒绝导入
+        let tampered = IdentityExport {
+            device_id: "old-device".to_string(),
+            public_key: manager.get_public_key_base64(),
+            verify_key: CryptoManager::new().get_signing_key_base64(),
+            identity: manager.export_identity(),
+        };
+        let mut data = IDENTITY_EXPORT_MAGIC.to_vec();
+        data.push(IDENTITY_EXPORT_VERSION);
+        data.extend(seal_with_passphrase("迁移口令", &serde_json::to_vec(&tampered).unwrap()).unwrap());
+        assert!(matches!(
+            CryptoManager::import_encrypted(&data, "迁移口令", |_| Ok(None)),
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        init();
+        let manager = CryptoManager::new();
+        let peer = KeyPair::generate();
+        manager.generate_shared_key("peer", &peer.public_key).unwrap();
+
+        let rotated = manager.rotated().unwrap();
+        assert_ne!(rotated.get_public_keys().0, manager.get_public_keys().0);
+        assert_ne!(rotated.get_public_keys().1, manager.get_public_keys().1);
+
+        // 对端使用新公钥即可解密轮换后加密的数据
+        let encrypted = rotated.encrypt("peer", b"hello").unwrap();
+        let peer_manager = CryptoManager::with_keys(
+            peer.public_key,
+            peer.secret_key.clone(),
+            SignKeyPair::generate().public_key,
+            sign::gen_keypair().1,
+        );
+        peer_manager
+            .generate_shared_key("me", &rotated.encryption_keypair.public_key)
+            .unwrap();
+        assert_eq!(peer_manager.decrypt("me", &encrypted).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_passphrase_seal() {
+        init();
+        let sealed = seal_with_passphrase("correct horse", b"profile").unwrap();
+        assert_eq!(open_with_passphrase("correct horse", &sealed).unwrap(), b"profile");
+        assert!(open_with_passphrase("wrong", &sealed).is_err());
+        assert!(open_with_passphrase("correct horse", &sealed[..10]).is_err());
+    }
 }
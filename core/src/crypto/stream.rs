@@ -0,0 +1,195 @@
+//! 流式认证加密
+//!
+//! 基于 libsodium secretstream (XChaCha20-Poly1305)，按块加解密大文件，
+//! 内存占用只与块大小有关。每块的认证状态依赖之前的所有块，
+//! 因此块被重排、篡改或丢弃都会导致解密失败；流必须以结束块收尾，
+//! 接收方据此发现被截断的传输。
+
+use crate::error::{Error, Result};
+use sodiumoxide::crypto::secretstream::{self, Header, Key, Pull, Push, Stream, Tag};
+use std::fmt;
+
+/// 每块密文比明文多出的字节数
+pub const STREAM_ABYTES: usize = secretstream::ABYTES;
+
+/// 流加密密钥
+#[derive(Clone)]
+pub struct StreamKey(Key);
+
+impl fmt::Debug for StreamKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamKey(..)")
+    }
+}
+
+impl StreamKey {
+    /// 生成随机密钥，每次传输使用新的密钥
+    pub fn generate() -> Self {
+        Self(secretstream::gen_key())
+    }
+
+    /// 导出为Base64编码
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.0.as_ref())
+    }
+
+    /// 从Base64编码导入
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = base64::decode(encoded)
+            .map_err(|e| Error::Crypto(format!("解析流加密密钥失败: {e}")))?;
+        Key::from_slice(&bytes)
+            .map(Self)
+            .ok_or_else(|| Error::Crypto("流加密密钥长度无效".to_string()))
+    }
+}
+
+/// 流加密器
+pub struct StreamEncryptor {
+    /// 加密状态
+    stream: Stream<Push>,
+    /// 绑定到每块的附加数据（如传输ID）
+    context: Vec<u8>,
+}
+
+impl StreamEncryptor {
+    /// 创建加密器，返回加密器和需要发送给接收方的流头部
+    ///
+    /// `context` 作为附加数据参与每块的认证，接收方必须使用相同的值。
+    pub fn new(key: &StreamKey, context: &[u8]) -> Result<(Self, Vec<u8>)> {
+        let (stream, header) = Stream::init_push(&key.0)
+            .map_err(|_| Error::Crypto("初始化流加密失败".to_string()))?;
+
+        Ok((
+            Self {
+                stream,
+                context: context.to_vec(),
+            },
+            header.as_ref().to_vec(),
+        ))
+    }
+
+    /// 加密一个数据块
+    pub fn encrypt_chunk(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.stream
+            .push(chunk, Some(&self.context), Tag::Message)
+            .map_err(|_| Error::Crypto("流加密失败".to_string()))
+    }
+
+    /// 生成结束块，此后不能再加密数据
+    pub fn finish(self) -> Result<Vec<u8>> {
+        self.stream
+            .finalize(Some(&self.context))
+            .map_err(|_| Error::Crypto("结束流加密失败".to_string()))
+    }
+}
+
+/// 流解密器
+pub struct StreamDecryptor {
+    /// 解密状态
+    stream: Stream<Pull>,
+    /// 绑定到每块的附加数据
+    context: Vec<u8>,
+}
+
+impl StreamDecryptor {
+    /// 使用发送方的流头部创建解密器
+    pub fn new(key: &StreamKey, header: &[u8], context: &[u8]) -> Result<Self> {
+        let header = Header::from_slice(header)
+            .ok_or_else(|| Error::Crypto("流头部长度无效".to_string()))?;
+        let stream = Stream::init_pull(&header, &key.0)
+            .map_err(|_| Error::Crypto("初始化流解密失败".to_string()))?;
+
+        Ok(Self {
+            stream,
+            context: context.to_vec(),
+        })
+    }
+
+    /// 解密一个数据块，收到结束块时返回None
+    ///
+    /// 块被篡改、重排、重复或在结束块之后继续接收时返回错误。
+    pub fn decrypt_chunk(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (plaintext, tag) = self
+            .stream
+            .pull(chunk, Some(&self.context))
+            .map_err(|_| Error::Crypto("数据块认证失败".to_string()))?;
+
+        match tag {
+            Tag::Message => Ok(Some(plaintext)),
+            Tag::Final => Ok(None),
+            _ => Err(Error::Crypto("不支持的数据块类型".to_string())),
+        }
+    }
+
+    /// 是否已收到结束块
+    ///
+    /// 数据读取完毕时仍未结束说明传输被截断。
+    pub fn is_finished(&self) -> bool {
+        self.stream.is_finalized()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt_all(key: &StreamKey, chunks: &[&[u8]]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let (mut encryptor, header) = StreamEncryptor::new(key, b"transfer").unwrap();
+        let mut encrypted: Vec<Vec<u8>> =
+            chunks.iter().map(|c| encryptor.encrypt_chunk(c).unwrap()).collect();
+        encrypted.push(encryptor.finish().unwrap());
+        (header, encrypted)
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        crate::crypto::init();
+        let key = StreamKey::from_base64(&StreamKey::generate().to_base64()).unwrap();
+        let (header, encrypted) = encrypt_all(&key, &[b"first", b"", b"third"]);
+        assert_eq!(encrypted[0].len(), 5 + STREAM_ABYTES);
+
+        let mut decryptor = StreamDecryptor::new(&key, &header, b"transfer").unwrap();
+        let mut output = Vec::new();
+        for chunk in &encrypted {
+            match decryptor.decrypt_chunk(chunk).unwrap() {
+                Some(data) => output.extend_from_slice(&data),
+                None => break,
+            }
+        }
+        assert!(decryptor.is_finished());
+        assert_eq!(output, b"firstthird");
+
+        // 结束之后的数据块被拒绝
+        assert!(decryptor.decrypt_chunk(&encrypted[0]).is_err());
+    }
+
+    #[test]
+    fn test_stream_detects_tampering() {
+        crate::crypto::init();
+        let key = StreamKey::generate();
+        let (header, encrypted) = encrypt_all(&key, &[b"one", b"two", b"three"]);
+
+        // 截断：缺少结束块
+        let mut decryptor = StreamDecryptor::new(&key, &header, b"transfer").unwrap();
+        for chunk in &encrypted[..3] {
+            assert!(decryptor.decrypt_chunk(chunk).unwrap().is_some());
+        }
+        assert!(!decryptor.is_finished());
+
+        // 重排
+        let mut decryptor = StreamDecryptor::new(&key, &header, b"transfer").unwrap();
+        assert!(decryptor.decrypt_chunk(&encrypted[1]).is_err());
+
+        // 篡改
+        let mut decryptor = StreamDecryptor::new(&key, &header, b"transfer").unwrap();
+        let mut tampered = encrypted[0].clone();
+        tampered[STREAM_ABYTES] ^= 1;
+        assert!(decryptor.decrypt_chunk(&tampered).is_err());
+
+        // 其他传输的上下文或密钥
+        let mut decryptor = StreamDecryptor::new(&key, &header, b"other").unwrap();
+        assert!(decryptor.decrypt_chunk(&encrypted[0]).is_err());
+        let mut decryptor = StreamDecryptor::new(&StreamKey::generate(), &header, b"transfer").unwrap();
+        assert!(decryptor.decrypt_chunk(&encrypted[0]).is_err());
+    }
+}
@@ -81,6 +81,10 @@ pub enum Error {
     #[error("超时错误: {0}")]
     Timeout(String),
 
+    /// 其他错误
+    #[error("其他错误: {0}")]
+    Other(String),
+
     /// 未知错误
     #[error("未知错误: {0}")]
     Unknown(String),
@@ -19,6 +19,8 @@ use log::error;
 use crate::error::{Error, Result};
 use crate::types::Config;
 use crate::clipboard;
+use crate::network::discovery_events::DiscoveryEvent;
+use crate::network::key_pinning::KeyChangeEvent;
 use crate::PasteAll;
 
 // 定义错误码
@@ -39,6 +41,9 @@ type ClipboardContentCallback = extern "C" fn(content_type: i32, content_json: *
 type PairingStatusCallback = extern "C" fn(device_json: *const c_char, status: i32);
 type TransferProgressCallback = extern "C" fn(device_id: *const c_char, file_path: *const c_char, progress: f32);
 type ErrorCallback = extern "C" fn(error_code: i32, error_msg: *const c_char);
+type HistoryEventCallback = extern "C" fn(event_json: *const c_char);
+type DiscoveryEventCallback = extern "C" fn(event_json: *const c_char);
+type SecurityEventCallback = extern "C" fn(event_json: *const c_char, notification_json: *const c_char);
 
 // 全局PasteAll实例
 static INSTANCE: Lazy<Mutex<Option<PasteAll>>> = Lazy::new(|| Mutex::new(None));
@@ -49,6 +54,9 @@ static CLIPBOARD_CONTENT_CALLBACK: Lazy<Mutex<Option<ClipboardContentCallback>>>
 static PAIRING_STATUS_CALLBACK: Lazy<Mutex<Option<PairingStatusCallback>>> = Lazy::new(|| Mutex::new(None));
 static TRANSFER_PROGRESS_CALLBACK: Lazy<Mutex<Option<TransferProgressCallback>>> = Lazy::new(|| Mutex::new(None));
 static ERROR_CALLBACK: Lazy<Mutex<Option<ErrorCallback>>> = Lazy::new(|| Mutex::new(None));
+static HISTORY_EVENT_CALLBACK: Lazy<Mutex<Option<HistoryEventCallback>>> = Lazy::new(|| Mutex::new(None));
+static DISCOVERY_EVENT_CALLBACK: Lazy<Mutex<Option<DiscoveryEventCallback>>> = Lazy::new(|| Mutex::new(None));
+static SECURITY_EVENT_CALLBACK: Lazy<Mutex<Option<SecurityEventCallback>>> = Lazy::new(|| Mutex::new(None));
 
 // 错误码和错误信息映射
 fn map_error_code(error: &Error) -> i32 {
@@ -352,6 +360,326 @@ pub extern "C" fn pasteall_register_error_callback(callback: ErrorCallback) -> i
     result_to_status_code(result)
 }
 
+#[no_mangle]
+/// 注册历史记录变更事件回调函数
+///
+/// 事件以JSON形式传递，格式为 `{"type": "Added|Updated|Removed|Cleared|Evicted", "data": ...}`，
+/// UI可据此增量更新列表，无需重新拉取全部历史记录。
+///
+/// # 参数
+///
+/// * `callback` - 历史记录变更事件回调函数
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+pub extern "C" fn pasteall_register_history_event_callback(callback: HistoryEventCallback) -> i32 {
+    let result = match HISTORY_EVENT_CALLBACK.lock() {
+        Ok(mut cb) => {
+            *cb = Some(callback);
+            Ok(())
+        },
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            Err(Error::Initialization("获取回调函数锁失败".to_string()))
+        }
+    };
+    
+    result_to_status_code(result)
+}
+
+#[no_mangle]
+/// 注册设备发现事件回调函数
+///
+/// 事件以JSON形式传递，格式为 `{"type": "Found|Updated|Lost", "data": ...}`。
+/// 同一设备的重复广播不会触发回调，UI可据此区分新设备、信息变化和设备离线。
+///
+/// # 参数
+///
+/// * `callback` - 设备发现事件回调函数
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+pub extern "C" fn pasteall_register_discovery_event_callback(callback: DiscoveryEventCallback) -> i32 {
+    let result = match DISCOVERY_EVENT_CALLBACK.lock() {
+        Ok(mut cb) => {
+            *cb = Some(callback);
+            Ok(())
+        },
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            Err(Error::Initialization("获取回调函数锁失败".to_string()))
+        }
+    };
+    
+    result_to_status_code(result)
+}
+
+/// 将设备发现事件转发给已注册的FFI回调
+pub(crate) fn forward_discovery_event(event: &DiscoveryEvent) {
+    let callback = match DISCOVERY_EVENT_CALLBACK.lock() {
+        Ok(cb) => *cb,
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            return;
+        }
+    };
+    
+    if let Some(callback) = callback {
+        match serde_json::to_string(event) {
+            Ok(json) => {
+                let event_json = CString::new(json).unwrap_or_default();
+                callback(event_json.as_ptr());
+            },
+            Err(e) => error!("序列化设备发现事件失败: {}", e),
+        }
+    }
+}
+
+#[no_mangle]
+/// 注册安全事件回调函数
+///
+/// 已知设备以不同的公钥出现时触发，同时传递事件详情和应展示给用户的通知。
+/// 在用户重新验证该设备之前，与其同步会被阻止。
+///
+/// # 参数
+///
+/// * `callback` - 安全事件回调函数
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+pub extern "C" fn pasteall_register_security_event_callback(callback: SecurityEventCallback) -> i32 {
+    let result = match SECURITY_EVENT_CALLBACK.lock() {
+        Ok(mut cb) => {
+            *cb = Some(callback);
+            Ok(())
+        },
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            Err(Error::Initialization("获取回调函数锁失败".to_string()))
+        }
+    };
+    
+    result_to_status_code(result)
+}
+
+/// 将密钥变更事件及其通知转发给已注册的FFI回调
+pub(crate) fn forward_security_event(event: &KeyChangeEvent) {
+    let callback = match SECURITY_EVENT_CALLBACK.lock() {
+        Ok(cb) => *cb,
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            return;
+        }
+    };
+    
+    if let Some(callback) = callback {
+        match (serde_json::to_string(event), serde_json::to_string(&event.notification())) {
+            (Ok(event_json), Ok(notification_json)) => {
+                let event_json = CString::new(event_json).unwrap_or_default();
+                let notification_json = CString::new(notification_json).unwrap_or_default();
+                callback(event_json.as_ptr(), notification_json.as_ptr());
+            },
+            (Err(e), _) | (_, Err(e)) => error!("序列化安全事件失败: {}", e),
+        }
+    }
+}
+
+/// 将历史记录变更事件转发给已注册的FFI回调
+pub(crate) fn forward_history_event(event: &clipboard::HistoryEvent) {
+    let callback = match HISTORY_EVENT_CALLBACK.lock() {
+        Ok(cb) => *cb,
+        Err(e) => {
+            error!("获取回调函数锁失败: {}", e);
+            return;
+        }
+    };
+    
+    if let Some(callback) = callback {
+        match serde_json::to_string(event) {
+            Ok(json) => {
+                let event_json = CString::new(json).unwrap_or_default();
+                callback(event_json.as_ptr());
+            },
+            Err(e) => error!("序列化历史记录事件失败: {}", e),
+        }
+    }
+}
+
+#[no_mangle]
+/// 导出加密的完整配置备份
+///
+/// # 参数
+///
+/// * `passphrase` - 备份口令
+///
+/// # 返回
+///
+/// * `ByteBuffer` - 加密后的备份数据，失败时为空
+///
+/// # Safety
+///
+/// `passphrase` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_export_profile(passphrase: *const c_char) -> ByteBuffer {
+    let result = unsafe {
+        cstr_to_string(passphrase).and_then(|passphrase| {
+            let instance = INSTANCE.lock().map_err(|e| {
+                error!("获取实例锁失败: {}", e);
+                Error::Initialization("获取实例锁失败".to_string())
+            })?;
+            
+            match &*instance {
+                Some(pasteall) => pasteall.export_profile(&passphrase),
+                None => Err(Error::Initialization("PasteAll未初始化".to_string())),
+            }
+        })
+    };
+    
+    match result {
+        Ok(data) => ByteBuffer::from_vec(data),
+        Err(e) => {
+            result_to_status_code::<()>(Err(e));
+            ByteBuffer::new_with_size(0)
+        }
+    }
+}
+
+#[no_mangle]
+/// 从备份恢复配置并初始化PasteAll核心库
+///
+/// 用于替代 `pasteall_init`，恢复后调用 `pasteall_start` 启动服务。
+///
+/// # 参数
+///
+/// * `data` - 备份数据
+/// * `len` - 备份数据长度
+/// * `passphrase` - 备份口令
+/// * `storage_path` - 本机数据库路径
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+///
+/// # Safety
+///
+/// `data` 必须指向至少 `len` 字节的可读内存；`passphrase` 和 `storage_path` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_restore_profile(
+    data: *const u8,
+    len: usize,
+    passphrase: *const c_char,
+    storage_path: *const c_char,
+) -> i32 {
+    if data.is_null() {
+        return ERROR_INVALID_PARAMETER;
+    }
+    
+    let result = unsafe {
+        let bundle = std::slice::from_raw_parts(data, len);
+        cstr_to_string(passphrase).and_then(|passphrase| {
+            let storage_path = cstr_to_string(storage_path)?;
+            let pasteall = PasteAll::restore_profile(bundle, &passphrase, &storage_path)?;
+            
+            match INSTANCE.lock() {
+                Ok(mut instance) => {
+                    *instance = Some(pasteall);
+                    Ok(())
+                },
+                Err(e) => {
+                    error!("获取实例锁失败: {}", e);
+                    Err(Error::Initialization("获取实例锁失败".to_string()))
+                }
+            }
+        })
+    };
+    
+    result_to_status_code(result)
+}
+
+#[no_mangle]
+/// 导出使用口令加密的身份密钥
+///
+/// # 参数
+///
+/// * `passphrase` - 导出口令
+///
+/// # 返回
+///
+/// * `ByteBuffer` - 加密后的身份密钥，失败时为空
+///
+/// # Safety
+///
+/// `passphrase` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_export_identity(passphrase: *const c_char) -> ByteBuffer {
+    let result = unsafe {
+        cstr_to_string(passphrase).and_then(|passphrase| {
+            let instance = INSTANCE.lock().map_err(|e| {
+                error!("获取实例锁失败: {}", e);
+                Error::Initialization("获取实例锁失败".to_string())
+            })?;
+            
+            match &*instance {
+                Some(pasteall) => pasteall.export_identity(&passphrase),
+                None => Err(Error::Initialization("PasteAll未初始化".to_string())),
+            }
+        })
+    };
+    
+    match result {
+        Ok(data) => ByteBuffer::from_vec(data),
+        Err(e) => {
+            result_to_status_code::<()>(Err(e));
+            ByteBuffer::new_with_size(0)
+        }
+    }
+}
+
+#[no_mangle]
+/// 导入从旧设备迁移的身份密钥
+///
+/// 需在 `pasteall_init` 之后、`pasteall_start` 之前调用。导入后本机沿用旧设备的ID。
+///
+/// # 参数
+///
+/// * `data` - 导出的身份密钥
+/// * `len` - 数据长度
+/// * `passphrase` - 导出口令
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+///
+/// # Safety
+///
+/// `data` 必须指向至少 `len` 字节的可读内存；`passphrase` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_import_identity(
+    data: *const u8,
+    len: usize,
+    passphrase: *const c_char,
+) -> i32 {
+    if data.is_null() {
+        return ERROR_INVALID_PARAMETER;
+    }
+    
+    let result = unsafe {
+        let exported = std::slice::from_raw_parts(data, len);
+        cstr_to_string(passphrase).and_then(|passphrase| {
+            let mut instance = INSTANCE.lock().map_err(|e| {
+                error!("获取实例锁失败: {}", e);
+                Error::Initialization("获取实例锁失败".to_string())
+            })?;
+            
+            match &mut *instance {
+                Some(pasteall) => pasteall.import_identity(exported, &passphrase).map(|_| ()),
+                None => Err(Error::Initialization("PasteAll未初始化".to_string())),
+            }
+        })
+    };
+    
+    result_to_status_code(result)
+}
+
 #[no_mangle]
 /// 获取设备列表
 ///
@@ -363,6 +691,77 @@ pub extern "C" fn pasteall_get_devices() -> ByteBuffer {
     ByteBuffer::new_with_size(0)
 }
 
+#[no_mangle]
+/// 获取与已配对设备的密钥指纹
+///
+/// # 参数
+///
+/// * `device_id` - 设备ID
+///
+/// # 返回
+///
+/// * `ByteBuffer` - 包含安全码和表情序列的JSON字符串，失败时为空
+///
+/// # Safety
+///
+/// `device_id` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_get_device_fingerprint(device_id: *const c_char) -> ByteBuffer {
+    let result = unsafe {
+        cstr_to_string(device_id).and_then(|device_id| {
+            let instance = INSTANCE.lock().map_err(|e| {
+                error!("获取实例锁失败: {}", e);
+                Error::Initialization("获取实例锁失败".to_string())
+            })?;
+            
+            match &*instance {
+                Some(pasteall) => pasteall.device_fingerprint(&device_id),
+                None => Err(Error::Initialization("PasteAll未初始化".to_string())),
+            }
+        })
+    };
+    
+    match result {
+        Ok(fingerprint) => json_to_buffer(&fingerprint),
+        Err(e) => {
+            result_to_status_code::<()>(Err(e));
+            ByteBuffer::new_with_size(0)
+        }
+    }
+}
+
+#[no_mangle]
+/// 标记设备的指纹验证状态
+///
+/// # 参数
+///
+/// * `device_id` - 设备ID
+/// * `verified` - 用户是否已核对指纹
+///
+/// # 返回
+///
+/// * `i32` - 错误码，0表示成功
+///
+/// # Safety
+///
+/// `device_id` 必须是有效的、以NUL结尾的UTF-8字符串指针。
+pub unsafe extern "C" fn pasteall_set_device_verified(device_id: *const c_char, verified: bool) -> i32 {
+    let result = unsafe {
+        cstr_to_string(device_id).and_then(|device_id| {
+            let instance = INSTANCE.lock().map_err(|e| {
+                error!("获取实例锁失败: {}", e);
+                Error::Initialization("获取实例锁失败".to_string())
+            })?;
+            
+            match &*instance {
+                Some(pasteall) => pasteall.set_device_verified(&device_id, verified),
+                None => Err(Error::Initialization("PasteAll未初始化".to_string())),
+            }
+        })
+    };
+    
+    result_to_status_code(result)
+}
+
 #[no_mangle]
 /// 开始配对流程
 ///
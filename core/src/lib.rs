@@ -31,66 +31,250 @@ pub mod types;
 
 use log::{info, warn};
 
+/// 存储中保存本机设备ID的设置项，导入旧设备身份后沿用旧设备的ID
+const DEVICE_ID_SETTING: &str = "device_id";
+
 /// PasteAll核心库的入口点
 pub struct PasteAll {
     /// PasteAll的配置信息
     config: types::Config,
+    /// 身份密钥文件的保护口令
+    identity_passphrase: Option<String>,
+    /// 设备发现管理器，启动后存在
+    discovery: tokio::sync::Mutex<Option<network::discovery_manager::DiscoveryManager>>,
+    /// 运行中共享的服务，启动后存在
+    services: std::sync::Mutex<Option<Services>>,
+}
+
+/// 启动后由各服务共享的状态
+///
+/// 吊销、公钥确认等操作必须作用在运行中的实例上，重新从存储加载的副本不会影响正在运行的服务。
+#[derive(Clone)]
+struct Services {
+    /// 存储
+    storage: std::sync::Arc<storage::Storage>,
+    /// 设备吊销列表
+    revocations: network::revocation::RevocationList,
+    /// 设备公钥固定表
+    key_pins: network::key_pinning::KeyPins,
+    /// 配对管理器
+    pairing: std::sync::Arc<network::pairing::PairingManager>,
+    /// 同步组
+    sync_groups: network::sync_group::SyncGroupManager,
+    /// 内容传输服务
+    transport: std::sync::Arc<network::transport::TransportService>,
+    /// 剪贴板历史记录
+    history: std::sync::Arc<clipboard::ClipboardHistory>,
 }
 
 impl PasteAll {
     /// 创建PasteAll实例
-    pub fn new(config: types::Config) -> Self {
+    pub fn new(mut config: types::Config) -> Self {
         info!("PasteAll核心库初始化");
-        Self { config }
+        if let Some(device_id) = Self::saved_device_id(&config.storage_path) {
+            config.device_id = device_id;
+        }
+        Self {
+            config,
+            identity_passphrase: None,
+            discovery: tokio::sync::Mutex::new(None),
+            services: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// 设置身份密钥文件的保护口令
+    ///
+    /// 设置后身份密钥使用口令加密保存，启动时需提供相同口令才能加载。
+    pub fn with_identity_passphrase(mut self, passphrase: &str) -> Self {
+        self.identity_passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// 存储中保存的本机设备ID，数据库尚不存在时返回None
+    fn saved_device_id(storage_path: &str) -> Option<String> {
+        if storage_path == ":memory:" || !std::path::Path::new(storage_path).exists() {
+            return None;
+        }
+
+        let result = storage::init(storage_path)
+            .and_then(storage::Storage::with_pool)
+            .and_then(|storage| storage.get_setting(DEVICE_ID_SETTING));
+        match result {
+            Ok(device_id) => device_id,
+            Err(e) => {
+                warn!("读取本机设备ID失败: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// 身份密钥文件路径
+    fn identity_path(&self) -> Option<std::path::PathBuf> {
+        crypto::identity_path(&self.config.storage_path)
+    }
+
+    /// 运行中共享的服务，未启动时为None
+    fn services(&self) -> Option<Services> {
+        self.services.lock().ok()?.clone()
+    }
+
+    /// 运行中的内容传输服务，未启动时创建只用于发送的实例
+    fn transport(&self, services: Option<&Services>) -> Result<std::sync::Arc<network::transport::TransportService>, error::Error> {
+        match services {
+            Some(services) => Ok(services.transport.clone()),
+            None => Ok(std::sync::Arc::new(network::transport::TransportService::new(
+                self.local_device()?,
+                network::transport::sync_port(self.config.listen_port),
+            ))),
+        }
+    }
+
+    /// 运行中的同步组，未启动时从存储加载
+    fn sync_groups(&self, services: Option<&Services>) -> Result<network::sync_group::SyncGroupManager, error::Error> {
+        match services {
+            Some(services) => Ok(services.sync_groups.clone()),
+            None => {
+                let storage = std::sync::Arc::new(storage::Storage::with_pool(storage::init(&self.config.storage_path)?)?);
+                network::sync_group::SyncGroupManager::load(&self.config.device_id, storage)
+            }
+        }
+    }
+
+    /// 运行中的存储和公钥固定表，未启动时从存储加载
+    ///
+    /// 服务运行中时必须修改共享的固定表，否则解除阻止要到重启后才生效。
+    fn key_pins(&self) -> Result<(std::sync::Arc<storage::Storage>, network::key_pinning::KeyPins), error::Error> {
+        match self.services() {
+            Some(services) => Ok((services.storage, services.key_pins)),
+            None => {
+                let storage = std::sync::Arc::new(storage::Storage::with_pool(storage::init(&self.config.storage_path)?)?);
+                let key_pins = network::key_pinning::KeyPins::load(storage.clone())?;
+                Ok((storage, key_pins))
+            }
+        }
+    }
+
+    /// 运行中的存储，未启动时在阻塞线程池上打开
+    async fn open_storage(&self, services: Option<&Services>) -> Result<std::sync::Arc<storage::Storage>, error::Error> {
+        match services {
+            Some(services) => Ok(services.storage.clone()),
+            None => {
+                let storage_path = self.config.storage_path.clone();
+                Self::blocking(move || Ok(std::sync::Arc::new(storage::Storage::with_pool(storage::init(&storage_path)?)?))).await
+            }
+        }
+    }
+
+    /// 在阻塞线程池上执行存储操作，避免阻塞异步运行时
+    async fn blocking<T, F>(f: F) -> Result<T, error::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, error::Error> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|e| error::Error::Storage(format!("存储任务执行失败: {e}")))?
+    }
+
+    /// 本地设备信息，需在加密模块初始化之后调用
+    fn local_device(&self) -> Result<types::DeviceInfo, error::Error> {
+        let mut local_device = types::DeviceInfo::new(
+            &self.config.device_name,
+            self.config.device_type,
+            &crypto::get_public_key()?
+        );
+        local_device.id = self.config.device_id.clone();
+        local_device.verify_key = crypto::get_verify_key()?;
+
+        Ok(local_device)
     }
 
     /// 启动PasteAll服务
     pub async fn start(&self) -> Result<(), error::Error> {
         info!("启动PasteAll核心服务");
 
-        // 初始化加密模块
-        crypto::init();
+        // 初始化加密模块，加载持久化的身份密钥
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        
+        // 初始化存储，写操作由后台任务批量提交
+        let storage_path = self.config.storage_path.clone();
+        let storage_pool = Self::blocking(move || storage::init(&storage_path)).await?;
+        let async_storage = storage::AsyncStorage::with_pool(storage_pool).await?;
+        let shared_storage = async_storage.storage();
+        
+        // 初始化剪贴板历史记录，并将变更事件转发给FFI层
+        let retention_secs = u64::from(self.config.options.trash_retention_days) * 24 * 3600;
+        let history_storage = async_storage.clone();
+        let history = std::sync::Arc::new(Self::blocking(move || {
+            Ok(clipboard::ClipboardHistory::new(clipboard::DEFAULT_MAX_HISTORY, Some(history_storage))
+                .with_purge_delay(std::time::Duration::from_secs(retention_secs)))
+        }).await?);
+        history.subscribe(Box::new(ffi::common::forward_history_event))?;
+
+        // 定期清理过期的回收站条目，服务停止、历史记录释放后任务退出
+        let purge_history = std::sync::Arc::downgrade(&history);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(clipboard::PURGE_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(history) = purge_history.upgrade() else {
+                    break;
+                };
+                if let Err(e) = history.purge_expired() {
+                    warn!("清理回收站失败: {e:?}");
+                }
+            }
+        });
         
         // 初始化剪贴板监听
-        let _clipboard_watcher = clipboard::ClipboardWatcher::new()?;
+        let _clipboard_watcher = clipboard::ClipboardWatcher::with_shared_history(history.clone())?;
         
         // 创建本地设备信息
-        let local_device = types::DeviceInfo::new(
-            &self.config.device_name,
-            self.config.device_type,
-            &crypto::get_public_key()?
-        );
-        
-        // 初始化设备发现服务
-        let mut discovery = network::discovery::DeviceDiscovery::new(&self.config)?;
+        let local_device = self.local_device()?;
+
+        // 加载设备吊销列表、公钥固定表和同步组，吊销列表由配对和传输服务共享，
+        // 公钥变更的设备在重新验证前不能同步
+        let load_storage = shared_storage.clone();
+        let local_device_id = local_device.id.clone();
+        let (revocations, key_pins, sync_groups) = Self::blocking(move || {
+            Ok((
+                network::revocation::RevocationList::load(load_storage.clone())?,
+                network::key_pinning::KeyPins::load(load_storage.clone())?,
+                network::sync_group::SyncGroupManager::load(&local_device_id, load_storage)?,
+            ))
+        }).await?;
         
-        // 尝试初始化BLE设备发现
-        let ble_discovery_result = network::ble_discovery::BleDiscovery::new(&self.config).await;
+        // 初始化设备发现管理器，合并UDP广播、mDNS和BLE的发现结果
+        let mut discovery = network::discovery_manager::DiscoveryManager::new(&self.config);
+        discovery.set_key_pins(key_pins.clone());
+        discovery.set_storage(shared_storage.clone());
+        discovery.set_key_change_callback(Box::new(|event| {
+            warn!("设备 {} ({}) 的公钥已变更，已暂停同步", event.device_name, event.device_id);
+            ffi::common::forward_security_event(event);
+        }));
+        discovery.start().await?;
         
-        if let Ok(mut ble_discovery) = ble_discovery_result {
-            info!("BLE设备发现服务已初始化");
-            
-            // 启动BLE设备发现（示例回调）
-            let ble_callback = Box::new(|device: types::DeviceInfo| {
-                info!("发现BLE设备: {} ({})", device.name, device.id);
-            });
-            
-            if let Err(e) = ble_discovery.start(ble_callback).await {
-                info!("BLE设备发现启动失败: {e:?}，将使用UDP广播");
+        // 将去重后的发现事件转发给FFI层
+        let mut discovery_receiver = discovery.subscribe();
+        *self.discovery.lock().await = Some(discovery);
+        tokio::spawn(async move {
+            loop {
+                match discovery_receiver.recv().await {
+                    Ok(event) => ffi::common::forward_discovery_event(&event),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("设备发现事件处理过慢，丢弃了 {skipped} 个事件");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
             }
-        } else {
-            info!("BLE设备发现不可用，将使用UDP广播");
-        }
-        
-        // 启动UDP设备发现（示例回调）
-        let udp_callback = Box::new(|device: types::DeviceInfo| {
-            info!("发现UDP设备: {} ({})", device.name, device.id);
         });
         
-        discovery.start(udp_callback).await?;
-        
         // 初始化配对管理器
         let mut pairing_manager = network::pairing::PairingManager::new(local_device.clone());
+        pairing_manager.set_revocation_list(revocations.clone());
+        pairing_manager.set_key_pins(key_pins.clone());
+        pairing_manager.set_storage(async_storage.clone());
         
         // 设置配对请求回调
         pairing_manager.set_pairing_request_callback(Box::new(|device, pin| {
@@ -108,21 +292,112 @@ impl PasteAll {
         if let Err(e) = pairing_manager.start_listening(self.config.listen_port).await {
             warn!("启动配对监听服务失败: {e:?}");
         }
-
-        // 初始化存储
-        let _storage = storage::Storage::new(&self.config.storage_path)?;
+        let pairing_manager = std::sync::Arc::new(pairing_manager);
 
         // 初始化Wi-Fi传输服务
         let mut wifi_transport = network::wifi_transport::WiFiTransport::new(
-            local_device,
+            local_device.clone(),
             self.config.listen_port + 1 // 使用listen_port+1作为文件传输端口
         );
+        wifi_transport.set_revocation_list(revocations.clone());
+
+        // 仅接受本次配对、已保存的配对设备或同步组成员的传输连接，
+        // 查找在阻塞线程池上执行，可以直接读取存储
+        let paired_lookup = pairing_manager.peer_key_lookup();
+        let group_members = sync_groups.clone();
+        let device_store = shared_storage.clone();
+        let blocked_pins = key_pins.clone();
+        let peer_lookup: network::secure_channel::PeerKeyLookup = std::sync::Arc::new(move |device_id: &str| {
+            if blocked_pins.is_blocked(device_id) {
+                return None;
+            }
+            paired_lookup(device_id)
+                .or_else(|| {
+                    let device = device_store.get_device(device_id).ok()??;
+                    crypto::KeyPair::public_key_from_base64(&device.public_key).ok()
+                })
+                .or_else(|| crypto::KeyPair::public_key_from_base64(&group_members.member_key(device_id)?).ok())
+        });
+        wifi_transport.set_peer_lookup(peer_lookup.clone());
         
         // 启动Wi-Fi传输服务
         if let Err(e) = wifi_transport.start_server().await {
             warn!("启动Wi-Fi传输服务失败: {e:?}");
         }
 
+        // 初始化内容传输服务，使用listen_port+2作为内容同步端口
+        let mut transport = network::transport::TransportService::new(
+            local_device,
+            network::transport::sync_port(self.config.listen_port)
+        );
+        transport.set_peer_lookup(peer_lookup);
+        transport.set_revocation_list(revocations.clone());
+        transport.set_sync_groups(sync_groups.clone());
+        transport.set_key_pins(key_pins.clone());
+        if self.config.options.propagate_history_deletes {
+            transport.set_history(history.clone());
+        }
+
+        // 已配对设备轮换身份密钥后更新设备记录，配对管理器只保留弱引用以便停止时回收
+        let rotation_pairing = std::sync::Arc::downgrade(&pairing_manager);
+        let rotation_store = shared_storage.clone();
+        transport.set_key_rotation_callback(Box::new(move |rotation| {
+            if let Some(pairing) = rotation_pairing.upgrade() {
+                pairing.update_device_keys(&rotation.device_id, &rotation.public_key, &rotation.verify_key);
+            }
+            // 回调在接收任务中执行，存储写入放到阻塞线程池上
+            let storage = rotation_store.clone();
+            let rotation = rotation.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = Self::apply_key_rotation(&storage, &rotation) {
+                    warn!("更新设备 {} 的新公钥失败: {e:?}", rotation.device_id);
+                }
+            });
+        }));
+        
+        // 启动内容传输服务
+        let content_callback: network::transport::TransportCallback = std::sync::Arc::new(|device, data| {
+            info!("收到设备 {} 的内容，{} 字节", device.id, data.len());
+        });
+        if let Err(e) = transport.start(content_callback).await {
+            warn!("启动内容传输服务失败: {e:?}");
+        }
+        let transport = std::sync::Arc::new(transport);
+
+        // 将本机的历史记录删除操作同步到其他已配对设备
+        if self.config.options.propagate_history_deletes {
+            // 历史记录由传输服务持有，回调只保留弱引用以免循环引用
+            let sender = std::sync::Arc::downgrade(&transport);
+            let recipients_store = async_storage.clone();
+            let runtime = tokio::runtime::Handle::current();
+            history.set_tombstone_callback(Some(Box::new(move |tombstone| {
+                let Some(transport) = sender.upgrade() else {
+                    return;
+                };
+                let recipients_store = recipients_store.clone();
+                runtime.spawn(async move {
+                    match recipients_store.get_all_devices().await {
+                        Ok(recipients) => {
+                            transport.send_tombstone(&tombstone, &recipients).await;
+                        }
+                        Err(e) => warn!("获取已配对设备失败，未发送删除标记: {e:?}"),
+                    }
+                });
+            })))?;
+        }
+
+        if let Ok(mut services) = self.services.lock() {
+            *services = Some(Services {
+                storage: shared_storage,
+                revocations,
+                key_pins,
+                pairing: pairing_manager,
+                sync_groups,
+                transport,
+                history,
+            });
+        }
+
         info!("PasteAll核心服务启动完成");
         Ok(())
     }
@@ -130,9 +405,436 @@ impl PasteAll {
     /// 停止PasteAll服务
     pub async fn stop(&self) -> Result<(), error::Error> {
         info!("停止PasteAll核心服务");
-        // 清理资源
+        if let Some(mut discovery) = self.discovery.lock().await.take() {
+            discovery.stop().await?;
+        }
+
+        // 服务的最后一个引用释放时监听任务随之退出
+        let services = self.services.lock().ok().and_then(|mut services| services.take());
+        if let Some(services) = services {
+            if let Ok(mut pairing) = std::sync::Arc::try_unwrap(services.pairing) {
+                pairing.stop_listening().await?;
+            }
+            if let Ok(mut transport) = std::sync::Arc::try_unwrap(services.transport) {
+                transport.stop().await?;
+            }
+        }
         Ok(())
     }
+
+    /// 获取各发现来源合并后的设备列表
+    pub async fn discovered_devices(&self) -> Vec<network::discovery_manager::DiscoveredDevice> {
+        self.discovery.lock().await.as_ref().map(|d| d.devices()).unwrap_or_default()
+    }
+
+    /// 运行时启用或停用某个设备发现来源，需在 [`PasteAll::start`] 之后调用
+    pub async fn set_discovery_source_enabled(
+        &self,
+        source: network::discovery_manager::DiscoverySource,
+        enabled: bool,
+    ) -> Result<(), error::Error> {
+        let mut discovery = self.discovery.lock().await;
+        let discovery = discovery
+            .as_mut()
+            .ok_or_else(|| error::Error::Initialization("设备发现服务尚未启动".to_string()))?;
+        if enabled {
+            discovery.enable(source).await
+        } else {
+            discovery.disable(source).await
+        }
+    }
+
+    /// 手动添加无法自动发现的设备，需在 [`PasteAll::start`] 之后调用
+    pub async fn add_manual_device(&self, device: types::DeviceInfo) -> Result<(), error::Error> {
+        let discovery = self.discovery.lock().await;
+        discovery
+            .as_ref()
+            .ok_or_else(|| error::Error::Initialization("设备发现服务尚未启动".to_string()))?
+            .add_manual(device)
+    }
+
+    /// 按 `host:port` 地址添加无法自动发现的设备，添加的设备重启后仍保留
+    pub async fn add_device_by_address(&self, address: &str) -> Result<types::DeviceInfo, error::Error> {
+        let discovery = self.discovery.lock().await;
+        discovery
+            .as_ref()
+            .ok_or_else(|| error::Error::Initialization("设备发现服务尚未启动".to_string()))?
+            .add_by_address(address)
+            .await
+    }
+
+    /// 按对方分享的邀请码添加设备
+    pub async fn add_device_by_invite(&self, code: &str) -> Result<types::DeviceInfo, error::Error> {
+        let discovery = self.discovery.lock().await;
+        discovery
+            .as_ref()
+            .ok_or_else(|| error::Error::Initialization("设备发现服务尚未启动".to_string()))?
+            .add_by_invite(code)
+            .await
+    }
+
+    /// 生成本设备的邀请码，包含设备ID、密钥标识和允许接口上的地址
+    pub fn invite_code(&self) -> Result<String, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let interfaces = network::interfaces::InterfaceRules::new(&self.config.options.discovery_interfaces)?
+            .allowed_interfaces();
+        network::invite::Invite::new(&self.local_device()?, &interfaces, self.config.discovery_port)?
+            .encode()
+    }
+
+    /// 向已发现或手动添加的设备发起配对
+    pub async fn pair_device(&self, device_id: &str) -> Result<(), error::Error> {
+        let device = self
+            .discovery
+            .lock()
+            .await
+            .as_ref()
+            .ok_or_else(|| error::Error::Initialization("设备发现服务尚未启动".to_string()))?
+            .device(device_id)
+            .ok_or_else(|| error::Error::InvalidArgument(format!("未找到设备: {device_id}")))?
+            .device;
+
+        // 使用运行中的配对管理器，配对结果才会进入共享的已配对设备和公钥固定表
+        let services = self
+            .services()
+            .ok_or_else(|| error::Error::Initialization("PasteAll服务尚未启动".to_string()))?;
+        services.pairing.request_pairing(&device).await
+    }
+
+    /// 导出加密的完整配置备份
+    ///
+    /// 备份包含配置、身份密钥、已配对设备、共享密钥和剪贴板历史记录。
+    pub fn export_profile(&self, passphrase: &str) -> Result<Vec<u8>, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let storage = storage::Storage::with_pool(storage::init(&self.config.storage_path)?)?;
+        storage::backup::export_profile(&storage, &self.config, &crypto::export_identity()?, passphrase)
+    }
+
+    /// 从备份恢复配置并创建PasteAll实例
+    ///
+    /// 数据恢复到本机的 `storage_path`，并加载备份中的身份密钥，
+    /// 因此已配对设备无需重新配对。需在 [`PasteAll::start`] 之前调用。
+    pub fn restore_profile(bundle: &[u8], passphrase: &str, storage_path: &str) -> Result<Self, error::Error> {
+        // 先解密并加载身份密钥，全部校验通过后再写入数据库和身份密钥文件
+        let opened = storage::backup::open_profile(bundle, passphrase)?;
+        crypto::init_with_identity(&opened.identity)?;
+
+        let storage = storage::Storage::with_pool(storage::init(storage_path)?)?;
+        let restored = opened.restore(&storage)?;
+        if let Some(path) = crypto::identity_path(storage_path) {
+            crypto::save_identity(&path, &restored.identity, None)?;
+        }
+        Self::restore_shared_keys(&storage)?;
+
+        let mut config = restored.config;
+        config.storage_path = storage_path.to_string();
+        info!("已从备份恢复配置，设备: {}", config.device_name);
+
+        Ok(Self::new(config))
+    }
+
+    /// 获取与已配对设备的密钥指纹
+    ///
+    /// 两台设备显示相同的安全码和表情序列时，说明配对时未被中间人替换密钥。
+    pub fn device_fingerprint(&self, device_id: &str) -> Result<crypto::Fingerprint, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let (storage, key_pins) = self.key_pins()?;
+        let pairing = self.services().map(|services| services.pairing);
+        Self::fingerprint_of(&self.local_device()?, &storage, &key_pins, pairing.as_deref(), device_id)
+    }
+
+    /// 标记已配对设备的指纹是否已由用户核对
+    ///
+    /// 设备公钥变更后，标记为已验证即确认新的公钥并恢复同步。
+    pub fn set_device_verified(&self, device_id: &str, verified: bool) -> Result<(), error::Error> {
+        let (storage, key_pins) = self.key_pins()?;
+        let pairing = self.services().map(|services| services.pairing);
+        Self::mark_device_verified(&storage, &key_pins, pairing.as_deref(), device_id, verified)?;
+        info!("设备 {device_id} 的验证状态已更新: {verified}");
+
+        Ok(())
+    }
+
+    /// 已配对设备信息，存储中没有时使用运行中配对管理器的记录
+    fn paired_device(
+        storage: &storage::Storage,
+        pairing: Option<&network::pairing::PairingManager>,
+        device_id: &str,
+    ) -> Result<Option<types::DeviceInfo>, error::Error> {
+        match storage.get_device(device_id)? {
+            Some(device) => Ok(Some(device)),
+            None => Ok(pairing.and_then(|pairing| pairing.paired_device(device_id))),
+        }
+    }
+
+    /// 计算本机与已配对设备的密钥指纹
+    fn fingerprint_of(
+        local: &types::DeviceInfo,
+        storage: &storage::Storage,
+        key_pins: &network::key_pinning::KeyPins,
+        pairing: Option<&network::pairing::PairingManager>,
+        device_id: &str,
+    ) -> Result<crypto::Fingerprint, error::Error> {
+        let mut remote = Self::paired_device(storage, pairing, device_id)?
+            .ok_or_else(|| error::Error::InvalidArgument(format!("未找到设备: {device_id}")))?;
+
+        // 公钥变更待确认时，用户需要比对的是新公钥的指纹
+        if let Some((public_key, verify_key)) = key_pins.changed_keys(device_id) {
+            remote.public_key = public_key;
+            remote.verify_key = verify_key;
+        }
+
+        crypto::compute_fingerprint(local, &remote)
+    }
+
+    /// 更新已配对设备的验证状态
+    ///
+    /// 只在运行中配对管理器里的设备先保存到存储，验证状态重启后依然有效。
+    fn mark_device_verified(
+        storage: &storage::Storage,
+        key_pins: &network::key_pinning::KeyPins,
+        pairing: Option<&network::pairing::PairingManager>,
+        device_id: &str,
+        verified: bool,
+    ) -> Result<(), error::Error> {
+        let stored = storage.get_device(device_id)?;
+        let mut save = stored.is_none();
+        let mut device = stored
+            .or_else(|| pairing.and_then(|pairing| pairing.paired_device(device_id)))
+            .ok_or_else(|| error::Error::InvalidArgument(format!("未找到设备: {device_id}")))?;
+
+        let changed_keys = if verified { key_pins.changed_keys(device_id) } else { None };
+        if let Some((public_key, verify_key)) = &changed_keys {
+            device.public_key = public_key.clone();
+            device.verify_key = verify_key.clone();
+            save = true;
+        }
+        if save {
+            storage.save_device(&device)?;
+        }
+        if changed_keys.is_some() {
+            key_pins.accept_change(device_id)?;
+        }
+        storage.set_device_verified(device_id, verified)?;
+        if let Some(pairing) = pairing {
+            pairing.set_device_trusted(device_id, verified);
+        }
+
+        Ok(())
+    }
+
+    /// 取消配对并吊销丢失或被盗的设备
+    ///
+    /// 删除共享密钥并记录吊销，然后通知其他已配对设备同样拒绝该设备，
+    /// 返回成功通知的设备数。离线设备需要在上线后重新通知。
+    /// 服务运行中时通过运行中的配对管理器吊销，立即断开该设备。
+    pub async fn revoke_device(&self, device_id: &str) -> Result<usize, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let services = self.services();
+        let storage = self.open_storage(services.as_ref()).await?;
+        let pairing = services.as_ref().map(|services| services.pairing.clone());
+        let device_id = device_id.to_string();
+        let (device, devices) = Self::blocking(move || {
+            let device = storage
+                .get_device(&device_id)?
+                .ok_or_else(|| error::Error::InvalidArgument(format!("未找到设备: {device_id}")))?;
+
+            match pairing {
+                Some(pairing) => pairing.revoke(&device)?,
+                None => network::revocation::RevocationList::load(storage.clone())?.revoke(&device.id, &device.public_key)?,
+            }
+
+            Ok((device, storage.get_all_devices()?))
+        }).await?;
+        info!("设备 {} ({}) 已吊销", device.name, device.id);
+
+        let transport = self.transport(services.as_ref())?;
+        Ok(transport.notify_revocation(&device, &devices).await)
+    }
+
+    /// 创建同步组，返回组ID
+    pub fn create_sync_group(&self, name: &str) -> Result<String, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let local_device = self.local_device()?;
+
+        self.sync_groups(self.services().as_ref())?.create_group(name, &local_device)
+    }
+
+    /// 将已配对设备加入同步组
+    ///
+    /// 组密钥随之轮换并分发给所有成员，返回成功送达的成员数。
+    pub async fn add_sync_group_member(&self, group_id: &str, device_id: &str) -> Result<usize, error::Error> {
+        self.update_sync_group(group_id, device_id, true).await
+    }
+
+    /// 将设备移出同步组
+    ///
+    /// 组密钥随之轮换，只分发给剩余成员，返回成功送达的成员数。
+    pub async fn remove_sync_group_member(&self, group_id: &str, device_id: &str) -> Result<usize, error::Error> {
+        self.update_sync_group(group_id, device_id, false).await
+    }
+
+    /// 修改同步组成员并分发新的组密钥
+    async fn update_sync_group(&self, group_id: &str, device_id: &str, add: bool) -> Result<usize, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let services = self.services();
+        let storage = self.open_storage(services.as_ref()).await?;
+        let groups = match &services {
+            Some(services) => services.sync_groups.clone(),
+            None => {
+                let (local_device_id, storage) = (self.config.device_id.clone(), storage.clone());
+                Self::blocking(move || network::sync_group::SyncGroupManager::load(&local_device_id, storage)).await?
+            }
+        };
+
+        let device = if add {
+            let lookup_storage = storage.clone();
+            let lookup_id = device_id.to_string();
+            let mut device = Self::blocking(move || lookup_storage.get_device(&lookup_id))
+                .await?
+                .ok_or_else(|| error::Error::InvalidArgument(format!("未找到设备: {device_id}")))?;
+            // 成员列表中记录最近发现的地址，供其他成员直接连接
+            if let Some(discovered) = self.discovery.lock().await.as_ref().and_then(|d| d.device(device_id)) {
+                device.ip_address = discovered.device.ip_address.or(device.ip_address);
+                device.pairing_port = discovered.device.pairing_port.or(device.pairing_port);
+            }
+            Some(device)
+        } else {
+            None
+        };
+
+        // 与本机配对的成员使用已保存的设备信息，其他成员使用成员列表中的地址
+        let (update_groups, group, member_id) = (groups.clone(), group_id.to_string(), device_id.to_string());
+        let (updates, devices) = Self::blocking(move || {
+            let updates = match device {
+                Some(device) => update_groups.add_member(&group, &device)?,
+                None => update_groups.remove_member(&group, &member_id)?,
+            };
+            Ok((updates, storage.get_all_devices()?))
+        }).await?;
+        let transport = self.transport(services.as_ref())?;
+        let members = groups.group(group_id).map(|group| group.members).unwrap_or_default();
+        let mut delivered = 0;
+        for update in &updates {
+            let receiver_id = update.receiver_id.as_deref();
+            let device = match devices.iter().find(|d| receiver_id == Some(d.id.as_str())) {
+                Some(device) => device.clone(),
+                None => match members.iter().find(|m| receiver_id == Some(m.device_id.as_str())) {
+                    Some(member) => member.to_device(),
+                    None => {
+                        warn!("同步组成员 {receiver_id:?} 的地址未知");
+                        continue;
+                    }
+                },
+            };
+            match transport.send_message(&device, update).await {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("向设备 {} 分发组密钥失败: {e:?}", device.id),
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// 重新计算与已配对设备的共享密钥
+    fn restore_shared_keys(storage: &storage::Storage) -> Result<(), error::Error> {
+        for device in storage.get_all_devices()? {
+            if let Err(e) = crypto::generate_shared_key(&device.id, &device.public_key) {
+                warn!("恢复设备 {} 的共享密钥失败: {e:?}", device.id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 导出使用口令加密的身份密钥，用于迁移到新设备
+    pub fn export_identity(&self, passphrase: &str) -> Result<Vec<u8>, error::Error> {
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        crypto::export_identity_encrypted(passphrase, &self.config.device_id)
+    }
+
+    /// 导入从旧设备迁移的身份密钥
+    ///
+    /// 本机已记录该设备的公钥时，导入的密钥必须与之一致。导入后本机沿用旧设备的ID
+    /// 并保存到存储中，重启后依然有效，已配对设备无需重新配对。需在 [`PasteAll::start`] 之前调用。
+    pub fn import_identity(&mut self, data: &[u8], passphrase: &str) -> Result<String, error::Error> {
+        if self.services().is_some() {
+            return Err(error::Error::InvalidArgument("服务运行中时不能导入身份密钥，请先停止服务".to_string()));
+        }
+        let storage = std::sync::Arc::new(storage::Storage::with_pool(storage::init(&self.config.storage_path)?)?);
+        let (device_id, _) = crypto::import_identity(
+            self.identity_path().as_deref(),
+            self.identity_passphrase.as_deref(),
+            data,
+            passphrase,
+            |device_id| Self::recorded_keys(&storage, device_id),
+        )?;
+
+        storage.save_setting(DEVICE_ID_SETTING, &device_id)?;
+        self.config.device_id = device_id.clone();
+        Self::restore_shared_keys(&storage)?;
+        info!("已导入设备 {device_id} 的身份密钥");
+
+        Ok(device_id)
+    }
+
+    /// 本机记录的设备公钥 (加密公钥, 签名公钥)，优先使用配对时固定的公钥
+    fn recorded_keys(
+        storage: &std::sync::Arc<storage::Storage>,
+        device_id: &str,
+    ) -> Result<Option<(String, String)>, error::Error> {
+        if let Some(keys) = network::key_pinning::KeyPins::load(storage.clone())?.pinned_keys(device_id) {
+            return Ok(Some(keys));
+        }
+        Ok(storage
+            .get_device(device_id)?
+            .map(|device| (device.public_key, device.verify_key)))
+    }
+
+    /// 轮换设备身份密钥
+    ///
+    /// 新密钥生效前，先用旧签名私钥签名的轮换通知告知在线的已配对设备，对方验证后直接固定新公钥；
+    /// 离线设备之后会检测到公钥变更，需要用户重新比对指纹。运行中的服务持有旧的设备信息，
+    /// 因此需在 [`PasteAll::start`] 之前或 [`PasteAll::stop`] 之后调用。返回新的加密公钥（Base64编码）。
+    pub async fn rotate_identity(&self) -> Result<String, error::Error> {
+        if self.services().is_some() {
+            return Err(error::Error::InvalidArgument("服务运行中时不能轮换身份密钥，请先停止服务".to_string()));
+        }
+        crypto::init_from_profile(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+
+        let rotated = crypto::prepare_rotation(self.identity_path().as_deref(), self.identity_passphrase.as_deref())?;
+        let (public_key, verify_key) = rotated.get_public_keys();
+        let storage = self.open_storage(None).await?;
+        let devices = Self::blocking(move || storage.get_all_devices()).await?;
+        let notified = self
+            .transport(None)?
+            .notify_key_rotation(&public_key, &verify_key, &devices)
+            .await;
+        crypto::activate_identity(rotated);
+        info!("设备身份密钥已轮换，已通知 {notified} 台设备");
+
+        Ok(public_key)
+    }
+
+    /// 更新已轮换身份密钥的设备记录和共享密钥
+    ///
+    /// 轮换通知由用户此前确认过的旧密钥签名，设备的验证状态随之保留。
+    fn apply_key_rotation(
+        storage: &storage::Storage,
+        rotation: &network::key_pinning::KeyRotation,
+    ) -> Result<(), error::Error> {
+        if let Some(mut device) = storage.get_device(&rotation.device_id)? {
+            let trusted = device.trusted;
+            device.public_key = rotation.public_key.clone();
+            device.verify_key = rotation.verify_key.clone();
+            storage.save_device(&device)?;
+            if trusted {
+                storage.set_device_verified(&device.id, true)?;
+            }
+        }
+
+        crypto::generate_shared_key(&rotation.device_id, &rotation.public_key)
+    }
 }
 
 /// 初始化日志系统
@@ -160,4 +862,96 @@ mod tests {
         let pasteall = PasteAll::new(config);
         assert_eq!(pasteall.config.device_name, "Test Device");
     }
+
+    fn pairing_device(name: &str, port: u16) -> types::DeviceInfo {
+        let mut device = types::DeviceInfo::new(name, types::DeviceType::Desktop, &crypto::get_public_key().unwrap());
+        device.verify_key = crypto::get_verify_key().unwrap();
+        device.ip_address = Some("127.0.0.1".to_string());
+        device.pairing_port = Some(port);
+        device
+    }
+
+    #[tokio::test]
+    async fn test_verify_device_paired_at_runtime() {
+        crypto::init();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let laptop = pairing_device("Laptop", 0);
+        let phone = pairing_device("Phone", port);
+
+        // 发起方保存配对结果，响应方未设置存储，只在内存中记录
+        let laptop_storage = storage::AsyncStorage::open(":memory:").await.unwrap();
+        let laptop_pins = network::key_pinning::KeyPins::new();
+        let mut laptop_pairing = network::pairing::PairingManager::new(laptop.clone());
+        laptop_pairing.set_key_pins(laptop_pins.clone());
+        laptop_pairing.set_storage(laptop_storage.clone());
+        let mut phone_pairing = network::pairing::PairingManager::new(phone.clone());
+        phone_pairing.start_listening(port).await.unwrap();
+
+        laptop_pairing.expect_pairing(&phone.id, "123456");
+        phone_pairing.expect_pairing(&laptop.id, "123456");
+        laptop_pairing.request_pairing(&phone).await.unwrap();
+        for _ in 0..100 {
+            if phone_pairing.paired_device(&laptop.id).is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let remote = phone_pairing.paired_device(&laptop.id).unwrap();
+        assert_eq!(remote.name, "Laptop");
+        assert_eq!(remote.device_type, types::DeviceType::Desktop);
+
+        // 两端看到相同的指纹
+        let laptop_storage = laptop_storage.storage();
+        let phone_storage = storage::Storage::new(":memory:").unwrap();
+        let phone_pins = network::key_pinning::KeyPins::new();
+        let laptop_fingerprint =
+            PasteAll::fingerprint_of(&laptop, &laptop_storage, &laptop_pins, Some(&laptop_pairing), &phone.id).unwrap();
+        let phone_fingerprint =
+            PasteAll::fingerprint_of(&phone, &phone_storage, &phone_pins, Some(&phone_pairing), &laptop.id).unwrap();
+        assert_eq!(laptop_fingerprint, phone_fingerprint);
+
+        // 已保存和只在内存中的设备都可以标记为已验证
+        PasteAll::mark_device_verified(&laptop_storage, &laptop_pins, Some(&laptop_pairing), &phone.id, true).unwrap();
+        PasteAll::mark_device_verified(&phone_storage, &phone_pins, Some(&phone_pairing), &laptop.id, true).unwrap();
+        assert!(laptop_storage.get_device(&phone.id).unwrap().unwrap().trusted);
+        assert!(phone_storage.get_device(&laptop.id).unwrap().unwrap().trusted);
+        assert!(phone_pairing.paired_device(&laptop.id).unwrap().trusted);
+        assert!(laptop_storage.get_shared_key(&phone.id).unwrap().is_some());
+
+        phone_pairing.stop_listening().await.unwrap();
+    }
+
+    #[test]
+    fn test_import_identity_checks_recorded_keys() {
+        crypto::init();
+        let old = crypto::CryptoManager::new();
+        let other = crypto::CryptoManager::new();
+        let exported = old.export_encrypted("迁移口令", "old-device").unwrap();
+        let storage = std::sync::Arc::new(storage::Storage::new(":memory:").unwrap());
+        let import = || {
+            crypto::CryptoManager::import_encrypted(&exported, "迁移口令", |device_id| {
+                PasteAll::recorded_keys(&storage, device_id)
+            })
+        };
+        let record = |manager: &crypto::CryptoManager| {
+            let (public_key, verify_key) = manager.get_public_keys();
+            let mut device = types::DeviceInfo::new("Old", types::DeviceType::Desktop, &public_key);
+            device.id = "old-device".to_string();
+            device.verify_key = verify_key;
+            device
+        };
+
+        // 本机没有该设备的记录
+        assert!(import().is_ok());
+
+        // 存储中的设备记录与导入的密钥不一致
+        storage.save_device(&record(&other)).unwrap();
+        assert!(matches!(import(), Err(error::Error::Authentication(_))));
+        storage.save_device(&record(&old)).unwrap();
+        assert!(import().is_ok());
+
+        // 固定的公钥优先于设备记录
+        network::key_pinning::KeyPins::load(storage.clone()).unwrap().pin(&record(&other)).unwrap();
+        assert!(matches!(import(), Err(error::Error::Authentication(_))));
+    }
 }
@@ -1,17 +1,25 @@
 //! 蓝牙低功耗(BLE)设备发现与连接模块
+//!
+//! PasteAll设备在广播中声明服务UUID，并在该服务的服务数据中携带命名空间标识
+//! （见 [`BleDiscovery::advertisement_service_data`]）。扫描时先按广播数据筛选，
+//! 只连接同一命名空间的设备读取设备信息。
 
 use crate::error::{Error, Result};
-use crate::types::{Config, DeviceInfo};
+use crate::network::discovery::{expire_devices, record_device, DeviceStatusCallback};
+use crate::network::discovery_events::{DiscoveryEvent, DiscoveryEvents};
+use crate::network::key_pinning::{KeyChangeCallback, KeyPins};
+use crate::network::namespace::NamespaceFilter;
+use crate::types::{Config, DeviceInfo, PairingStatus};
 use btleplug::api::{
     Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter,
 };
 use btleplug::platform::{Adapter, Manager, Peripheral};
 use futures::stream::StreamExt;
 use log::{debug, error, info, warn};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::time;
 use uuid::Uuid;
 
@@ -24,6 +32,9 @@ const PAIRING_REQ_CHAR_UUID: Uuid = Uuid::from_u128(0x00001002_0000_1000_8000_00
 /// 配对响应特性UUID
 const PAIRING_RESP_CHAR_UUID: Uuid = Uuid::from_u128(0x00001003_0000_1000_8000_00805f9b34fb);
 
+/// 重新扫描的间隔，设备连续多个间隔未被扫描到时视为离线
+const BLE_RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+
 /// 设备发现回调函数类型
 pub type BleDeviceDiscoveryCallback = Box<dyn Fn(DeviceInfo) + Send + Sync + 'static>;
 
@@ -37,6 +48,18 @@ pub struct BleDiscovery {
     stop_tx: Option<mpsc::Sender<()>>,
     /// 蓝牙适配器
     adapter: Option<Adapter>,
+    /// 设备发现事件流
+    events: DiscoveryEvents,
+    /// 发现命名空间筛选器
+    namespace: NamespaceFilter,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 密钥变更回调
+    key_change_callback: Option<Arc<KeyChangeCallback>>,
+    /// 设备状态变化回调
+    status_callback: Option<Arc<DeviceStatusCallback>>,
+    /// 判定离线前允许错过的扫描次数
+    offline_after_missed: u32,
 }
 
 impl BleDiscovery {
@@ -61,16 +84,57 @@ impl BleDiscovery {
         let adapter = adapters.into_iter().next().unwrap();
         info!("使用蓝牙适配器: {}", adapter.adapter_info().await.unwrap());
 
-        let local_device = DeviceInfo::new(&config.device_name, config.device_type, "dummy_key");
+        // 设备信息特性中携带命名空间的哈希
+        let namespace = NamespaceFilter::from_options(&config.options)?;
+        let mut local_device = DeviceInfo::new(&config.device_name, config.device_type, "dummy_key");
+        local_device.namespace = namespace.id().map(str::to_string);
 
         Ok(Self {
             local_device,
             devices: Arc::new(Mutex::new(HashMap::new())),
             stop_tx: None,
             adapter: Some(adapter),
+            events: DiscoveryEvents::default(),
+            namespace,
+            key_pins: KeyPins::new(),
+            key_change_callback: None,
+            status_callback: None,
+            offline_after_missed: config.options.offline_after_missed.max(1),
         })
     }
 
+    /// 设置共享的设备公钥固定表
+    ///
+    /// 公钥与固定的公钥不一致的设备不会被记录。
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置密钥变更回调
+    pub fn set_key_change_callback(&mut self, callback: KeyChangeCallback) {
+        self.key_change_callback = Some(Arc::new(callback));
+    }
+
+    /// 设置设备状态变化回调，设备超时未被扫描到时以离线状态调用
+    pub fn set_status_callback(&mut self, callback: DeviceStatusCallback) {
+        self.status_callback = Some(Arc::new(callback));
+    }
+
+    /// 设置共享的设备发现事件流
+    pub fn set_discovery_events(&mut self, events: DiscoveryEvents) {
+        self.events = events;
+    }
+
+    /// 订阅去重后的设备发现事件
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    /// 平台层广播本设备时使用的服务UUID和服务数据（命名空间标识）
+    pub fn advertisement_service_data(&self) -> (Uuid, Vec<u8>) {
+        (PASTEALL_SERVICE_UUID, self.namespace.advertised_tag())
+    }
+
     /// 启动BLE设备发现
     pub async fn start(&mut self, callback: BleDeviceDiscoveryCallback) -> Result<()> {
         if self.stop_tx.is_some() {
@@ -89,9 +153,18 @@ impl BleDiscovery {
 
         // 保存设备列表的引用
         let devices = self.devices.clone();
+        let discovery_events = self.events.clone();
+        let namespace = self.namespace.clone();
+        let key_pins = self.key_pins.clone();
+        let key_change_callback = self.key_change_callback.clone();
+        let status_callback = self.status_callback.clone();
+        let offline_timeout = BLE_RESCAN_INTERVAL * self.offline_after_missed;
         
-        // 开始扫描
-        adapter.start_scan(ScanFilter::default()).await.map_err(|e| {
+        // 只扫描声明了PasteAll服务的设备
+        let scan_filter = ScanFilter {
+            services: vec![PASTEALL_SERVICE_UUID],
+        };
+        adapter.start_scan(scan_filter.clone()).await.map_err(|e| {
             error!("启动蓝牙扫描失败: {e:?}");
             Error::Network("无法启动蓝牙扫描".to_string())
         })?;
@@ -106,6 +179,9 @@ impl BleDiscovery {
 
         // 启动事件处理任务
         tokio::spawn(async move {
+            let mut rescan = time::interval_at(time::Instant::now() + BLE_RESCAN_INTERVAL, BLE_RESCAN_INTERVAL);
+            // 本轮扫描中已尝试连接的设备，每轮最多连接一次
+            let mut checked = HashSet::new();
             loop {
                 tokio::select! {
                     _ = stop_rx.recv() => {
@@ -114,21 +190,52 @@ impl BleDiscovery {
                         break;
                     }
                     event = events.next() => {
-                        if let Some(CentralEvent::DeviceDiscovered(id)) = event {
-                            // 尝试连接设备并检查是否为PasteAll设备
-                            if let Ok(peripheral) = adapter.peripheral(&id).await {
-                                if let Err(e) = Self::process_discovered_device(&peripheral, devices.clone(), &callback).await {
-                                    error!("处理发现的设备失败: {e:?}");
-                                }
+                        // 服务数据可能在设备首次出现之后才收到
+                        let id = match event {
+                            Some(CentralEvent::DeviceDiscovered(id))
+                            | Some(CentralEvent::ServiceDataAdvertisement { id, .. }) => id,
+                            _ => continue,
+                        };
+                        if checked.contains(&id) {
+                            continue;
+                        }
+                        if let Ok(peripheral) = adapter.peripheral(&id).await {
+                            if !Self::advertises_namespace(&peripheral, &namespace).await {
+                                continue;
+                            }
+                            checked.insert(id);
+
+                            // 连接设备读取设备信息
+                            let recorder = |device: &DeviceInfo| {
+                                record_device(&devices, &key_pins, key_change_callback.as_deref(), device)
+                            };
+                            if let Err(e) = Self::process_discovered_device(&peripheral, &recorder, &discovery_events, &namespace, &callback).await {
+                                error!("处理发现的设备失败: {e:?}");
                             }
                         }
                     }
-                    _ = time::sleep(Duration::from_secs(30)) => {
-                        // 每30秒重新扫描一次
+                    _ = rescan.tick() => {
+                        // 定期重新扫描，并将长时间未扫描到的设备标记为离线
                         debug!("刷新BLE设备扫描");
                         let _ = adapter.stop_scan().await;
                         let _ = time::sleep(Duration::from_millis(100)).await;
-                        let _ = adapter.start_scan(ScanFilter::default()).await;
+                        let _ = adapter.start_scan(scan_filter.clone()).await;
+                        checked.clear();
+
+                        let expired = match devices.lock() {
+                            Ok(mut devices) => expire_devices(&mut devices, now_secs(), offline_timeout.as_secs()),
+                            Err(e) => {
+                                error!("获取设备列表锁失败: {e:?}");
+                                continue;
+                            }
+                        };
+                        for device in expired {
+                            info!("BLE设备已离线: {} ({})", device.name, device.id);
+                            discovery_events.lost(&device.id);
+                            if let Some(callback) = &status_callback {
+                                callback(device);
+                            }
+                        }
                     }
                 }
             }
@@ -169,10 +276,36 @@ impl BleDiscovery {
         Ok(devices.values().cloned().collect())
     }
 
+    /// 广播数据是否声明了PasteAll服务且命名空间标识匹配
+    ///
+    /// 未携带服务数据的设备视为未配置命名空间。其他命名空间的设备不会被连接。
+    async fn advertises_namespace(peripheral: &Peripheral, namespace: &NamespaceFilter) -> bool {
+        let properties = match peripheral.properties().await {
+            Ok(Some(properties)) => properties,
+            _ => return false,
+        };
+
+        let tag = match properties.service_data.get(&PASTEALL_SERVICE_UUID) {
+            Some(tag) => tag.as_slice(),
+            None if properties.services.contains(&PASTEALL_SERVICE_UUID) => &[],
+            None => return false,
+        };
+        if !namespace.allows_advertised(tag) {
+            debug!("忽略其他命名空间的BLE设备 {}", properties.address);
+            return false;
+        }
+
+        true
+    }
+
     /// 处理发现的设备
+    ///
+    /// 设备信息经 `record` 检查公钥固定后才会记录和上报。
     async fn process_discovered_device(
         peripheral: &Peripheral,
-        devices: Arc<Mutex<HashMap<String, DeviceInfo>>>,
+        record: &(dyn Fn(&DeviceInfo) -> bool + Sync),
+        discovery_events: &DiscoveryEvents,
+        namespace: &NamespaceFilter,
         callback: &BleDeviceDiscoveryCallback,
     ) -> Result<()> {
         // 连接到设备
@@ -211,7 +344,7 @@ impl BleDiscovery {
             })?;
 
         // 解析设备信息
-        let device_info: DeviceInfo = match serde_json::from_slice(&data) {
+        let mut device_info: DeviceInfo = match serde_json::from_slice(&data) {
             Ok(info) => info,
             Err(e) => {
                 error!("解析设备信息失败: {e:?}");
@@ -223,26 +356,41 @@ impl BleDiscovery {
         // 断开连接，节省电池
         let _ = peripheral.disconnect().await;
 
-        // 保存设备信息
-        {
-            let mut devices_guard = match devices.lock() {
-                Ok(guard) => guard,
-                Err(e) => {
-                    error!("获取设备列表锁失败: {e:?}");
-                    return Err(Error::Network("获取设备列表锁失败".to_string()));
-                }
-            };
+        if !namespace.allows(device_info.namespace.as_deref()) {
+            debug!("忽略其他命名空间的设备 {}", device_info.id);
+            return Ok(());
+        }
 
-            devices_guard.insert(device_info.id.clone(), device_info.clone());
+        // BLE设备信息未经签名，状态字段以本机观察为准
+        device_info.online = true;
+        device_info.last_seen = Some(now_secs());
+        device_info.ip_address = None;
+        device_info.addresses = Vec::new();
+        device_info.announcement_verified = false;
+        device_info.trusted = false;
+        device_info.pairing_status = PairingStatus::Unpaired;
+
+        // 检查公钥固定后保存设备信息
+        if !record(&device_info) {
+            return Ok(());
         }
 
         // 触发回调
+        discovery_events.observe(&device_info);
         callback(device_info);
 
         Ok(())
     }
 }
 
+/// 当前时间（Unix时间戳，秒）
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
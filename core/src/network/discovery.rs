@@ -1,20 +1,72 @@
 //! 设备发现模块，负责在局域网内发现其他设备
+//!
+//! 发现包在每个允许的网络接口上分别发送子网定向广播、IPv4多播和IPv6链路本地多播，
+//! 在屏蔽广播的网络和仅有IPv6的网络中也能发现设备，且不会在不可信的接口上公布本设备。
+//! 收到的发现包按来源地址限速，设备列表有容量上限，防止恶意设备刷屏或耗尽内存。
+//! 配置了发现命名空间时只记录命名空间相同的设备。
 
 use crate::{
+    crypto,
     error::{Error, Result},
-    types::{Config, DeviceInfo, DiscoveryPacket, PairingStatus},
+    network::{
+        discovery_events::{DiscoveryEvent, DiscoveryEvents},
+        discovery_wire::{self, RECV_BUFFER_SIZE},
+        interfaces::{InterfaceRules, NetworkInterface},
+        key_pinning::{KeyChangeCallback, KeyPins, PinCheck},
+        namespace::NamespaceFilter,
+        rate_limit::RateLimiter,
+        replay::{DEFAULT_CLOCK_SKEW_SECS, DEFAULT_REPLAY_WINDOW_SECS},
+    },
+    types::{Config, DeviceInfo, DiscoveryPacket, PairingStatus, SecurityPolicy},
 };
 use log::{debug, error, info, warn};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::{self, Duration};
 
 /// 设备发现回调函数类型
 pub type DeviceDiscoveryCallback = Box<dyn Fn(DeviceInfo) + Send + Sync + 'static>;
 
+/// 设备在线状态变更回调函数类型，参数中的 `online` 为变更后的状态
+pub type DeviceStatusCallback = Box<dyn Fn(DeviceInfo) + Send + Sync + 'static>;
+
+/// 默认广播间隔
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 默认的单播探测超时时间
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 设备列表最多保存的设备数量
+pub const MAX_DISCOVERED_DEVICES: usize = 256;
+
+/// 广播包类型
+const PACKET_ANNOUNCE: &str = "discovery";
+
+/// 查询包类型，接收方记录查询方后单播回复自己的广播包
+const PACKET_QUERY: &str = "discovery_query";
+
+/// IPv4 发现多播组（组织本地范围）
+pub const DISCOVERY_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 45, 78);
+
+/// IPv6 链路本地发现多播组
+pub const DISCOVERY_MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x4578);
+
+/// 发现广播包的签名检查结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnouncementCheck {
+    /// 签名有效
+    Verified,
+    /// 未签名，按安全策略接受但标记为未验证
+    Unsigned,
+    /// 应丢弃
+    Rejected,
+}
+
 /// 设备发现服务
 pub struct DeviceDiscovery {
     /// 本地设备信息
@@ -23,33 +75,96 @@ pub struct DeviceDiscovery {
     devices: Arc<Mutex<HashMap<String, DeviceInfo>>>,
     /// 停止信号发送端
     stop_tx: Option<mpsc::Sender<()>>,
-    /// 广播端口
-    broadcast_port: u16,
-    /// 监听端口
-    listen_port: u16,
+    /// 发现端口，监听和发送广播包使用同一端口
+    discovery_port: u16,
+    /// 是否同时在回环接口上广播，用于本机测试
+    loopback: bool,
+    /// 公布的配对监听端口
+    pairing_port: u16,
+    /// 公布的文件传输端口
+    transfer_port: u16,
+    /// 广播间隔
+    announce_interval: Duration,
+    /// 连续错过多少次广播后标记为离线
+    offline_after_missed: u32,
+    /// 在线状态变更回调
+    status_callback: Option<Arc<DeviceStatusCallback>>,
+    /// 设备发现事件流
+    events: DiscoveryEvents,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 密钥变更回调
+    key_change_callback: Option<Arc<KeyChangeCallback>>,
+    /// 安全策略，决定是否接受未签名的广播包
+    security_policy: SecurityPolicy,
+    /// 网络接口过滤规则
+    interface_rules: InterfaceRules,
+    /// 发现命名空间筛选器
+    namespace: NamespaceFilter,
 }
 
 impl DeviceDiscovery {
     /// 创建新的设备发现服务
     pub fn new(config: &Config) -> Result<Self> {
-        // 这里使用UUID作为设备ID
-        let _device_id = uuid::Uuid::new_v4().to_string();
-
-        // 假设已经生成或加载了密钥对
-        // 实际实现中应该从安全存储中加载或生成
-        let public_key = "dummy_public_key_base64_encoded";
-
-        let local_device = DeviceInfo::new(&config.device_name, config.device_type, public_key);
-
         Ok(Self {
-            local_device,
+            local_device: local_device_info(config)?,
             devices: Arc::new(Mutex::new(HashMap::new())),
             stop_tx: None,
-            broadcast_port: 45678,
-            listen_port: 45679,
+            discovery_port: config.discovery_port,
+            loopback: false,
+            pairing_port: config.listen_port,
+            transfer_port: config.listen_port + 1,
+            announce_interval: DEFAULT_ANNOUNCE_INTERVAL,
+            offline_after_missed: config.options.offline_after_missed.max(1),
+            status_callback: None,
+            events: DiscoveryEvents::default(),
+            key_pins: KeyPins::new(),
+            key_change_callback: None,
+            security_policy: config.options.security_policy,
+            interface_rules: InterfaceRules::new(&config.options.discovery_interfaces)?,
+            namespace: NamespaceFilter::from_options(&config.options)?,
         })
     }
 
+    /// 设置是否同时在回环接口上广播（默认关闭）
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled;
+    }
+
+    /// 设置共享的设备公钥固定表
+    ///
+    /// 已知设备以不同公钥出现时不会更新设备列表，而是触发密钥变更回调。
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置密钥变更回调
+    pub fn set_key_change_callback(&mut self, callback: KeyChangeCallback) {
+        self.key_change_callback = Some(Arc::new(callback));
+    }
+
+    /// 设置在线状态变更回调
+    ///
+    /// 设备连续错过配置次数的广播后被标记为离线，重新收到广播时恢复在线，两种变化都会触发回调。
+    pub fn set_status_callback(&mut self, callback: DeviceStatusCallback) {
+        self.status_callback = Some(Arc::new(callback));
+    }
+
+    /// 设置共享的设备发现事件流
+    pub fn set_discovery_events(&mut self, events: DiscoveryEvents) {
+        self.events = events;
+    }
+
+    /// 订阅去重后的设备发现事件
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    /// 设置广播间隔，离线判定时间随之变化
+    pub fn set_announce_interval(&mut self, interval: Duration) {
+        self.announce_interval = interval;
+    }
+
     /// 开始设备发现服务
     pub async fn start(&mut self, callback: DeviceDiscoveryCallback) -> Result<()> {
         if self.stop_tx.is_some() {
@@ -63,69 +178,69 @@ impl DeviceDiscovery {
         self.stop_tx = Some(stop_tx);
 
         let devices = self.devices.clone();
-        let _local_device_id = self.local_device.id.clone(); // 只克隆ID，避免移动问题
-        let local_device_broadcast = self.local_device.clone();
         let local_device_listen = self.local_device.clone();
-        let broadcast_port = self.broadcast_port;
-        let listen_port = self.listen_port;
-
-        // 启动广播任务
+        let mut discovery_packet = self.announcement();
+        let reply_packet = self.announcement();
+        let discovery_port = self.discovery_port;
+        let loopback = self.loopback;
+        let key_pins = self.key_pins.clone();
+        let key_change_callback = self.key_change_callback.clone();
+        let status_callback = self.status_callback.clone();
+        let events = self.events.clone();
+        let announce_interval = self.announce_interval;
+        let offline_timeout = announce_interval * self.offline_after_missed;
+        let security_policy = self.security_policy;
+        let announce_rules = self.interface_rules.clone();
+        let listen_rules = self.interface_rules.clone();
+        let namespace = self.namespace.clone();
+
+        // 启动广播任务，在每个允许的接口上发送定向广播、IPv4多播和IPv6链路本地多播
         let broadcast_task = tokio::spawn(async move {
-            let local_device = local_device_broadcast; // 在任务内部使用本地变量
-            let socket = match UdpSocket::bind("0.0.0.0:0").await {
-                Ok(s) => s,
+            let socket_v4 = match UdpSocket::bind("0.0.0.0:0").await {
+                Ok(s) => {
+                    if let Err(e) = s.set_broadcast(true) {
+                        warn!("设置广播套接字选项失败: {e:?}");
+                    }
+                    Some(s)
+                }
                 Err(e) => {
-                    error!("绑定广播套接字失败: {e:?}");
-                    return;
+                    warn!("绑定IPv4广播套接字失败: {e:?}");
+                    None
                 }
             };
-
-            if let Err(e) = socket.set_broadcast(true) {
-                error!("设置广播套接字选项失败: {e:?}");
-                return;
-            }
-
-            let broadcast_addr = SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)),
-                broadcast_port,
-            );
-
-            let mut interval = time::interval(Duration::from_secs(5));
-
-            // 创建广播包
-            let discovery_packet = DiscoveryPacket {
-                r#type: "discovery".to_string(),
-                device_id: local_device.id.clone(),
-                device_name: local_device.name.clone(),
-                public_key: local_device.public_key.clone(),
-                timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-                device_type: local_device.device_type,
-                port: broadcast_port,
-                ip_address: None, // 这里可以获取本地IP地址
-                capabilities: local_device.capabilities,
-                app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
-                system_version: None,
-                protocol_version: "1.0".to_string(),
-            };
-
-            let packet_json = match serde_json::to_string(&discovery_packet) {
-                Ok(json) => json,
+            let socket_v6 = match UdpSocket::bind("[::]:0").await {
+                Ok(s) => Some(s),
                 Err(e) => {
-                    error!("序列化发现包失败: {e:?}");
-                    return;
+                    warn!("绑定IPv6多播套接字失败: {e:?}");
+                    None
                 }
             };
+            if socket_v4.is_none() && socket_v6.is_none() {
+                error!("没有可用的广播套接字");
+                return;
+            }
+
+            let mut interval = time::interval(announce_interval);
 
             loop {
                 tokio::select! {
                     _ = interval.tick() => {
-                        debug!("发送广播包: {packet_json}");
-                        if let Err(e) = socket.send_to(packet_json.as_bytes(), broadcast_addr).await {
-                            error!("发送广播包失败: {e:?}");
+                        discovery_packet.timestamp = now_secs();
+                        discovery_packet.signature = sign_announcement(&discovery_packet);
+                        let data = match discovery_wire::encode(&discovery_packet) {
+                            Ok(data) => data,
+                            Err(e) => {
+                                error!("编码发现包失败: {e:?}");
+                                return;
+                            }
+                        };
+                        debug!("发送广播包: {} 字节", data.len());
+                        // 网络接口可能变化，每次广播前重新枚举
+                        let mut interfaces = announce_rules.allowed_interfaces();
+                        if loopback {
+                            interfaces.push(loopback_interface());
                         }
+                        announce(socket_v4.as_ref(), socket_v6.as_ref(), &interfaces, &data, discovery_port).await;
                     }
                     _ = stop_rx.recv() => {
                         info!("停止设备发现广播");
@@ -135,63 +250,133 @@ impl DeviceDiscovery {
             }
         });
 
-        // 启动监听任务
+        // 启动监听任务，分别监听IPv4和IPv6
         let listen_task = tokio::spawn(async move {
             let local_device = local_device_listen; // 在任务内部使用本地变量
-            let socket = match UdpSocket::bind(format!("0.0.0.0:{listen_port}")).await {
-                Ok(s) => s,
+            let socket_v4 = match bind_listener_v4(discovery_port) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    warn!("绑定IPv4监听套接字失败: {e:?}");
+                    None
+                }
+            };
+            let socket_v6 = match bind_listener_v6(discovery_port) {
+                Ok(s) => Some(s),
                 Err(e) => {
-                    error!("绑定监听套接字失败: {e:?}");
+                    warn!("绑定IPv6监听套接字失败: {e:?}");
+                    None
+                }
+            };
+            if socket_v4.is_none() && socket_v6.is_none() {
+                error!("没有可用的监听套接字");
+                return;
+            }
+
+            // 处理收到的发现包，查询包经由收到它的套接字回复。限速同时避免被利用为反射放大源
+            let mut rate_limiter = RateLimiter::default();
+            let mut handle_packet = |socket: Option<&UdpSocket>, data: &[u8], addr: SocketAddr, interfaces: &[NetworkInterface]| {
+                if !listen_rules.allows_source(&addr, interfaces) {
+                    debug!("忽略来自不允许的网络的发现包: {addr}");
+                    return;
+                }
+                if !rate_limiter.allow(addr.ip(), Instant::now()) {
+                    debug!("来自 {addr} 的发现包过于频繁，已丢弃");
+                    return;
+                }
+
+                let packet = match discovery_wire::decode(data) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        debug!("丢弃来自 {addr} 的无效发现包: {e:?}");
+                        return;
+                    }
+                };
+                // 忽略自己发送的包
+                if packet.device_id == local_device.id {
+                    return;
+                }
+
+                let is_query = packet.r#type == PACKET_QUERY;
+                // 其他命名空间的设备既不记录也不回复，避免向其暴露本设备
+                if !namespace.allows(packet.namespace.as_deref()) {
+                    debug!("忽略其他命名空间的设备 {}", packet.device_id);
+                    return;
+                }
+
+                let check = check_announcement(&packet, &key_pins, security_policy, now_secs());
+                if check == AnnouncementCheck::Rejected {
+                    warn!("丢弃设备 {} 来自 {addr} 的未通过签名检查的广播包", packet.device_id);
                     return;
                 }
+
+                let mut device = device_from_packet(packet, check == AnnouncementCheck::Verified);
+
+                // 同一设备可能通过多个地址族和接口到达，保留此前观察到的地址
+                let (was_offline, known_addresses) = devices
+                    .lock()
+                    .map(|devices| {
+                        devices
+                            .get(&device.id)
+                            .map(|d| (!d.online, d.addresses.clone()))
+                            .unwrap_or_default()
+                    })
+                    .unwrap_or_default();
+                device.addresses = known_addresses;
+                device.add_address(&observed_address(&addr));
+
+                // 更新设备列表并触发回调
+                if record_device(&devices, &key_pins, key_change_callback.as_deref(), &device) {
+                    if was_offline {
+                        info!("设备重新上线: {} ({})", device.name, device.id);
+                        if let Some(status_callback) = &status_callback {
+                            status_callback(device.clone());
+                        }
+                    }
+                    events.observe(&device);
+                    callback(device);
+
+                    if is_query {
+                        send_reply(socket, &reply_packet, addr);
+                    }
+                }
             };
 
-            let mut buf = vec![0u8; 1024];
+            let mut buf_v4 = vec![0u8; RECV_BUFFER_SIZE];
+            let mut buf_v6 = vec![0u8; RECV_BUFFER_SIZE];
+            let mut sweep = time::interval(announce_interval);
+            let mut interfaces = Vec::new();
 
             loop {
                 tokio::select! {
-                    result = socket.recv_from(&mut buf) => {
-                        match result {
-                            Ok((len, addr)) => {
-                                if let Ok(packet_str) = String::from_utf8(buf[..len].to_vec()) {
-                                    debug!("收到数据包: {packet_str} 来自: {addr}");
-
-                                    if let Ok(packet) = serde_json::from_str::<DiscoveryPacket>(&packet_str) {
-                                        // 忽略自己发送的包
-                                        if packet.device_id != local_device.id {
-                                            let device = DeviceInfo {
-                                                id: packet.device_id,
-                                                name: packet.device_name,
-                                                device_type: packet.device_type,
-                                                public_key: packet.public_key,
-                                                online: true,
-                                                ip_address: packet.ip_address,
-                                                system_version: packet.system_version,
-                                                app_version: packet.app_version,
-                                                capabilities: packet.capabilities,
-                                                last_seen: Some(packet.timestamp),
-                                                pairing_status: PairingStatus::default(),
-                                                description: None,
-                                                trusted: false,
-                                            };
-
-                                            // 更新设备列表
-                                            {
-                                                let mut devices_map = devices.lock().unwrap();
-                                                devices_map.insert(device.id.clone(), device.clone());
-                                            }
-
-                                            // 触发回调
-                                            callback(device);
-                                        }
-                                    }
-                                }
-                            }
+                    _ = sweep.tick() => {
+                        // 网络接口可能变化，定期重新枚举并加入新接口上的多播组
+                        interfaces = listen_rules.allowed_interfaces();
+                        join_multicast_groups(socket_v4.as_ref(), socket_v6.as_ref(), &interfaces);
+
+                        let expired = match devices.lock() {
+                            Ok(mut devices) => expire_devices(&mut devices, now_secs(), offline_timeout.as_secs()),
                             Err(e) => {
-                                error!("接收数据包失败: {e:?}");
+                                error!("获取设备列表锁失败: {e:?}");
+                                continue;
+                            }
+                        };
+                        events.flush();
+                        for device in expired {
+                            info!("设备已离线: {} ({})", device.name, device.id);
+                            events.lost(&device.id);
+                            if let Some(callback) = &status_callback {
+                                callback(device);
                             }
                         }
                     }
+                    result = recv_from(socket_v4.as_ref(), &mut buf_v4) => match result {
+                        Ok((len, addr)) => handle_packet(socket_v4.as_ref(), &buf_v4[..len], addr, &interfaces),
+                        Err(e) => error!("接收数据包失败: {e:?}"),
+                    },
+                    result = recv_from(socket_v6.as_ref(), &mut buf_v6) => match result {
+                        Ok((len, addr)) => handle_packet(socket_v6.as_ref(), &buf_v6[..len], addr, &interfaces),
+                        Err(e) => error!("接收IPv6数据包失败: {e:?}"),
+                    },
                 }
             }
         });
@@ -218,6 +403,77 @@ impl DeviceDiscovery {
         Ok(())
     }
 
+    /// 向指定地址发送查询并等待对方单播回复的广播包
+    ///
+    /// 用于广播和多播无法到达的网络。只接受签名有效的回复；返回的设备尚未写入设备列表，
+    /// 也未固定公钥，调用方确认身份（如比对邀请码中的密钥标识）后再记录。
+    pub async fn probe(&self, addr: SocketAddr, timeout: Duration) -> Result<DeviceInfo> {
+        let bind_addr = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| Error::Network(format!("绑定探测套接字失败: {e}")))?;
+
+        let mut query = self.announcement();
+        query.r#type = PACKET_QUERY.to_string();
+        query.timestamp = now_secs();
+        query.signature = sign_announcement(&query);
+        socket
+            .send_to(&discovery_wire::encode(&query)?, addr)
+            .await
+            .map_err(|e| Error::Network(format!("向 {addr} 发送查询失败: {e}")))?;
+
+        let mut buf = vec![0u8; RECV_BUFFER_SIZE];
+        let (packet, from) = time::timeout(timeout, async {
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                if from.ip() != addr.ip() {
+                    continue;
+                }
+                if let Ok(packet) = discovery_wire::decode(&buf[..len]) {
+                    if packet.r#type == PACKET_ANNOUNCE {
+                        return Ok::<_, std::io::Error>((packet, from));
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Network(format!("{addr} 未响应查询")))?
+        .map_err(|e| Error::Network(format!("接收 {addr} 的回复失败: {e}")))?;
+
+        if check_announcement(&packet, &self.key_pins, self.security_policy, now_secs()) != AnnouncementCheck::Verified {
+            return Err(Error::Authentication(format!("{addr} 的回复未通过签名验证")));
+        }
+
+        let mut device = device_from_packet(packet, true);
+        device.add_address(&observed_address(&from));
+        Ok(device)
+    }
+
+    /// 生成本设备的广播包，发送前需填写时间戳并签名
+    fn announcement(&self) -> DiscoveryPacket {
+        DiscoveryPacket {
+            r#type: PACKET_ANNOUNCE.to_string(),
+            device_id: self.local_device.id.clone(),
+            device_name: discovery_wire::truncate_name(&self.local_device.name),
+            public_key: self.local_device.public_key.clone(),
+            verify_key: self.local_device.verify_key.clone(),
+            timestamp: 0,
+            device_type: self.local_device.device_type,
+            port: self.pairing_port,
+            transfer_port: Some(self.transfer_port),
+            ip_address: None,
+            capabilities: self.local_device.capabilities,
+            app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            system_version: None,
+            protocol_version: "1.0".to_string(),
+            signature: None,
+            namespace: self.local_device.namespace.clone(),
+        }
+    }
+
     /// 获取发现的设备列表
     pub fn get_devices(&self) -> Vec<DeviceInfo> {
         let devices = match self.devices.lock() {
@@ -245,6 +501,314 @@ impl DeviceDiscovery {
     }
 }
 
+/// 在指定的网络接口上发送发现包
+///
+/// IPv4 发送各子网的定向广播和多播，多播需逐个接口指定出口；IPv6 只能使用链路本地多播，
+/// 目标地址的接口索引决定从哪个接口发出。
+async fn announce(
+    socket_v4: Option<&UdpSocket>,
+    socket_v6: Option<&UdpSocket>,
+    interfaces: &[NetworkInterface],
+    packet: &[u8],
+    port: u16,
+) {
+    let mut v6_indexes = Vec::new();
+    for interface in interfaces {
+        match (interface.ip, socket_v4, socket_v6) {
+            (IpAddr::V4(ip), Some(socket), _) => {
+                let broadcast = interface.broadcast.unwrap_or_else(|| directed_broadcast(ip, interface.prefix_len));
+                if let Err(e) = socket.send_to(packet, (broadcast, port)).await {
+                    debug!("向 {broadcast} 发送定向广播包失败: {e:?}");
+                }
+                if let Err(e) = SockRef::from(socket).set_multicast_if_v4(&ip) {
+                    debug!("设置多播出口 {ip} 失败: {e:?}");
+                    continue;
+                }
+                if let Err(e) = socket.send_to(packet, (DISCOVERY_MULTICAST_V4, port)).await {
+                    debug!("通过 {ip} 发送IPv4多播包失败: {e:?}");
+                }
+            }
+            (IpAddr::V6(_), _, Some(socket)) => {
+                // 同一接口可能有多个IPv6地址，每个接口只发送一次
+                let Some(index) = interface.index.filter(|i| !v6_indexes.contains(i)) else {
+                    continue;
+                };
+                v6_indexes.push(index);
+                let target = SocketAddrV6::new(DISCOVERY_MULTICAST_V6, port, 0, index);
+                if let Err(e) = socket.send_to(packet, target).await {
+                    debug!("通过 {} 发送IPv6多播包失败: {e:?}", interface.name);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 在指定的网络接口上加入发现多播组，已加入的接口会返回错误，忽略即可
+fn join_multicast_groups(socket_v4: Option<&UdpSocket>, socket_v6: Option<&UdpSocket>, interfaces: &[NetworkInterface]) {
+    for interface in interfaces {
+        let result = match (interface.ip, socket_v4, socket_v6) {
+            (IpAddr::V4(ip), Some(socket), _) => socket.join_multicast_v4(DISCOVERY_MULTICAST_V4, ip),
+            (IpAddr::V6(_), _, Some(socket)) => match interface.index {
+                Some(index) => socket.join_multicast_v6(&DISCOVERY_MULTICAST_V6, index),
+                None => continue,
+            },
+            _ => continue,
+        };
+        if let Err(e) = result {
+            debug!("在 {} ({}) 上加入多播组失败: {e:?}", interface.name, interface.ip);
+        }
+    }
+}
+
+/// 根据接口地址和前缀长度计算子网定向广播地址
+fn directed_broadcast(ip: Ipv4Addr, prefix_len: u8) -> Ipv4Addr {
+    let host_mask = u32::MAX.checked_shr(u32::from(prefix_len)).unwrap_or(0);
+    Ipv4Addr::from(u32::from(ip) | host_mask)
+}
+
+/// 回环接口，广播地址 127.255.255.255 会投递给本机所有绑定发现端口的套接字
+fn loopback_interface() -> NetworkInterface {
+    NetworkInterface {
+        name: "lo".to_string(),
+        index: None,
+        ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        prefix_len: 8,
+        broadcast: Some(Ipv4Addr::new(127, 255, 255, 255)),
+    }
+}
+
+/// 绑定IPv4监听套接字
+///
+/// 允许地址复用，同一台机器上的多个实例都能收到广播和多播包。
+fn bind_listener_v4(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// 绑定IPv6监听套接字
+///
+/// 设置仅接收IPv6，否则双栈系统上会与同端口的IPv4监听套接字冲突。
+fn bind_listener_v6(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// 从可能不存在的套接字接收数据，套接字不存在时永不返回
+async fn recv_from(socket: Option<&UdpSocket>, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 由发现包构造设备信息
+///
+/// 地址由调用方按观察到的来源地址填写，不信任包内声明的地址；最后在线时间使用本机接收时间。
+fn device_from_packet(packet: DiscoveryPacket, announcement_verified: bool) -> DeviceInfo {
+    DeviceInfo {
+        id: packet.device_id,
+        name: packet.device_name,
+        device_type: packet.device_type,
+        public_key: packet.public_key,
+        verify_key: packet.verify_key,
+        online: true,
+        ip_address: None,
+        pairing_port: Some(packet.port),
+        transfer_port: packet.transfer_port,
+        system_version: packet.system_version,
+        app_version: packet.app_version,
+        capabilities: packet.capabilities,
+        last_seen: Some(now_secs()),
+        pairing_status: PairingStatus::default(),
+        description: None,
+        trusted: false,
+        announcement_verified,
+        addresses: Vec::new(),
+        namespace: packet.namespace,
+    }
+}
+
+/// 单播回复本设备的广播包
+fn send_reply(socket: Option<&UdpSocket>, packet: &DiscoveryPacket, addr: SocketAddr) {
+    let Some(socket) = socket else {
+        return;
+    };
+    let mut packet = packet.clone();
+    packet.timestamp = now_secs();
+    packet.signature = sign_announcement(&packet);
+    match discovery_wire::encode(&packet) {
+        Ok(data) => {
+            if let Err(e) = socket.try_send_to(&data, addr) {
+                debug!("回复 {addr} 的查询失败: {e:?}");
+            }
+        }
+        Err(e) => error!("编码发现包失败: {e:?}"),
+    }
+}
+
+/// 数据包的来源地址，IPv6链路本地地址附带接口索引以便回连
+fn observed_address(addr: &SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(addr) if addr.scope_id() != 0 => format!("{}%{}", addr.ip(), addr.scope_id()),
+        _ => addr.ip().to_string(),
+    }
+}
+
+/// 使用本设备的签名私钥签名广播包，加密模块未初始化时不签名
+fn sign_announcement(packet: &DiscoveryPacket) -> Option<String> {
+    let manager = crypto::manager().ok()?;
+    Some(base64::encode(manager.sign(packet.signing_payload().as_bytes())))
+}
+
+/// 检查发现广播包的签名
+///
+/// 签名使用包内的签名公钥验证，公钥本身与固定公钥的比对由 [`record_device`] 完成，
+/// 因此伪造者无法冒用已知设备的公钥，换用自己的公钥则会触发密钥变更。
+/// 签名无效或时间戳超出有效窗口的包总是丢弃；已固定签名公钥的设备发送未签名的包视为降级攻击，
+/// 同样丢弃；其他未签名的包在 [`SecurityPolicy::AcceptPairedOnly`] 策略下丢弃，否则接受并标记为未验证。
+pub fn check_announcement(
+    packet: &DiscoveryPacket,
+    key_pins: &KeyPins,
+    policy: SecurityPolicy,
+    now: u64,
+) -> AnnouncementCheck {
+    let Some(signature) = &packet.signature else {
+        if policy == SecurityPolicy::AcceptPairedOnly || key_pins.pinned_verify_key(&packet.device_id).is_some() {
+            return AnnouncementCheck::Rejected;
+        }
+        return AnnouncementCheck::Unsigned;
+    };
+
+    if packet.timestamp + DEFAULT_REPLAY_WINDOW_SECS < now || packet.timestamp > now + DEFAULT_CLOCK_SKEW_SECS {
+        debug!("设备 {} 的广播包时间戳超出有效窗口", packet.device_id);
+        return AnnouncementCheck::Rejected;
+    }
+
+    let Ok(signature) = base64::decode(signature) else {
+        return AnnouncementCheck::Rejected;
+    };
+    let verified = crypto::manager()
+        .and_then(|manager| manager.verify(&signature, packet.signing_payload().as_bytes(), &packet.verify_key))
+        .unwrap_or(false);
+    if verified {
+        AnnouncementCheck::Verified
+    } else {
+        AnnouncementCheck::Rejected
+    }
+}
+
+/// 将超过 `timeout_secs` 未收到广播的在线设备标记为离线，返回状态发生变化的设备
+pub(crate) fn expire_devices(devices: &mut HashMap<String, DeviceInfo>, now: u64, timeout_secs: u64) -> Vec<DeviceInfo> {
+    devices
+        .values_mut()
+        .filter(|device| device.online && now.saturating_sub(device.last_seen.unwrap_or(0)) > timeout_secs)
+        .map(|device| {
+            device.online = false;
+            device.clone()
+        })
+        .collect()
+}
+
+/// 当前时间（Unix时间戳，秒）
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 根据配置生成对外公布的本地设备信息，包含发现命名空间的哈希
+pub(crate) fn local_device_info(config: &Config) -> Result<DeviceInfo> {
+    // 公布本设备的身份公钥，加密模块未初始化时（如测试环境）使用占位值
+    let (public_key, verify_key) = match crypto::manager() {
+        Ok(manager) => manager.get_public_keys(),
+        Err(_) => ("dummy_public_key_base64_encoded".to_string(), String::new()),
+    };
+
+    let mut local_device = DeviceInfo::new(&config.device_name, config.device_type, &public_key);
+    local_device.id = config.device_id.clone();
+    local_device.verify_key = verify_key;
+    local_device.namespace = NamespaceFilter::from_options(&config.options)?.id().map(str::to_string);
+    Ok(local_device)
+}
+
+/// 设备列表已满时淘汰最久未见的离线设备，返回是否有空位
+fn make_room(devices: &mut HashMap<String, DeviceInfo>, capacity: usize) -> bool {
+    if devices.len() < capacity {
+        return true;
+    }
+    let oldest = devices
+        .values()
+        .filter(|device| !device.online)
+        .min_by_key(|device| device.last_seen.unwrap_or(0))
+        .map(|device| device.id.clone());
+    match oldest {
+        Some(id) => {
+            devices.remove(&id);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 将发现的设备写入设备列表，返回是否应通知上层
+///
+/// 设备列表已满且没有可淘汰的离线设备时忽略新设备。只检查已固定的公钥，不固定新设备的公钥；
+/// 公钥与固定的公钥不一致时不覆盖已知设备，首次检测到变更时触发密钥变更回调。
+pub(crate) fn record_device(
+    devices: &Mutex<HashMap<String, DeviceInfo>>,
+    key_pins: &KeyPins,
+    key_change_callback: Option<&KeyChangeCallback>,
+    device: &DeviceInfo,
+) -> bool {
+    let has_room = match devices.lock() {
+        Ok(mut devices) => devices.contains_key(&device.id) || make_room(&mut devices, MAX_DISCOVERED_DEVICES),
+        Err(e) => {
+            error!("获取设备列表锁失败: {e:?}");
+            return false;
+        }
+    };
+    if !has_room {
+        warn!("设备列表已满，忽略新设备 {}", device.id);
+        return false;
+    }
+
+    match key_pins.check(device) {
+        Ok(PinCheck::Unpinned | PinCheck::Pinned | PinCheck::Matched) => {}
+        Ok(PinCheck::Changed(event)) => {
+            warn!("设备 {} 的公钥已变更", device.id);
+            if let Some(callback) = key_change_callback {
+                callback(&event);
+            }
+            return false;
+        }
+        Ok(PinCheck::Mismatch) => return false,
+        Err(e) => {
+            error!("检查设备 {} 的公钥失败: {e:?}", device.id);
+            return false;
+        }
+    }
+
+    match devices.lock() {
+        Ok(mut devices) => {
+            devices.insert(device.id.clone(), device.clone());
+            true
+        }
+        Err(e) => {
+            error!("获取设备列表锁失败: {e:?}");
+            false
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -266,4 +830,174 @@ mod tests {
         let discovery = DeviceDiscovery::new(&config);
         assert!(discovery.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_discovery_on_loopback() {
+        crypto::init();
+        let config = |device_id: &str, listen_port: u16| Config {
+            device_name: device_id.to_string(),
+            device_type: DeviceType::Desktop,
+            storage_path: ":memory:".to_string(),
+            device_id: device_id.to_string(),
+            discovery_port: 46500,
+            capabilities: crate::types::DeviceCapabilities::default(),
+            listen_port,
+            options: crate::types::ConfigOptions::default(),
+        };
+        let mut laptop = DeviceDiscovery::new(&config("udp-laptop", 46510)).unwrap();
+        let mut phone = DeviceDiscovery::new(&config("udp-phone", 46520)).unwrap();
+        for discovery in [&mut laptop, &mut phone] {
+            discovery.set_loopback(true);
+            discovery.set_announce_interval(Duration::from_millis(200));
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        laptop
+            .start(Box::new(move |device| {
+                let _ = tx.send(device);
+            }))
+            .await
+            .unwrap();
+        phone.start(Box::new(|_| {})).await.unwrap();
+
+        let found = time::timeout(Duration::from_secs(10), async {
+            loop {
+                let device = rx.recv().await.unwrap();
+                if device.id == "udp-phone" {
+                    return device;
+                }
+            }
+        })
+        .await
+        .expect("应在回环接口上收到对方的广播");
+
+        // 本机其他接口上的定向广播也可能先到达
+        assert!(found.ip_address.is_some());
+        assert_eq!(found.pairing_port, Some(46520));
+        assert_eq!(found.transfer_port, Some(46521));
+        assert!(found.announcement_verified);
+        assert!(laptop.get_device_by_id("udp-phone").is_some());
+        assert!(laptop.get_device_by_id("udp-laptop").is_none());
+
+        laptop.stop().await.unwrap();
+        phone.stop().await.unwrap();
+    }
+
+    #[test]
+    fn test_expire_devices() {
+        let mut devices = HashMap::new();
+        for (id, last_seen) in [("fresh", 100), ("stale", 80)] {
+            let mut device = DeviceInfo::new(id, DeviceType::Mobile, "key");
+            device.id = id.to_string();
+            device.last_seen = Some(last_seen);
+            devices.insert(device.id.clone(), device);
+        }
+
+        // 超过3次5秒的广播间隔未收到广播
+        let expired = expire_devices(&mut devices, 100, 15);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, "stale");
+        assert!(!expired[0].online);
+        assert!(devices["fresh"].online);
+
+        // 已离线的设备不重复通知
+        assert!(expire_devices(&mut devices, 100, 15).is_empty());
+    }
+
+    #[test]
+    fn test_make_room() {
+        let mut devices = HashMap::new();
+        for (id, online, last_seen) in [("a", true, 10), ("b", false, 30), ("c", false, 20)] {
+            let mut device = DeviceInfo::new(id, DeviceType::Mobile, "key");
+            device.id = id.to_string();
+            device.online = online;
+            device.last_seen = Some(last_seen);
+            devices.insert(device.id.clone(), device);
+        }
+
+        assert!(make_room(&mut devices, 4));
+        assert_eq!(devices.len(), 3);
+
+        // 淘汰最久未见的离线设备，在线设备不淘汰
+        assert!(make_room(&mut devices, 3));
+        assert!(!devices.contains_key("c"));
+        assert!(make_room(&mut devices, 2));
+        assert!(!devices.contains_key("b"));
+        assert!(!make_room(&mut devices, 1));
+        assert!(devices.contains_key("a"));
+    }
+
+    #[test]
+    fn test_directed_broadcast() {
+        let ip = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(directed_broadcast(ip, 24), Ipv4Addr::new(192, 168, 1, 255));
+        assert_eq!(directed_broadcast(ip, 32), ip);
+        assert_eq!(directed_broadcast(ip, 0), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn test_observed_address() {
+        let v4: SocketAddr = "192.168.1.20:45679".parse().unwrap();
+        assert_eq!(observed_address(&v4), "192.168.1.20");
+
+        let link_local = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 45679, 0, 3));
+        assert_eq!(observed_address(&link_local), "fe80::1%3");
+
+        // 带接口索引的地址可直接用于回连
+        let mut device = DeviceInfo::new("手机", DeviceType::Mobile, "key");
+        device.add_address(&observed_address(&link_local));
+        let addr: SocketAddr = device.socket_address(45680).unwrap().parse().unwrap();
+        assert_eq!(addr, SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 45680, 0, 3)));
+    }
+
+    #[test]
+    fn test_check_announcement() {
+        crypto::init();
+        let manager = crypto::manager().unwrap();
+        let (public_key, verify_key) = manager.get_public_keys();
+        let now = now_secs();
+        let mut packet = DiscoveryPacket {
+            r#type: "discovery".to_string(),
+            device_id: "announcer".to_string(),
+            device_name: "笔记本".to_string(),
+            public_key,
+            verify_key,
+            timestamp: now,
+            device_type: DeviceType::Desktop,
+            port: 45680,
+            transfer_port: Some(45681),
+            ip_address: None,
+            capabilities: crate::types::DeviceCapabilities::default(),
+            app_version: None,
+            system_version: None,
+            protocol_version: "1.0".to_string(),
+            signature: None,
+            namespace: None,
+        };
+        let key_pins = KeyPins::new();
+        let policy = SecurityPolicy::AlwaysAsk;
+
+        // 未签名的包按安全策略标记或丢弃
+        assert_eq!(check_announcement(&packet, &key_pins, policy, now), AnnouncementCheck::Unsigned);
+        assert_eq!(
+            check_announcement(&packet, &key_pins, SecurityPolicy::AcceptPairedOnly, now),
+            AnnouncementCheck::Rejected
+        );
+
+        packet.signature = Some(base64::encode(manager.sign(packet.signing_payload().as_bytes())));
+        assert_eq!(check_announcement(&packet, &key_pins, policy, now), AnnouncementCheck::Verified);
+        assert_eq!(check_announcement(&packet, &key_pins, policy, now + 3600), AnnouncementCheck::Rejected);
+
+        let mut forged = packet.clone();
+        forged.port = 9999;
+        assert_eq!(check_announcement(&forged, &key_pins, policy, now), AnnouncementCheck::Rejected);
+
+        // 已固定签名公钥的设备不能降级为未签名的包
+        let mut device = DeviceInfo::new(&packet.device_name, packet.device_type, &packet.public_key);
+        device.id = packet.device_id.clone();
+        device.verify_key = packet.verify_key.clone();
+        key_pins.pin(&device).unwrap();
+        packet.signature = None;
+        assert_eq!(check_announcement(&packet, &key_pins, policy, now), AnnouncementCheck::Rejected);
+    }
 }
@@ -0,0 +1,314 @@
+//! 设备发现事件流
+//!
+//! 各发现后端每收到一次广播都会上报完整的设备信息。本模块按设备ID去重，
+//! 只在设备首次出现、信息发生变化或离线时产生事件。同一设备的更新在防抖间隔内合并，
+//! 间隔结束后与上次发出的状态比较，只报告真正变化的字段。
+
+use crate::types::DeviceInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// 默认防抖间隔
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// 事件通道容量，订阅者处理过慢时会丢失最旧的事件
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// 最多跟踪的设备数量，与发现后端的设备列表上限一致
+pub const MAX_TRACKED_DEVICES: usize = crate::network::discovery::MAX_DISCOVERED_DEVICES;
+
+/// 设备信息中可变化的字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceField {
+    /// 设备名称
+    Name,
+    /// 设备类型
+    DeviceType,
+    /// 身份公钥
+    Keys,
+    /// IP地址
+    Address,
+    /// 配对或传输端口
+    Ports,
+    /// 设备能力
+    Capabilities,
+    /// 应用或系统版本
+    Version,
+}
+
+/// 设备发现事件
+///
+/// 序列化为 `{"type": "...", "data": ...}` 形式，便于前端按类型分发。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum DiscoveryEvent {
+    /// 发现新设备，或离线设备重新上线
+    Found(DeviceInfo),
+    /// 设备信息发生变化
+    Updated {
+        /// 最新的设备信息
+        device: DeviceInfo,
+        /// 变化的字段
+        changes: Vec<DeviceField>,
+    },
+    /// 设备离线
+    Lost(DeviceInfo),
+}
+
+impl DiscoveryEvent {
+    /// 事件对应的设备
+    pub fn device(&self) -> &DeviceInfo {
+        match self {
+            Self::Found(device) | Self::Lost(device) => device,
+            Self::Updated { device, .. } => device,
+        }
+    }
+}
+
+/// 单个设备的跟踪状态
+struct TrackedDevice {
+    /// 最近一次发出事件时的设备信息
+    emitted: DeviceInfo,
+    /// 最近一次观察到的设备信息
+    latest: DeviceInfo,
+    /// 最近一次发出事件的时间
+    emitted_at: Instant,
+    /// 是否在线
+    online: bool,
+}
+
+/// 设备发现事件流
+///
+/// 可在多个发现后端之间共享，克隆后指向同一份状态和同一个事件通道。
+#[derive(Clone)]
+pub struct DiscoveryEvents {
+    /// 设备ID -> 跟踪状态
+    devices: Arc<Mutex<HashMap<String, TrackedDevice>>>,
+    /// 事件发送端
+    sender: broadcast::Sender<DiscoveryEvent>,
+    /// 防抖间隔
+    debounce: Duration,
+}
+
+impl Default for DiscoveryEvents {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEBOUNCE)
+    }
+}
+
+impl DiscoveryEvents {
+    /// 创建事件流
+    pub fn new(debounce: Duration) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            devices: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+            debounce,
+        }
+    }
+
+    /// 订阅事件
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.sender.subscribe()
+    }
+
+    /// 上报发现的设备
+    pub fn observe(&self, device: &DeviceInfo) {
+        if let Some(event) = self.observe_at(device, Instant::now()) {
+            self.send(event);
+        }
+    }
+
+    /// 上报设备离线，设备未知或已离线时不产生事件
+    pub fn lost(&self, device_id: &str) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(tracked) = devices.get_mut(device_id) else {
+            return;
+        };
+        if !tracked.online {
+            return;
+        }
+
+        tracked.online = false;
+        tracked.emitted = tracked.latest.clone();
+        tracked.emitted.online = false;
+        tracked.emitted_at = Instant::now();
+        let event = DiscoveryEvent::Lost(tracked.emitted.clone());
+        drop(devices);
+
+        self.send(event);
+    }
+
+    /// 发出防抖间隔已结束的待发更新
+    ///
+    /// 发现后端应定期调用，确保不再重复广播的设备的最后一次变化也能送达。
+    pub fn flush(&self) {
+        for event in self.flush_at(Instant::now()) {
+            self.send(event);
+        }
+    }
+
+    /// 处理一次观察，返回需要发出的事件
+    ///
+    /// 跟踪的设备已满时淘汰最久未变化的离线设备，没有可淘汰的设备时忽略新设备。
+    fn observe_at(&self, device: &DeviceInfo, now: Instant) -> Option<DiscoveryEvent> {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(tracked) = devices.get_mut(&device.id) else {
+            if devices.len() >= MAX_TRACKED_DEVICES {
+                let oldest = devices
+                    .iter()
+                    .filter(|(_, tracked)| !tracked.online)
+                    .min_by_key(|(_, tracked)| tracked.emitted_at)
+                    .map(|(id, _)| id.clone())?;
+                devices.remove(&oldest);
+            }
+            devices.insert(
+                device.id.clone(),
+                TrackedDevice {
+                    emitted: device.clone(),
+                    latest: device.clone(),
+                    emitted_at: now,
+                    online: true,
+                },
+            );
+            return Some(DiscoveryEvent::Found(device.clone()));
+        };
+
+        tracked.latest = device.clone();
+        if !tracked.online {
+            tracked.online = true;
+            tracked.emitted = device.clone();
+            tracked.emitted_at = now;
+            return Some(DiscoveryEvent::Found(device.clone()));
+        }
+
+        Self::pending_update(tracked, now, self.debounce)
+    }
+
+    /// 收集所有待发的更新
+    fn flush_at(&self, now: Instant) -> Vec<DiscoveryEvent> {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        devices
+            .values_mut()
+            .filter(|tracked| tracked.online)
+            .filter_map(|tracked| Self::pending_update(tracked, now, self.debounce))
+            .collect()
+    }
+
+    /// 防抖间隔结束且信息有变化时生成更新事件
+    fn pending_update(tracked: &mut TrackedDevice, now: Instant, debounce: Duration) -> Option<DiscoveryEvent> {
+        if now.duration_since(tracked.emitted_at) < debounce {
+            return None;
+        }
+
+        let changes = changed_fields(&tracked.emitted, &tracked.latest);
+        if changes.is_empty() {
+            return None;
+        }
+
+        tracked.emitted = tracked.latest.clone();
+        tracked.emitted_at = now;
+        Some(DiscoveryEvent::Updated {
+            device: tracked.latest.clone(),
+            changes,
+        })
+    }
+
+    /// 发送事件，没有订阅者时丢弃
+    fn send(&self, event: DiscoveryEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+/// 比较两次设备信息，返回变化的字段
+fn changed_fields(old: &DeviceInfo, new: &DeviceInfo) -> Vec<DeviceField> {
+    let mut changes = Vec::new();
+    if old.name != new.name {
+        changes.push(DeviceField::Name);
+    }
+    if old.device_type != new.device_type {
+        changes.push(DeviceField::DeviceType);
+    }
+    if old.public_key != new.public_key || old.verify_key != new.verify_key {
+        changes.push(DeviceField::Keys);
+    }
+    if old.ip_address != new.ip_address || old.addresses != new.addresses {
+        changes.push(DeviceField::Address);
+    }
+    if old.pairing_port != new.pairing_port || old.transfer_port != new.transfer_port {
+        changes.push(DeviceField::Ports);
+    }
+    if old.capabilities != new.capabilities {
+        changes.push(DeviceField::Capabilities);
+    }
+    if old.app_version != new.app_version || old.system_version != new.system_version {
+        changes.push(DeviceField::Version);
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceType;
+
+    #[test]
+    fn test_events_deduplicated_and_debounced() {
+        let events = DiscoveryEvents::new(Duration::from_secs(2));
+        let start = Instant::now();
+        let mut phone = DeviceInfo::new("手机", DeviceType::Mobile, "key");
+        phone.ip_address = Some("192.168.1.20".to_string());
+
+        assert!(matches!(events.observe_at(&phone, start), Some(DiscoveryEvent::Found(_))));
+        // 重复广播不产生事件
+        assert!(events.observe_at(&phone, start + Duration::from_secs(5)).is_none());
+
+        // 防抖间隔内的多次变化合并为一次更新
+        let t = start + Duration::from_secs(6);
+        let mut renamed = phone.clone();
+        renamed.name = "我的手机".to_string();
+        let Some(DiscoveryEvent::Updated { changes, .. }) = events.observe_at(&renamed, t) else {
+            panic!("应产生更新事件");
+        };
+        assert_eq!(changes, vec![DeviceField::Name]);
+
+        renamed.ip_address = Some("192.168.1.21".to_string());
+        renamed.pairing_port = Some(45680);
+        assert!(events.observe_at(&renamed, t + Duration::from_millis(500)).is_none());
+        let flushed = events.flush_at(t + Duration::from_secs(3));
+        let [DiscoveryEvent::Updated { changes, device }] = flushed.as_slice() else {
+            panic!("应发出待发的更新");
+        };
+        assert_eq!(changes, &vec![DeviceField::Address, DeviceField::Ports]);
+        assert_eq!(device.name, "我的手机");
+        assert!(events.flush_at(t + Duration::from_secs(10)).is_empty());
+
+        // 离线后重新出现视为新发现
+        let mut receiver = events.subscribe();
+        events.lost(&phone.id);
+        events.lost(&phone.id);
+        assert!(matches!(receiver.try_recv(), Ok(DiscoveryEvent::Lost(d)) if !d.online));
+        assert!(receiver.try_recv().is_err());
+        assert!(matches!(
+            events.observe_at(&renamed, t + Duration::from_secs(11)),
+            Some(DiscoveryEvent::Found(_))
+        ));
+
+        // 跟踪数量有上限，满时淘汰离线设备，全部在线时忽略新设备
+        let full = DiscoveryEvents::new(Duration::from_secs(2));
+        let devices: Vec<_> = (0..MAX_TRACKED_DEVICES)
+            .map(|_| DeviceInfo::new("设备", DeviceType::Unknown, "key"))
+            .collect();
+        for device in &devices {
+            assert!(full.observe_at(device, start).is_some());
+        }
+        let stranger = DeviceInfo::new("陌生设备", DeviceType::Unknown, "key");
+        assert!(full.observe_at(&stranger, start).is_none());
+        full.lost(&devices[0].id);
+        assert!(full.observe_at(&stranger, start).is_some());
+        assert_eq!(full.devices.lock().unwrap().len(), MAX_TRACKED_DEVICES);
+    }
+}
@@ -0,0 +1,598 @@
+//! 统一的设备发现管理器
+//!
+//! 管理 UDP 广播、mDNS、BLE 和手动添加等发现来源，按设备ID合并各来源的结果，
+//! 并记录设备在哪些来源上可达。各来源可在运行时单独启用或停用，
+//! 设备在所有来源上都不可达时才视为离线。无法自动发现的设备可通过地址或邀请码手动添加，
+//! 手动添加的设备保存在存储中，重启后自动恢复。
+
+use crate::{
+    error::{Error, Result},
+    network::{
+        ble_discovery::BleDiscovery,
+        discovery::{DeviceDiscovery, DEFAULT_PROBE_TIMEOUT, MAX_DISCOVERED_DEVICES},
+        discovery_events::{DiscoveryEvent, DiscoveryEvents},
+        invite::Invite,
+        key_pinning::{KeyChangeCallback, KeyPins, PinCheck},
+        mdns_discovery::MdnsDiscovery,
+    },
+    storage::{ManualDeviceRecord, Storage},
+    types::{Config, DeviceInfo},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// 设备发现来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DiscoverySource {
+    /// UDP广播
+    Broadcast,
+    /// mDNS/DNS-SD
+    Mdns,
+    /// 蓝牙低功耗
+    Ble,
+    /// 用户手动添加
+    Manual,
+}
+
+/// 设备在某个来源上的可达性
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceReachability {
+    /// 发现来源
+    pub source: DiscoverySource,
+    /// 当前是否可达
+    pub reachable: bool,
+    /// 该来源报告的地址
+    pub address: Option<String>,
+    /// 最后一次从该来源发现的时间（Unix时间戳，秒）
+    pub last_seen: u64,
+}
+
+/// 合并后的设备
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredDevice {
+    /// 设备信息
+    pub device: DeviceInfo,
+    /// 各来源的可达性
+    pub sources: Vec<SourceReachability>,
+}
+
+impl DiscoveredDevice {
+    /// 设备是否可通过指定来源到达
+    pub fn is_reachable_via(&self, source: DiscoverySource) -> bool {
+        self.sources.iter().any(|s| s.source == source && s.reachable)
+    }
+}
+
+/// 各来源共享的合并状态
+struct MergedDevices {
+    /// 设备ID -> 合并后的设备
+    devices: Mutex<HashMap<String, DiscoveredDevice>>,
+    /// 去重后的发现事件流
+    events: DiscoveryEvents,
+}
+
+impl MergedDevices {
+    /// 合并某个来源发现的设备
+    ///
+    /// 设备数量达到上限时淘汰最久未见的离线设备，没有可淘汰的设备时忽略新设备。
+    /// 未经签名验证的发现结果不能替换已知设备的公钥。
+    fn merge(&self, source: DiscoverySource, device: DeviceInfo) {
+        let now = now_secs();
+        let address = device.ip_address.clone();
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(known) = devices.get(&device.id) {
+            let keys_changed = known.device.public_key != device.public_key
+                || (!known.device.verify_key.is_empty()
+                    && !device.verify_key.is_empty()
+                    && known.device.verify_key != device.verify_key);
+            if keys_changed && !device.announcement_verified {
+                warn!("忽略 {source:?} 发现的设备 {} 的未验证公钥变更", device.id);
+                return;
+            }
+        }
+        if !devices.contains_key(&device.id) && devices.len() >= MAX_DISCOVERED_DEVICES {
+            let oldest = devices
+                .values()
+                .filter(|merged| !merged.device.online)
+                .min_by_key(|merged| merged.device.last_seen.unwrap_or(0))
+                .map(|merged| merged.device.id.clone());
+            match oldest {
+                Some(id) => {
+                    devices.remove(&id);
+                }
+                None => {
+                    warn!("设备数量已达上限，忽略 {source:?} 发现的新设备 {}", device.id);
+                    return;
+                }
+            }
+        }
+        let merged = devices.entry(device.id.clone()).or_insert_with(|| DiscoveredDevice {
+            device: device.clone(),
+            sources: Vec::new(),
+        });
+
+        // BLE等来源不提供地址和端口时保留其他来源的值
+        let previous = std::mem::replace(&mut merged.device, device);
+        let current = &mut merged.device;
+        let observed = std::mem::replace(&mut current.addresses, previous.addresses);
+        for address in &observed {
+            current.add_address(address);
+        }
+        current.ip_address = address.clone().or(previous.ip_address);
+        current.pairing_port = current.pairing_port.or(previous.pairing_port);
+        current.transfer_port = current.transfer_port.or(previous.transfer_port);
+        current.online = true;
+        current.last_seen = Some(now);
+
+        match merged.sources.iter_mut().find(|s| s.source == source) {
+            Some(reachability) => {
+                reachability.reachable = true;
+                reachability.address = address;
+                reachability.last_seen = now;
+            }
+            None => merged.sources.push(SourceReachability {
+                source,
+                reachable: true,
+                address,
+                last_seen: now,
+            }),
+        }
+
+        let device = merged.device.clone();
+        drop(devices);
+        self.events.observe(&device);
+        self.events.flush();
+    }
+
+    /// 标记设备在某个来源上不可达，所有来源都不可达时设备离线
+    fn mark_unreachable(&self, source: DiscoverySource, device_id: &str) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(merged) = devices.get_mut(device_id) else {
+            return;
+        };
+
+        for reachability in merged.sources.iter_mut().filter(|s| s.source == source) {
+            reachability.reachable = false;
+        }
+        if merged.device.online && !merged.sources.iter().any(|s| s.reachable) {
+            merged.device.online = false;
+            drop(devices);
+            self.events.lost(device_id);
+        }
+    }
+
+    /// 标记所有设备在某个来源上不可达
+    fn mark_source_unreachable(&self, source: DiscoverySource) {
+        let device_ids: Vec<String> = {
+            let devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+            devices
+                .values()
+                .filter(|d| d.is_reachable_via(source))
+                .map(|d| d.device.id.clone())
+                .collect()
+        };
+        for device_id in device_ids {
+            self.mark_unreachable(source, &device_id);
+        }
+    }
+}
+
+/// 统一的设备发现管理器
+pub struct DiscoveryManager {
+    /// 配置信息
+    config: Config,
+    /// 合并状态
+    merged: Arc<MergedDevices>,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 密钥变更回调
+    key_change_callback: Option<Arc<KeyChangeCallback>>,
+    /// UDP广播发现，启用时存在
+    broadcast: Option<DeviceDiscovery>,
+    /// mDNS发现，启用时存在
+    mdns: Option<MdnsDiscovery>,
+    /// BLE发现，启用时存在
+    ble: Option<BleDiscovery>,
+    /// 保存手动添加设备的存储，未设置时仅保存在内存中
+    storage: Option<Arc<Storage>>,
+}
+
+impl DiscoveryManager {
+    /// 创建设备发现管理器，不启用任何来源
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            merged: Arc::new(MergedDevices {
+                devices: Mutex::new(HashMap::new()),
+                events: DiscoveryEvents::default(),
+            }),
+            key_pins: KeyPins::new(),
+            key_change_callback: None,
+            broadcast: None,
+            mdns: None,
+            ble: None,
+            storage: None,
+        }
+    }
+
+    /// 设置存储，手动添加的设备将被保存并在启动时恢复
+    pub fn set_storage(&mut self, storage: Arc<Storage>) {
+        self.storage = Some(storage);
+    }
+
+    /// 设置共享的设备公钥固定表，对之后启用的来源生效
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置密钥变更回调，对之后启用的来源生效
+    pub fn set_key_change_callback(&mut self, callback: KeyChangeCallback) {
+        self.key_change_callback = Some(Arc::new(callback));
+    }
+
+    /// 订阅合并去重后的设备发现事件
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.merged.events.subscribe()
+    }
+
+    /// 恢复手动添加的设备，并按配置启用各发现来源
+    ///
+    /// BLE不可用时仅记录日志，不影响其他来源。
+    pub async fn start(&mut self) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            for record in storage.get_manual_devices()? {
+                // 恢复的设备同样需要与固定的公钥一致
+                match self.check_pin(&record.device, false) {
+                    Ok(()) => self.merged.merge(DiscoverySource::Manual, record.device),
+                    Err(e) => warn!("恢复手动添加的设备 {} 失败: {e:?}", record.device.id),
+                }
+            }
+        }
+
+        let backend = self.config.options.discovery_backend;
+        if backend.uses_broadcast() {
+            self.enable(DiscoverySource::Broadcast).await?;
+        }
+        if backend.uses_mdns() {
+            if let Err(e) = self.enable(DiscoverySource::Mdns).await {
+                warn!("mDNS设备发现启动失败: {e:?}");
+            }
+        }
+        if let Err(e) = self.enable(DiscoverySource::Ble).await {
+            info!("BLE设备发现不可用: {e:?}");
+        }
+
+        Ok(())
+    }
+
+    /// 停用所有发现来源
+    pub async fn stop(&mut self) -> Result<()> {
+        for source in [DiscoverySource::Broadcast, DiscoverySource::Mdns, DiscoverySource::Ble] {
+            self.disable(source).await?;
+        }
+        Ok(())
+    }
+
+    /// 来源是否已启用，手动添加始终可用
+    pub fn is_enabled(&self, source: DiscoverySource) -> bool {
+        match source {
+            DiscoverySource::Broadcast => self.broadcast.is_some(),
+            DiscoverySource::Mdns => self.mdns.is_some(),
+            DiscoverySource::Ble => self.ble.is_some(),
+            DiscoverySource::Manual => true,
+        }
+    }
+
+    /// 启用发现来源，已启用时不做任何操作
+    pub async fn enable(&mut self, source: DiscoverySource) -> Result<()> {
+        if self.is_enabled(source) {
+            return Ok(());
+        }
+
+        match source {
+            DiscoverySource::Broadcast => {
+                let mut discovery = DeviceDiscovery::new(&self.config)?;
+                discovery.set_key_pins(self.key_pins.clone());
+                if let Some(callback) = self.forward_key_change() {
+                    discovery.set_key_change_callback(callback);
+                }
+                let merged = self.merged.clone();
+                discovery.set_status_callback(Box::new(move |device| {
+                    if !device.online {
+                        merged.mark_unreachable(DiscoverySource::Broadcast, &device.id);
+                    }
+                }));
+                discovery.start(self.merge_callback(source)).await?;
+                self.broadcast = Some(discovery);
+            }
+            DiscoverySource::Mdns => {
+                let mut discovery = MdnsDiscovery::new(&self.config)?;
+                discovery.set_key_pins(self.key_pins.clone());
+                if let Some(callback) = self.forward_key_change() {
+                    discovery.set_key_change_callback(callback);
+                }
+                let merged = self.merged.clone();
+                discovery.set_status_callback(Box::new(move |device| {
+                    if !device.online {
+                        merged.mark_unreachable(DiscoverySource::Mdns, &device.id);
+                    }
+                }));
+                discovery.start(self.merge_callback(source)).await?;
+                self.mdns = Some(discovery);
+            }
+            DiscoverySource::Ble => {
+                let mut discovery = BleDiscovery::new(&self.config).await?;
+                discovery.set_key_pins(self.key_pins.clone());
+                if let Some(callback) = self.forward_key_change() {
+                    discovery.set_key_change_callback(callback);
+                }
+                let merged = self.merged.clone();
+                discovery.set_status_callback(Box::new(move |device| {
+                    if !device.online {
+                        merged.mark_unreachable(DiscoverySource::Ble, &device.id);
+                    }
+                }));
+                discovery.start(self.merge_callback(source)).await?;
+                self.ble = Some(discovery);
+            }
+            DiscoverySource::Manual => {}
+        }
+        info!("已启用设备发现来源: {source:?}");
+
+        Ok(())
+    }
+
+    /// 停用发现来源，仅通过该来源可达的设备随之离线
+    pub async fn disable(&mut self, source: DiscoverySource) -> Result<()> {
+        let stopped = match source {
+            DiscoverySource::Broadcast => match self.broadcast.take() {
+                Some(mut discovery) => discovery.stop().await.map(|_| true),
+                None => Ok(false),
+            },
+            DiscoverySource::Mdns => match self.mdns.take() {
+                Some(mut discovery) => discovery.stop().await.map(|_| true),
+                None => Ok(false),
+            },
+            DiscoverySource::Ble => match self.ble.take() {
+                Some(mut discovery) => discovery.stop().await.map(|_| true),
+                None => Ok(false),
+            },
+            DiscoverySource::Manual => {
+                return Err(Error::InvalidArgument("手动添加的来源不能停用".to_string()))
+            }
+        }?;
+
+        if stopped {
+            self.merged.mark_source_unreachable(source);
+            info!("已停用设备发现来源: {source:?}");
+        }
+        Ok(())
+    }
+
+    /// 按地址手动添加设备
+    ///
+    /// 地址格式为 `host:port`，省略端口时使用配置的发现端口。向该地址发送查询，
+    /// 对方以签名的广播包回复后添加。
+    pub async fn add_by_address(&self, address: &str) -> Result<DeviceInfo> {
+        let addr = resolve_address(address, self.config.discovery_port).await?;
+        let device = self.prober()?.probe(addr, DEFAULT_PROBE_TIMEOUT).await?;
+        self.add_manual(device.clone())?;
+        Ok(device)
+    }
+
+    /// 按邀请码手动添加设备
+    ///
+    /// 依次探测邀请码中的地址，应答设备的身份须与邀请码一致。
+    pub async fn add_by_invite(&self, code: &str) -> Result<DeviceInfo> {
+        let invite = Invite::decode(code)?;
+        let prober = self.prober()?;
+        for address in &invite.addresses {
+            let addr = match resolve_address(address, self.config.discovery_port).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    warn!("邀请码中的地址 {address} 无效: {e:?}");
+                    continue;
+                }
+            };
+            match prober.probe(addr, DEFAULT_PROBE_TIMEOUT).await {
+                Ok(device) => {
+                    invite.verify(&device)?;
+                    self.add_manual(device.clone())?;
+                    return Ok(device);
+                }
+                Err(e) => info!("探测 {addr} 失败: {e:?}"),
+            }
+        }
+
+        Err(Error::Network(format!("无法连接到邀请码中的设备 {}", invite.device_name)))
+    }
+
+    /// 删除手动添加的设备，仍可通过其他来源到达的设备保持在线
+    pub fn remove_manual(&self, device_id: &str) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.delete_manual_device(device_id)?;
+        }
+        self.merged.mark_unreachable(DiscoverySource::Manual, device_id);
+        Ok(())
+    }
+
+    /// 手动添加设备
+    ///
+    /// 首次添加的设备固定其公钥，与固定的公钥不一致时拒绝添加。设置了存储时同时保存。
+    pub fn add_manual(&self, device: DeviceInfo) -> Result<()> {
+        self.check_pin(&device, true)?;
+
+        if let Some(storage) = &self.storage {
+            storage.save_manual_device(&ManualDeviceRecord {
+                device: device.clone(),
+                added_at: now_secs(),
+            })?;
+        }
+        self.merged.merge(DiscoverySource::Manual, device);
+        Ok(())
+    }
+
+    /// 检查设备公钥与固定的公钥一致，`pin_new` 为true时固定未知设备的公钥
+    fn check_pin(&self, device: &DeviceInfo, pin_new: bool) -> Result<()> {
+        let check = if pin_new {
+            self.key_pins.pin(device)?
+        } else {
+            self.key_pins.check(device)?
+        };
+
+        match check {
+            PinCheck::Unpinned | PinCheck::Pinned | PinCheck::Matched => Ok(()),
+            PinCheck::Changed(event) => {
+                if let Some(callback) = &self.key_change_callback {
+                    callback(&event);
+                }
+                Err(Error::Authentication(format!("设备 {} 的公钥已变更，需重新验证", device.id)))
+            }
+            PinCheck::Mismatch => {
+                Err(Error::Authentication(format!("设备 {} 的公钥已变更，需重新验证", device.id)))
+            }
+        }
+    }
+
+    /// 获取合并后的设备列表
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        let devices = self.merged.devices.lock().unwrap_or_else(|e| e.into_inner());
+        devices.values().cloned().collect()
+    }
+
+    /// 通过ID获取合并后的设备
+    pub fn device(&self, device_id: &str) -> Option<DiscoveredDevice> {
+        let devices = self.merged.devices.lock().unwrap_or_else(|e| e.into_inner());
+        devices.get(device_id).cloned()
+    }
+
+    /// 生成将某个来源的发现结果合并到设备列表的回调
+    fn merge_callback(&self, source: DiscoverySource) -> Box<dyn Fn(DeviceInfo) + Send + Sync + 'static> {
+        let merged = self.merged.clone();
+        Box::new(move |device| merged.merge(source, device))
+    }
+
+    /// 创建用于单播探测的发现服务，与已启用的UDP广播来源相互独立
+    fn prober(&self) -> Result<DeviceDiscovery> {
+        let mut prober = DeviceDiscovery::new(&self.config)?;
+        prober.set_key_pins(self.key_pins.clone());
+        Ok(prober)
+    }
+
+    /// 将密钥变更回调转交给各来源
+    fn forward_key_change(&self) -> Option<KeyChangeCallback> {
+        let callback = self.key_change_callback.clone()?;
+        Some(Box::new(move |event| callback(event)))
+    }
+}
+
+/// 解析手动输入的地址，支持IP地址、`host:port` 和 `[IPv6]:port`，省略端口时使用 `default_port`
+async fn resolve_address(address: &str, default_port: u16) -> Result<SocketAddr> {
+    let address = address.trim();
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| Error::InvalidArgument(format!("无效的端口: {address}")))?;
+            (host, port)
+        }
+        None => (address, default_port),
+    };
+    if host.is_empty() {
+        return Err(Error::InvalidArgument(format!("无效的地址: {address}")));
+    }
+
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| Error::Network(format!("解析地址 {address} 失败: {e}")))?
+        .next()
+        .ok_or_else(|| Error::Network(format!("地址 {address} 没有可用的IP")))
+}
+
+/// 当前时间（Unix时间戳，秒）
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceType;
+
+    #[test]
+    fn test_merge_tracks_reachability_per_source() {
+        let merged = MergedDevices {
+            devices: Mutex::new(HashMap::new()),
+            events: DiscoveryEvents::default(),
+        };
+        let mut receiver = merged.events.subscribe();
+
+        let mut over_wifi = DeviceInfo::new("手机", DeviceType::Mobile, "key");
+        over_wifi.ip_address = Some("192.168.1.20".to_string());
+        over_wifi.pairing_port = Some(45680);
+        let mut over_ble = over_wifi.clone();
+        over_ble.ip_address = None;
+        over_ble.pairing_port = None;
+
+        merged.merge(DiscoverySource::Broadcast, over_wifi.clone());
+        merged.merge(DiscoverySource::Ble, over_ble.clone());
+
+        // 未经签名验证的来源不能替换已知公钥
+        let mut impostor = over_ble;
+        impostor.public_key = "impostor_key".to_string();
+        merged.merge(DiscoverySource::Ble, impostor);
+
+        let device = merged.devices.lock().unwrap()[&over_wifi.id].clone();
+        assert_eq!(device.device.public_key, "key");
+        assert_eq!(device.sources.len(), 2);
+        assert!(device.is_reachable_via(DiscoverySource::Ble));
+        // BLE不提供地址，保留UDP广播得到的地址
+        assert_eq!(device.device.ip_address.as_deref(), Some("192.168.1.20"));
+        assert_eq!(device.device.pairing_port, Some(45680));
+
+        // 仍可通过BLE到达时不视为离线
+        merged.mark_source_unreachable(DiscoverySource::Broadcast);
+        let device = merged.devices.lock().unwrap()[&over_wifi.id].clone();
+        assert!(!device.is_reachable_via(DiscoverySource::Broadcast));
+        assert!(device.device.online);
+
+        merged.mark_unreachable(DiscoverySource::Ble, &over_wifi.id);
+        assert!(!merged.devices.lock().unwrap()[&over_wifi.id].device.online);
+
+        assert!(matches!(receiver.try_recv(), Ok(DiscoveryEvent::Found(_))));
+        assert!(matches!(receiver.try_recv(), Ok(DiscoveryEvent::Lost(_))));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_address() {
+        let default_port = 45678;
+        assert_eq!(resolve_address("192.168.1.20:5000", default_port).await.unwrap(), "192.168.1.20:5000".parse().unwrap());
+        assert_eq!(
+            resolve_address(" 192.168.1.20 ", default_port).await.unwrap(),
+            SocketAddr::new("192.168.1.20".parse().unwrap(), default_port)
+        );
+        assert_eq!(resolve_address("[2001:db8::1]:5000", default_port).await.unwrap(), "[2001:db8::1]:5000".parse().unwrap());
+        assert_eq!(
+            resolve_address("2001:db8::1", default_port).await.unwrap(),
+            SocketAddr::new("2001:db8::1".parse().unwrap(), default_port)
+        );
+        assert_eq!(resolve_address("localhost:5000", default_port).await.unwrap().port(), 5000);
+        assert!(resolve_address("localhost:port", default_port).await.is_err());
+        assert!(resolve_address(":5000", default_port).await.is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! 发现包的线上格式
+//!
+//! 发现包以 2 字节魔数和 1 字节格式版本开头，后接使用短字段名的 JSON。整个包不超过
+//! [`MAX_PACKET_SIZE`]，在 IPv6 最小 MTU 内无需分片；接收方用更大的缓冲区接收，
+//! 以识别超长（会被截断）的包。为兼容旧版本，仍接受不带包头的完整 JSON 发现包。
+
+use crate::{
+    error::{Error, Result},
+    types::{DeviceCapabilities, DeviceType, DiscoveryPacket},
+};
+use serde::{Deserialize, Serialize};
+
+/// 包头魔数
+pub const WIRE_MAGIC: [u8; 2] = *b"PA";
+
+/// 当前格式版本
+pub const WIRE_VERSION: u8 = 1;
+
+/// 发现包最大字节数（IPv6 最小 MTU 1280 减去 IP 和 UDP 头）
+pub const MAX_PACKET_SIZE: usize = 1232;
+
+/// 接收缓冲区大小，比最大包长多一个字节，收满即说明包被截断
+pub const RECV_BUFFER_SIZE: usize = MAX_PACKET_SIZE + 1;
+
+/// 设备名称最大字符数
+pub const MAX_NAME_CHARS: usize = 64;
+
+/// 设备ID、公钥、签名等标识字段的最大字节数
+const MAX_FIELD_LEN: usize = 128;
+
+/// 包头长度
+const HEADER_LEN: usize = WIRE_MAGIC.len() + 1;
+
+/// 紧凑格式的发现包
+///
+/// 省略接收方不使用的 `ip_address`，默认值和空值不写出。
+#[derive(Serialize, Deserialize)]
+struct WirePacket {
+    #[serde(rename = "t")]
+    packet_type: String,
+    #[serde(rename = "i")]
+    device_id: String,
+    #[serde(rename = "n")]
+    device_name: String,
+    #[serde(rename = "k")]
+    public_key: String,
+    #[serde(rename = "v", default, skip_serializing_if = "String::is_empty")]
+    verify_key: String,
+    #[serde(rename = "ts")]
+    timestamp: u64,
+    #[serde(rename = "d")]
+    device_type: DeviceType,
+    #[serde(rename = "p")]
+    port: u16,
+    #[serde(rename = "tp", default, skip_serializing_if = "Option::is_none")]
+    transfer_port: Option<u16>,
+    #[serde(rename = "c", default, skip_serializing_if = "is_default_capabilities")]
+    capabilities: DeviceCapabilities,
+    #[serde(rename = "av", default, skip_serializing_if = "Option::is_none")]
+    app_version: Option<String>,
+    #[serde(rename = "sv", default, skip_serializing_if = "Option::is_none")]
+    system_version: Option<String>,
+    #[serde(rename = "pv")]
+    protocol_version: String,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+    #[serde(rename = "ns", default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+fn is_default_capabilities(capabilities: &DeviceCapabilities) -> bool {
+    *capabilities == DeviceCapabilities::default()
+}
+
+impl From<&DiscoveryPacket> for WirePacket {
+    fn from(packet: &DiscoveryPacket) -> Self {
+        Self {
+            packet_type: packet.r#type.clone(),
+            device_id: packet.device_id.clone(),
+            device_name: packet.device_name.clone(),
+            public_key: packet.public_key.clone(),
+            verify_key: packet.verify_key.clone(),
+            timestamp: packet.timestamp,
+            device_type: packet.device_type,
+            port: packet.port,
+            transfer_port: packet.transfer_port,
+            capabilities: packet.capabilities,
+            app_version: packet.app_version.clone(),
+            system_version: packet.system_version.clone(),
+            protocol_version: packet.protocol_version.clone(),
+            signature: packet.signature.clone(),
+            namespace: packet.namespace.clone(),
+        }
+    }
+}
+
+impl From<WirePacket> for DiscoveryPacket {
+    fn from(packet: WirePacket) -> Self {
+        Self {
+            r#type: packet.packet_type,
+            device_id: packet.device_id,
+            device_name: packet.device_name,
+            public_key: packet.public_key,
+            verify_key: packet.verify_key,
+            timestamp: packet.timestamp,
+            device_type: packet.device_type,
+            port: packet.port,
+            transfer_port: packet.transfer_port,
+            ip_address: None,
+            capabilities: packet.capabilities,
+            app_version: packet.app_version,
+            system_version: packet.system_version,
+            protocol_version: packet.protocol_version,
+            signature: packet.signature,
+            namespace: packet.namespace,
+        }
+    }
+}
+
+/// 将设备名称截断到 [`MAX_NAME_CHARS`] 个字符，需在签名前调用
+pub fn truncate_name(name: &str) -> String {
+    name.chars().take(MAX_NAME_CHARS).collect()
+}
+
+/// 编码发现包，字段超出限制或编码后超过最大包长时返回错误
+pub fn encode(packet: &DiscoveryPacket) -> Result<Vec<u8>> {
+    validate(packet)?;
+    let mut data = Vec::with_capacity(MAX_PACKET_SIZE);
+    data.extend_from_slice(&WIRE_MAGIC);
+    data.push(WIRE_VERSION);
+    serde_json::to_writer(&mut data, &WirePacket::from(packet))?;
+    if data.len() > MAX_PACKET_SIZE {
+        return Err(Error::Discovery(format!("发现包长度 {} 超过上限 {MAX_PACKET_SIZE}", data.len())));
+    }
+    Ok(data)
+}
+
+/// 解码发现包
+///
+/// 拒绝超长、魔数或版本不符以及字段超出限制的包；以 `{` 开头的视为旧版本的 JSON 发现包。
+pub fn decode(data: &[u8]) -> Result<DiscoveryPacket> {
+    if data.len() > MAX_PACKET_SIZE {
+        return Err(Error::Discovery(format!("发现包长度 {} 超过上限 {MAX_PACKET_SIZE}", data.len())));
+    }
+
+    let packet = if data.first() == Some(&b'{') {
+        serde_json::from_slice::<DiscoveryPacket>(data)?
+    } else {
+        if data.len() < HEADER_LEN || data[..WIRE_MAGIC.len()] != WIRE_MAGIC {
+            return Err(Error::Discovery("不是发现包".to_string()));
+        }
+        let version = data[WIRE_MAGIC.len()];
+        if version != WIRE_VERSION {
+            return Err(Error::Discovery(format!("不支持的发现包格式版本: {version}")));
+        }
+        serde_json::from_slice::<WirePacket>(&data[HEADER_LEN..])?.into()
+    };
+
+    validate(&packet)?;
+    Ok(packet)
+}
+
+/// 检查各字段长度
+fn validate(packet: &DiscoveryPacket) -> Result<()> {
+    if packet.device_id.is_empty() {
+        return Err(Error::Discovery("发现包缺少设备ID".to_string()));
+    }
+    if packet.device_name.chars().count() > MAX_NAME_CHARS {
+        return Err(Error::Discovery(format!("设备 {} 的名称过长", packet.device_id)));
+    }
+
+    let fields = [
+        Some(&packet.r#type),
+        Some(&packet.device_id),
+        Some(&packet.public_key),
+        Some(&packet.verify_key),
+        Some(&packet.protocol_version),
+        packet.app_version.as_ref(),
+        packet.system_version.as_ref(),
+        packet.signature.as_ref(),
+        packet.namespace.as_ref(),
+    ];
+    if fields.into_iter().flatten().any(|field| field.len() > MAX_FIELD_LEN) {
+        return Err(Error::Discovery(format!("设备 {} 的发现包字段过长", packet.device_id)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet() -> DiscoveryPacket {
+        DiscoveryPacket {
+            r#type: "discovery".to_string(),
+            device_id: "device-1".to_string(),
+            device_name: "客厅的电脑".to_string(),
+            public_key: "a".repeat(44),
+            verify_key: "b".repeat(44),
+            timestamp: 1_700_000_000,
+            device_type: DeviceType::Desktop,
+            port: 45680,
+            transfer_port: Some(45681),
+            ip_address: Some("192.168.1.20".to_string()),
+            capabilities: DeviceCapabilities::default(),
+            app_version: Some("0.1.0".to_string()),
+            system_version: None,
+            protocol_version: "1.0".to_string(),
+            signature: Some("c".repeat(88)),
+            namespace: Some("d".repeat(22)),
+        }
+    }
+
+    #[test]
+    fn test_wire_round_trip() {
+        let packet = packet();
+        let data = encode(&packet).unwrap();
+        assert_eq!(&data[..HEADER_LEN], b"PA\x01");
+        assert!(data.len() < serde_json::to_vec(&packet).unwrap().len());
+
+        // 签名内容在编解码后保持不变，接收方不使用包内声明的地址
+        let decoded = decode(&data).unwrap();
+        assert_eq!(decoded.signing_payload(), packet.signing_payload());
+        assert_eq!(decoded.signature, packet.signature);
+        assert_eq!(decoded.namespace, packet.namespace);
+        assert_eq!(decoded.ip_address, None);
+
+        // 旧版本的 JSON 发现包
+        let legacy = decode(&serde_json::to_vec(&packet).unwrap()).unwrap();
+        assert_eq!(legacy.signing_payload(), packet.signing_payload());
+
+        // 名称截断按字符计算
+        let mut long_name = packet.clone();
+        long_name.device_name = "名".repeat(MAX_NAME_CHARS + 1);
+        assert!(encode(&long_name).is_err());
+        long_name.device_name = truncate_name(&long_name.device_name);
+        assert_eq!(long_name.device_name.chars().count(), MAX_NAME_CHARS);
+        assert!(encode(&long_name).is_ok());
+
+        let mut data = data;
+        data[WIRE_MAGIC.len()] = WIRE_VERSION + 1;
+        assert!(decode(&data).is_err());
+        assert!(decode(b"PA").is_err());
+        assert!(decode(&vec![b'{'; MAX_PACKET_SIZE + 1]).is_err());
+
+        let mut oversized = packet;
+        oversized.app_version = Some("x".repeat(MAX_FIELD_LEN + 1));
+        assert!(encode(&oversized).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! 网络接口枚举与过滤
+//!
+//! 装有 Docker 网桥、VPN 或多块网卡的机器上，只应在可信的网络中公布本设备。
+//! 本模块枚举本机网络接口，并按配置中的接口名称和网段（CIDR）允许或禁止列表筛选，
+//! 同时用于判断收到的发现包是否来自允许的网络。
+
+use crate::{
+    error::{Error, Result},
+    types::InterfaceFilter,
+};
+use log::warn;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+/// IP网段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    /// 网络地址
+    network: IpAddr,
+    /// 前缀长度
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// 创建网段，前缀长度超出地址位数时返回错误
+    pub fn new(network: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(Error::InvalidArgument(format!("前缀长度 {prefix_len} 超出范围")));
+        }
+        Ok(Self { network, prefix_len })
+    }
+
+    /// 网段是否包含指定地址，地址族不同时不包含
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(u128::from(u32::from(network)), u128::from(u32::from(ip)), 32, self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(network), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    /// 解析 `地址/前缀长度` 形式的网段，省略前缀长度时表示单个地址
+    fn from_str(s: &str) -> Result<Self> {
+        let (ip, prefix_len) = match s.split_once('/') {
+            Some((ip, prefix_len)) => (ip, Some(prefix_len)),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(ip.trim())
+            .map_err(|_| Error::InvalidArgument(format!("无效的网段: {s}")))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse()
+                .map_err(|_| Error::InvalidArgument(format!("无效的网段: {s}")))?,
+            None if network.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(network, prefix_len)
+    }
+}
+
+/// 比较两个地址的前 `prefix_len` 位
+fn prefix_matches(a: u128, b: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix_len);
+    (a >> shift) == (b >> shift)
+}
+
+/// 本机网络接口上的一个地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInterface {
+    /// 接口名称
+    pub name: String,
+    /// 接口索引，用于IPv6作用域
+    pub index: Option<u32>,
+    /// 接口地址
+    pub ip: IpAddr,
+    /// 子网前缀长度
+    pub prefix_len: u8,
+    /// IPv4子网定向广播地址
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+impl NetworkInterface {
+    /// 接口所在的子网
+    pub fn subnet(&self) -> Cidr {
+        Cidr {
+            network: self.ip,
+            prefix_len: self.prefix_len,
+        }
+    }
+}
+
+/// 枚举本机的非回环网络接口地址
+pub fn enumerate() -> Vec<NetworkInterface> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            warn!("枚举网络接口失败: {e:?}");
+            return Vec::new();
+        }
+    };
+
+    interfaces
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .map(|interface| {
+            let (prefix_len, broadcast) = match &interface.addr {
+                if_addrs::IfAddr::V4(addr) => (addr.prefixlen, addr.broadcast),
+                if_addrs::IfAddr::V6(addr) => (addr.prefixlen, None),
+            };
+            NetworkInterface {
+                ip: interface.ip(),
+                name: interface.name,
+                index: interface.index,
+                prefix_len,
+                broadcast,
+            }
+        })
+        .collect()
+}
+
+/// 单条过滤规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    /// 接口名称，以 `*` 结尾时按前缀匹配
+    Name(String),
+    /// 网段
+    Network(Cidr),
+}
+
+impl Rule {
+    /// 解析规则，能解析为地址或网段的视为网段，否则视为接口名称
+    fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(Error::InvalidArgument("接口过滤规则不能为空".to_string()));
+        }
+        if s.contains('/') || IpAddr::from_str(s).is_ok() {
+            return Cidr::from_str(s).map(Self::Network);
+        }
+        Ok(Self::Name(s.to_string()))
+    }
+
+    /// 规则是否匹配接口
+    fn matches_interface(&self, interface: &NetworkInterface) -> bool {
+        match self {
+            Self::Name(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => interface.name.starts_with(prefix),
+                None => interface.name == *pattern,
+            },
+            Self::Network(cidr) => cidr.contains(interface.ip),
+        }
+    }
+}
+
+/// 编译后的接口过滤规则
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceRules {
+    /// 允许列表，为空时允许所有未被禁止的接口
+    allow: Vec<Rule>,
+    /// 禁止列表，优先于允许列表
+    deny: Vec<Rule>,
+}
+
+impl InterfaceRules {
+    /// 从配置编译过滤规则，规则格式无效时返回错误
+    pub fn new(filter: &InterfaceFilter) -> Result<Self> {
+        Ok(Self {
+            allow: filter.allow.iter().map(|s| Rule::parse(s)).collect::<Result<_>>()?,
+            deny: filter.deny.iter().map(|s| Rule::parse(s)).collect::<Result<_>>()?,
+        })
+    }
+
+    /// 是否没有任何规则
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// 接口是否允许用于设备发现
+    pub fn allows_interface(&self, interface: &NetworkInterface) -> bool {
+        if self.deny.iter().any(|rule| rule.matches_interface(interface)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches_interface(interface))
+    }
+
+    /// 枚举允许用于设备发现的接口
+    pub fn allowed_interfaces(&self) -> Vec<NetworkInterface> {
+        enumerate().into_iter().filter(|i| self.allows_interface(i)).collect()
+    }
+
+    /// 来源地址是否属于允许的网络
+    ///
+    /// 没有任何规则时接受所有来源。否则来源不能落在禁止的网段内，
+    /// 且需落在允许的网段内，或位于某个允许接口的子网中；
+    /// IPv6链路本地来源按接收接口的索引判断。
+    pub fn allows_source(&self, source: &SocketAddr, interfaces: &[NetworkInterface]) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let ip = source.ip();
+        let in_network = |rule: &Rule| matches!(rule, Rule::Network(cidr) if cidr.contains(ip));
+        if self.deny.iter().any(in_network) {
+            return false;
+        }
+        if self.allow.iter().any(in_network) {
+            return true;
+        }
+
+        match source {
+            SocketAddr::V6(addr) if addr.scope_id() != 0 => interfaces
+                .iter()
+                .any(|i| i.ip.is_ipv6() && i.index == Some(addr.scope_id())),
+            _ => interfaces.iter().any(|i| i.subnet().contains(ip)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, ip: &str, prefix_len: u8, index: u32) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            index: Some(index),
+            ip: ip.parse().unwrap(),
+            prefix_len,
+            broadcast: None,
+        }
+    }
+
+    #[test]
+    fn test_interface_rules() {
+        let lan = interface("eth0", "192.168.1.10", 24, 2);
+        let docker = interface("docker0", "172.17.0.1", 16, 3);
+        let vpn = interface("tun0", "10.8.0.2", 24, 4);
+        let lan_v6 = interface("eth0", "fe80::1", 64, 2);
+
+        let rules = InterfaceRules::new(&InterfaceFilter {
+            allow: Vec::new(),
+            deny: vec!["docker*".to_string(), "10.0.0.0/8".to_string()],
+        })
+        .unwrap();
+        assert!(rules.allows_interface(&lan));
+        assert!(!rules.allows_interface(&docker));
+        assert!(!rules.allows_interface(&vpn));
+
+        // 只接受来自允许接口子网的发现包
+        let allowed = [lan.clone(), lan_v6];
+        assert!(rules.allows_source(&"192.168.1.20:45679".parse().unwrap(), &allowed));
+        assert!(!rules.allows_source(&"172.17.0.5:45679".parse().unwrap(), &allowed));
+        assert!(!rules.allows_source(&"10.8.0.9:45679".parse().unwrap(), &allowed));
+        assert!(rules.allows_source(&"[fe80::2%2]:45679".parse().unwrap(), &allowed));
+        assert!(!rules.allows_source(&"[fe80::2%3]:45679".parse().unwrap(), &allowed));
+
+        let rules = InterfaceRules::new(&InterfaceFilter {
+            allow: vec!["tun0".to_string(), "10.9.0.0/16".to_string()],
+            deny: Vec::new(),
+        })
+        .unwrap();
+        assert!(!rules.allows_interface(&lan));
+        assert!(rules.allows_interface(&vpn));
+        assert!(rules.allows_source(&"10.9.3.4:45679".parse().unwrap(), &[vpn]));
+
+        assert!(InterfaceRules::new(&InterfaceFilter {
+            allow: vec!["10.0.0.0/40".to_string()],
+            deny: Vec::new(),
+        })
+        .is_err());
+        assert!(InterfaceRules::default().allows_source(&"8.8.8.8:45679".parse().unwrap(), &[]));
+    }
+}
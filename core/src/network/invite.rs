@@ -0,0 +1,137 @@
+//! 设备邀请码
+//!
+//! 隔离的 VLAN、手机热点或防火墙后的设备无法通过广播发现。邀请码携带设备ID、
+//! 身份公钥的短标识和可尝试连接的地址，对方粘贴后逐个地址探测，
+//! 并用密钥标识确认应答的是邀请码所属的设备，而不是同一网络中的冒充者。
+
+use crate::{
+    crypto,
+    error::{Error, Result},
+    network::interfaces::NetworkInterface,
+    types::DeviceInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// 邀请码前缀，包含格式版本
+pub const INVITE_PREFIX: &str = "pasteall1:";
+
+/// 设备邀请
+///
+/// 序列化时使用单字母字段名以缩短邀请码。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invite {
+    /// 设备ID
+    #[serde(rename = "i")]
+    pub device_id: String,
+    /// 设备名称
+    #[serde(rename = "n")]
+    pub device_name: String,
+    /// 身份公钥的短标识，见 [`crypto::key_id`]
+    #[serde(rename = "k")]
+    pub key_id: String,
+    /// 可尝试探测的地址（`host:port`）
+    #[serde(rename = "a")]
+    pub addresses: Vec<String>,
+}
+
+impl Invite {
+    /// 为本设备生成邀请
+    ///
+    /// 地址取自允许的网络接口，跳过仅在本机有意义的IPv6链路本地地址。
+    pub fn new(device: &DeviceInfo, interfaces: &[NetworkInterface], port: u16) -> Result<Self> {
+        let addresses = interfaces
+            .iter()
+            .filter(|interface| !is_ipv6_link_local(interface.ip))
+            .map(|interface| SocketAddr::new(interface.ip, port).to_string())
+            .collect();
+
+        Ok(Self {
+            device_id: device.id.clone(),
+            device_name: device.name.clone(),
+            key_id: crypto::key_id(device)?,
+            addresses,
+        })
+    }
+
+    /// 编码为邀请码
+    pub fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(self)?;
+        Ok(format!("{INVITE_PREFIX}{}", base64::encode_config(json, base64::URL_SAFE_NO_PAD)))
+    }
+
+    /// 解析邀请码，忽略首尾空白
+    pub fn decode(code: &str) -> Result<Self> {
+        let invalid = || Error::InvalidArgument("无效的邀请码".to_string());
+        let payload = code.trim().strip_prefix(INVITE_PREFIX).ok_or_else(invalid)?;
+        let json = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let invite: Self = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if invite.device_id.is_empty() || invite.addresses.is_empty() {
+            return Err(invalid());
+        }
+        Ok(invite)
+    }
+
+    /// 确认探测到的设备就是邀请码所属的设备
+    pub fn verify(&self, device: &DeviceInfo) -> Result<()> {
+        if device.id != self.device_id || crypto::key_id(device)? != self.key_id {
+            return Err(Error::Authentication(format!(
+                "设备 {} 的身份与邀请码不一致",
+                device.id
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 是否为IPv6链路本地地址（fe80::/10）
+fn is_ipv6_link_local(ip: IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(ip) if (ip.segments()[0] & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+    use crate::types::DeviceType;
+
+    fn device() -> DeviceInfo {
+        let (public_key, verify_key) = CryptoManager::new().get_public_keys();
+        let mut device = DeviceInfo::new("笔记本", DeviceType::Desktop, &public_key);
+        device.verify_key = verify_key;
+        device
+    }
+
+    fn interface(ip: &str) -> NetworkInterface {
+        NetworkInterface {
+            name: "eth0".to_string(),
+            index: Some(2),
+            ip: ip.parse().unwrap(),
+            prefix_len: 24,
+            broadcast: None,
+        }
+    }
+
+    #[test]
+    fn test_invite_round_trip() {
+        crypto::init();
+        let laptop = device();
+        let interfaces = [interface("192.168.1.10"), interface("fe80::1"), interface("2001:db8::10")];
+        let invite = Invite::new(&laptop, &interfaces, 45679).unwrap();
+        assert_eq!(invite.addresses, vec!["192.168.1.10:45679", "[2001:db8::10]:45679"]);
+
+        let code = invite.encode().unwrap();
+        assert!(code.starts_with(INVITE_PREFIX));
+        let decoded = Invite::decode(&format!("  {code}\n")).unwrap();
+        assert_eq!(decoded, invite);
+        decoded.verify(&laptop).unwrap();
+
+        // 同一设备ID但公钥不同的冒充者
+        let mut impostor = device();
+        impostor.id = laptop.id.clone();
+        assert!(decoded.verify(&impostor).is_err());
+
+        assert!(Invite::decode("pasteall1:not-base64!").is_err());
+        assert!(Invite::decode(&code.replacen(INVITE_PREFIX, "other:", 1)).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+//! 设备公钥固定（首次使用信任）
+//!
+//! 设备配对、手动添加或通过邀请码验证时记录其身份公钥；仅被发现的陌生设备不会被固定，
+//! 避免伪造的广播无限占用存储。此后同一设备ID携带不同公钥出现时不会覆盖记录，
+//! 而是产生密钥变更事件，并阻止与该设备同步，直到用户重新比对指纹并确认新的公钥。
+//!
+//! 设备主动轮换身份密钥时，使用旧签名私钥签名的轮换通知告知已配对设备新的公钥，
+//! 对方用固定的旧签名公钥验证后直接固定新公钥，无需重新比对指纹。
+
+use crate::{
+    crypto,
+    error::{Error, Result},
+    storage::{PinnedKeyRecord, Storage},
+    types::{
+        DeviceInfo, Message, MessageType, Notification, NotificationAction, NotificationPriority,
+        NotificationType,
+    },
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// 密钥变更回调函数类型
+pub type KeyChangeCallback = Box<dyn Fn(&KeyChangeEvent) + Send + Sync + 'static>;
+
+/// 密钥轮换回调函数类型
+pub type KeyRotationCallback = Box<dyn Fn(&KeyRotation) + Send + Sync + 'static>;
+
+/// 轮换通知签名内容的域分隔标识
+const ROTATION_DOMAIN: &str = "PasteAll-KeyRotation-v1";
+
+/// 已接受的身份密钥轮换
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRotation {
+    /// 设备ID
+    pub device_id: String,
+    /// 新的加密公钥
+    pub public_key: String,
+    /// 新的签名公钥
+    pub verify_key: String,
+}
+
+/// 公钥检查结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinCheck {
+    /// 设备尚未固定公钥
+    Unpinned,
+    /// 首次出现，已固定公钥
+    Pinned,
+    /// 与固定的公钥一致
+    Matched,
+    /// 检测到新的公钥变更
+    Changed(KeyChangeEvent),
+    /// 与固定的公钥不一致，且该变更此前已报告
+    Mismatch,
+}
+
+/// 密钥变更安全事件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyChangeEvent {
+    /// 设备ID
+    pub device_id: String,
+    /// 设备名称
+    pub device_name: String,
+    /// 固定的加密公钥
+    pub pinned_public_key: String,
+    /// 新出现的加密公钥
+    pub new_public_key: String,
+    /// 检测时间（Unix时间戳，秒）
+    pub detected_at: u64,
+}
+
+impl KeyChangeEvent {
+    /// 生成提示用户重新验证设备的通知
+    pub fn notification(&self) -> Notification {
+        Notification::new(
+            NotificationType::SecurityWarning,
+            "设备密钥已变更",
+            &format!(
+                "设备 {} 的身份密钥与首次配对时不一致，已暂停同步。请重新比对安全码后确认该设备。",
+                self.device_name
+            ),
+            NotificationPriority::Urgent,
+        )
+        .with_device_id(&self.device_id)
+        .with_action(NotificationAction::view_details("重新验证", "key_change", &self.device_id))
+    }
+}
+
+/// 公钥固定表
+///
+/// 可在多个服务之间共享，克隆后指向同一份记录。
+#[derive(Clone, Default)]
+pub struct KeyPins {
+    /// 设备ID -> 固定记录
+    pins: Arc<RwLock<HashMap<String, PinnedKeyRecord>>>,
+    /// 持久化存储，未设置时仅保存在内存中
+    storage: Option<Arc<Storage>>,
+}
+
+impl KeyPins {
+    /// 创建仅保存在内存中的公钥固定表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从存储加载公钥固定表，此后的变化同时写入存储
+    pub fn load(storage: Arc<Storage>) -> Result<Self> {
+        let pins = storage
+            .get_pinned_keys()?
+            .into_iter()
+            .map(|record| (record.device_id.clone(), record))
+            .collect();
+
+        Ok(Self {
+            pins: Arc::new(RwLock::new(pins)),
+            storage: Some(storage),
+        })
+    }
+
+    /// 检查设备公钥，不固定未知设备
+    pub fn check(&self, device: &DeviceInfo) -> Result<PinCheck> {
+        self.check_or_pin(device, false)
+    }
+
+    /// 检查设备公钥，未知设备固定其公钥
+    ///
+    /// 仅在配对、手动添加或验证邀请码时调用。
+    pub fn pin(&self, device: &DeviceInfo) -> Result<PinCheck> {
+        self.check_or_pin(device, true)
+    }
+
+    /// 检查设备公钥，`pin_new` 为true时固定未知设备
+    fn check_or_pin(&self, device: &DeviceInfo, pin_new: bool) -> Result<PinCheck> {
+        let mut pins = self.pins.write().unwrap_or_else(|e| e.into_inner());
+        let Some(pin) = pins.get_mut(&device.id) else {
+            if !pin_new {
+                return Ok(PinCheck::Unpinned);
+            }
+            let record = PinnedKeyRecord {
+                device_id: device.id.clone(),
+                public_key: device.public_key.clone(),
+                verify_key: device.verify_key.clone(),
+                pinned_at: now_secs(),
+                changed_public_key: None,
+                changed_verify_key: None,
+            };
+            self.persist(&record)?;
+            pins.insert(device.id.clone(), record);
+            return Ok(PinCheck::Pinned);
+        };
+
+        if keys_match(&pin.public_key, &pin.verify_key, device) {
+            // 旧版本设备升级后补充签名公钥
+            if pin.verify_key.is_empty() && !device.verify_key.is_empty() {
+                let mut updated = pin.clone();
+                updated.verify_key = device.verify_key.clone();
+                self.persist(&updated)?;
+                *pin = updated;
+            }
+            return Ok(PinCheck::Matched);
+        }
+
+        // 同一变更重复出现时不再报告
+        if let Some(changed) = &pin.changed_public_key {
+            let changed_verify_key = pin.changed_verify_key.as_deref().unwrap_or_default();
+            if keys_match(changed, changed_verify_key, device) {
+                return Ok(PinCheck::Mismatch);
+            }
+        }
+
+        let mut updated = pin.clone();
+        updated.changed_public_key = Some(device.public_key.clone());
+        updated.changed_verify_key = Some(device.verify_key.clone());
+        self.persist(&updated)?;
+        *pin = updated;
+        warn!("设备 {} 的公钥与固定的公钥不一致，已阻止同步", device.id);
+
+        Ok(PinCheck::Changed(KeyChangeEvent {
+            device_id: device.id.clone(),
+            device_name: device.name.clone(),
+            pinned_public_key: pin.public_key.clone(),
+            new_public_key: device.public_key.clone(),
+            detected_at: now_secs(),
+        }))
+    }
+
+    /// 设备是否因公钥变更被阻止同步
+    pub fn is_blocked(&self, device_id: &str) -> bool {
+        let pins = self.pins.read().unwrap_or_else(|e| e.into_inner());
+        pins.get(device_id)
+            .is_some_and(|pin| pin.changed_public_key.is_some())
+    }
+
+    /// 获取固定的公钥 (加密公钥, 签名公钥)
+    pub fn pinned_keys(&self, device_id: &str) -> Option<(String, String)> {
+        let pins = self.pins.read().unwrap_or_else(|e| e.into_inner());
+        pins.get(device_id)
+            .map(|pin| (pin.public_key.clone(), pin.verify_key.clone()))
+    }
+
+    /// 获取固定的签名公钥，设备未固定或旧版本设备没有签名公钥时返回None
+    pub fn pinned_verify_key(&self, device_id: &str) -> Option<String> {
+        let pins = self.pins.read().unwrap_or_else(|e| e.into_inner());
+        pins.get(device_id)
+            .map(|pin| pin.verify_key.clone())
+            .filter(|key| !key.is_empty())
+    }
+
+    /// 获取待确认的新公钥 (加密公钥, 签名公钥)
+    pub fn changed_keys(&self, device_id: &str) -> Option<(String, String)> {
+        let pins = self.pins.read().unwrap_or_else(|e| e.into_inner());
+        let pin = pins.get(device_id)?;
+        Some((
+            pin.changed_public_key.clone()?,
+            pin.changed_verify_key.clone().unwrap_or_default(),
+        ))
+    }
+
+    /// 用户重新验证后固定新的公钥并恢复同步，返回是否存在待确认的变更
+    pub fn accept_change(&self, device_id: &str) -> Result<bool> {
+        let mut pins = self.pins.write().unwrap_or_else(|e| e.into_inner());
+        let Some(pin) = pins.get_mut(device_id) else {
+            return Ok(false);
+        };
+        let Some(public_key) = pin.changed_public_key.clone() else {
+            return Ok(false);
+        };
+
+        let updated = PinnedKeyRecord {
+            device_id: device_id.to_string(),
+            public_key,
+            verify_key: pin.changed_verify_key.clone().unwrap_or_default(),
+            pinned_at: now_secs(),
+            changed_public_key: None,
+            changed_verify_key: None,
+        };
+        self.persist(&updated)?;
+        *pin = updated;
+        info!("已确认设备 {device_id} 的新公钥");
+
+        Ok(true)
+    }
+
+    /// 处理已配对设备发来的身份密钥轮换通知
+    ///
+    /// `sender_id` 为会话认证的发送方。通知必须由固定的旧签名公钥签名，验证通过后固定新公钥，
+    /// 返回已接受的轮换；未固定公钥的设备发来的通知被忽略。
+    pub fn apply_rotation(&self, sender_id: &str, message: &Message) -> Result<Option<KeyRotation>> {
+        let MessageType::KeyRotation { public_key, verify_key, signature } = &message.message_type else {
+            return Ok(None);
+        };
+        if message.sender_id != sender_id || public_key.is_empty() || verify_key.is_empty() {
+            warn!("忽略来自 {sender_id} 的无效密钥轮换通知");
+            return Ok(None);
+        }
+
+        let mut pins = self.pins.write().unwrap_or_else(|e| e.into_inner());
+        let Some(pin) = pins.get_mut(sender_id) else {
+            warn!("忽略未固定公钥的设备 {sender_id} 的密钥轮换通知");
+            return Ok(None);
+        };
+        if pin.verify_key.is_empty() {
+            return Err(Error::Authentication(format!("设备 {sender_id} 没有固定的签名公钥，无法验证密钥轮换")));
+        }
+
+        let payload = rotation_payload(sender_id, public_key, verify_key, message.timestamp);
+        if !crypto::verify_signature(&payload, signature, &pin.verify_key)? {
+            return Err(Error::Authentication(format!("设备 {sender_id} 的密钥轮换通知签名无效")));
+        }
+
+        let updated = PinnedKeyRecord {
+            device_id: sender_id.to_string(),
+            public_key: public_key.clone(),
+            verify_key: verify_key.clone(),
+            pinned_at: now_secs(),
+            changed_public_key: None,
+            changed_verify_key: None,
+        };
+        self.persist(&updated)?;
+        *pin = updated;
+        info!("设备 {sender_id} 已轮换身份密钥");
+
+        Ok(Some(KeyRotation {
+            device_id: sender_id.to_string(),
+            public_key: public_key.clone(),
+            verify_key: verify_key.clone(),
+        }))
+    }
+
+    /// 写入存储
+    fn persist(&self, record: &PinnedKeyRecord) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.save_pinned_key(record)?;
+        }
+        Ok(())
+    }
+}
+
+/// 创建发给 `receiver_id` 的身份密钥轮换通知
+///
+/// 使用当前的签名私钥签名，必须在新密钥生效前调用。
+pub fn rotation_notice(local_device_id: &str, public_key: &str, verify_key: &str, receiver_id: &str) -> Result<Message> {
+    let mut message = Message::new(
+        local_device_id,
+        MessageType::KeyRotation {
+            public_key: public_key.to_string(),
+            verify_key: verify_key.to_string(),
+            signature: String::new(),
+        },
+        false,
+        Some(receiver_id),
+    );
+    let payload = rotation_payload(local_device_id, public_key, verify_key, message.timestamp);
+    if let MessageType::KeyRotation { signature, .. } = &mut message.message_type {
+        *signature = crypto::sign(&payload)?;
+    }
+
+    Ok(message)
+}
+
+/// 轮换通知的签名内容
+fn rotation_payload(device_id: &str, public_key: &str, verify_key: &str, timestamp: u64) -> String {
+    format!("{ROTATION_DOMAIN}|{device_id}|{public_key}|{verify_key}|{timestamp}")
+}
+
+/// 设备公钥是否与给定公钥一致，任一方没有签名公钥时只比较加密公钥
+fn keys_match(public_key: &str, verify_key: &str, device: &DeviceInfo) -> bool {
+    public_key == device.public_key
+        && (verify_key.is_empty() || device.verify_key.is_empty() || verify_key == device.verify_key)
+}
+
+/// 当前时间（Unix时间戳，秒）
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceType;
+
+    #[test]
+    fn test_key_change_blocks_until_accepted() {
+        let storage = Arc::new(Storage::new(":memory:").unwrap());
+        let pins = KeyPins::load(storage.clone()).unwrap();
+        let phone = DeviceInfo::new("手机", DeviceType::Mobile, "original_key");
+
+        // 仅被发现的设备不会被固定
+        assert_eq!(pins.check(&phone).unwrap(), PinCheck::Unpinned);
+        assert!(storage.get_pinned_keys().unwrap().is_empty());
+
+        assert_eq!(pins.pin(&phone).unwrap(), PinCheck::Pinned);
+        assert_eq!(pins.check(&phone).unwrap(), PinCheck::Matched);
+        assert!(!pins.is_blocked(&phone.id));
+
+        let mut impostor = phone.clone();
+        impostor.public_key = "impostor_key".to_string();
+        let PinCheck::Changed(event) = pins.check(&impostor).unwrap() else {
+            panic!("应检测到公钥变更");
+        };
+        assert_eq!(event.pinned_public_key, "original_key");
+        assert_eq!(event.notification().notification_type, NotificationType::SecurityWarning);
+        assert!(pins.is_blocked(&phone.id));
+
+        // 重复出现不再报告，原公钥出现也不解除阻止
+        assert_eq!(pins.check(&impostor).unwrap(), PinCheck::Mismatch);
+        assert_eq!(pins.check(&phone).unwrap(), PinCheck::Matched);
+        assert!(pins.is_blocked(&phone.id));
+
+        // 阻止状态在重新加载后依然有效
+        let reloaded = KeyPins::load(storage).unwrap();
+        assert!(reloaded.is_blocked(&phone.id));
+        assert_eq!(reloaded.changed_keys(&phone.id).unwrap().0, "impostor_key");
+
+        assert!(reloaded.accept_change(&phone.id).unwrap());
+        assert!(!reloaded.is_blocked(&phone.id));
+        assert_eq!(reloaded.check(&impostor).unwrap(), PinCheck::Matched);
+        assert!(!reloaded.accept_change(&phone.id).unwrap());
+    }
+
+    #[test]
+    fn test_signed_key_rotation() {
+        crypto::init();
+        let pins = KeyPins::new();
+        let mut laptop = DeviceInfo::new("电脑", DeviceType::Desktop, &crypto::get_public_key().unwrap());
+        laptop.verify_key = crypto::get_verify_key().unwrap();
+        pins.pin(&laptop).unwrap();
+
+        let rotated = crypto::CryptoManager::new();
+        let (public_key, verify_key) = rotated.get_public_keys();
+        let notice = rotation_notice(&laptop.id, &public_key, &verify_key, "peer").unwrap();
+
+        // 只接受会话发送方自己的通知
+        assert_eq!(pins.apply_rotation("other", &notice).unwrap(), None);
+
+        // 篡改新公钥后签名无效
+        let mut tampered = notice.clone();
+        if let MessageType::KeyRotation { public_key, .. } = &mut tampered.message_type {
+            *public_key = crypto::CryptoManager::new().get_public_key_base64();
+        }
+        assert!(pins.apply_rotation(&laptop.id, &tampered).is_err());
+
+        let rotation = pins.apply_rotation(&laptop.id, &notice).unwrap().unwrap();
+        assert_eq!(rotation.public_key, public_key);
+        let mut rekeyed = laptop.clone();
+        rekeyed.public_key = public_key;
+        rekeyed.verify_key = verify_key;
+        assert_eq!(pins.check(&rekeyed).unwrap(), PinCheck::Matched);
+        assert!(!pins.is_blocked(&laptop.id));
+
+        // 旧签名公钥已不再固定，重放的通知无法通过验证
+        assert!(pins.apply_rotation(&laptop.id, &notice).is_err());
+    }
+}
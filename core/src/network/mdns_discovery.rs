@@ -0,0 +1,361 @@
+//! 基于 mDNS/DNS-SD 的设备发现
+//!
+//! 许多企业网络和访客网络会屏蔽 255.255.255.255 广播，但通常允许 mDNS，
+//! 部署了 mDNS 反射器的网络还可以跨越 VLAN。本模块以 `_pasteall._tcp` 服务类型
+//! 公布本设备，并在 TXT 记录中携带设备ID、类型、协议版本、端口和发现命名空间的哈希，
+//! 解析出的设备与 UDP 广播发现的设备使用相同的 [`DeviceInfo`] 回调。
+
+use crate::{
+    error::{Error, Result},
+    network::{
+        discovery::{local_device_info, record_device, DeviceDiscoveryCallback, DeviceStatusCallback},
+        discovery_events::{DiscoveryEvent, DiscoveryEvents},
+        interfaces::InterfaceRules,
+        key_pinning::{KeyChangeCallback, KeyPins},
+        namespace::NamespaceFilter,
+    },
+    types::{Config, DeviceInfo, DeviceType},
+};
+use log::{debug, error, info, warn};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// DNS-SD 服务类型
+pub const MDNS_SERVICE_TYPE: &str = "_pasteall._tcp.local.";
+
+/// 公布的协议版本
+const PROTOCOL_VERSION: &str = "1.0";
+
+/// TXT 记录键名
+mod txt {
+    pub const DEVICE_ID: &str = "id";
+    pub const DEVICE_NAME: &str = "name";
+    pub const DEVICE_TYPE: &str = "type";
+    pub const PROTOCOL: &str = "proto";
+    pub const PAIRING_PORT: &str = "port";
+    pub const TRANSFER_PORT: &str = "tport";
+    pub const PUBLIC_KEY: &str = "pk";
+    pub const VERIFY_KEY: &str = "vk";
+    pub const NAMESPACE: &str = "ns";
+}
+
+/// mDNS 设备发现服务
+pub struct MdnsDiscovery {
+    /// 本地设备信息
+    local_device: DeviceInfo,
+    /// 发现的设备列表
+    devices: Arc<Mutex<HashMap<String, DeviceInfo>>>,
+    /// mDNS 守护进程，运行时存在
+    daemon: Option<ServiceDaemon>,
+    /// 配对监听端口
+    pairing_port: u16,
+    /// 文件传输端口
+    transfer_port: u16,
+    /// 是否在回环接口上公布和发现，用于本机测试
+    loopback: bool,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 密钥变更回调
+    key_change_callback: Option<Arc<KeyChangeCallback>>,
+    /// 设备发现事件流
+    events: DiscoveryEvents,
+    /// 设备离线回调
+    status_callback: Option<Arc<DeviceStatusCallback>>,
+    /// 网络接口过滤规则
+    interface_rules: InterfaceRules,
+    /// 发现命名空间筛选器
+    namespace: NamespaceFilter,
+}
+
+impl MdnsDiscovery {
+    /// 创建新的 mDNS 设备发现服务
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            local_device: local_device_info(config)?,
+            devices: Arc::new(Mutex::new(HashMap::new())),
+            daemon: None,
+            pairing_port: config.listen_port,
+            transfer_port: config.listen_port + 1,
+            loopback: false,
+            key_pins: KeyPins::new(),
+            key_change_callback: None,
+            events: DiscoveryEvents::default(),
+            status_callback: None,
+            interface_rules: InterfaceRules::new(&config.options.discovery_interfaces)?,
+            namespace: NamespaceFilter::from_options(&config.options)?,
+        })
+    }
+
+    /// 设置是否启用回环接口（默认关闭）
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled;
+    }
+
+    /// 设置共享的设备公钥固定表
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置密钥变更回调
+    pub fn set_key_change_callback(&mut self, callback: KeyChangeCallback) {
+        self.key_change_callback = Some(Arc::new(callback));
+    }
+
+    /// 设置共享的设备发现事件流
+    pub fn set_discovery_events(&mut self, events: DiscoveryEvents) {
+        self.events = events;
+    }
+
+    /// 设置设备离线回调，服务撤销公布时触发
+    pub fn set_status_callback(&mut self, callback: DeviceStatusCallback) {
+        self.status_callback = Some(Arc::new(callback));
+    }
+
+    /// 订阅去重后的设备发现事件
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    /// 开始公布本设备并发现其他设备
+    pub async fn start(&mut self, callback: DeviceDiscoveryCallback) -> Result<()> {
+        if self.daemon.is_some() {
+            warn!("mDNS设备发现服务已经在运行中");
+            return Ok(());
+        }
+
+        info!("开始mDNS设备发现服务");
+
+        let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+        if self.loopback {
+            daemon.enable_interface(IfKind::LoopbackV4).map_err(mdns_error)?;
+        }
+        // 配置了接口过滤时只在允许的接口上公布和发现
+        if !self.interface_rules.is_empty() {
+            daemon.disable_interface(IfKind::All).map_err(mdns_error)?;
+            let allowed: Vec<IfKind> = self
+                .interface_rules
+                .allowed_interfaces()
+                .into_iter()
+                .map(|interface| IfKind::Addr(interface.ip))
+                .collect();
+            if !allowed.is_empty() {
+                daemon.enable_interface(allowed).map_err(mdns_error)?;
+            }
+        }
+        daemon.register(self.service_info()?).map_err(mdns_error)?;
+        let receiver = daemon.browse(MDNS_SERVICE_TYPE).map_err(mdns_error)?;
+
+        let devices = self.devices.clone();
+        let local_device_id = self.local_device.id.clone();
+        let key_pins = self.key_pins.clone();
+        let key_change_callback = self.key_change_callback.clone();
+        let events = self.events.clone();
+        let status_callback = self.status_callback.clone();
+        let namespace = self.namespace.clone();
+
+        // 守护进程关闭后通道断开，任务随之结束
+        tokio::spawn(async move {
+            while let Ok(event) = receiver.recv_async().await {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let Some(device) = device_from_service(&info) else {
+                            debug!("忽略无效的mDNS服务: {}", info.get_fullname());
+                            continue;
+                        };
+                        if device.id == local_device_id {
+                            continue;
+                        }
+                        if !namespace.allows(device.namespace.as_deref()) {
+                            debug!("忽略其他命名空间的设备 {}", device.id);
+                            continue;
+                        }
+
+                        if record_device(&devices, &key_pins, key_change_callback.as_deref(), &device) {
+                            events.observe(&device);
+                            callback(device);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        let device_id = fullname.strip_suffix(MDNS_SERVICE_TYPE).unwrap_or(&fullname);
+                        let device_id = device_id.trim_end_matches('.');
+                        let removed = devices.lock().ok().and_then(|mut devices| {
+                            let device = devices.get_mut(device_id)?;
+                            device.online = false;
+                            Some(device.clone())
+                        });
+                        events.lost(device_id);
+                        if let (Some(device), Some(callback)) = (removed, &status_callback) {
+                            callback(device);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            info!("停止mDNS设备发现");
+        });
+
+        self.daemon = Some(daemon);
+        Ok(())
+    }
+
+    /// 停止 mDNS 设备发现服务，同时撤销本设备的服务公布
+    pub async fn stop(&mut self) -> Result<()> {
+        if let Some(daemon) = self.daemon.take() {
+            if let Err(e) = daemon.shutdown() {
+                error!("停止mDNS守护进程失败: {e:?}");
+                return Err(Error::Discovery("停止mDNS设备发现服务失败".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 获取发现的设备列表
+    pub fn get_devices(&self) -> Vec<DeviceInfo> {
+        match self.devices.lock() {
+            Ok(devices) => devices.values().cloned().collect(),
+            Err(e) => {
+                error!("获取设备列表锁失败: {e:?}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// 通过ID获取设备
+    pub fn get_device_by_id(&self, device_id: &str) -> Option<DeviceInfo> {
+        self.devices.lock().ok()?.get(device_id).cloned()
+    }
+
+    /// 生成本设备的 DNS-SD 服务信息
+    ///
+    /// 实例名使用设备ID以避免同名设备冲突，地址由守护进程按网络接口自动填充。
+    fn service_info(&self) -> Result<ServiceInfo> {
+        let device = &self.local_device;
+        let device_type = serde_json::to_value(device.device_type)?;
+        let pairing_port = self.pairing_port.to_string();
+        let transfer_port = self.transfer_port.to_string();
+        let mut properties = vec![
+            (txt::DEVICE_ID, device.id.as_str()),
+            (txt::DEVICE_NAME, device.name.as_str()),
+            (txt::DEVICE_TYPE, device_type.as_str().unwrap_or_default()),
+            (txt::PROTOCOL, PROTOCOL_VERSION),
+            (txt::PAIRING_PORT, pairing_port.as_str()),
+            (txt::TRANSFER_PORT, transfer_port.as_str()),
+            (txt::PUBLIC_KEY, device.public_key.as_str()),
+            (txt::VERIFY_KEY, device.verify_key.as_str()),
+        ];
+        if let Some(namespace) = &device.namespace {
+            properties.push((txt::NAMESPACE, namespace.as_str()));
+        }
+
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &device.id,
+            &format!("{}.local.", device.id),
+            "",
+            self.pairing_port,
+            &properties[..],
+        )
+        .map(ServiceInfo::enable_addr_auto)
+        .map_err(mdns_error)
+    }
+}
+
+/// 从解析出的服务信息构造设备信息，缺少必要字段时返回None
+fn device_from_service(info: &ServiceInfo) -> Option<DeviceInfo> {
+    let id = info.get_property_val_str(txt::DEVICE_ID)?;
+    let public_key = info.get_property_val_str(txt::PUBLIC_KEY)?;
+    let device_type = info
+        .get_property_val_str(txt::DEVICE_TYPE)
+        .and_then(|t| serde_json::from_value::<DeviceType>(serde_json::Value::String(t.to_string())).ok())
+        .unwrap_or_default();
+    if info.get_property_val_str(txt::PROTOCOL) != Some(PROTOCOL_VERSION) {
+        debug!("设备 {id} 使用不同的协议版本");
+    }
+
+    let mut device = DeviceInfo::new(
+        info.get_property_val_str(txt::DEVICE_NAME).unwrap_or(id),
+        device_type,
+        public_key,
+    );
+    device.id = id.to_string();
+    device.verify_key = info.get_property_val_str(txt::VERIFY_KEY).unwrap_or_default().to_string();
+    device.namespace = info.get_property_val_str(txt::NAMESPACE).map(str::to_string);
+    // 地址集合无序，排序后记录以免设备信息每次解析都发生变化
+    let mut addresses: Vec<String> = info.get_addresses().iter().map(ToString::to_string).collect();
+    addresses.sort();
+    for address in &addresses {
+        device.add_address(address);
+    }
+    device.pairing_port = Some(info.get_port());
+    device.transfer_port = info.get_property_val_str(txt::TRANSFER_PORT).and_then(|p| p.parse().ok());
+
+    Some(device)
+}
+
+/// 转换 mDNS 错误
+fn mdns_error(e: mdns_sd::Error) -> Error {
+    Error::Discovery(format!("mDNS操作失败: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ConfigOptions, DeviceCapabilities};
+    use tokio::sync::mpsc;
+    use tokio::time::{timeout, Duration};
+
+    fn config(device_id: &str, name: &str, listen_port: u16) -> Config {
+        Config {
+            device_name: name.to_string(),
+            device_type: DeviceType::Desktop,
+            storage_path: ":memory:".to_string(),
+            device_id: device_id.to_string(),
+            discovery_port: 45678,
+            capabilities: DeviceCapabilities::default(),
+            listen_port,
+            options: ConfigOptions::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mdns_discovery_on_loopback() {
+        let mut laptop = MdnsDiscovery::new(&config("mdns-laptop", "笔记本", 46100)).unwrap();
+        let mut phone = MdnsDiscovery::new(&config("mdns-phone", "手机", 46200)).unwrap();
+        laptop.set_loopback(true);
+        phone.set_loopback(true);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        laptop
+            .start(Box::new(move |device| {
+                let _ = tx.send(device);
+            }))
+            .await
+            .unwrap();
+        phone.start(Box::new(|_| {})).await.unwrap();
+
+        let found = timeout(Duration::from_secs(10), async {
+            loop {
+                let device = rx.recv().await.unwrap();
+                if device.id == "mdns-phone" {
+                    return device;
+                }
+            }
+        })
+        .await
+        .expect("应在回环接口上发现设备");
+
+        assert_eq!(found.name, "手机");
+        assert_eq!(found.device_type, DeviceType::Desktop);
+        assert!(found.ip_address.is_some());
+        assert_eq!(found.pairing_port, Some(46200));
+        assert_eq!(found.transfer_port, Some(46201));
+        assert!(laptop.get_device_by_id("mdns-phone").is_some());
+        assert!(laptop.get_device_by_id("mdns-laptop").is_none());
+
+        laptop.stop().await.unwrap();
+        phone.stop().await.unwrap();
+    }
+}
@@ -2,10 +2,36 @@
 
 /// 基于UDP广播的设备发现模块
 pub mod discovery;
+/// 去重的设备发现事件流
+pub mod discovery_events;
+/// 合并各发现来源的设备发现管理器
+pub mod discovery_manager;
+/// 发现包的线上格式
+pub mod discovery_wire;
 /// 蓝牙低功耗(BLE)设备发现与连接模块
 pub mod ble_discovery;
+/// 网络接口枚举与过滤
+pub mod interfaces;
+/// 设备邀请码
+pub mod invite;
+/// 设备公钥固定（首次使用信任）
+pub mod key_pinning;
+/// 基于mDNS/DNS-SD的设备发现模块
+pub mod mdns_discovery;
+/// 设备发现命名空间
+pub mod namespace;
 /// 设备配对与认证模块
 pub mod pairing;
+/// 按来源地址的速率限制
+pub mod rate_limit;
+/// 防重放检查
+pub mod replay;
+/// 设备吊销列表
+pub mod revocation;
+/// 加密认证的会话通道
+pub mod secure_channel;
+/// 共享组密钥的同步组
+pub mod sync_group;
 /// 基本传输协议相关模块
 pub mod transport;
 /// 高效Wi-Fi文件传输模块
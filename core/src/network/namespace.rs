@@ -0,0 +1,131 @@
+//! 设备发现命名空间
+//!
+//! 多人共用一个局域网时，设备列表里会出现大量他人的设备。配置了相同命名空间（如团队名或同步组ID）
+//! 的设备在UDP广播、mDNS和BLE中携带命名空间的哈希，彼此只显示命名空间相同的设备；
+//! 未配置命名空间的设备之间互相可见。开启开放发现后显示所有设备。
+//! 哈希只用于分组，不能阻止他人冒用命名空间，设备身份仍由配对和公钥固定保证。
+
+use crate::{
+    error::{Error, Result},
+    types::ConfigOptions,
+};
+use sodiumoxide::crypto::generichash;
+
+/// 命名空间哈希派生的域分隔标识
+const NAMESPACE_DOMAIN: &[u8] = b"PasteAll-Namespace-v1";
+
+/// 命名空间哈希长度（字节）
+const NAMESPACE_ID_BYTES: usize = 16;
+
+/// BLE广播中携带的命名空间标识长度（字节），受广播包长度限制只取哈希的前缀
+pub const ADVERTISED_NAMESPACE_BYTES: usize = 8;
+
+/// 计算命名空间的哈希，忽略首尾空白
+pub fn namespace_id(namespace: &str) -> Result<String> {
+    let namespace = namespace.trim();
+    if namespace.is_empty() {
+        return Err(Error::InvalidArgument("命名空间不能为空".to_string()));
+    }
+
+    let hash_error = |_| Error::Crypto("计算命名空间哈希失败".to_string());
+    let mut state = generichash::State::new(Some(NAMESPACE_ID_BYTES), None).map_err(hash_error)?;
+    state.update(NAMESPACE_DOMAIN).map_err(hash_error)?;
+    state.update(namespace.as_bytes()).map_err(hash_error)?;
+    let digest = state.finalize().map_err(hash_error)?;
+
+    Ok(base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD))
+}
+
+/// 按命名空间筛选发现的设备
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamespaceFilter {
+    /// 本设备命名空间的哈希
+    id: Option<String>,
+    /// 是否显示所有命名空间的设备
+    open: bool,
+}
+
+impl NamespaceFilter {
+    /// 创建筛选器，命名空间为空白时视为未配置
+    pub fn new(namespace: Option<&str>, open: bool) -> Result<Self> {
+        let id = match namespace.map(str::trim).filter(|n| !n.is_empty()) {
+            Some(namespace) => Some(namespace_id(namespace)?),
+            None => None,
+        };
+        Ok(Self { id, open })
+    }
+
+    /// 按配置创建筛选器
+    pub fn from_options(options: &ConfigOptions) -> Result<Self> {
+        Self::new(options.discovery_namespace.as_deref(), options.open_discovery)
+    }
+
+    /// 本设备公布的命名空间哈希
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// 是否显示带有指定命名空间哈希的设备
+    pub fn allows(&self, peer: Option<&str>) -> bool {
+        self.open || self.id.as_deref() == peer
+    }
+
+    /// BLE广播服务数据中携带的命名空间标识，未配置命名空间时为空
+    pub fn advertised_tag(&self) -> Vec<u8> {
+        self.id
+            .as_deref()
+            .and_then(|id| base64::decode_config(id, base64::URL_SAFE_NO_PAD).ok())
+            .map(|mut digest| {
+                digest.truncate(ADVERTISED_NAMESPACE_BYTES);
+                digest
+            })
+            .unwrap_or_default()
+    }
+
+    /// 是否连接BLE广播中带有指定命名空间标识的设备
+    pub fn allows_advertised(&self, tag: &[u8]) -> bool {
+        self.open || self.advertised_tag() == tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_filter() {
+        let design = NamespaceFilter::new(Some("design-team"), false).unwrap();
+        let design_id = namespace_id(" design-team\n").unwrap();
+        assert_eq!(design.id(), Some(design_id.as_str()));
+        assert_ne!(design_id, namespace_id("sales-team").unwrap());
+        assert!(!design_id.contains("design"));
+
+        // 只显示同一命名空间的设备
+        assert!(design.allows(Some(&design_id)));
+        assert!(!design.allows(Some(&namespace_id("sales-team").unwrap())));
+        assert!(!design.allows(None));
+
+        // 未配置命名空间的设备之间互相可见
+        let default = NamespaceFilter::new(Some("  "), false).unwrap();
+        assert_eq!(default.id(), None);
+        assert!(default.allows(None));
+        assert!(!default.allows(Some(&design_id)));
+
+        // 开放发现显示所有设备，但仍公布自己的命名空间
+        let open = NamespaceFilter::new(Some("design-team"), true).unwrap();
+        assert_eq!(open.id(), Some(design_id.as_str()));
+        assert!(open.allows(None));
+        assert!(open.allows(Some(&namespace_id("sales-team").unwrap())));
+
+        // BLE广播只携带哈希前缀
+        let sales = NamespaceFilter::new(Some("sales-team"), false).unwrap();
+        assert_eq!(design.advertised_tag().len(), ADVERTISED_NAMESPACE_BYTES);
+        assert!(design.allows_advertised(&design.advertised_tag()));
+        assert!(!design.allows_advertised(&sales.advertised_tag()));
+        assert!(!design.allows_advertised(&[]));
+        assert!(default.allows_advertised(&[]));
+        assert!(open.allows_advertised(&sales.advertised_tag()));
+
+        assert!(namespace_id("").is_err());
+    }
+}
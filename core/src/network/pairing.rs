@@ -1,15 +1,22 @@
 //! 设备配对模块，负责设备间的安全配对与认证
 
 use crate::{
-    crypto,
+    crypto::{self, CryptoManager, KeyPair},
     error::{Error, Result},
+    network::{
+        replay::ReplayGuard,
+        key_pinning::{KeyPins, PinCheck},
+        revocation::RevocationList,
+        secure_channel::{PeerKeyLookup, PeerPolicy, SecureChannel},
+    },
+    storage::AsyncStorage,
     types::{AuthRequestPacket, DeviceInfo, PairingStatus},
 };
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::box_::PublicKey;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 
@@ -28,6 +35,27 @@ struct PairingResponse {
     pin: String,
 }
 
+/// 配对监听任务共享的状态
+#[derive(Clone)]
+struct ListenerState {
+    /// 等待配对的设备 (设备ID -> PIN码)
+    awaiting_pairing: Arc<Mutex<HashMap<String, String>>>,
+    /// 已配对设备映射表
+    paired_devices: Arc<Mutex<HashMap<String, DeviceInfo>>>,
+    /// 本地设备ID
+    local_device_id: String,
+    /// 配对状态变更回调
+    status_callback: Option<Arc<PairingStatusCallback>>,
+    /// 配对请求的防重放检查
+    replay_guard: Arc<ReplayGuard>,
+    /// 设备吊销列表
+    revocations: RevocationList,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 保存配对设备的存储
+    storage: Option<AsyncStorage>,
+}
+
 /// 设备配对管理器
 pub struct PairingManager {
     /// 本地设备信息
@@ -42,6 +70,14 @@ pub struct PairingManager {
     awaiting_pairing: Arc<Mutex<HashMap<String, String>>>, // 设备ID -> PIN码
     /// 停止信号发送端
     stop_tx: Option<mpsc::Sender<()>>,
+    /// 配对请求的防重放检查
+    replay_guard: Arc<ReplayGuard>,
+    /// 设备吊销列表
+    revocations: RevocationList,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 保存配对设备的存储
+    storage: Option<AsyncStorage>,
 }
 
 impl PairingManager {
@@ -54,6 +90,10 @@ impl PairingManager {
             status_callback: None,
             awaiting_pairing: Arc::new(Mutex::new(HashMap::new())),
             stop_tx: None,
+            replay_guard: Arc::new(ReplayGuard::new()),
+            revocations: RevocationList::new(),
+            key_pins: KeyPins::new(),
+            storage: None,
         }
     }
 
@@ -67,6 +107,27 @@ impl PairingManager {
         self.status_callback = Some(Arc::new(callback));
     }
 
+    /// 设置共享的设备吊销列表
+    ///
+    /// 已吊销的设备不能再次配对，也不会通过 [`PairingManager::peer_key_lookup`] 的认证。
+    pub fn set_revocation_list(&mut self, revocations: RevocationList) {
+        self.revocations = revocations;
+    }
+
+    /// 设置共享的设备公钥固定表
+    ///
+    /// 公钥变更后尚未重新验证的设备不能配对，也不会通过认证。
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置保存配对设备和共享密钥的存储
+    ///
+    /// 未设置时配对结果只保存在内存中，重启后失效。
+    pub fn set_storage(&mut self, storage: AsyncStorage) {
+        self.storage = Some(storage);
+    }
+
     /// 启动配对监听服务
     pub async fn start_listening(&mut self, port: u16) -> Result<()> {
         if self.stop_tx.is_some() {
@@ -74,13 +135,20 @@ impl PairingManager {
             return Ok(());
         }
 
+        let manager = crypto::manager()?;
         let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
         self.stop_tx = Some(stop_tx);
 
-        let awaiting_pairing = self.awaiting_pairing.clone();
-        let paired_devices = self.paired_devices.clone();
-        let local_device_id = self.local_device.id.clone();
-        let status_callback = self.status_callback.clone();
+        let state = ListenerState {
+            awaiting_pairing: self.awaiting_pairing.clone(),
+            paired_devices: self.paired_devices.clone(),
+            local_device_id: self.local_device.id.clone(),
+            status_callback: self.status_callback.clone(),
+            replay_guard: self.replay_guard.clone(),
+            revocations: self.revocations.clone(),
+            key_pins: self.key_pins.clone(),
+            storage: self.storage.clone(),
+        };
 
         // 启动TCP监听服务，接收配对请求
         let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await
@@ -102,13 +170,11 @@ impl PairingManager {
                         match accept_result {
                             Ok((socket, addr)) => {
                                 info!("接受新的配对连接: {addr}");
-                                let awaiting = awaiting_pairing.clone();
-                                let devices = paired_devices.clone();
-                                let local_id = local_device_id.clone();
-                                let callback = status_callback.clone();
+                                let state = state.clone();
+                                let manager = manager.clone();
                                 
                                 tokio::spawn(async move {
-                                    if let Err(e) = Self::handle_pairing_connection(socket, awaiting, devices, local_id, callback).await {
+                                    if let Err(e) = Self::handle_pairing_connection(socket, &manager, state).await {
                                         error!("处理配对连接失败: {e:?}");
                                     }
                                 });
@@ -138,6 +204,13 @@ impl PairingManager {
 
     /// 请求与设备配对
     pub async fn request_pairing(&self, device: &DeviceInfo) -> Result<()> {
+        if self.revocations.is_device_revoked(device) {
+            return Err(Error::Pairing("设备已被吊销，不能重新配对".to_string()));
+        }
+        if self.key_pins.is_blocked(&device.id) {
+            return Err(Error::Pairing("设备公钥已变更，请先重新验证".to_string()));
+        }
+
         // 检查是否已经配对
         {
             let devices = self.paired_devices.lock().unwrap();
@@ -146,52 +219,60 @@ impl PairingManager {
             }
         }
 
-        // 生成随机PIN码
-        let pin = Self::generate_pin();
-
-        // 存储等待配对设备
-        {
-            let mut awaiting = self.awaiting_pairing.lock().unwrap();
-            awaiting.insert(device.id.clone(), pin.clone());
-        }
+        // 沿用用户已输入或之前显示的PIN码，否则生成随机PIN码并存储等待配对设备
+        let pin = self
+            .awaiting_pairing
+            .lock()
+            .unwrap()
+            .entry(device.id.clone())
+            .or_insert_with(Self::generate_pin)
+            .clone();
 
         // 连接到目标设备
-        if let Some(ip) = &device.ip_address {
-            let addr = format!("{}:45680", ip); // 假设目标设备在45680端口监听配对请求
-            let mut stream = TcpStream::connect(&addr).await
+        // 旧版本设备未公布端口时使用默认配对端口
+        if let Some(addr) = device.socket_address(device.pairing_port.unwrap_or(45680)) {
+            let stream = TcpStream::connect(&addr).await
                 .map_err(|e| {
                     error!("连接到目标设备失败: {e:?}");
                     Error::Network(format!("无法连接到 {addr}"))
                 })?;
 
-            // 创建配对请求包
-            let request = AuthRequestPacket {
+            // 建立加密会话，确认对方持有发现阶段公布的公钥
+            let manager = crypto::manager()?;
+            let peer_public_key = KeyPair::public_key_from_base64(&device.public_key)?;
+            let mut channel = SecureChannel::connect(
+                stream,
+                &manager,
+                &self.local_device.id,
+                &device.id,
+                &peer_public_key,
+            ).await?;
+
+            // 创建配对请求包，使用本机身份签名
+            let mut request = AuthRequestPacket {
                 r#type: "pairing_request".to_string(),
                 device_id: self.local_device.id.clone(),
                 nonce: uuid::Uuid::new_v4().to_string(), // 使用随机UUID作为nonce
-                signature: crypto::sign(&self.local_device.id, &self.local_device.public_key)?,
+                timestamp: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                verify_key: crypto::get_verify_key()?,
+                device_name: self.local_device.name.clone(),
+                device_type: self.local_device.device_type,
+                signature: String::new(),
             };
+            request.signature = crypto::sign(&request.signing_payload())?;
 
             // 序列化并发送请求
             let request_json = serde_json::to_string(&request)
                 .map_err(|e| Error::Serialization(e))?;
             
-            let len_bytes = (request_json.len() as u32).to_be_bytes();
-            stream.write_all(&len_bytes).await
-                .map_err(|e| Error::Network(format!("发送请求长度失败: {e}")))?;
-            
-            stream.write_all(request_json.as_bytes()).await
+            channel.send(request_json.as_bytes()).await
                 .map_err(|e| Error::Network(format!("发送配对请求失败: {e}")))?;
 
             // 读取响应
-            let mut len_bytes = [0u8; 4];
-            stream.read_exact(&mut len_bytes).await
-                .map_err(|e| Error::Network(format!("读取响应长度失败: {e}")))?;
-            
-            let len = u32::from_be_bytes(len_bytes) as usize;
-            let mut buffer = vec![0u8; len];
-            
-            stream.read_exact(&mut buffer).await
+            let buffer = channel.recv().await
                 .map_err(|e| Error::Network(format!("读取响应数据失败: {e}")))?;
 
             // 解析响应
@@ -204,10 +285,15 @@ impl PairingManager {
                 if response.pin == pin {
                     // 配对成功，保存设备
                     let mut device_info = device.clone();
+                    // 用户比对指纹后才标记为可信
                     device_info.pairing_status = PairingStatus::Paired;
-                    device_info.trusted = true;
+                    device_info.trusted = false;
                     
-                    // 添加到配对设备列表
+                    // 固定对方公钥并添加到配对设备列表
+                    if !matches!(self.key_pins.pin(&device_info)?, PinCheck::Pinned | PinCheck::Matched) {
+                        return Err(Error::Authentication(format!("设备 {} 的公钥已变更，需重新验证", device_info.id)));
+                    }
+                    Self::save_paired_device(self.storage.as_ref(), &manager, &device_info, &peer_public_key).await?;
                     {
                         let mut devices = self.paired_devices.lock().unwrap();
                         devices.insert(device_info.id.clone(), device_info.clone());
@@ -232,33 +318,76 @@ impl PairingManager {
 
     /// 处理配对连接
     async fn handle_pairing_connection(
-        mut socket: TcpStream,
-        awaiting_pairing: Arc<Mutex<HashMap<String, String>>>,
-        paired_devices: Arc<Mutex<HashMap<String, DeviceInfo>>>,
-        _local_device_id: String,
-        status_callback: Option<Arc<PairingStatusCallback>>,
+        socket: TcpStream,
+        manager: &CryptoManager,
+        state: ListenerState,
     ) -> Result<()> {
-        // 读取请求长度
-        let mut len_bytes = [0u8; 4];
-        socket.read_exact(&mut len_bytes).await
-            .map_err(|e| Error::Network(format!("读取请求长度失败: {e}")))?;
-        
-        let len = u32::from_be_bytes(len_bytes) as usize;
-        let mut buffer = vec![0u8; len];
-        
+        let ListenerState {
+            awaiting_pairing,
+            paired_devices,
+            local_device_id,
+            status_callback,
+            replay_guard,
+            revocations,
+            key_pins,
+            storage,
+        } = state;
+
+        // 配对时对方尚未配对，只要求其证明持有声明的身份私钥
+        let mut channel = SecureChannel::accept(
+            socket,
+            manager,
+            &local_device_id,
+            &PeerPolicy::AnyAuthenticated,
+        ).await?;
+
         // 读取请求数据
-        socket.read_exact(&mut buffer).await
+        let buffer = channel.recv().await
             .map_err(|e| Error::Network(format!("读取请求数据失败: {e}")))?;
 
         // 解析请求
         let request: AuthRequestPacket = serde_json::from_slice(&buffer)
             .map_err(|e| Error::Serialization(e))?;
+        let peer = channel.peer().clone();
+        if request.device_id != peer.device_id {
+            return Err(Error::Authentication(format!(
+                "配对请求设备ID与会话身份不一致: {}",
+                request.device_id
+            )));
+        }
+        if revocations.is_revoked(&peer.device_id, &peer.public_key_base64()) {
+            return Err(Error::Authentication(format!("拒绝已吊销设备的配对请求: {}", peer.device_id)));
+        }
 
-        // 检查是否已经配对
-        let already_paired = {
+        // 已配对设备使用保存的签名公钥验证，新设备使用请求中携带的公钥
+        let paired_device = {
             let devices = paired_devices.lock().unwrap();
-            devices.contains_key(&request.device_id)
+            devices.get(&request.device_id).cloned()
+        };
+        let already_paired = paired_device.is_some();
+        let signer = match paired_device {
+            // 旧版本保存的设备没有签名公钥，沿用请求中的公钥
+            Some(device) if !device.verify_key.is_empty() => device,
+            _ => {
+                let mut signer = DeviceInfo::new("", crate::types::DeviceType::Unknown, "");
+                signer.id = request.device_id.clone();
+                signer.verify_key = request.verify_key.clone();
+                signer
+            }
         };
+        crypto::verify_device_signature(&signer, &request.signing_payload(), &request.signature)?;
+        replay_guard.check(&request)?;
+
+        // 设备ID已固定其他公钥时拒绝配对，直到用户重新验证
+        let mut peer_device = DeviceInfo::new("", crate::types::DeviceType::Unknown, &peer.public_key_base64());
+        peer_device.id = peer.device_id.clone();
+        peer_device.verify_key = request.verify_key.clone();
+        if let PinCheck::Changed(event) = key_pins.check(&peer_device)? {
+            warn!("设备 {} 以新的公钥请求配对: {}", event.device_id, event.new_public_key);
+        }
+        if key_pins.is_blocked(&peer.device_id) {
+            return Err(Error::Authentication(format!("设备 {} 的公钥已变更，需重新验证", peer.device_id)));
+        }
 
         // 准备响应
         let mut response = PairingResponse {
@@ -287,19 +416,21 @@ impl PairingManager {
         let response_json = serde_json::to_string(&response)
             .map_err(|e| Error::Serialization(e))?;
         
-        let len_bytes = (response_json.len() as u32).to_be_bytes();
-        socket.write_all(&len_bytes).await
-            .map_err(|e| Error::Network(format!("发送响应长度失败: {e}")))?;
-        
-        socket.write_all(response_json.as_bytes()).await
+        channel.send(response_json.as_bytes()).await
             .map_err(|e| Error::Network(format!("发送响应失败: {e}")))?;
 
         // 如果配对成功，更新设备状态
         if response.accepted && !already_paired {
-            // TODO: 创建设备信息，目前简化处理
-            let device_info = DeviceInfo::new("远程设备", crate::types::DeviceType::Unknown, "placeholder");
+            // 旧版本设备的请求不携带名称
+            let name = if request.device_name.is_empty() { "远程设备" } else { &request.device_name };
+            let mut device_info = DeviceInfo::new(name, request.device_type, &peer.public_key_base64());
+            device_info.id = peer.device_id.clone();
+            device_info.verify_key = request.verify_key.clone();
+            device_info.pairing_status = PairingStatus::Paired;
             
-            // 添加到配对设备列表
+            // 固定对方公钥并添加到配对设备列表
+            key_pins.pin(&device_info)?;
+            Self::save_paired_device(storage.as_ref(), manager, &device_info, &peer.public_key).await?;
             {
                 let mut devices = paired_devices.lock().unwrap();
                 devices.insert(device_info.id.clone(), device_info.clone());
@@ -314,6 +445,32 @@ impl PairingManager {
         Ok(())
     }
 
+    /// 记录用户为对方设备输入的PIN码
+    ///
+    /// 此后该设备携带相同PIN码的配对请求会被接受；本机向其发起配对时也使用该PIN码。
+    pub fn expect_pairing(&self, device_id: &str, pin: &str) {
+        self.awaiting_pairing
+            .lock()
+            .unwrap()
+            .insert(device_id.to_string(), pin.to_string());
+    }
+
+    /// 生成共享密钥并保存配对设备
+    async fn save_paired_device(
+        storage: Option<&AsyncStorage>,
+        manager: &CryptoManager,
+        device: &DeviceInfo,
+        public_key: &PublicKey,
+    ) -> Result<()> {
+        manager.generate_shared_key(&device.id, public_key)?;
+
+        if let Some(storage) = storage {
+            storage.save_device(device).await?;
+            storage.save_shared_key(&device.id, &manager.key_agreement(public_key)?).await?;
+        }
+        Ok(())
+    }
+
     /// 生成6位数PIN码
     fn generate_pin() -> String {
         use rand::Rng;
@@ -326,4 +483,79 @@ impl PairingManager {
         let devices = self.paired_devices.lock().unwrap();
         devices.values().cloned().collect()
     }
+
+    /// 获取已配对设备信息
+    pub fn paired_device(&self, device_id: &str) -> Option<DeviceInfo> {
+        self.paired_devices.lock().unwrap().get(device_id).cloned()
+    }
+
+    /// 更新已配对设备的可信状态，返回设备是否已配对
+    pub fn set_device_trusted(&self, device_id: &str, trusted: bool) -> bool {
+        let mut devices = self.paired_devices.lock().unwrap();
+        let Some(device) = devices.get_mut(device_id) else {
+            return false;
+        };
+        device.trusted = trusted;
+        true
+    }
+
+    /// 取消配对并吊销设备
+    ///
+    /// 删除共享密钥并将设备加入吊销列表，此后该设备的连接和配对请求都会被拒绝。
+    /// 返回被吊销的设备信息，由调用方通知其他已配对设备。
+    pub fn unpair(&self, device_id: &str) -> Result<DeviceInfo> {
+        let device = self
+            .paired_devices
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .cloned()
+            .ok_or_else(|| Error::Pairing(format!("设备未配对: {device_id}")))?;
+
+        self.revoke(&device)?;
+        Ok(device)
+    }
+
+    /// 吊销设备
+    ///
+    /// 与 [`PairingManager::unpair`] 相同，但也适用于之前启动时配对、只保存在存储中的设备。
+    pub fn revoke(&self, device: &DeviceInfo) -> Result<()> {
+        self.revocations.revoke(&device.id, &device.public_key)?;
+        self.paired_devices.lock().unwrap().remove(&device.id);
+
+        let mut device_info = device.clone();
+        device_info.pairing_status = PairingStatus::Unpaired;
+        device_info.trusted = false;
+        if let Some(callback) = &self.status_callback {
+            callback(device_info, PairingStatus::Unpaired);
+        }
+
+        Ok(())
+    }
+
+    /// 更新已配对设备轮换后的公钥，返回设备是否已配对
+    pub fn update_device_keys(&self, device_id: &str, public_key: &str, verify_key: &str) -> bool {
+        let mut devices = self.paired_devices.lock().unwrap();
+        let Some(device) = devices.get_mut(device_id) else {
+            return false;
+        };
+        device.public_key = public_key.to_string();
+        device.verify_key = verify_key.to_string();
+        true
+    }
+
+    /// 获取已配对设备公钥的查询函数，供传输服务认证入站连接
+    pub fn peer_key_lookup(&self) -> PeerKeyLookup {
+        let paired_devices = self.paired_devices.clone();
+        let revocations = self.revocations.clone();
+        let key_pins = self.key_pins.clone();
+        Arc::new(move |device_id| {
+            let devices = paired_devices.lock().ok()?;
+            let device = devices.get(device_id)?;
+            if revocations.is_device_revoked(device) || key_pins.is_blocked(device_id) {
+                return None;
+            }
+            KeyPair::public_key_from_base64(&device.public_key).ok()
+        })
+    }
 }
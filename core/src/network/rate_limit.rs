@@ -0,0 +1,147 @@
+//! 按来源地址的速率限制
+//!
+//! 每个来源地址拥有一个令牌桶，允许短时突发，超出部分丢弃。跟踪的来源数量有上限，
+//! 满时先淘汰令牌已回满（近期空闲）的来源，仍无空位时拒绝新来源，避免伪造大量来源地址耗尽内存。
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 发现包的默认突发上限，容纳一次广播在多个接口和地址族上的副本及查询
+pub const DEFAULT_DISCOVERY_BURST: u32 = 10;
+
+/// 发现包的默认令牌恢复间隔
+pub const DEFAULT_DISCOVERY_REFILL: Duration = Duration::from_millis(500);
+
+/// 默认最多跟踪的来源数量
+pub const DEFAULT_MAX_SOURCES: usize = 1024;
+
+/// 单个来源的令牌桶
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// 剩余令牌数
+    tokens: u32,
+    /// 上次恢复令牌的时间
+    refilled_at: Instant,
+}
+
+/// 按来源地址的令牌桶速率限制器
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// 来源地址 -> 令牌桶
+    buckets: HashMap<IpAddr, Bucket>,
+    /// 突发上限
+    burst: u32,
+    /// 每恢复一个令牌的间隔
+    refill: Duration,
+    /// 最多跟踪的来源数量
+    max_sources: usize,
+}
+
+impl RateLimiter {
+    /// 创建速率限制器
+    pub fn new(burst: u32, refill: Duration, max_sources: usize) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            burst: burst.max(1),
+            refill: refill.max(Duration::from_millis(1)),
+            max_sources: max_sources.max(1),
+        }
+    }
+
+    /// 来源在 `now` 时刻是否可以再发送一个包，允许时消耗一个令牌
+    pub fn allow(&mut self, source: IpAddr, now: Instant) -> bool {
+        if !self.buckets.contains_key(&source) && self.buckets.len() >= self.max_sources {
+            self.evict_idle(now);
+            if self.buckets.len() >= self.max_sources {
+                return false;
+            }
+        }
+
+        let (burst, refill) = (self.burst, self.refill);
+        let bucket = self.buckets.entry(source).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        refill_bucket(bucket, burst, refill, now);
+        if bucket.tokens == 0 {
+            return false;
+        }
+        bucket.tokens -= 1;
+        true
+    }
+
+    /// 当前跟踪的来源数量
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// 是否没有跟踪任何来源
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// 淘汰令牌已回满的来源
+    fn evict_idle(&mut self, now: Instant) {
+        let (burst, refill) = (self.burst, self.refill);
+        self.buckets.retain(|_, bucket| {
+            refill_bucket(bucket, burst, refill, now);
+            bucket.tokens < burst
+        });
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_DISCOVERY_BURST, DEFAULT_DISCOVERY_REFILL, DEFAULT_MAX_SOURCES)
+    }
+}
+
+/// 按经过的时间恢复令牌，不足一个令牌的时间留到下次计算
+fn refill_bucket(bucket: &mut Bucket, burst: u32, refill: Duration, now: Instant) {
+    let elapsed = now.saturating_duration_since(bucket.refilled_at);
+    let earned = elapsed.as_nanos() / refill.as_nanos();
+    if earned == 0 {
+        return;
+    }
+    let earned = u32::try_from(earned).unwrap_or(u32::MAX);
+    bucket.tokens = bucket.tokens.saturating_add(earned).min(burst);
+    bucket.refilled_at = if bucket.tokens == burst {
+        now
+    } else {
+        bucket.refilled_at + refill * earned
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let a: IpAddr = "192.168.1.20".parse().unwrap();
+        let b: IpAddr = "192.168.1.21".parse().unwrap();
+        let c: IpAddr = "192.168.1.22".parse().unwrap();
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(1), 2);
+
+        // 突发上限内放行，超出后丢弃，其他来源不受影响
+        assert!((0..3).all(|_| limiter.allow(a, start)));
+        assert!(!limiter.allow(a, start));
+        assert!((0..3).all(|_| limiter.allow(b, start)));
+
+        // 令牌按间隔恢复
+        assert!(!limiter.allow(a, start + Duration::from_millis(900)));
+        assert!(limiter.allow(a, start + Duration::from_millis(1500)));
+        assert!(!limiter.allow(a, start + Duration::from_millis(1900)));
+        assert!(limiter.allow(a, start + Duration::from_millis(2000)));
+
+        // 来源数量已满且都不空闲时拒绝新来源
+        assert!(!limiter.allow(c, start + Duration::from_millis(2000)));
+        assert_eq!(limiter.len(), 2);
+
+        // 空闲来源被淘汰后接受新来源
+        assert!(limiter.allow(c, start + Duration::from_secs(10)));
+        assert_eq!(limiter.len(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+//! 防重放检查
+//!
+//! 配对请求、内容包和控制消息都携带发送时间和随机数（或消息ID）。
+//! 接收方拒绝时间戳超出有效窗口、已过期或在窗口内重复出现的包，
+//! 被截获的包无法在新的会话中再次使用。
+
+use crate::{
+    error::{Error, Result},
+    types::{AuthRequestPacket, ContentPacket, Message},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 默认有效窗口（秒）
+pub const DEFAULT_REPLAY_WINDOW_SECS: u64 = 300;
+
+/// 默认允许的时钟偏差（秒）
+pub const DEFAULT_CLOCK_SKEW_SECS: u64 = 30;
+
+/// 可进行防重放检查的包
+pub trait ReplayProtected {
+    /// 发送方设备ID
+    fn replay_sender(&self) -> &str;
+    /// 随机数，同一发送方在有效窗口内不得重复
+    fn replay_nonce(&self) -> &str;
+    /// 发送时间（Unix时间戳，秒）
+    fn replay_timestamp(&self) -> u64;
+    /// 过期时间（Unix时间戳，秒，0表示仅受有效窗口限制）
+    fn replay_expires_at(&self) -> u64 {
+        0
+    }
+}
+
+impl ReplayProtected for AuthRequestPacket {
+    fn replay_sender(&self) -> &str {
+        &self.device_id
+    }
+
+    fn replay_nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    fn replay_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl ReplayProtected for ContentPacket {
+    fn replay_sender(&self) -> &str {
+        &self.device_id
+    }
+
+    fn replay_nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    fn replay_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn replay_expires_at(&self) -> u64 {
+        self.expires_at
+    }
+}
+
+impl ReplayProtected for Message {
+    fn replay_sender(&self) -> &str {
+        &self.sender_id
+    }
+
+    fn replay_nonce(&self) -> &str {
+        &self.id
+    }
+
+    fn replay_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn replay_expires_at(&self) -> u64 {
+        self.expires_at
+    }
+}
+
+/// 防重放检查器
+///
+/// 记录有效窗口内见过的（发送方, 随机数），窗口外的记录会被清理，
+/// 对应的包也会因时间戳过旧而被拒绝。
+pub struct ReplayGuard {
+    /// 有效窗口（秒）
+    window: u64,
+    /// 允许的时钟偏差（秒）
+    max_skew: u64,
+    /// 已见过的随机数 ((发送方, 随机数) -> 发送时间)
+    seen: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayGuard {
+    /// 使用默认窗口创建检查器
+    pub fn new() -> Self {
+        Self::with_window(DEFAULT_REPLAY_WINDOW_SECS, DEFAULT_CLOCK_SKEW_SECS)
+    }
+
+    /// 使用指定的有效窗口和时钟偏差创建检查器
+    pub fn with_window(window_secs: u64, max_skew_secs: u64) -> Self {
+        Self {
+            window: window_secs,
+            max_skew: max_skew_secs,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 检查包是否为新鲜的包，通过后记录其随机数
+    ///
+    /// 应在验证签名或会话身份之后调用，避免伪造的包占用记录。
+    pub fn check<P: ReplayProtected>(&self, packet: &P) -> Result<()> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.check_at(packet, now)
+    }
+
+    /// 以指定的当前时间进行检查
+    fn check_at<P: ReplayProtected>(&self, packet: &P, now: u64) -> Result<()> {
+        let sender = packet.replay_sender();
+        let nonce = packet.replay_nonce();
+        let timestamp = packet.replay_timestamp();
+
+        if nonce.is_empty() {
+            return Err(Error::Authentication(format!("来自 {sender} 的包缺少随机数")));
+        }
+        if timestamp > now.saturating_add(self.max_skew) {
+            return Err(Error::Authentication(format!(
+                "来自 {sender} 的包时间戳超前: {timestamp}"
+            )));
+        }
+        if timestamp.saturating_add(self.window) < now {
+            return Err(Error::Authentication(format!(
+                "来自 {sender} 的包时间戳过旧: {timestamp}"
+            )));
+        }
+        let expires_at = packet.replay_expires_at();
+        if expires_at != 0 && expires_at < now {
+            return Err(Error::Authentication(format!("来自 {sender} 的包已过期")));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        let window = self.window;
+        seen.retain(|_, seen_at| seen_at.saturating_add(window) >= now);
+
+        let key = (sender.to_string(), nonce.to_string());
+        if seen.contains_key(&key) {
+            return Err(Error::Authentication(format!("检测到来自 {sender} 的重放包")));
+        }
+        seen.insert(key, timestamp);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ContentMetadata, MessageType};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn auth_request(nonce: &str, timestamp: u64) -> AuthRequestPacket {
+        AuthRequestPacket {
+            r#type: "pairing_request".to_string(),
+            device_id: "phone".to_string(),
+            nonce: nonce.to_string(),
+            timestamp,
+            verify_key: String::new(),
+            device_name: String::new(),
+            device_type: Default::default(),
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_replayed_packets_rejected() {
+        let guard = ReplayGuard::new();
+
+        // 截获的配对请求原样重放
+        let captured = serde_json::to_vec(&auth_request("n1", NOW)).unwrap();
+        let first: AuthRequestPacket = serde_json::from_slice(&captured).unwrap();
+        let replayed: AuthRequestPacket = serde_json::from_slice(&captured).unwrap();
+        assert!(guard.check_at(&first, NOW).is_ok());
+        assert!(matches!(guard.check_at(&replayed, NOW + 10), Err(Error::Authentication(_))));
+
+        // 新随机数的请求不受影响，其他设备可使用相同随机数
+        assert!(guard.check_at(&auth_request("n2", NOW), NOW).is_ok());
+        let mut other = auth_request("n1", NOW);
+        other.device_id = "laptop".to_string();
+        assert!(guard.check_at(&other, NOW).is_ok());
+
+        let content = ContentPacket {
+            r#type: "content".to_string(),
+            device_id: "phone".to_string(),
+            content_type: "text".to_string(),
+            content: base64::encode("hello"),
+            metadata: ContentMetadata {
+                filename: None,
+                size: 5,
+                mime_type: "text/plain".to_string(),
+            },
+            timestamp: NOW,
+            nonce: "c1".to_string(),
+            expires_at: 0,
+        };
+        assert!(guard.check_at(&content, NOW).is_ok());
+        assert!(guard.check_at(&content.clone(), NOW + 1).is_err());
+
+        // 构造的内容包带有随机数和过期时间
+        let built = ContentPacket::new("phone", "text", b"hello", content.metadata.clone());
+        assert!(built.expires_at > built.timestamp);
+        assert!(guard.check(&built).is_ok());
+        assert!(guard.check(&built).is_err());
+
+        let message = Message::new("phone", MessageType::Heartbeat, false, None);
+        assert!(guard.check(&message).is_ok());
+        assert!(guard.check(&message).is_err());
+    }
+
+    #[test]
+    fn test_stale_and_expired_packets_rejected() {
+        let guard = ReplayGuard::with_window(60, 5);
+
+        assert!(guard.check_at(&auth_request("old", NOW - 61), NOW).is_err());
+        assert!(guard.check_at(&auth_request("future", NOW + 6), NOW).is_err());
+        assert!(guard.check_at(&auth_request("", NOW), NOW).is_err());
+        assert!(guard.check_at(&auth_request("skewed", NOW + 5), NOW).is_ok());
+
+        // 有效窗口之后重放的包因时间戳过旧被拒绝，过期的记录会被清理
+        assert!(guard.check_at(&auth_request("n", NOW), NOW).is_ok());
+        assert!(guard.check_at(&auth_request("n", NOW), NOW + 61).is_err());
+        assert!(guard.check_at(&auth_request("fresh", NOW + 70), NOW + 70).is_ok());
+        assert_eq!(guard.seen.lock().unwrap().len(), 1);
+
+        let mut message = Message::new("phone", MessageType::Heartbeat, false, None);
+        message.timestamp = NOW;
+        message.expires_at = NOW + 10;
+        assert!(guard.check_at(&message, NOW + 11).is_err());
+        assert!(guard.check_at(&message, NOW + 5).is_ok());
+    }
+}
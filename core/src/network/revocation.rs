@@ -0,0 +1,164 @@
+//! 设备吊销列表
+//!
+//! 丢失或被盗的设备被吊销后，其设备ID和加密公钥都会被记录。配对、传输服务在建立连接时
+//! 和传输过程中检查吊销列表，已建立的连接也会立即中断。吊销通知通过 [`MessageType::DeviceRevoked`]
+//! 发送给其他已配对设备，接收方只接受来自已认证会话的通知。
+//!
+//! [`MessageType::DeviceRevoked`]: crate::types::MessageType::DeviceRevoked
+
+use crate::{
+    crypto,
+    error::Result,
+    storage::Storage,
+    types::{DeviceInfo, Message, MessageType},
+};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// 吊销列表内容
+#[derive(Default)]
+struct RevokedSet {
+    /// 已吊销的设备ID
+    device_ids: HashSet<String>,
+    /// 已吊销的加密公钥（Base64编码）
+    public_keys: HashSet<String>,
+}
+
+/// 设备吊销列表
+///
+/// 可在多个服务之间共享，克隆后指向同一份列表。
+#[derive(Clone, Default)]
+pub struct RevocationList {
+    /// 吊销内容
+    revoked: Arc<RwLock<RevokedSet>>,
+    /// 持久化存储，未设置时仅保存在内存中
+    storage: Option<Arc<Storage>>,
+}
+
+impl RevocationList {
+    /// 创建仅保存在内存中的吊销列表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从存储加载吊销列表，此后的吊销操作同时写入存储
+    pub fn load(storage: Arc<Storage>) -> Result<Self> {
+        let mut revoked = RevokedSet::default();
+        for device in storage.get_revoked_devices()? {
+            revoked.device_ids.insert(device.device_id);
+            revoked.public_keys.insert(device.public_key);
+        }
+
+        Ok(Self {
+            revoked: Arc::new(RwLock::new(revoked)),
+            storage: Some(storage),
+        })
+    }
+
+    /// 吊销设备
+    ///
+    /// 删除共享密钥和已保存的设备信息，并记录设备ID和公钥。
+    pub fn revoke(&self, device_id: &str, public_key: &str) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.revoke_device(device_id, public_key)?;
+        }
+        if let Err(e) = crypto::remove_shared_key(device_id) {
+            warn!("删除设备 {device_id} 的共享密钥失败: {e:?}");
+        }
+
+        let mut revoked = self.revoked.write().unwrap_or_else(|e| e.into_inner());
+        revoked.device_ids.insert(device_id.to_string());
+        revoked.public_keys.insert(public_key.to_string());
+        info!("设备已吊销: {device_id}");
+
+        Ok(())
+    }
+
+    /// 设备ID或公钥是否已被吊销
+    pub fn is_revoked(&self, device_id: &str, public_key: &str) -> bool {
+        let revoked = self.revoked.read().unwrap_or_else(|e| e.into_inner());
+        revoked.device_ids.contains(device_id) || revoked.public_keys.contains(public_key)
+    }
+
+    /// 设备是否已被吊销
+    pub fn is_device_revoked(&self, device: &DeviceInfo) -> bool {
+        self.is_revoked(&device.id, &device.public_key)
+    }
+
+    /// 创建发送给其他已配对设备的吊销通知
+    pub fn notice(local_device_id: &str, revoked: &DeviceInfo, receiver_id: &str) -> Message {
+        Message::new(
+            local_device_id,
+            MessageType::DeviceRevoked {
+                device_id: revoked.id.clone(),
+                public_key: revoked.public_key.clone(),
+            },
+            false,
+            Some(receiver_id),
+        )
+    }
+
+    /// 处理其他设备发来的吊销通知
+    ///
+    /// `sender_id` 为会话认证的发送方。设备不能通过通知吊销本机或发送方自身，
+    /// 返回是否新增了吊销记录。
+    pub fn apply_notice(&self, local_device_id: &str, sender_id: &str, message: &Message) -> Result<bool> {
+        let MessageType::DeviceRevoked { device_id, public_key } = &message.message_type else {
+            return Ok(false);
+        };
+        if device_id == local_device_id || device_id == sender_id {
+            warn!("忽略来自 {sender_id} 的无效吊销通知: {device_id}");
+            return Ok(false);
+        }
+        if self.is_revoked(device_id, public_key) {
+            return Ok(false);
+        }
+
+        info!("收到来自 {sender_id} 的吊销通知: {device_id}");
+        self.revoke(device_id, public_key)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceType;
+
+    #[test]
+    fn test_revocation_persisted_and_propagated() {
+        crypto::init();
+        let storage = Arc::new(Storage::new(":memory:").unwrap());
+        let lost = DeviceInfo::new("丢失的手机", DeviceType::Mobile, "lost_key");
+        storage.save_device(&lost).unwrap();
+        storage.save_shared_key(&lost.id, &[1; 32]).unwrap();
+
+        let list = RevocationList::load(storage.clone()).unwrap();
+        assert!(!list.is_device_revoked(&lost));
+        list.revoke(&lost.id, &lost.public_key).unwrap();
+        assert!(list.is_device_revoked(&lost));
+        assert!(list.is_revoked("new_id", "lost_key"));
+        assert!(storage.get_device(&lost.id).unwrap().is_none());
+        assert!(storage.get_shared_key(&lost.id).unwrap().is_none());
+
+        // 重新加载后吊销记录依然有效
+        let reloaded = RevocationList::load(storage).unwrap();
+        assert!(reloaded.is_device_revoked(&lost));
+
+        // 其他设备收到通知后同样拒绝该设备
+        let peer_list = RevocationList::new();
+        let notice = RevocationList::notice("laptop", &lost, "tablet");
+        assert!(peer_list.apply_notice("tablet", "laptop", &notice).unwrap());
+        assert!(peer_list.is_device_revoked(&lost));
+        assert!(!peer_list.apply_notice("tablet", "laptop", &notice).unwrap());
+
+        // 不能吊销本机或通知发送方自身
+        let laptop = DeviceInfo::new("笔记本", DeviceType::Desktop, "laptop_key");
+        let self_notice = RevocationList::notice("laptop", &laptop, "tablet");
+        assert!(!peer_list.apply_notice("tablet", &laptop.id, &self_notice).unwrap());
+        assert!(!peer_list.apply_notice(&laptop.id, "other", &self_notice).unwrap());
+        assert!(!peer_list.is_device_revoked(&laptop));
+    }
+}
@@ -0,0 +1,489 @@
+//! 加密会话通道
+//!
+//! 所有TCP连接在传输数据前都要完成双向认证握手（参考Noise XX模式）：
+//!
+//! 1. 发起方发送 `Hello`：设备ID、身份公钥和临时公钥
+//! 2. 响应方按 [`PeerPolicy`] 检查发起方，不允许的设备在读取任何业务数据前直接断开
+//! 3. 响应方回复自己的 `Hello`，发起方检查其设备ID和身份公钥与预期一致
+//! 4. 双方将 ee、es、se 三次X25519协商结果与握手记录一起派生出两个方向的会话密钥，
+//!    再互相发送握手摘要，确认对方确实持有声明的身份私钥
+//!
+//! 会话密钥依赖一次性的临时密钥，身份私钥泄露后也无法解密之前的会话。
+//! 握手完成后每个帧使用XSalsa20-Poly1305加密，nonce为递增计数器，
+//! 被篡改、重放或调换顺序的帧都会解密失败。
+
+use crate::crypto::{self, CryptoManager, KeyPair};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::{box_::PublicKey, generichash, secretbox};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 协议名称，参与密钥派生
+const PROTOCOL_NAME: &[u8] = b"PasteAll_XX_25519_XSalsa20Poly1305_BLAKE2b";
+
+/// 握手协议版本
+const PROTOCOL_VERSION: u8 = 1;
+
+/// 握手消息的最大长度
+const MAX_HANDSHAKE_SIZE: usize = 4096;
+
+/// 单个加密帧的最大明文长度：16MB
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// 根据设备ID查找已配对设备身份公钥的回调函数类型
+pub type PeerKeyLookup = Arc<dyn Fn(&str) -> Option<PublicKey> + Send + Sync + 'static>;
+
+/// 响应方接受连接的策略
+#[derive(Clone)]
+pub enum PeerPolicy {
+    /// 仅接受已配对的设备，且身份公钥必须与配对时记录的一致
+    Paired(PeerKeyLookup),
+    /// 接受任何能证明持有所声明身份私钥的设备，仅用于配对流程
+    AnyAuthenticated,
+}
+
+impl PeerPolicy {
+    /// 拒绝所有设备的策略，在提供已配对设备列表之前使用
+    pub fn deny_all() -> Self {
+        Self::Paired(Arc::new(|_| None))
+    }
+
+    /// 检查设备是否允许连接
+    ///
+    /// 查找回调可能读取存储，因此在阻塞线程池上执行。
+    async fn check(&self, device_id: &str, public_key: &PublicKey) -> Result<()> {
+        match self {
+            Self::AnyAuthenticated => Ok(()),
+            Self::Paired(lookup) => match Self::lookup(lookup.clone(), device_id).await? {
+                Some(expected) if expected == *public_key => Ok(()),
+                Some(_) => Err(Error::Authentication(format!(
+                    "设备 {device_id} 的身份公钥与配对记录不一致"
+                ))),
+                None => Err(Error::Authentication(format!(
+                    "拒绝未配对设备的连接: {device_id}"
+                ))),
+            },
+        }
+    }
+
+    /// 在阻塞线程池上查找设备的身份公钥
+    async fn lookup(lookup: PeerKeyLookup, device_id: &str) -> Result<Option<PublicKey>> {
+        let device_id = device_id.to_string();
+        tokio::task::spawn_blocking(move || lookup(&device_id))
+            .await
+            .map_err(|e| Error::Other(format!("查找设备公钥失败: {e}")))
+    }
+}
+
+/// 握手消息
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    /// 协议版本
+    version: u8,
+    /// 设备ID
+    device_id: String,
+    /// 身份公钥（Base64编码）
+    static_key: String,
+    /// 临时公钥（Base64编码）
+    ephemeral_key: String,
+}
+
+/// 已通过认证的对端
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    /// 设备ID
+    pub device_id: String,
+    /// 身份公钥
+    pub public_key: PublicKey,
+}
+
+impl PeerIdentity {
+    /// 获取身份公钥的Base64编码
+    pub fn public_key_base64(&self) -> String {
+        base64::encode(self.public_key.as_ref())
+    }
+}
+
+/// 加密会话通道
+pub struct SecureChannel<S> {
+    /// 底层连接
+    stream: S,
+    /// 对端身份
+    peer: PeerIdentity,
+    /// 发送方向的会话密钥
+    send_key: secretbox::Key,
+    /// 接收方向的会话密钥
+    recv_key: secretbox::Key,
+    /// 已发送帧计数
+    send_counter: u64,
+    /// 已接收帧计数
+    recv_counter: u64,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> SecureChannel<S> {
+    /// 作为发起方建立加密会话
+    ///
+    /// `peer_device_id` 和 `peer_public_key` 为预期的对端身份，响应方身份不符时握手失败。
+    pub async fn connect(
+        mut stream: S,
+        local: &CryptoManager,
+        local_device_id: &str,
+        peer_device_id: &str,
+        peer_public_key: &PublicKey,
+    ) -> Result<Self> {
+        let ephemeral = KeyPair::generate();
+        let msg1 = Self::hello(local, local_device_id, &ephemeral)?;
+        write_raw(&mut stream, &msg1).await?;
+
+        let msg2 = read_raw(&mut stream, MAX_HANDSHAKE_SIZE).await?;
+        let (peer, peer_ephemeral) = Self::parse_hello(&msg2)?;
+        if peer.device_id != peer_device_id || peer.public_key != *peer_public_key {
+            return Err(Error::Authentication(format!(
+                "对端身份与预期不符: {}",
+                peer.device_id
+            )));
+        }
+
+        let ee = crypto::x25519(&ephemeral.secret_key, &peer_ephemeral)?;
+        let es = crypto::x25519(&ephemeral.secret_key, &peer.public_key)?;
+        let se = local.key_agreement(&peer_ephemeral)?;
+        let (initiator_key, responder_key) = derive_session_keys(&msg1, &msg2, &[ee, es, se])?;
+
+        let mut channel = Self {
+            stream,
+            peer,
+            send_key: initiator_key,
+            recv_key: responder_key,
+            send_counter: 0,
+            recv_counter: 0,
+        };
+
+        let transcript = transcript_hash(&msg1, &msg2)?;
+        channel.send(&transcript).await?;
+        channel.expect_transcript(&transcript).await?;
+
+        Ok(channel)
+    }
+
+    /// 作为响应方建立加密会话
+    ///
+    /// 发起方不满足 `policy` 时立即返回错误，调用方应直接关闭连接。
+    pub async fn accept(
+        mut stream: S,
+        local: &CryptoManager,
+        local_device_id: &str,
+        policy: &PeerPolicy,
+    ) -> Result<Self> {
+        let msg1 = read_raw(&mut stream, MAX_HANDSHAKE_SIZE).await?;
+        let (peer, peer_ephemeral) = Self::parse_hello(&msg1)?;
+        policy.check(&peer.device_id, &peer.public_key).await?;
+
+        let ephemeral = KeyPair::generate();
+        let msg2 = Self::hello(local, local_device_id, &ephemeral)?;
+        write_raw(&mut stream, &msg2).await?;
+
+        let ee = crypto::x25519(&ephemeral.secret_key, &peer_ephemeral)?;
+        let es = local.key_agreement(&peer_ephemeral)?;
+        let se = crypto::x25519(&ephemeral.secret_key, &peer.public_key)?;
+        let (initiator_key, responder_key) = derive_session_keys(&msg1, &msg2, &[ee, es, se])?;
+
+        let mut channel = Self {
+            stream,
+            peer,
+            send_key: responder_key,
+            recv_key: initiator_key,
+            send_counter: 0,
+            recv_counter: 0,
+        };
+
+        let transcript = transcript_hash(&msg1, &msg2)?;
+        channel.expect_transcript(&transcript).await?;
+        channel.send(&transcript).await?;
+
+        Ok(channel)
+    }
+
+    /// 获取对端身份
+    pub fn peer(&self) -> &PeerIdentity {
+        &self.peer
+    }
+
+    /// 发送一个加密帧
+    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_FRAME_SIZE {
+            return Err(Error::InvalidArgument(format!(
+                "帧长度 {} 超过上限 {MAX_FRAME_SIZE}",
+                data.len()
+            )));
+        }
+
+        let nonce = counter_nonce(&mut self.send_counter)?;
+        let encrypted = secretbox::seal(data, &nonce, &self.send_key);
+        write_raw(&mut self.stream, &encrypted).await
+    }
+
+    /// 接收并解密一个帧
+    pub async fn recv(&mut self) -> Result<Vec<u8>> {
+        let encrypted = read_raw(&mut self.stream, MAX_FRAME_SIZE + secretbox::MACBYTES).await?;
+        let nonce = counter_nonce(&mut self.recv_counter)?;
+        secretbox::open(&encrypted, &nonce, &self.recv_key)
+            .map_err(|_| Error::Authentication("加密帧校验失败".to_string()))
+    }
+
+    /// 关闭发送方向
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stream
+            .shutdown()
+            .await
+            .map_err(|e| Error::Network(format!("关闭连接失败: {e}")))
+    }
+
+    /// 读取并校验对方的握手摘要
+    async fn expect_transcript(&mut self, transcript: &[u8]) -> Result<()> {
+        let confirm = self.recv().await.map_err(|_| {
+            Error::Authentication("握手确认失败，对方未持有声明的身份私钥".to_string())
+        })?;
+        if confirm != transcript {
+            return Err(Error::Authentication("握手记录不一致".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// 构造握手消息
+    fn hello(local: &CryptoManager, device_id: &str, ephemeral: &KeyPair) -> Result<Vec<u8>> {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            device_id: device_id.to_string(),
+            static_key: local.get_public_key_base64(),
+            ephemeral_key: ephemeral.public_key_base64(),
+        };
+
+        Ok(serde_json::to_vec(&hello)?)
+    }
+
+    /// 解析握手消息，返回对端身份和临时公钥
+    fn parse_hello(data: &[u8]) -> Result<(PeerIdentity, PublicKey)> {
+        let hello: Hello = serde_json::from_slice(data)?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(Error::Authentication(format!(
+                "不支持的握手协议版本: {}",
+                hello.version
+            )));
+        }
+
+        let peer = PeerIdentity {
+            device_id: hello.device_id,
+            public_key: KeyPair::public_key_from_base64(&hello.static_key)?,
+        };
+        let ephemeral = KeyPair::public_key_from_base64(&hello.ephemeral_key)?;
+
+        Ok((peer, ephemeral))
+    }
+}
+
+/// 派生两个方向的会话密钥，返回（发起方发送密钥，响应方发送密钥）
+fn derive_session_keys(
+    msg1: &[u8],
+    msg2: &[u8],
+    shared_secrets: &[[u8; 32]],
+) -> Result<(secretbox::Key, secretbox::Key)> {
+    let hash_error = |_| Error::Crypto("派生会话密钥失败".to_string());
+
+    let mut state = generichash::State::new(Some(2 * secretbox::KEYBYTES), None).map_err(hash_error)?;
+    state.update(PROTOCOL_NAME).map_err(hash_error)?;
+    for message in [msg1, msg2] {
+        state.update(&(message.len() as u32).to_be_bytes()).map_err(hash_error)?;
+        state.update(message).map_err(hash_error)?;
+    }
+    for secret in shared_secrets {
+        state.update(secret).map_err(hash_error)?;
+    }
+    let output = state.finalize().map_err(hash_error)?;
+
+    let (first, second) = output.as_ref().split_at(secretbox::KEYBYTES);
+    let initiator_key = secretbox::Key::from_slice(first).ok_or_else(|| hash_error(()))?;
+    let responder_key = secretbox::Key::from_slice(second).ok_or_else(|| hash_error(()))?;
+
+    Ok((initiator_key, responder_key))
+}
+
+/// 计算握手记录摘要
+fn transcript_hash(msg1: &[u8], msg2: &[u8]) -> Result<Vec<u8>> {
+    let hash_error = |_| Error::Crypto("计算握手摘要失败".to_string());
+
+    let mut state = generichash::State::new(Some(32), None).map_err(hash_error)?;
+    state.update(PROTOCOL_NAME).map_err(hash_error)?;
+    for message in [msg1, msg2] {
+        state.update(&(message.len() as u32).to_be_bytes()).map_err(hash_error)?;
+        state.update(message).map_err(hash_error)?;
+    }
+
+    Ok(state.finalize().map_err(hash_error)?.as_ref().to_vec())
+}
+
+/// 由帧计数生成nonce，并递增计数
+fn counter_nonce(counter: &mut u64) -> Result<secretbox::Nonce> {
+    let mut nonce = [0u8; secretbox::NONCEBYTES];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    *counter = counter
+        .checked_add(1)
+        .ok_or_else(|| Error::Crypto("会话帧计数溢出".to_string()))?;
+
+    Ok(secretbox::Nonce(nonce))
+}
+
+/// 写入带长度前缀的数据
+async fn write_raw<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> Result<()> {
+    let len_bytes = (data.len() as u32).to_be_bytes();
+    stream
+        .write_all(&len_bytes)
+        .await
+        .map_err(|e| Error::Network(format!("发送数据长度失败: {e}")))?;
+    stream
+        .write_all(data)
+        .await
+        .map_err(|e| Error::Network(format!("发送数据失败: {e}")))?;
+
+    Ok(())
+}
+
+/// 读取带长度前缀的数据，超过 `max_len` 时返回错误
+async fn read_raw<S: AsyncRead + Unpin>(stream: &mut S, max_len: usize) -> Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 4];
+    stream
+        .read_exact(&mut len_bytes)
+        .await
+        .map_err(|e| Error::Network(format!("读取数据长度失败: {e}")))?;
+
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max_len {
+        return Err(Error::Network(format!("数据长度 {len} 超过上限 {max_len}")));
+    }
+
+    let mut buffer = vec![0u8; len];
+    stream
+        .read_exact(&mut buffer)
+        .await
+        .map_err(|e| Error::Network(format!("读取数据失败: {e}")))?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// 返回只认识指定设备的策略
+    fn paired_with(device_id: &str, public_key: PublicKey) -> PeerPolicy {
+        let device_id = device_id.to_string();
+        PeerPolicy::Paired(Arc::new(move |id| (id == device_id).then_some(public_key)))
+    }
+
+    async fn handshake(
+        client: &CryptoManager,
+        server: &CryptoManager,
+        expected_server_key: PublicKey,
+        policy: PeerPolicy,
+    ) -> (Result<SecureChannel<DuplexStream>>, Result<SecureChannel<DuplexStream>>) {
+        let (a, b) = duplex(64 * 1024);
+        tokio::join!(
+            SecureChannel::connect(a, client, "client", "server", &expected_server_key),
+            SecureChannel::accept(b, server, "server", &policy),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_frames() {
+        crypto::init();
+        let client = CryptoManager::new();
+        let server = CryptoManager::new();
+
+        let policy = paired_with("client", client.public_key());
+        let (client_channel, server_channel) =
+            handshake(&client, &server, server.public_key(), policy).await;
+        let mut client_channel = client_channel.unwrap();
+        let mut server_channel = server_channel.unwrap();
+        assert_eq!(server_channel.peer().device_id, "client");
+        assert_eq!(client_channel.peer().public_key, server.public_key());
+
+        client_channel.send(b"hello").await.unwrap();
+        client_channel.send(b"").await.unwrap();
+        assert_eq!(server_channel.recv().await.unwrap(), b"hello");
+        assert!(server_channel.recv().await.unwrap().is_empty());
+
+        server_channel.send(b"world").await.unwrap();
+        assert_eq!(client_channel.recv().await.unwrap(), b"world");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unpaired_and_impostor() {
+        crypto::init();
+        let client = CryptoManager::new();
+        let server = CryptoManager::new();
+        let impostor = CryptoManager::new();
+
+        // 未配对的设备在握手阶段被拒绝
+        let (client_result, server_result) =
+            handshake(&client, &server, server.public_key(), PeerPolicy::deny_all()).await;
+        assert!(matches!(server_result, Err(Error::Authentication(_))));
+        assert!(client_result.is_err());
+
+        // 冒用已配对设备ID但没有对应私钥
+        let policy = paired_with("client", client.public_key());
+        let (_, server_result) = handshake(&impostor, &server, server.public_key(), policy).await;
+        assert!(matches!(server_result, Err(Error::Authentication(_))));
+
+        // 响应方不是预期的设备
+        let policy = paired_with("client", client.public_key());
+        let (client_result, _) = handshake(&client, &impostor, server.public_key(), policy).await;
+        assert!(matches!(client_result, Err(Error::Authentication(_))));
+    }
+
+    /// 使用接收端的会话状态解密一个原始帧
+    async fn deliver(frame: &[u8], receiver: &SecureChannel<DuplexStream>) -> Result<Vec<u8>> {
+        let (mut sender, stream) = duplex(64 * 1024);
+        write_raw(&mut sender, frame).await.unwrap();
+
+        let mut channel = SecureChannel {
+            stream,
+            peer: receiver.peer.clone(),
+            send_key: receiver.send_key.clone(),
+            recv_key: receiver.recv_key.clone(),
+            send_counter: receiver.send_counter,
+            recv_counter: receiver.recv_counter,
+        };
+        channel.recv().await
+    }
+
+    #[tokio::test]
+    async fn test_tampered_and_reordered_frames_rejected() {
+        crypto::init();
+        let client = CryptoManager::new();
+        let server = CryptoManager::new();
+
+        let (client_channel, server_channel) =
+            handshake(&client, &server, server.public_key(), PeerPolicy::AnyAuthenticated).await;
+        let mut client_channel = client_channel.unwrap();
+        let mut server_channel = server_channel.unwrap();
+
+        // 截获客户端发出的两个帧
+        client_channel.send(b"first").await.unwrap();
+        client_channel.send(b"second").await.unwrap();
+        let first = read_raw(&mut server_channel.stream, 1024).await.unwrap();
+        let second = read_raw(&mut server_channel.stream, 1024).await.unwrap();
+
+        // 调换顺序
+        assert!(matches!(deliver(&second, &server_channel).await, Err(Error::Authentication(_))));
+
+        // 篡改内容
+        let mut tampered = first.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(deliver(&tampered, &server_channel).await, Err(Error::Authentication(_))));
+
+        assert_eq!(deliver(&first, &server_channel).await.unwrap(), b"first");
+    }
+}
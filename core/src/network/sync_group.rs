@@ -0,0 +1,435 @@
+//! 同步组
+//!
+//! 同步组是一组共享组密钥的设备。剪贴板内容使用组密钥加密一次后即可发送给所有成员；
+//! 新设备只需与任一成员配对，由该成员将其加入同步组并把新的组密钥分发给所有成员。
+//! 成员加入或移除时都会轮换组密钥，被移除的设备无法解密之后的内容。
+//! 成员列表带有各成员的公钥和最近地址，成员之间即使没有直接配对也能互相认证和分发密钥。
+//! 两个成员同时轮换出相同版本的密钥时，所有成员都保留摘要较大的密钥，避免组被分裂。
+
+use crate::{
+    crypto::{self, GroupKey},
+    error::{Error, Result},
+    storage::{Storage, SyncGroupRecord},
+    types::{ContentPacket, DeviceInfo, GroupContentPacket, GroupMember, Message, MessageType},
+};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// 同步组
+#[derive(Debug, Clone)]
+pub struct SyncGroup {
+    /// 同步组ID
+    pub id: String,
+    /// 同步组名称
+    pub name: String,
+    /// 组密钥版本，每次轮换递增
+    pub epoch: u64,
+    /// 成员列表
+    pub members: Vec<GroupMember>,
+    /// 当前组密钥
+    key: GroupKey,
+}
+
+impl SyncGroup {
+    /// 创建只包含本设备的同步组
+    pub fn new(name: &str, local_device: &DeviceInfo) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            epoch: 1,
+            members: vec![GroupMember::from_device(local_device)],
+            key: GroupKey::generate(),
+        }
+    }
+
+    /// 设备是否为组成员
+    pub fn is_member(&self, device_id: &str) -> bool {
+        self.members.iter().any(|m| m.device_id == device_id)
+    }
+
+    /// 添加成员并轮换组密钥
+    pub fn add_member(&mut self, device: &DeviceInfo) -> Result<()> {
+        if self.is_member(&device.id) {
+            return Err(Error::InvalidArgument(format!("设备已在同步组中: {}", device.id)));
+        }
+
+        self.members.push(GroupMember::from_device(device));
+        self.rotate();
+
+        Ok(())
+    }
+
+    /// 移除成员并轮换组密钥
+    pub fn remove_member(&mut self, device_id: &str) -> Result<()> {
+        if !self.is_member(device_id) {
+            return Err(Error::InvalidArgument(format!("设备不在同步组中: {device_id}")));
+        }
+
+        self.members.retain(|m| m.device_id != device_id);
+        self.rotate();
+
+        Ok(())
+    }
+
+    /// 生成新的组密钥
+    fn rotate(&mut self) {
+        self.key = GroupKey::generate();
+        self.epoch += 1;
+    }
+
+    /// 使用组密钥加密内容包
+    pub fn encrypt(&self, packet: &ContentPacket) -> Result<GroupContentPacket> {
+        if !self.is_member(&packet.device_id) {
+            return Err(Error::Authentication(format!("设备不在同步组中: {}", packet.device_id)));
+        }
+
+        Ok(GroupContentPacket {
+            group_id: self.id.clone(),
+            epoch: self.epoch,
+            sender_id: packet.device_id.clone(),
+            data: base64::encode(self.key.seal(&serde_json::to_vec(packet)?)),
+        })
+    }
+
+    /// 解密组内容包
+    ///
+    /// 拒绝旧版本密钥加密的内容，以及非成员或冒用发送方身份的内容。
+    pub fn decrypt(&self, packet: &GroupContentPacket) -> Result<ContentPacket> {
+        if packet.group_id != self.id {
+            return Err(Error::InvalidArgument(format!("不属于同步组 {} 的内容包", self.id)));
+        }
+        if packet.epoch != self.epoch {
+            return Err(Error::Authentication(format!(
+                "组密钥版本不匹配: 期望 {}，实际 {}",
+                self.epoch, packet.epoch
+            )));
+        }
+        if !self.is_member(&packet.sender_id) {
+            return Err(Error::Authentication(format!("发送方不在同步组中: {}", packet.sender_id)));
+        }
+
+        let sealed = base64::decode(&packet.data)
+            .map_err(|e| Error::Crypto(format!("解析组内容包失败: {e}")))?;
+        let content: ContentPacket = serde_json::from_slice(&self.key.open(&sealed)?)?;
+        if content.device_id != packet.sender_id {
+            return Err(Error::Authentication("组内容包的发送方不一致".to_string()));
+        }
+
+        Ok(content)
+    }
+
+    /// 生成发送给指定成员的组密钥更新消息
+    pub fn key_update(&self, sender_id: &str, member: &GroupMember) -> Result<Message> {
+        let sealed = self.key.seal_for_member(&*crypto::manager()?, &member.public_key)?;
+
+        Ok(Message::new(
+            sender_id,
+            MessageType::GroupKeyUpdate {
+                group_id: self.id.clone(),
+                name: self.name.clone(),
+                epoch: self.epoch,
+                members: self.members.clone(),
+                sealed_key: base64::encode(sealed),
+            },
+            false,
+            Some(&member.device_id),
+        ))
+    }
+
+    /// 转换为存储记录
+    fn to_record(&self) -> Result<SyncGroupRecord> {
+        Ok(SyncGroupRecord {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            epoch: self.epoch,
+            group_key: self.key.as_bytes().to_vec(),
+            members: serde_json::to_string(&self.members)?,
+        })
+    }
+
+    /// 从存储记录恢复
+    fn from_record(record: &SyncGroupRecord) -> Result<Self> {
+        Ok(Self {
+            id: record.id.clone(),
+            name: record.name.clone(),
+            epoch: record.epoch,
+            members: serde_json::from_str(&record.members)?,
+            key: GroupKey::from_bytes(&record.group_key)?,
+        })
+    }
+}
+
+/// 同步组管理器
+///
+/// 可在多个服务之间共享，克隆后指向同一组数据。
+#[derive(Clone)]
+pub struct SyncGroupManager {
+    /// 本地设备ID
+    local_device_id: String,
+    /// 同步组 (组ID -> 同步组)
+    groups: Arc<RwLock<HashMap<String, SyncGroup>>>,
+    /// 持久化存储，未设置时仅保存在内存中
+    storage: Option<Arc<Storage>>,
+}
+
+impl SyncGroupManager {
+    /// 创建仅保存在内存中的同步组管理器
+    pub fn new(local_device_id: &str) -> Self {
+        Self {
+            local_device_id: local_device_id.to_string(),
+            groups: Arc::new(RwLock::new(HashMap::new())),
+            storage: None,
+        }
+    }
+
+    /// 从存储加载同步组
+    pub fn load(local_device_id: &str, storage: Arc<Storage>) -> Result<Self> {
+        let mut groups = HashMap::new();
+        for record in storage.get_sync_groups()? {
+            match SyncGroup::from_record(&record) {
+                Ok(group) => {
+                    groups.insert(group.id.clone(), group);
+                }
+                Err(e) => warn!("加载同步组 {} 失败: {e:?}", record.id),
+            }
+        }
+
+        Ok(Self {
+            local_device_id: local_device_id.to_string(),
+            groups: Arc::new(RwLock::new(groups)),
+            storage: Some(storage),
+        })
+    }
+
+    /// 获取所有同步组
+    pub fn groups(&self) -> Vec<SyncGroup> {
+        let groups = self.groups.read().unwrap_or_else(|e| e.into_inner());
+        groups.values().cloned().collect()
+    }
+
+    /// 获取同步组
+    pub fn group(&self, group_id: &str) -> Option<SyncGroup> {
+        let groups = self.groups.read().unwrap_or_else(|e| e.into_inner());
+        groups.get(group_id).cloned()
+    }
+
+    /// 创建同步组，返回组ID
+    pub fn create_group(&self, name: &str, local_device: &DeviceInfo) -> Result<String> {
+        let group = SyncGroup::new(name, local_device);
+        let group_id = group.id.clone();
+        self.store(group)?;
+        info!("创建同步组: {name} ({group_id})");
+
+        Ok(group_id)
+    }
+
+    /// 将已配对设备加入同步组
+    ///
+    /// 组密钥随之轮换，返回需要发送给其他成员的密钥更新消息。
+    pub fn add_member(&self, group_id: &str, device: &DeviceInfo) -> Result<Vec<Message>> {
+        self.update_membership(group_id, |group| group.add_member(device))
+    }
+
+    /// 将设备移出同步组
+    ///
+    /// 组密钥随之轮换，被移除的设备不会收到新密钥。
+    pub fn remove_member(&self, group_id: &str, device_id: &str) -> Result<Vec<Message>> {
+        self.update_membership(group_id, |group| group.remove_member(device_id))
+    }
+
+    /// 修改成员并生成密钥更新消息
+    fn update_membership<F>(&self, group_id: &str, change: F) -> Result<Vec<Message>>
+    where
+        F: FnOnce(&mut SyncGroup) -> Result<()>,
+    {
+        let mut group = self
+            .group(group_id)
+            .ok_or_else(|| Error::InvalidArgument(format!("同步组不存在: {group_id}")))?;
+        change(&mut group)?;
+
+        let updates = group
+            .members
+            .iter()
+            .filter(|m| m.device_id != self.local_device_id)
+            .map(|m| group.key_update(&self.local_device_id, m))
+            .collect::<Result<Vec<_>>>()?;
+        self.store(group)?;
+
+        Ok(updates)
+    }
+
+    /// 任一同步组中该成员的公钥，供传输服务认证未直接配对的成员
+    pub fn member_key(&self, device_id: &str) -> Option<String> {
+        let groups = self.groups.read().unwrap_or_else(|e| e.into_inner());
+        groups
+            .values()
+            .flat_map(|group| group.members.iter())
+            .find(|member| member.device_id == device_id)
+            .map(|member| member.public_key.clone())
+    }
+
+    /// 使用组密钥加密内容包
+    pub fn encrypt(&self, group_id: &str, packet: &ContentPacket) -> Result<GroupContentPacket> {
+        self.group(group_id)
+            .ok_or_else(|| Error::InvalidArgument(format!("同步组不存在: {group_id}")))?
+            .encrypt(packet)
+    }
+
+    /// 解密组内容包
+    pub fn decrypt(&self, packet: &GroupContentPacket) -> Result<ContentPacket> {
+        self.group(&packet.group_id)
+            .ok_or_else(|| Error::InvalidArgument(format!("同步组不存在: {}", packet.group_id)))?
+            .decrypt(packet)
+    }
+
+    /// 处理其他成员发来的组密钥更新
+    ///
+    /// `sender_id` 为会话认证的发送方，必须是同步组的现有成员（首次加入时为邀请方）。
+    /// 版本相同但密钥不同时说明两个成员并发轮换，保留摘要较大的密钥。返回是否接受了更新。
+    pub fn apply_key_update(&self, sender_id: &str, message: &Message) -> Result<bool> {
+        let MessageType::GroupKeyUpdate { group_id, name, epoch, members, sealed_key } = &message.message_type else {
+            return Ok(false);
+        };
+
+        let current = self.group(group_id);
+        let sender_allowed = match &current {
+            Some(group) => group.is_member(sender_id),
+            None => members.iter().any(|m| m.device_id == sender_id),
+        };
+        if !sender_allowed {
+            return Err(Error::Authentication(format!("{sender_id} 不是同步组 {group_id} 的成员")));
+        }
+        if current.as_ref().is_some_and(|group| *epoch < group.epoch) {
+            return Ok(false);
+        }
+        let same_epoch = current.as_ref().filter(|group| group.epoch == *epoch);
+
+        // 本机被移出同步组
+        if !members.iter().any(|m| m.device_id == self.local_device_id) {
+            if same_epoch.is_some() {
+                return Ok(false);
+            }
+            info!("已被移出同步组: {name} ({group_id})");
+            self.remove(group_id)?;
+            return Ok(true);
+        }
+
+        let sealed = base64::decode(sealed_key)
+            .map_err(|e| Error::Crypto(format!("解析组密钥失败: {e}")))?;
+        let key = GroupKey::open_from_member(&*crypto::manager()?, &sealed)?;
+        if let Some(group) = same_epoch {
+            if key.digest()? <= group.key.digest()? {
+                return Ok(false);
+            }
+            warn!("同步组 {name} 存在并发的密钥轮换，改用摘要较大的版本 {epoch} 密钥");
+        }
+        self.store(SyncGroup {
+            id: group_id.clone(),
+            name: name.clone(),
+            epoch: *epoch,
+            members: members.clone(),
+            key,
+        })?;
+        info!("同步组 {name} 的密钥已更新到版本 {epoch}");
+
+        Ok(true)
+    }
+
+    /// 保存同步组
+    fn store(&self, group: SyncGroup) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.save_sync_group(&group.to_record()?)?;
+        }
+
+        let mut groups = self.groups.write().unwrap_or_else(|e| e.into_inner());
+        groups.insert(group.id.clone(), group);
+        Ok(())
+    }
+
+    /// 删除同步组
+    fn remove(&self, group_id: &str) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.delete_sync_group(group_id)?;
+        }
+
+        let mut groups = self.groups.write().unwrap_or_else(|e| e.into_inner());
+        groups.remove(group_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ContentMetadata, DeviceType};
+
+    fn content(device_id: &str, text: &str) -> ContentPacket {
+        let metadata = ContentMetadata {
+            filename: None,
+            size: text.len() as u64,
+            mime_type: "text/plain".to_string(),
+        };
+        ContentPacket::new(device_id, "text", text.as_bytes(), metadata)
+    }
+
+    #[test]
+    fn test_group_membership_and_rotation() {
+        crypto::init();
+        let local_public_key = crypto::get_public_key().unwrap();
+        let laptop = DeviceInfo::new("笔记本", DeviceType::Desktop, &local_public_key);
+        // 测试中所有设备共享同一加密模块，使用相同的身份公钥
+        let phone = DeviceInfo::new("手机", DeviceType::Mobile, &local_public_key);
+        let tablet = DeviceInfo::new("平板", DeviceType::Mobile, &local_public_key);
+
+        let storage = Arc::new(Storage::new(":memory:").unwrap());
+        let owner = SyncGroupManager::load(&laptop.id, storage.clone()).unwrap();
+        let group_id = owner.create_group("家庭", &laptop).unwrap();
+
+        // 手机只与笔记本配对一次，通过密钥更新加入同步组
+        let updates = owner.add_member(&group_id, &phone).unwrap();
+        assert_eq!(updates.len(), 1);
+        let member = SyncGroupManager::new(&phone.id);
+        assert!(member.apply_key_update(&laptop.id, &updates[0]).unwrap());
+        assert!(!member.apply_key_update(&laptop.id, &updates[0]).unwrap());
+        assert!(member.apply_key_update(&tablet.id, &updates[0]).is_err());
+
+        // 内容只加密一次，所有成员均可解密
+        let packet = owner.encrypt(&group_id, &content(&laptop.id, "hello")).unwrap();
+        assert_eq!(member.decrypt(&packet).unwrap().content, base64::encode("hello"));
+        assert!(owner.encrypt(&group_id, &content(&tablet.id, "x")).is_err());
+
+        // 移除成员后密钥轮换，旧密钥加密的内容被拒绝
+        owner.add_member(&group_id, &tablet).unwrap();
+        let updates = owner.remove_member(&group_id, &phone.id).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].receiver_id.as_deref(), Some(tablet.id.as_str()));
+        let new_packet = owner.encrypt(&group_id, &content(&laptop.id, "secret")).unwrap();
+        assert!(member.decrypt(&new_packet).is_err());
+        assert!(owner.decrypt(&packet).is_err());
+
+        // 组数据持久化
+        let reloaded = SyncGroupManager::load(&laptop.id, storage).unwrap();
+        let group = reloaded.group(&group_id).unwrap();
+        assert_eq!(group.epoch, 4);
+        assert_eq!(group.members.len(), 2);
+        assert_eq!(reloaded.decrypt(&new_packet).unwrap().device_id, laptop.id);
+
+        // 成员列表带有公钥，未直接配对的成员也能互相认证
+        assert_eq!(reloaded.member_key(&tablet.id), Some(local_public_key.clone()));
+        assert_eq!(reloaded.member_key(&phone.id), None);
+
+        // 两个成员并发轮换出相同版本时，双方都保留摘要较大的密钥
+        let tablet_groups = SyncGroupManager::new(&tablet.id);
+        let updates = reloaded.add_member(&group_id, &phone).unwrap();
+        let to_tablet = updates.iter().find(|u| u.receiver_id.as_deref() == Some(tablet.id.as_str())).unwrap();
+        assert!(tablet_groups.apply_key_update(&laptop.id, to_tablet).unwrap());
+        let from_laptop = reloaded.remove_member(&group_id, &phone.id).unwrap().remove(0);
+        let from_tablet = tablet_groups.remove_member(&group_id, &phone.id).unwrap().remove(0);
+        reloaded.apply_key_update(&tablet.id, &from_tablet).unwrap();
+        tablet_groups.apply_key_update(&laptop.id, &from_laptop).unwrap();
+        let packet = reloaded.encrypt(&group_id, &content(&laptop.id, "same")).unwrap();
+        assert_eq!(packet.epoch, 6);
+        assert_eq!(tablet_groups.decrypt(&packet).unwrap().device_id, laptop.id);
+    }
+}
@@ -1,8 +1,17 @@
 //! 数据传输协议实现
 
 use crate::{
+    clipboard::{ClipboardHistory, Tombstone},
+    crypto::{self, KeyPair},
     error::{Error, Result},
-    types::{ContentPacket, DeviceInfo},
+    network::{
+        key_pinning::{self, KeyPins, KeyRotationCallback},
+        replay::ReplayGuard,
+        revocation::RevocationList,
+        secure_channel::{PeerKeyLookup, PeerPolicy, SecureChannel},
+        sync_group::SyncGroupManager,
+    },
+    types::{ContentMetadata, ContentPacket, DeviceInfo, GroupContentPacket, Message, MessageType},
 };
 use log::{error, info, warn};
 use std::collections::HashMap;
@@ -10,6 +19,14 @@ use std::sync::{Arc, Mutex};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 
+/// 内容同步端口相对配对监听端口的偏移，配对端口+1为文件传输端口
+pub const SYNC_PORT_OFFSET: u16 = 2;
+
+/// 按配对监听端口计算内容同步端口
+pub fn sync_port(pairing_port: u16) -> u16 {
+    pairing_port.wrapping_add(SYNC_PORT_OFFSET)
+}
+
 /// 传输服务状态回调函数类型
 pub type TransportCallback = Arc<dyn Fn(DeviceInfo, Vec<u8>) + Send + Sync + 'static>;
 
@@ -23,19 +40,82 @@ pub struct TransportService {
     stop_tx: Option<mpsc::Sender<()>>,
     /// 监听端口
     listen_port: u16,
+    /// 入站连接的认证策略
+    peer_policy: PeerPolicy,
+    /// 内容包的防重放检查
+    replay_guard: Arc<ReplayGuard>,
+    /// 设备吊销列表
+    revocations: RevocationList,
+    /// 同步组
+    sync_groups: SyncGroupManager,
+    /// 设备公钥固定表
+    key_pins: KeyPins,
+    /// 剪贴板历史记录，设置后应用其他设备发来的删除标记
+    history: Option<Arc<ClipboardHistory>>,
+    /// 已配对设备轮换身份密钥后的回调
+    key_rotation_callback: Option<Arc<KeyRotationCallback>>,
 }
 
 impl TransportService {
-    /// 创建新的数据传输服务
-    pub fn new(local_device: DeviceInfo) -> Self {
+    /// 创建新的数据传输服务，在 `listen_port` 上接收内容
+    pub fn new(local_device: DeviceInfo, listen_port: u16) -> Self {
         Self {
-            local_device,
             sessions: Arc::new(Mutex::new(HashMap::new())),
             stop_tx: None,
-            listen_port: 45680,
+            listen_port,
+            peer_policy: PeerPolicy::deny_all(),
+            replay_guard: Arc::new(ReplayGuard::new()),
+            revocations: RevocationList::new(),
+            sync_groups: SyncGroupManager::new(&local_device.id),
+            key_pins: KeyPins::new(),
+            history: None,
+            key_rotation_callback: None,
+            local_device,
         }
     }
 
+    /// 设置已配对设备公钥的查询函数
+    ///
+    /// 未设置时拒绝所有入站连接。
+    pub fn set_peer_lookup(&mut self, lookup: PeerKeyLookup) {
+        self.peer_policy = PeerPolicy::Paired(lookup);
+    }
+
+    /// 设置共享的设备吊销列表
+    ///
+    /// 已吊销设备的连接会被拒绝，收到的吊销通知也会写入该列表。
+    pub fn set_revocation_list(&mut self, revocations: RevocationList) {
+        self.revocations = revocations;
+    }
+
+    /// 设置共享的同步组管理器
+    ///
+    /// 用于解密组内容包和处理组密钥更新。
+    pub fn set_sync_groups(&mut self, sync_groups: SyncGroupManager) {
+        self.sync_groups = sync_groups;
+    }
+
+    /// 设置共享的设备公钥固定表
+    ///
+    /// 公钥变更后尚未重新验证的设备既不能连接，也不会收到数据。
+    pub fn set_key_pins(&mut self, key_pins: KeyPins) {
+        self.key_pins = key_pins;
+    }
+
+    /// 设置共享的剪贴板历史记录
+    ///
+    /// 设置后，其他设备发来的删除标记会将本地对应条目移入回收站。
+    pub fn set_history(&mut self, history: Arc<ClipboardHistory>) {
+        self.history = Some(history);
+    }
+
+    /// 设置密钥轮换回调
+    ///
+    /// 已配对设备发来的轮换通知通过签名验证并固定新公钥后调用，由调用方更新设备记录。
+    pub fn set_key_rotation_callback(&mut self, callback: KeyRotationCallback) {
+        self.key_rotation_callback = Some(Arc::new(callback));
+    }
+
     /// 启动数据传输服务
     pub async fn start(&mut self, callback: TransportCallback) -> Result<()> {
         if self.stop_tx.is_some() {
@@ -45,12 +125,20 @@ impl TransportService {
 
         info!("启动数据传输服务");
 
+        let manager = crypto::manager()?;
         let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
         self.stop_tx = Some(stop_tx);
 
         let sessions = self.sessions.clone();
-        let _local_device = self.local_device.clone();
+        let local_device_id = self.local_device.id.clone();
         let listen_port = self.listen_port;
+        let peer_policy = self.peer_policy.clone();
+        let replay_guard = self.replay_guard.clone();
+        let revocations = self.revocations.clone();
+        let sync_groups = self.sync_groups.clone();
+        let key_pins = self.key_pins.clone();
+        let history = self.history.clone();
+        let key_rotation_callback = self.key_rotation_callback.clone();
 
         // 启动监听任务
         tokio::spawn(async move {
@@ -76,40 +164,90 @@ impl TransportService {
                                 let _sessions_clone = sessions.clone();
                                 // 使用Arc而不是clone回调
                                 let callback = Arc::clone(&callback);
+                                let manager = manager.clone();
+                                let local_device_id = local_device_id.clone();
+                                let peer_policy = peer_policy.clone();
+                                let replay_guard = replay_guard.clone();
+                                let revocations = revocations.clone();
+                                let sync_groups = sync_groups.clone();
+                                let key_pins = key_pins.clone();
+                                let history = history.clone();
+                                let key_rotation_callback = key_rotation_callback.clone();
                                 tokio::spawn(async move {
-                                    // 这里应该进行认证
-                                    // 简化起见，直接接受连接
-
-                                    // 读取数据
-                                    let mut buffer = Vec::new();
-                                    let socket = socket;
-
-                                    // 读取头部长度
-                                    let mut header_buf = [0u8; 4];
-                                    if let Err(e) = socket.try_read(&mut header_buf) {
-                                        error!("读取数据头部失败: {e:?}");
+                                    // 认证失败的连接在读取任何数据前关闭
+                                    let mut channel = match SecureChannel::accept(socket, &manager, &local_device_id, &peer_policy).await {
+                                        Ok(channel) => channel,
+                                        Err(e) => {
+                                            warn!("拒绝来自 {addr} 的连接: {e:?}");
+                                            return;
+                                        }
+                                    };
+                                    let peer = channel.peer().clone();
+                                    if revocations.is_revoked(&peer.device_id, &peer.public_key_base64()) {
+                                        warn!("拒绝已吊销设备的连接: {}", peer.device_id);
                                         return;
                                     }
-
-                                    let length = u32::from_be_bytes(header_buf) as usize;
-                                    buffer.resize(length, 0);
-
-                                    // 读取数据体
-                                    if let Err(e) = socket.try_read(&mut buffer) {
-                                        error!("读取数据体失败: {e:?}");
+                                    if key_pins.is_blocked(&peer.device_id) {
+                                        warn!("拒绝公钥已变更设备的连接: {}", peer.device_id);
                                         return;
                                     }
 
+                                    // 读取数据
+                                    let buffer = match channel.recv().await {
+                                        Ok(buffer) => buffer,
+                                        Err(e) => {
+                                            error!("读取数据失败: {e:?}");
+                                            return;
+                                        }
+                                    };
+
+                                    // 组内容包解密后按普通内容包处理
+                                    let (packet, buffer) = match serde_json::from_slice::<GroupContentPacket>(&buffer) {
+                                        Ok(group_packet) => {
+                                            if group_packet.sender_id != peer.device_id {
+                                                warn!("组内容包发送方与会话身份不一致: {}", group_packet.sender_id);
+                                                return;
+                                            }
+                                            let packet = match sync_groups.decrypt(&group_packet) {
+                                                Ok(packet) => packet,
+                                                Err(e) => {
+                                                    warn!("丢弃组内容包: {e:?}");
+                                                    return;
+                                                }
+                                            };
+                                            match serde_json::to_vec(&packet) {
+                                                Ok(buffer) => (Some(packet), buffer),
+                                                Err(e) => {
+                                                    error!("序列化内容包失败: {e:?}");
+                                                    return;
+                                                }
+                                            }
+                                        }
+                                        Err(_) => (serde_json::from_slice::<ContentPacket>(&buffer).ok(), buffer),
+                                    };
+
                                     // 解析内容包
-                                    if let Ok(packet) = serde_json::from_slice::<ContentPacket>(&buffer) {
+                                    if let Some(packet) = packet {
+                                        if packet.device_id != peer.device_id {
+                                            warn!("内容包设备ID与会话身份不一致: {}", packet.device_id);
+                                            return;
+                                        }
+                                        if let Err(e) = replay_guard.check(&packet) {
+                                            warn!("丢弃内容包: {e:?}");
+                                            return;
+                                        }
+
                                         // 获取设备信息
                                         let device = DeviceInfo {
                                             id: packet.device_id,
-                                            name: "远程设备".to_string(), // 这里应从会话中获取
+                                            name: "远程设备".to_string(), // 这里应从已配对设备中获取
                                             device_type: crate::types::DeviceType::Unknown,
-                                            public_key: "".to_string(), // 这里应从会话中获取
+                                            public_key: peer.public_key_base64(),
+                                            verify_key: String::new(),
                                             online: true,
-                                            ip_address: None,
+                                            ip_address: Some(addr.ip().to_string()),
+                                            pairing_port: None,
+                                            transfer_port: None,
                                             system_version: None,
                                             app_version: None,
                                             capabilities: crate::types::DeviceCapabilities::default(),
@@ -117,13 +255,55 @@ impl TransportService {
                                                 .duration_since(std::time::UNIX_EPOCH)
                                                 .unwrap_or_default()
                                                 .as_secs()),
-                                            pairing_status: crate::types::PairingStatus::default(),
+                                            pairing_status: crate::types::PairingStatus::Paired,
                                             description: None,
                                             trusted: false,
+                                            announcement_verified: false,
+                                            addresses: Vec::new(),
+                                            namespace: None,
                                         };
 
                                         // 触发回调
                                         callback(device, buffer);
+                                    } else if let Ok(message) = serde_json::from_slice::<Message>(&buffer) {
+                                        // 控制消息
+                                        if message.sender_id != peer.device_id {
+                                            warn!("消息发送方与会话身份不一致: {}", message.sender_id);
+                                            return;
+                                        }
+                                        if let Err(e) = replay_guard.check(&message) {
+                                            warn!("丢弃控制消息: {e:?}");
+                                            return;
+                                        }
+                                        let result = match &message.message_type {
+                                            MessageType::DeviceRevoked { .. } => {
+                                                revocations.apply_notice(&local_device_id, &peer.device_id, &message)
+                                            }
+                                            MessageType::GroupKeyUpdate { .. } => {
+                                                sync_groups.apply_key_update(&peer.device_id, &message)
+                                            }
+                                            MessageType::HistoryTombstone { content_hash, deleted_at } => match &history {
+                                                Some(history) => history
+                                                    .apply_tombstone(&Tombstone {
+                                                        content_hash: content_hash.clone(),
+                                                        deleted_at: *deleted_at,
+                                                    })
+                                                    .map(|count| count > 0),
+                                                None => Ok(false),
+                                            },
+                                            MessageType::KeyRotation { .. } => {
+                                                key_pins.apply_rotation(&peer.device_id, &message).map(|rotation| {
+                                                    if let (Some(rotation), Some(callback)) = (&rotation, &key_rotation_callback) {
+                                                        callback(rotation);
+                                                    }
+                                                    rotation.is_some()
+                                                })
+                                            }
+                                            _ => Ok(false),
+                                        };
+                                        if let Err(e) = result {
+                                            error!("处理控制消息失败: {e:?}");
+                                        }
                                     }
                                 });
                             }
@@ -156,8 +336,17 @@ impl TransportService {
     }
 
     /// 发送数据到指定设备
+    ///
+    /// 目标端口由设备的配对端口推算，未知时假定对方与本机使用相同端口。
     pub async fn send_data(&self, device: &DeviceInfo, data: &[u8]) -> Result<()> {
-        let addr = format!("{}:{}", device.id, self.listen_port);
+        if self.key_pins.is_blocked(&device.id) {
+            return Err(Error::Authentication(format!("设备 {} 的公钥已变更，需重新验证", device.id)));
+        }
+        let port = device.pairing_port.map(sync_port).unwrap_or(self.listen_port);
+        let addr = device
+            .socket_address(port)
+            .ok_or_else(|| Error::Network("设备IP地址未知".to_string()))?;
+        let peer_public_key = KeyPair::public_key_from_base64(&device.public_key)?;
 
         // 连接到目标设备
         let stream = match TcpStream::connect(addr).await {
@@ -168,22 +357,148 @@ impl TransportService {
             }
         };
 
-        // 发送数据长度头部
-        let length = data.len() as u32;
-        let length_bytes = length.to_be_bytes();
+        // 建立加密会话
+        let manager = crypto::manager()?;
+        let mut channel = SecureChannel::connect(
+            stream,
+            &manager,
+            &self.local_device.id,
+            &device.id,
+            &peer_public_key,
+        )
+        .await?;
 
-        if let Err(e) = stream.try_write(&length_bytes) {
-            error!("发送数据长度失败: {e:?}");
-            return Err(Error::Network("发送数据长度失败".to_string()));
+        // 发送数据
+        if let Err(e) = channel.send(data).await {
+            error!("发送数据失败: {e:?}");
+            return Err(Error::Network("发送数据失败".to_string()));
         }
 
-        // 发送数据体
-        if let Err(e) = stream.try_write(data) {
-            error!("发送数据体失败: {e:?}");
-            return Err(Error::Network("发送数据体失败".to_string()));
+        Ok(())
+    }
+
+    /// 发送控制消息到指定设备
+    pub async fn send_message(&self, device: &DeviceInfo, message: &Message) -> Result<()> {
+        self.send_data(device, &serde_json::to_vec(message)?).await
+    }
+
+    /// 发送内容到指定设备
+    pub async fn send_content(
+        &self,
+        device: &DeviceInfo,
+        content_type: &str,
+        content: &[u8],
+        metadata: ContentMetadata,
+    ) -> Result<()> {
+        let packet = ContentPacket::new(&self.local_device.id, content_type, content, metadata);
+        self.send_data(device, &serde_json::to_vec(&packet)?).await
+    }
+
+    /// 使用组密钥加密内容，并发送给同步组的其他成员
+    ///
+    /// 内容只加密一次，返回成功送达的设备数。
+    pub async fn send_to_group(
+        &self,
+        group_id: &str,
+        content_type: &str,
+        content: &[u8],
+        metadata: ContentMetadata,
+        devices: &[DeviceInfo],
+    ) -> Result<usize> {
+        let group = self
+            .sync_groups
+            .group(group_id)
+            .ok_or_else(|| Error::InvalidArgument(format!("同步组不存在: {group_id}")))?;
+        let packet = ContentPacket::new(&self.local_device.id, content_type, content, metadata);
+        let data = serde_json::to_vec(&group.encrypt(&packet)?)?;
+
+        let mut delivered = 0;
+        for device in devices {
+            if device.id == self.local_device.id || !group.is_member(&device.id) {
+                continue;
+            }
+            match self.send_data(device, &data).await {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("向设备 {} 发送组内容失败: {e:?}", device.id),
+            }
         }
 
-        Ok(())
+        Ok(delivered)
+    }
+
+    /// 将历史记录删除标记发送给其他已配对设备
+    ///
+    /// 逐个尝试发送，离线设备会被跳过，返回成功送达的设备数。
+    pub async fn send_tombstone(&self, tombstone: &Tombstone, recipients: &[DeviceInfo]) -> usize {
+        let mut delivered = 0;
+        for recipient in recipients {
+            if recipient.id == self.local_device.id {
+                continue;
+            }
+
+            let message = Message::new(
+                &self.local_device.id,
+                MessageType::HistoryTombstone {
+                    content_hash: tombstone.content_hash.clone(),
+                    deleted_at: tombstone.deleted_at,
+                },
+                false,
+                Some(&recipient.id),
+            );
+            match self.send_message(recipient, &message).await {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("向设备 {} 发送删除标记失败: {e:?}", recipient.id),
+            }
+        }
+
+        delivered
+    }
+
+    /// 通知其他已配对设备本机即将轮换身份密钥
+    ///
+    /// 通知使用当前的签名私钥签名，必须在新密钥生效前调用。逐个尝试发送，离线设备会被跳过，
+    /// 返回成功通知的设备数。
+    pub async fn notify_key_rotation(&self, public_key: &str, verify_key: &str, recipients: &[DeviceInfo]) -> usize {
+        let mut notified = 0;
+        for recipient in recipients {
+            if recipient.id == self.local_device.id {
+                continue;
+            }
+
+            let message = match key_pinning::rotation_notice(&self.local_device.id, public_key, verify_key, &recipient.id) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("创建密钥轮换通知失败: {e:?}");
+                    return notified;
+                }
+            };
+            match self.send_message(recipient, &message).await {
+                Ok(()) => notified += 1,
+                Err(e) => warn!("向设备 {} 发送密钥轮换通知失败: {e:?}", recipient.id),
+            }
+        }
+
+        notified
+    }
+
+    /// 通知其他已配对设备某设备已被吊销
+    ///
+    /// 逐个尝试发送，离线设备会被跳过，返回成功通知的设备数。
+    pub async fn notify_revocation(&self, revoked: &DeviceInfo, recipients: &[DeviceInfo]) -> usize {
+        let mut notified = 0;
+        for recipient in recipients {
+            if recipient.id == revoked.id || recipient.id == self.local_device.id {
+                continue;
+            }
+
+            let message = RevocationList::notice(&self.local_device.id, revoked, &recipient.id);
+            match self.send_message(recipient, &message).await {
+                Ok(()) => notified += 1,
+                Err(e) => warn!("向设备 {} 发送吊销通知失败: {e:?}", recipient.id),
+            }
+        }
+
+        notified
     }
 }
 
@@ -196,7 +511,7 @@ mod tests {
     fn test_transport_service_creation() {
         let device = DeviceInfo::new("Test Device", DeviceType::Desktop, "test_public_key");
 
-        let transport = TransportService::new(device);
-        assert_eq!(transport.listen_port, 45680);
+        let transport = TransportService::new(device, sync_port(45680));
+        assert_eq!(transport.listen_port, 45682);
     }
 }
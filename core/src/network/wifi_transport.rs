@@ -1,6 +1,9 @@
 //! Wi-Fi数据传输模块，专门用于高效的文件和大数据传输
 
+use crate::crypto::{self, KeyPair, StreamDecryptor, StreamEncryptor, StreamKey, STREAM_ABYTES};
 use crate::error::{Error, Result};
+use crate::network::revocation::RevocationList;
+use crate::network::secure_channel::{PeerKeyLookup, PeerPolicy, SecureChannel};
 use crate::types::{DeviceInfo, TransferProgress, TransferStatus};
 use log::{error, info, warn};
 use std::path::Path;
@@ -26,6 +29,10 @@ pub struct WiFiTransport {
     port: u16,
     /// 当前传输进度
     progress: Arc<Mutex<HashMap<String, TransferProgress>>>,
+    /// 入站连接的认证策略
+    peer_policy: PeerPolicy,
+    /// 设备吊销列表
+    revocations: RevocationList,
 }
 
 impl WiFiTransport {
@@ -36,9 +43,25 @@ impl WiFiTransport {
             stop_tx: None,
             port,
             progress: Arc::new(Mutex::new(HashMap::new())),
+            peer_policy: PeerPolicy::deny_all(),
+            revocations: RevocationList::new(),
         }
     }
 
+    /// 设置已配对设备公钥的查询函数
+    ///
+    /// 未设置时拒绝所有入站传输。
+    pub fn set_peer_lookup(&mut self, lookup: PeerKeyLookup) {
+        self.peer_policy = PeerPolicy::Paired(lookup);
+    }
+
+    /// 设置共享的设备吊销列表
+    ///
+    /// 设备被吊销后，其正在进行的传输会在下一个数据块时中断。
+    pub fn set_revocation_list(&mut self, revocations: RevocationList) {
+        self.revocations = revocations;
+    }
+
     /// 启动服务器端
     pub async fn start_server(&mut self) -> Result<()> {
         if self.stop_tx.is_some() {
@@ -46,6 +69,7 @@ impl WiFiTransport {
             return Ok(());
         }
 
+        let manager = crypto::manager()?;
         let addr = format!("0.0.0.0:{}", self.port);
         let listener = TcpListener::bind(&addr).await.map_err(|e| {
             error!("绑定TCP监听器失败: {e:?}");
@@ -58,6 +82,9 @@ impl WiFiTransport {
         self.stop_tx = Some(stop_tx);
 
         let progress = self.progress.clone();
+        let local_device_id = self.local_device.id.clone();
+        let peer_policy = self.peer_policy.clone();
+        let revocations = self.revocations.clone();
 
         // 启动监听任务
         tokio::spawn(async move {
@@ -72,8 +99,19 @@ impl WiFiTransport {
                             Ok((socket, addr)) => {
                                 info!("接受新的传输连接: {addr}");
                                 let progress_clone = progress.clone();
+                                let manager = manager.clone();
+                                let local_device_id = local_device_id.clone();
+                                let peer_policy = peer_policy.clone();
+                                let revocations = revocations.clone();
                                 tokio::spawn(async move {
-                                    if let Err(e) = Self::handle_incoming(socket, progress_clone).await {
+                                    let channel = match SecureChannel::accept(socket, &manager, &local_device_id, &peer_policy).await {
+                                        Ok(channel) => channel,
+                                        Err(e) => {
+                                            warn!("拒绝来自 {addr} 的传输连接: {e:?}");
+                                            return;
+                                        }
+                                    };
+                                    if let Err(e) = Self::handle_incoming(channel, progress_clone, revocations).await {
                                         error!("处理传输连接失败: {e:?}");
                                     }
                                 });
@@ -148,9 +186,10 @@ impl WiFiTransport {
         }
 
         // 连接到目标设备
-        let ip = device_info.ip_address.as_ref().ok_or_else(|| Error::Network("设备IP地址未知".to_string()))?;
-        let addr = format!("{}:{}", ip, self.port);
-        let mut stream = match TcpStream::connect(&addr).await {
+        let addr = device_info
+            .socket_address(device_info.transfer_port.unwrap_or(self.port))
+            .ok_or_else(|| Error::Network("设备IP地址未知".to_string()))?;
+        let stream = match TcpStream::connect(&addr).await {
             Ok(stream) => stream,
             Err(e) => {
                 error!("连接到目标设备失败: {e:?}");
@@ -164,27 +203,36 @@ impl WiFiTransport {
             }
         };
 
+        // 建立加密会话
+        let mut channel = match self.secure_connect(stream, device_info).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                error!("与目标设备建立加密会话失败: {e:?}");
+                self.update_progress(
+                    &transfer_id,
+                    0,
+                    TransferStatus::Failed("认证失败".to_string()),
+                    &callback,
+                );
+                return Err(e);
+            }
+        };
+
+        // 每次传输使用新的流密钥，随文件头在加密会话中发送
+        let stream_key = StreamKey::generate();
+        let (mut encryptor, stream_header) = StreamEncryptor::new(&stream_key, transfer_id.as_bytes())?;
+
         // 发送文件头信息
         let header = serde_json::to_string(&FileHeader {
             transfer_id: transfer_id.clone(),
             file_name,
             file_size,
+            stream_key: stream_key.to_base64(),
+            stream_header: base64::encode(&stream_header),
         })
         .unwrap();
 
-        let header_len = header.len() as u32;
-        if let Err(e) = stream.write_all(&header_len.to_be_bytes()).await {
-            error!("发送头部长度失败: {e:?}");
-            self.update_progress(
-                &transfer_id,
-                0,
-                TransferStatus::Failed("发送文件信息失败".to_string()),
-                &callback,
-            );
-            return Err(Error::Network("发送文件头信息失败".to_string()));
-        }
-
-        if let Err(e) = stream.write_all(header.as_bytes()).await {
+        if let Err(e) = channel.send(header.as_bytes()).await {
             error!("发送头部内容失败: {e:?}");
             self.update_progress(
                 &transfer_id,
@@ -232,7 +280,8 @@ impl WiFiTransport {
                 }
             };
 
-            if let Err(e) = stream.write_all(&buffer[..n]).await {
+            let encrypted = encryptor.encrypt_chunk(&buffer[..n])?;
+            if let Err(e) = channel.send(&encrypted).await {
                 error!("发送文件数据失败: {e:?}");
                 self.update_progress(
                     &transfer_id,
@@ -247,6 +296,18 @@ impl WiFiTransport {
             self.update_progress(&transfer_id, transferred, TransferStatus::InProgress, &callback);
         }
 
+        // 结束块表示文件结束，接收方据此区分正常结束和传输被截断
+        if let Err(e) = channel.send(&encryptor.finish()?).await {
+            error!("发送结束标记失败: {e:?}");
+            self.update_progress(
+                &transfer_id,
+                transferred,
+                TransferStatus::Failed("发送数据失败".to_string()),
+                &callback,
+            );
+            return Err(Error::Network("发送文件数据失败".to_string()));
+        }
+
         // 传输完成
         self.update_progress(
             &transfer_id,
@@ -259,6 +320,24 @@ impl WiFiTransport {
         Ok(())
     }
 
+    /// 与目标设备建立加密会话
+    async fn secure_connect(
+        &self,
+        stream: TcpStream,
+        device_info: &DeviceInfo,
+    ) -> Result<SecureChannel<TcpStream>> {
+        let manager = crypto::manager()?;
+        let peer_public_key = KeyPair::public_key_from_base64(&device_info.public_key)?;
+        SecureChannel::connect(
+            stream,
+            &manager,
+            &self.local_device.id,
+            &device_info.id,
+            &peer_public_key,
+        )
+        .await
+    }
+
     /// 更新传输进度
     fn update_progress(
         &self,
@@ -283,23 +362,32 @@ impl WiFiTransport {
         }
     }
 
+    /// 将传输状态标记为失败
+    fn fail_transfer(
+        progress: &Arc<Mutex<HashMap<String, TransferProgress>>>,
+        transfer_id: &str,
+        reason: &str,
+    ) {
+        let mut progress_guard = progress.lock().unwrap();
+        if let Some(p) = progress_guard.get_mut(transfer_id) {
+            p.status = TransferStatus::Failed(reason.to_string());
+        }
+    }
+
     /// 处理入站连接
     async fn handle_incoming(
-        mut socket: TcpStream,
+        mut channel: SecureChannel<TcpStream>,
         progress: Arc<Mutex<HashMap<String, TransferProgress>>>,
+        revocations: RevocationList,
     ) -> Result<()> {
-        // 读取头部长度
-        let mut header_len_bytes = [0u8; 4];
-        socket.read_exact(&mut header_len_bytes).await.map_err(|e| {
-            error!("读取头部长度失败: {e:?}");
-            Error::Network("读取文件头长度失败".to_string())
-        })?;
-
-        let header_len = u32::from_be_bytes(header_len_bytes);
+        let peer = channel.peer().clone();
+        let peer_public_key = peer.public_key_base64();
+        if revocations.is_revoked(&peer.device_id, &peer_public_key) {
+            return Err(Error::Authentication(format!("设备已被吊销: {}", peer.device_id)));
+        }
 
         // 读取头部
-        let mut header_bytes = vec![0u8; header_len as usize];
-        socket.read_exact(&mut header_bytes).await.map_err(|e| {
+        let header_bytes = channel.recv().await.map_err(|e| {
             error!("读取头部内容失败: {e:?}");
             Error::Network("读取文件头内容失败".to_string())
         })?;
@@ -314,6 +402,12 @@ impl WiFiTransport {
             header.file_name, header.file_size
         );
 
+        let stream_key = StreamKey::from_base64(&header.stream_key)?;
+        let stream_header = base64::decode(&header.stream_header)
+            .map_err(|e| Error::Network(format!("解析流头部失败: {e}")))?;
+        let mut decryptor =
+            StreamDecryptor::new(&stream_key, &stream_header, header.transfer_id.as_bytes())?;
+
         // 添加到进度跟踪
         {
             let mut progress_guard = progress.lock().unwrap();
@@ -339,7 +433,6 @@ impl WiFiTransport {
         })?;
 
         let mut writer = BufWriter::new(file);
-        let mut buffer = vec![0u8; BLOCK_SIZE];
         let mut received = 0u64;
 
         // 更新状态
@@ -350,25 +443,39 @@ impl WiFiTransport {
             }
         }
 
-        // 接收文件数据
-        loop {
-            let n = match socket.read(&mut buffer).await {
-                Ok(0) => break, // 连接关闭
-                Ok(n) => n,
+        // 接收文件数据，直到收到结束块
+        while !decryptor.is_finished() {
+            if revocations.is_revoked(&peer.device_id, &peer_public_key) {
+                Self::fail_transfer(&progress, &header.transfer_id, "设备已被吊销");
+                return Err(Error::Authentication(format!("设备已被吊销: {}", peer.device_id)));
+            }
+
+            let encrypted = match channel.recv().await {
+                Ok(encrypted) if encrypted.len() > BLOCK_SIZE + STREAM_ABYTES => {
+                    Self::fail_transfer(&progress, &header.transfer_id, "数据块过大");
+                    return Err(Error::Network("接收的数据块超过块大小".to_string()));
+                }
+                Ok(encrypted) => encrypted,
                 Err(e) => {
                     error!("读取文件数据失败: {e:?}");
-                    // 更新状态为失败
-                    {
-                        let mut progress_guard = progress.lock().unwrap();
-                        if let Some(p) = progress_guard.get_mut(&header.transfer_id) {
-                            p.status = TransferStatus::Failed("读取数据失败".to_string());
-                        }
-                    }
+                    Self::fail_transfer(&progress, &header.transfer_id, "传输被截断");
                     return Err(Error::Network(format!("读取文件数据失败: {e}")));
                 }
             };
+            let chunk = match decryptor.decrypt_chunk(&encrypted) {
+                Ok(Some(chunk)) if received + chunk.len() as u64 > header.file_size => {
+                    Self::fail_transfer(&progress, &header.transfer_id, "数据超出文件大小");
+                    return Err(Error::Network("接收的数据超出文件大小".to_string()));
+                }
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    Self::fail_transfer(&progress, &header.transfer_id, "数据块认证失败");
+                    return Err(e);
+                }
+            };
 
-            writer.write_all(&buffer[..n]).await.map_err(|e| {
+            writer.write_all(&chunk).await.map_err(|e| {
                 error!("写入文件数据失败: {e:?}");
                 // 更新状态为失败
                 {
@@ -380,7 +487,7 @@ impl WiFiTransport {
                 Error::Network(format!("写入文件失败: {e}"))
             })?;
 
-            received += n as u64;
+            received += chunk.len() as u64;
 
             // 更新进度
             {
@@ -391,6 +498,14 @@ impl WiFiTransport {
             }
         }
 
+        if received != header.file_size {
+            Self::fail_transfer(&progress, &header.transfer_id, "文件不完整");
+            return Err(Error::Network(format!(
+                "文件不完整: 期望 {} 字节，实际 {received} 字节",
+                header.file_size
+            )));
+        }
+
         // 确保所有数据都写入磁盘
         writer.flush().await.map_err(|e| {
             error!("刷新文件数据失败: {e:?}");
@@ -420,6 +535,10 @@ struct FileHeader {
     file_name: String,
     /// 文件大小（字节）
     file_size: u64,
+    /// 流加密密钥（Base64编码）
+    stream_key: String,
+    /// 流加密头部（Base64编码）
+    stream_header: String,
 }
 
 use std::collections::HashMap;
@@ -0,0 +1,276 @@
+//! 异步存储接口，在阻塞线程池上执行数据库操作并批量提交写入
+
+use super::{ConnectionPool, HistoryEntry, Storage, WriteOp};
+use crate::error::{Error, Result};
+use crate::types::DeviceInfo;
+use log::{debug, error};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+/// 单个批次最多合并的写操作数量
+const MAX_BATCH_SIZE: usize = 256;
+
+/// 待提交的写请求，写操作为None时只等待之前的请求完成
+type WriteRequest = (Option<WriteOp>, oneshot::Sender<Result<()>>);
+
+/// 异步存储管理器
+///
+/// 读操作通过 `spawn_blocking` 在读连接上执行；写操作进入队列，由后台
+/// 任务将同一时刻积压的请求合并为一个事务提交，避免剪贴板事件突发时
+/// 逐条争抢写锁。写操作按提交顺序执行。
+#[derive(Clone)]
+pub struct AsyncStorage {
+    /// 同步存储管理器
+    inner: Arc<Storage>,
+    /// 写请求发送端
+    write_tx: mpsc::UnboundedSender<WriteRequest>,
+}
+
+impl AsyncStorage {
+    /// 创建异步存储管理器
+    ///
+    /// 需要在tokio运行时中调用，会启动后台写入任务。
+    pub fn new(storage: Storage) -> Self {
+        let inner = Arc::new(storage);
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(Self::run_writer(inner.clone(), write_rx));
+
+        Self { inner, write_tx }
+    }
+
+    /// 打开数据库并创建异步存储管理器
+    pub async fn open(db_path: &str) -> Result<Self> {
+        let db_path = db_path.to_string();
+        let storage = Self::blocking(move || Storage::new(&db_path)).await?;
+        Ok(Self::new(storage))
+    }
+
+    /// 基于已有的连接池创建异步存储管理器
+    pub async fn with_pool(pool: Arc<ConnectionPool>) -> Result<Self> {
+        let storage = Self::blocking(move || Storage::with_pool(pool)).await?;
+        Ok(Self::new(storage))
+    }
+
+    /// 获取同步存储管理器
+    pub fn storage(&self) -> Arc<Storage> {
+        self.inner.clone()
+    }
+
+    /// 保存设备信息
+    pub async fn save_device(&self, device: &DeviceInfo) -> Result<()> {
+        self.write(WriteOp::SaveDevice(device.clone())).await
+    }
+
+    /// 获取设备信息
+    pub async fn get_device(&self, device_id: &str) -> Result<Option<DeviceInfo>> {
+        let storage = self.inner.clone();
+        let device_id = device_id.to_string();
+        Self::blocking(move || storage.get_device(&device_id)).await
+    }
+
+    /// 获取所有已配对设备
+    pub async fn get_all_devices(&self) -> Result<Vec<DeviceInfo>> {
+        let storage = self.inner.clone();
+        Self::blocking(move || storage.get_all_devices()).await
+    }
+
+    /// 删除设备
+    pub async fn delete_device(&self, device_id: &str) -> Result<()> {
+        self.write(WriteOp::DeleteDevice(device_id.to_string()))
+            .await
+    }
+
+    /// 保存共享密钥
+    pub async fn save_shared_key(&self, device_id: &str, key_data: &[u8]) -> Result<()> {
+        self.write(WriteOp::SaveSharedKey {
+            device_id: device_id.to_string(),
+            key_data: key_data.to_vec(),
+        })
+        .await
+    }
+
+    /// 获取共享密钥
+    pub async fn get_shared_key(&self, device_id: &str) -> Result<Option<Vec<u8>>> {
+        let storage = self.inner.clone();
+        let device_id = device_id.to_string();
+        Self::blocking(move || storage.get_shared_key(&device_id)).await
+    }
+
+    /// 添加历史记录
+    pub async fn add_history(
+        &self,
+        device_id: &str,
+        content_type: &str,
+        content_hash: &str,
+        metadata: &str,
+    ) -> Result<()> {
+        self.write(WriteOp::AddHistory {
+            device_id: device_id.to_string(),
+            content_type: content_type.to_string(),
+            content_hash: content_hash.to_string(),
+            metadata: metadata.to_string(),
+        })
+        .await
+    }
+
+    /// 获取历史记录
+    pub async fn get_history(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let storage = self.inner.clone();
+        Self::blocking(move || storage.get_history(limit)).await
+    }
+
+    /// 清除历史记录
+    pub async fn clear_history(&self) -> Result<()> {
+        self.write(WriteOp::ClearHistory).await
+    }
+
+    /// 提交写操作并等待其所在批次完成
+    pub async fn write(&self, op: WriteOp) -> Result<()> {
+        self.enqueue(Some(op))?
+            .await
+            .map_err(|_| Error::Storage("存储写入任务已停止".to_string()))?
+    }
+
+    /// 提交写操作但不等待完成，可在同步代码中调用
+    ///
+    /// 写入失败时只记录日志。
+    pub fn submit(&self, op: WriteOp) {
+        if let Err(e) = self.enqueue(Some(op)) {
+            error!("提交写入操作失败: {e:?}");
+        }
+    }
+
+    /// 阻塞等待此前提交的写操作全部完成
+    ///
+    /// 用于同步代码在读取前看到自己提交的写入，不能在异步任务中调用。
+    pub fn wait_for_writes(&self) -> Result<()> {
+        self.enqueue(None)?
+            .blocking_recv()
+            .map_err(|_| Error::Storage("存储写入任务已停止".to_string()))?
+    }
+
+    /// 将写请求放入队列
+    fn enqueue(&self, op: Option<WriteOp>) -> Result<oneshot::Receiver<Result<()>>> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        self.write_tx
+            .send((op, result_tx))
+            .map_err(|_| Error::Storage("存储写入任务已停止".to_string()))?;
+
+        Ok(result_rx)
+    }
+
+    /// 在阻塞线程池上执行数据库操作
+    async fn blocking<T, F>(f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(f).await.map_err(|e| {
+            error!("数据库任务执行失败: {e:?}");
+            Error::Storage(format!("数据库任务执行失败: {e}"))
+        })?
+    }
+
+    /// 后台写入任务：合并积压的写请求并在单个事务中提交
+    ///
+    /// 每个请求单独返回结果，某个操作失败不会导致同批次的其他请求失败。
+    async fn run_writer(storage: Arc<Storage>, mut write_rx: mpsc::UnboundedReceiver<WriteRequest>) {
+        while let Some(first) = write_rx.recv().await {
+            let mut batch = vec![first];
+            while batch.len() < MAX_BATCH_SIZE {
+                match write_rx.try_recv() {
+                    Ok(request) => batch.push(request),
+                    Err(_) => break,
+                }
+            }
+
+            let mut ops = Vec::with_capacity(batch.len());
+            let mut senders = Vec::with_capacity(batch.len());
+            for (op, sender) in batch {
+                senders.push((op.is_some(), sender));
+                ops.extend(op);
+            }
+            let count = ops.len();
+            let storage = storage.clone();
+
+            let result = Self::blocking(move || storage.execute_each(&ops)).await;
+            debug!("提交写入批次: {count} 条操作");
+
+            match result {
+                Ok(results) => {
+                    let mut results = results.into_iter();
+                    for (is_write, sender) in senders {
+                        let result = if is_write { results.next().unwrap_or(Ok(())) } else { Ok(()) };
+                        if let Err(e) = &result {
+                            error!("写入操作失败: {e:?}");
+                        }
+                        let _ = sender.send(result);
+                    }
+                }
+                Err(e) => {
+                    error!("批量写入失败: {e:?}");
+                    let message = e.to_string();
+                    for (_, sender) in senders {
+                        let _ = sender.send(Err(Error::Storage(message.clone())));
+                    }
+                }
+            }
+        }
+
+        debug!("存储写入任务退出");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceType;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_async_storage_batched_writes() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let storage = AsyncStorage::open(temp_file.path().to_str().unwrap())
+            .await
+            .unwrap();
+
+        // 并发提交多条写入，由后台任务合并提交
+        let mut handles = Vec::new();
+        for i in 0..20 {
+            let storage = storage.clone();
+            handles.push(tokio::spawn(async move {
+                let device = DeviceInfo::new(&format!("设备{i}"), DeviceType::Desktop, "key");
+                storage.save_device(&device).await
+            }));
+        }
+        for handle in handles {
+            assert!(handle.await.unwrap().is_ok());
+        }
+
+        let devices = storage.get_all_devices().await.unwrap();
+        assert_eq!(devices.len(), 20);
+
+        storage.save_shared_key(&devices[0].id, b"secret").await.unwrap();
+        let key = storage.get_shared_key(&devices[0].id).await.unwrap();
+        assert_eq!(key, Some(b"secret".to_vec()));
+
+        // 同一批次中失败的操作不影响其他操作
+        storage
+            .storage()
+            .pool()
+            .writer()
+            .unwrap()
+            .execute_batch("DROP TABLE keys")
+            .unwrap();
+        let device = DeviceInfo::new("新设备", DeviceType::Mobile, "key");
+        let (saved, key_saved) = tokio::join!(
+            storage.save_device(&device),
+            storage.save_shared_key(&device.id, b"secret")
+        );
+        assert!(saved.is_ok());
+        assert!(key_saved.is_err());
+        assert!(storage.get_device(&device.id).await.unwrap().is_some());
+    }
+}
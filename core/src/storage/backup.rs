@@ -0,0 +1,176 @@
+//! 完整配置备份与恢复
+//!
+//! 备份包包含配置、设备身份密钥和数据库快照（已配对设备、共享密钥、剪贴板历史
+//! 及其图片数据），整体使用口令加密为单个文件。新设备恢复同一身份密钥后，
+//! 原有配对无需重新建立。
+
+use crate::{
+    crypto::{self, IdentityKeys},
+    error::{Error, Result},
+    storage::Storage,
+    types::Config,
+};
+use serde::{Deserialize, Serialize};
+
+/// 备份文件标识
+const BUNDLE_MAGIC: &[u8; 4] = b"PABK";
+
+/// 备份格式版本
+const BUNDLE_VERSION: u8 = 1;
+
+/// 备份包内容（加密前）
+#[derive(Serialize, Deserialize)]
+struct ProfileBundle {
+    /// 格式版本
+    version: u8,
+    /// 创建时间（Unix时间戳，秒）
+    created_at: u64,
+    /// 配置
+    config: Config,
+    /// 设备身份密钥
+    identity: IdentityKeys,
+    /// 数据库快照（Base64编码）
+    database: String,
+}
+
+/// 恢复结果
+#[derive(Debug, Clone)]
+pub struct RestoredProfile {
+    /// 备份中的配置，`storage_path` 等本机路径由调用方按需调整
+    pub config: Config,
+    /// 设备身份密钥，需通过 [`crypto::init_with_identity`] 加载
+    pub identity: IdentityKeys,
+    /// 备份创建时间（Unix时间戳，秒）
+    pub created_at: u64,
+}
+
+/// 导出加密的完整配置备份
+pub fn export_profile(
+    storage: &Storage,
+    config: &Config,
+    identity: &IdentityKeys,
+    passphrase: &str,
+) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(Error::InvalidArgument("备份口令不能为空".to_string()));
+    }
+
+    let created_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let bundle = ProfileBundle {
+        version: BUNDLE_VERSION,
+        created_at,
+        config: config.clone(),
+        identity: identity.clone(),
+        database: base64::encode(storage.snapshot()?),
+    };
+    let payload = serde_json::to_vec(&bundle)?;
+    let sealed = crypto::seal_with_passphrase(passphrase, &payload)?;
+
+    let mut result = Vec::with_capacity(BUNDLE_MAGIC.len() + 1 + sealed.len());
+    result.extend_from_slice(BUNDLE_MAGIC);
+    result.push(BUNDLE_VERSION);
+    result.extend_from_slice(&sealed);
+
+    Ok(result)
+}
+
+/// 已解密并校验身份密钥的备份，尚未写入存储
+pub struct OpenedProfile {
+    /// 备份中的设备身份密钥
+    pub identity: IdentityKeys,
+    config: Config,
+    created_at: u64,
+    database: Vec<u8>,
+}
+
+impl OpenedProfile {
+    /// 将数据库快照导入存储
+    ///
+    /// 快照在单个事务中导入，失败时存储保持不变。
+    pub fn restore(self, storage: &Storage) -> Result<RestoredProfile> {
+        storage.import_snapshot(&self.database)?;
+
+        Ok(RestoredProfile {
+            config: self.config,
+            identity: self.identity,
+            created_at: self.created_at,
+        })
+    }
+}
+
+/// 解密备份并校验其中的身份密钥，不修改任何存储
+pub fn open_profile(data: &[u8], passphrase: &str) -> Result<OpenedProfile> {
+    let header_len = BUNDLE_MAGIC.len() + 1;
+    if data.len() < header_len || &data[..BUNDLE_MAGIC.len()] != BUNDLE_MAGIC {
+        return Err(Error::InvalidArgument("不是有效的PasteAll备份文件".to_string()));
+    }
+    if data[BUNDLE_MAGIC.len()] != BUNDLE_VERSION {
+        return Err(Error::InvalidArgument(format!(
+            "不支持的备份版本: {}",
+            data[BUNDLE_MAGIC.len()]
+        )));
+    }
+
+    let payload = crypto::open_with_passphrase(passphrase, &data[header_len..])?;
+    let bundle: ProfileBundle = serde_json::from_slice(&payload)?;
+    let database = base64::decode(&bundle.database)
+        .map_err(|e| Error::Storage(format!("解析数据库快照失败: {e}")))?;
+
+    crypto::CryptoManager::from_identity(&bundle.identity)?;
+
+    Ok(OpenedProfile {
+        identity: bundle.identity,
+        config: bundle.config,
+        created_at: bundle.created_at,
+        database,
+    })
+}
+
+/// 解密备份并将数据恢复到存储中
+///
+/// 口令错误或备份损坏时不会修改存储。
+pub fn restore_profile(storage: &Storage, data: &[u8], passphrase: &str) -> Result<RestoredProfile> {
+    open_profile(data, passphrase)?.restore(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DeviceInfo, DeviceType};
+
+    #[test]
+    fn test_profile_roundtrip() {
+        crypto::init();
+        let identity = crypto::CryptoManager::new().export_identity();
+        let config = Config {
+            device_name: "旧电脑".to_string(),
+            ..Config::default()
+        };
+
+        let source = Storage::new(":memory:").unwrap();
+        let device = DeviceInfo::new("手机", DeviceType::Mobile, "key");
+        source.save_device(&device).unwrap();
+        source.save_shared_key(&device.id, &[7; 32]).unwrap();
+
+        let bundle = export_profile(&source, &config, &identity, "口令").unwrap();
+        assert_eq!(&bundle[..4], BUNDLE_MAGIC);
+
+        // 口令错误时不修改存储
+        let target = Storage::new(":memory:").unwrap();
+        assert!(restore_profile(&target, &bundle, "错误口令").is_err());
+        assert!(target.get_all_devices().unwrap().is_empty());
+
+        let restored = restore_profile(&target, &bundle, "口令").unwrap();
+        assert_eq!(restored.identity, identity);
+        assert_eq!(restored.config.device_name, "旧电脑");
+        assert_eq!(target.get_device(&device.id).unwrap().unwrap().name, "手机");
+        assert_eq!(target.get_shared_key(&device.id).unwrap(), Some(vec![7; 32]));
+
+        assert!(export_profile(&source, &config, &identity, "").is_err());
+        assert!(restore_profile(&target, b"PABK", "口令").is_err());
+    }
+}
@@ -1,48 +1,162 @@
 //! 存储模块，负责保存配对设备信息、共享密钥和历史记录
+//!
+//! 底层使用WAL模式的SQLite连接池：多个只读连接并发查询，唯一的写连接
+//! 串行执行写操作。[`AsyncStorage`] 在阻塞线程池上运行查询，并将突发的
+//! 写操作合并到单个事务中提交。
 
 use crate::{
     error::{Error, Result},
     types::{DeviceInfo, DeviceType},
 };
-use log::error;
+use log::{error, warn};
 use rusqlite::{params, Connection, OptionalExtension};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+mod async_storage;
+pub mod backup;
+mod pool;
+
+pub use async_storage::AsyncStorage;
+pub use pool::{ConnectionPool, DEFAULT_READER_COUNT};
+
+// 按数据库路径索引的全局连接池
+static POOLS: OnceLock<Mutex<HashMap<String, Arc<ConnectionPool>>>> = OnceLock::new();
+
+/// 获取指定数据库的全局连接池
+///
+/// 同一路径重复调用时返回已打开的连接池，不同路径的数据库使用各自的连接池。
+pub fn init(db_path: &str) -> Result<Arc<ConnectionPool>> {
+    let mut pools = POOLS.get_or_init(Default::default).lock().map_err(|e| {
+        error!("获取连接池表锁失败: {e:?}");
+        Error::Storage("获取连接池表锁失败".to_string())
+    })?;
+
+    if let Some(pool) = pools.get(db_path) {
+        return Ok(pool.clone());
+    }
+
+    let pool = Arc::new(ConnectionPool::open(db_path, DEFAULT_READER_COUNT)?);
+    pools.insert(db_path.to_string(), pool.clone());
+    Ok(pool)
+}
+
+/// 写操作
+///
+/// 所有写入都表示为 `WriteOp`，以便合并到同一个事务中批量提交。
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    /// 保存设备信息
+    SaveDevice(DeviceInfo),
+    /// 删除设备及其共享密钥
+    DeleteDevice(String),
+    /// 吊销设备：删除设备及其共享密钥，并记录到吊销列表
+    RevokeDevice {
+        /// 设备ID
+        device_id: String,
+        /// 被吊销的加密公钥（Base64编码）
+        public_key: String,
+    },
+    /// 保存同步组
+    SaveSyncGroup(SyncGroupRecord),
+    /// 删除同步组
+    DeleteSyncGroup(String),
+    /// 保存固定的设备公钥
+    SavePinnedKey(PinnedKeyRecord),
+    /// 保存手动添加的设备
+    SaveManualDevice(ManualDeviceRecord),
+    /// 删除手动添加的设备
+    DeleteManualDevice(String),
+    /// 保存本机设置
+    SaveSetting {
+        /// 设置项名称
+        key: String,
+        /// 设置值
+        value: String,
+    },
+    /// 设置设备的指纹验证状态
+    SetDeviceVerified {
+        /// 设备ID
+        device_id: String,
+        /// 是否已验证
+        verified: bool,
+    },
+    /// 保存共享密钥
+    SaveSharedKey {
+        /// 设备ID
+        device_id: String,
+        /// 密钥数据
+        key_data: Vec<u8>,
+    },
+    /// 添加历史记录
+    AddHistory {
+        /// 设备ID
+        device_id: String,
+        /// 内容类型
+        content_type: String,
+        /// 内容哈希
+        content_hash: String,
+        /// 元数据（JSON格式）
+        metadata: String,
+    },
+    /// 清除历史记录
+    ClearHistory,
+    /// 保存剪贴板历史记录条目
+    SaveClipboardEntry(ClipboardRecord),
+    /// 永久删除剪贴板历史记录条目
+    DeleteClipboardEntry(String),
+    /// 修改剪贴板历史记录条目的标签
+    SetClipboardTags {
+        /// 条目ID
+        id: String,
+        /// 新的标签列表
+        tags: Vec<String>,
+    },
+    /// 将所有未删除的剪贴板历史记录移入回收站
+    TrashClipboardEntries {
+        /// 删除时间（Unix时间戳，毫秒）
+        deleted_at: u64,
+    },
+    /// 恢复在指定时间移入回收站的剪贴板历史记录
+    RestoreClipboardEntries {
+        /// 删除时间（Unix时间戳，毫秒）
+        deleted_at: u64,
+    },
+    /// 保存智能收藏夹
+    SaveCollection(CollectionRecord),
+    /// 删除智能收藏夹
+    DeleteCollection(String),
+}
 
 /// 存储管理器
 pub struct Storage {
-    /// 数据库连接
-    conn: Arc<Mutex<Connection>>,
+    /// 数据库连接池
+    pool: Arc<ConnectionPool>,
 }
 
 impl Storage {
     /// 创建新的存储管理器
     pub fn new(db_path: &str) -> Result<Self> {
-        let conn = match Connection::open(db_path) {
-            Ok(conn) => conn,
-            Err(e) => {
-                error!("打开数据库失败: {e:?}");
-                return Err(Error::Storage(format!("打开数据库失败: {e}")));
-            }
-        };
+        let pool = ConnectionPool::open(db_path, DEFAULT_READER_COUNT)?;
+        Self::with_pool(Arc::new(pool))
+    }
 
-        // 初始化数据库
-        let instance = Self {
-            conn: Arc::new(Mutex::new(conn)),
-        };
+    /// 基于已有的连接池创建存储管理器
+    pub fn with_pool(pool: Arc<ConnectionPool>) -> Result<Self> {
+        let instance = Self { pool };
         instance.init_db()?;
 
         Ok(instance)
     }
 
+    /// 获取连接池
+    pub fn pool(&self) -> Arc<ConnectionPool> {
+        self.pool.clone()
+    }
+
     /// 初始化数据库表结构
     fn init_db(&self) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        let conn = self.pool.writer()?;
 
         // 创建设备表
         conn.execute(
@@ -51,12 +165,30 @@ impl Storage {
                 name TEXT NOT NULL,
                 device_type INTEGER NOT NULL,
                 public_key TEXT NOT NULL,
-                last_seen INTEGER NOT NULL
+                last_seen INTEGER NOT NULL,
+                verify_key TEXT NOT NULL DEFAULT '',
+                verified INTEGER NOT NULL DEFAULT 0
             )",
             [],
         )
         .map_err(Error::Database)?;
 
+        // 旧版本的设备表没有签名公钥列
+        if conn.prepare("SELECT verify_key FROM devices LIMIT 0").is_err() {
+            conn.execute(
+                "ALTER TABLE devices ADD COLUMN verify_key TEXT NOT NULL DEFAULT ''",
+                [],
+            )
+            .map_err(Error::Database)?;
+        }
+        if conn.prepare("SELECT verified FROM devices LIMIT 0").is_err() {
+            conn.execute(
+                "ALTER TABLE devices ADD COLUMN verified INTEGER NOT NULL DEFAULT 0",
+                [],
+            )
+            .map_err(Error::Database)?;
+        }
+
         // 创建密钥表
         conn.execute(
             "CREATE TABLE IF NOT EXISTS keys (
@@ -69,6 +201,65 @@ impl Storage {
         )
         .map_err(Error::Database)?;
 
+        // 创建设备吊销表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS revoked_devices (
+                device_id TEXT PRIMARY KEY,
+                public_key TEXT NOT NULL,
+                revoked_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
+
+        // 创建公钥固定表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS pinned_keys (
+                device_id TEXT PRIMARY KEY,
+                public_key TEXT NOT NULL,
+                verify_key TEXT NOT NULL,
+                pinned_at INTEGER NOT NULL,
+                changed_public_key TEXT,
+                changed_verify_key TEXT
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
+
+        // 创建手动添加设备表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS manual_devices (
+                device_id TEXT PRIMARY KEY,
+                device TEXT NOT NULL,
+                added_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
+
+        // 创建本机设置表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS local_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
+
+        // 创建同步组表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sync_groups (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                epoch INTEGER NOT NULL,
+                group_key BLOB NOT NULL,
+                members TEXT NOT NULL
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
+
         // 创建历史记录表
         conn.execute(
             "CREATE TABLE IF NOT EXISTS history (
@@ -84,65 +275,71 @@ impl Storage {
         )
         .map_err(Error::Database)?;
 
-        Ok(())
-    }
+        // 创建剪贴板历史记录表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS clipboard_history (
+                id TEXT PRIMARY KEY,
+                content BLOB NOT NULL,
+                content_type INTEGER NOT NULL,
+                timestamp INTEGER NOT NULL,
+                tags TEXT,
+                is_favorite INTEGER NOT NULL,
+                source_device_id TEXT,
+                deleted_at INTEGER
+            )",
+            [],
+        )
+        .map_err(Error::Database)?;
 
-    /// 保存设备信息
-    pub fn save_device(&self, device: &DeviceInfo) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
+        // 旧版本的剪贴板历史记录表缺少的列
+        for (column, column_type) in [("source_device_id", "TEXT"), ("deleted_at", "INTEGER")] {
+            if conn
+                .prepare(&format!("SELECT {column} FROM clipboard_history LIMIT 0"))
+                .is_err()
+            {
+                conn.execute(
+                    &format!("ALTER TABLE clipboard_history ADD COLUMN {column} {column_type}"),
+                    [],
+                )
+                .map_err(Error::Database)?;
             }
-        };
-
-        let device_type = match device.device_type {
-            DeviceType::Desktop => 0,
-            DeviceType::Mobile => 1,
-            DeviceType::Unknown => 2,
-        };
-
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        }
 
+        // 创建智能收藏夹表
         conn.execute(
-            "INSERT OR REPLACE INTO devices (id, name, device_type, public_key, last_seen)
-             VALUES (?, ?, ?, ?, ?)",
-            params![
-                device.id,
-                device.name,
-                device_type,
-                device.public_key,
-                timestamp
-            ],
+            "CREATE TABLE IF NOT EXISTS smart_collections (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                query TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
         )
         .map_err(Error::Database)?;
 
         Ok(())
     }
 
+    /// 保存设备信息
+    pub fn save_device(&self, device: &DeviceInfo) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SaveDevice(device.clone())])
+    }
+
     /// 获取设备信息
     pub fn get_device(&self, device_id: &str) -> Result<Option<DeviceInfo>> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        let conn = self.pool.reader()?;
 
         let result = conn
             .query_row(
-                "SELECT id, name, device_type, public_key FROM devices WHERE id = ?",
+                "SELECT id, name, device_type, public_key, verify_key, verified FROM devices WHERE id = ?",
                 params![device_id],
                 |row| {
                     let id: String = row.get(0)?;
                     let name: String = row.get(1)?;
                     let device_type_int: i64 = row.get(2)?;
                     let public_key: String = row.get(3)?;
+                    let verify_key: String = row.get(4)?;
+                    let verified: bool = row.get(5)?;
 
                     let device_type = match device_type_int {
                         0 => DeviceType::Desktop,
@@ -155,15 +352,21 @@ impl Storage {
                         name,
                         device_type,
                         public_key,
+                        verify_key,
                         online: false, // 从数据库中加载的设备默认为离线状态
                         ip_address: None,
+                        pairing_port: None,
+                        transfer_port: None,
                         system_version: None,
                         app_version: None,
                         capabilities: crate::types::DeviceCapabilities::default(),
                         last_seen: None,
                         pairing_status: crate::types::PairingStatus::default(),
                         description: None,
-                        trusted: false,
+                        trusted: verified,
+                        announcement_verified: false,
+                        addresses: Vec::new(),
+                        namespace: None,
                     })
                 },
             )
@@ -175,16 +378,10 @@ impl Storage {
 
     /// 获取所有已配对设备
     pub fn get_all_devices(&self) -> Result<Vec<DeviceInfo>> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        let conn = self.pool.reader()?;
 
         let mut stmt = conn
-            .prepare("SELECT id, name, device_type, public_key FROM devices")
+            .prepare("SELECT id, name, device_type, public_key, verify_key, verified FROM devices")
             .map_err(Error::Database)?;
 
         let rows = stmt
@@ -193,6 +390,8 @@ impl Storage {
                 let name: String = row.get(1)?;
                 let device_type_int: i64 = row.get(2)?;
                 let public_key: String = row.get(3)?;
+                let verify_key: String = row.get(4)?;
+                let verified: bool = row.get(5)?;
 
                 let device_type = match device_type_int {
                     0 => DeviceType::Desktop,
@@ -205,15 +404,21 @@ impl Storage {
                     name,
                     device_type,
                     public_key,
+                    verify_key,
                     online: false, // 从数据库中加载的设备默认为离线状态
                     ip_address: None,
+                    pairing_port: None,
+                    transfer_port: None,
                     system_version: None,
                     app_version: None,
                     capabilities: crate::types::DeviceCapabilities::default(),
                     last_seen: None,
                     pairing_status: crate::types::PairingStatus::default(),
                     description: None,
-                    trusted: false,
+                    trusted: verified,
+                    announcement_verified: false,
+                    addresses: Vec::new(),
+                    namespace: None,
                 })
             })
             .map_err(Error::Database)?;
@@ -229,59 +434,183 @@ impl Storage {
         Ok(devices)
     }
 
+    /// 设置设备的指纹验证状态
+    ///
+    /// 验证状态保存在 `DeviceInfo.trusted` 中，设备公钥变化后自动失效。
+    pub fn set_device_verified(&self, device_id: &str, verified: bool) -> Result<()> {
+        if self.get_device(device_id)?.is_none() {
+            return Err(Error::Storage(format!("设备不存在: {device_id}")));
+        }
+
+        self.execute_batch(vec![WriteOp::SetDeviceVerified {
+            device_id: device_id.to_string(),
+            verified,
+        }])
+    }
+
     /// 删除设备
     pub fn delete_device(&self, device_id: &str) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        self.execute_batch(vec![WriteOp::DeleteDevice(device_id.to_string())])
+    }
+
+    /// 吊销设备
+    ///
+    /// 删除设备信息和共享密钥，并保留吊销记录，此后该设备和公钥不能再次配对。
+    pub fn revoke_device(&self, device_id: &str, public_key: &str) -> Result<()> {
+        self.execute_batch(vec![WriteOp::RevokeDevice {
+            device_id: device_id.to_string(),
+            public_key: public_key.to_string(),
+        }])
+    }
 
-        conn.execute("DELETE FROM keys WHERE device_id = ?", params![device_id])
+    /// 获取所有已吊销的设备
+    pub fn get_revoked_devices(&self) -> Result<Vec<RevokedDevice>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn
+            .prepare("SELECT device_id, public_key, revoked_at FROM revoked_devices ORDER BY revoked_at")
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(RevokedDevice {
+                    device_id: row.get(0)?,
+                    public_key: row.get(1)?,
+                    revoked_at: row.get::<_, i64>(2)? as u64,
+                })
+            })
             .map_err(Error::Database)?;
 
-        conn.execute("DELETE FROM devices WHERE id = ?", params![device_id])
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Error::Database)
+    }
+
+    /// 保存同步组
+    pub fn save_sync_group(&self, group: &SyncGroupRecord) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SaveSyncGroup(group.clone())])
+    }
+
+    /// 删除同步组
+    pub fn delete_sync_group(&self, group_id: &str) -> Result<()> {
+        self.execute_batch(vec![WriteOp::DeleteSyncGroup(group_id.to_string())])
+    }
+
+    /// 获取所有同步组
+    pub fn get_sync_groups(&self) -> Result<Vec<SyncGroupRecord>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn
+            .prepare("SELECT id, name, epoch, group_key, members FROM sync_groups")
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(SyncGroupRecord {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    epoch: row.get::<_, i64>(2)? as u64,
+                    group_key: row.get(3)?,
+                    members: row.get(4)?,
+                })
+            })
             .map_err(Error::Database)?;
 
-        Ok(())
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Error::Database)
     }
 
-    /// 保存共享密钥
-    pub fn save_shared_key(&self, device_id: &str, key_data: &[u8]) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
+    /// 保存固定的设备公钥
+    pub fn save_pinned_key(&self, record: &PinnedKeyRecord) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SavePinnedKey(record.clone())])
+    }
+
+    /// 保存手动添加的设备
+    pub fn save_manual_device(&self, record: &ManualDeviceRecord) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SaveManualDevice(record.clone())])
+    }
+
+    /// 删除手动添加的设备
+    pub fn delete_manual_device(&self, device_id: &str) -> Result<()> {
+        self.execute_batch(vec![WriteOp::DeleteManualDevice(device_id.to_string())])
+    }
+
+    /// 保存本机设置
+    pub fn save_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SaveSetting {
+            key: key.to_string(),
+            value: value.to_string(),
+        }])
+    }
+
+    /// 获取本机设置，不存在时返回None
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.pool.reader()?;
+
+        conn.query_row("SELECT value FROM local_settings WHERE key = ?", params![key], |row| row.get(0))
+            .optional()
+            .map_err(Error::Database)
+    }
+
+    /// 获取所有手动添加的设备
+    pub fn get_manual_devices(&self) -> Result<Vec<ManualDeviceRecord>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn
+            .prepare("SELECT device, added_at FROM manual_devices")
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)))
+            .map_err(Error::Database)?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            let (device, added_at) = row.map_err(Error::Database)?;
+            // 单条损坏的记录不影响其他设备的恢复
+            match serde_json::from_str(&device) {
+                Ok(device) => records.push(ManualDeviceRecord { device, added_at }),
+                Err(e) => warn!("解析手动添加的设备失败: {e:?}"),
             }
-        };
+        }
 
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        Ok(records)
+    }
 
-        conn.execute(
-            "INSERT OR REPLACE INTO keys (device_id, shared_key, created_at)
-             VALUES (?, ?, ?)",
-            params![device_id, key_data, timestamp],
-        )
-        .map_err(Error::Database)?;
+    /// 获取所有固定的设备公钥
+    pub fn get_pinned_keys(&self) -> Result<Vec<PinnedKeyRecord>> {
+        let conn = self.pool.reader()?;
 
-        Ok(())
+        let mut stmt = conn
+            .prepare(
+                "SELECT device_id, public_key, verify_key, pinned_at, changed_public_key, changed_verify_key
+                 FROM pinned_keys",
+            )
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(PinnedKeyRecord {
+                    device_id: row.get(0)?,
+                    public_key: row.get(1)?,
+                    verify_key: row.get(2)?,
+                    pinned_at: row.get::<_, i64>(3)? as u64,
+                    changed_public_key: row.get(4)?,
+                    changed_verify_key: row.get(5)?,
+                })
+            })
+            .map_err(Error::Database)?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Error::Database)
+    }
+
+    /// 保存共享密钥
+    pub fn save_shared_key(&self, device_id: &str, key_data: &[u8]) -> Result<()> {
+        self.execute_batch(vec![WriteOp::SaveSharedKey {
+            device_id: device_id.to_string(),
+            key_data: key_data.to_vec(),
+        }])
     }
 
     /// 获取共享密钥
     pub fn get_shared_key(&self, device_id: &str) -> Result<Option<Vec<u8>>> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        let conn = self.pool.reader()?;
 
         let result = conn
             .query_row(
@@ -306,38 +635,17 @@ impl Storage {
         content_hash: &str,
         metadata: &str,
     ) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
-
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
-        conn.execute(
-            "INSERT INTO history (device_id, content_type, content_hash, metadata, timestamp)
-             VALUES (?, ?, ?, ?, ?)",
-            params![device_id, content_type, content_hash, metadata, timestamp],
-        )
-        .map_err(Error::Database)?;
-
-        Ok(())
+        self.execute_batch(vec![WriteOp::AddHistory {
+            device_id: device_id.to_string(),
+            content_type: content_type.to_string(),
+            content_hash: content_hash.to_string(),
+            metadata: metadata.to_string(),
+        }])
     }
 
     /// 获取历史记录
     pub fn get_history(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
-            }
-        };
+        let conn = self.pool.reader()?;
 
         let mut stmt = conn
             .prepare(
@@ -382,19 +690,652 @@ impl Storage {
 
     /// 清除历史记录
     pub fn clear_history(&self) -> Result<()> {
-        let conn = match self.conn.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                error!("获取数据库连接锁失败: {e:?}");
-                return Err(Error::Storage("获取数据库连接锁失败".to_string()));
+        self.execute_batch(vec![WriteOp::ClearHistory])
+    }
+
+    /// 获取未删除的剪贴板历史记录，按时间降序排列
+    pub fn get_clipboard_entries(&self, limit: usize) -> Result<Vec<ClipboardRecord>> {
+        self.query_clipboard_records(
+            "SELECT id, content, content_type, timestamp, tags, is_favorite, source_device_id, deleted_at
+             FROM clipboard_history
+             WHERE deleted_at IS NULL
+             ORDER BY timestamp DESC
+             LIMIT ?",
+            params![limit as i64],
+        )
+    }
+
+    /// 获取回收站中的剪贴板历史记录，按删除时间降序排列
+    pub fn get_clipboard_trash(&self) -> Result<Vec<ClipboardRecord>> {
+        self.query_clipboard_records(
+            "SELECT id, content, content_type, timestamp, tags, is_favorite, source_device_id, deleted_at
+             FROM clipboard_history
+             WHERE deleted_at IS NOT NULL
+             ORDER BY deleted_at DESC",
+            [],
+        )
+    }
+
+    /// 查询剪贴板历史记录，跳过无法读取的记录
+    fn query_clipboard_records<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<Vec<ClipboardRecord>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn.prepare(sql).map_err(Error::Database)?;
+        let rows = stmt
+            .query_map(params, |row| {
+                let tags: Option<String> = row.get(4)?;
+                Ok(ClipboardRecord {
+                    id: row.get(0)?,
+                    content: row.get(1)?,
+                    content_type: row.get(2)?,
+                    timestamp: row.get::<_, i64>(3)? as u64,
+                    tags: tags
+                        .and_then(|tags| serde_json::from_str(&tags).ok())
+                        .unwrap_or_default(),
+                    is_favorite: row.get(5)?,
+                    source_device_id: row.get(6)?,
+                    deleted_at: row.get::<_, Option<i64>>(7)?.map(|ts| ts as u64),
+                })
+            })
+            .map_err(Error::Database)?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            match row {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("读取剪贴板历史记录失败: {e:?}"),
             }
+        }
+
+        Ok(records)
+    }
+
+    /// 获取所有剪贴板历史记录的标签，返回 `(ID, 标签, 是否已删除)`
+    ///
+    /// 标签无法解析的记录被跳过。
+    pub fn get_clipboard_tags(&self) -> Result<Vec<(String, Vec<String>, bool)>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn
+            .prepare("SELECT id, tags, deleted_at IS NOT NULL FROM clipboard_history")
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, bool>(2)?))
+            })
+            .map_err(Error::Database)?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            let (id, tags, deleted) = row.map_err(Error::Database)?;
+            let tags = match tags.as_deref().map(serde_json::from_str::<Vec<String>>) {
+                Some(Ok(tags)) => tags,
+                Some(Err(e)) => {
+                    warn!("解析历史记录 {id} 的标签失败: {e:?}");
+                    continue;
+                }
+                None => Vec::new(),
+            };
+            result.push((id, tags, deleted));
+        }
+
+        Ok(result)
+    }
+
+    /// 获取所有智能收藏夹，按创建时间升序排列
+    pub fn get_collections(&self) -> Result<Vec<CollectionRecord>> {
+        let conn = self.pool.reader()?;
+
+        let mut stmt = conn
+            .prepare("SELECT id, name, query, created_at FROM smart_collections ORDER BY created_at")
+            .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(CollectionRecord {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    query: row.get(2)?,
+                    created_at: row.get::<_, i64>(3)? as u64,
+                })
+            })
+            .map_err(Error::Database)?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Error::Database)
+    }
+
+    /// 导出整个数据库的快照（SQLite文件内容）
+    ///
+    /// 快照包含同一数据库中的所有表，包括剪贴板历史记录及其图片数据。
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let temp = PrivateDbFile::create(self.pool.db_path(), &[])?;
+        let conn = self.pool.writer()?;
+        conn.execute("VACUUM INTO ?", params![temp.path_str()])
+            .map_err(Error::Database)?;
+        drop(conn);
+
+        std::fs::read(&temp.path).map_err(Error::Io)
+    }
+
+    /// 将快照中的数据合并到当前数据库
+    ///
+    /// 当前数据库缺少的表会按快照中的结构创建；主键相同的记录以快照为准。
+    pub fn import_snapshot(&self, data: &[u8]) -> Result<()> {
+        let temp = PrivateDbFile::create(self.pool.db_path(), data)?;
+
+        let mut conn = self.pool.writer()?;
+        conn.execute("ATTACH DATABASE ? AS backup", params![temp.path_str()])
+            .map_err(Error::Database)?;
+
+        let result = Self::copy_attached_tables(&mut conn);
+
+        if let Err(e) = conn.execute("DETACH DATABASE backup", []) {
+            error!("分离备份数据库失败: {e:?}");
+        }
+
+        result
+    }
+
+    /// 将已附加的备份数据库中的表复制到主数据库
+    fn copy_attached_tables(conn: &mut Connection) -> Result<()> {
+        let tables = {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT name, sql FROM backup.sqlite_master
+                     WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+                )
+                .map_err(Error::Database)?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+                .map_err(Error::Database)?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(Error::Database)?
         };
 
-        conn.execute("DELETE FROM history", [])
+        let tx = conn.transaction().map_err(Error::Database)?;
+
+        for (name, sql) in tables {
+            let exists: bool = tx
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM main.sqlite_master WHERE type = 'table' AND name = ?)",
+                    params![name],
+                    |row| row.get(0),
+                )
+                .map_err(Error::Database)?;
+            if !exists {
+                tx.execute(&sql, []).map_err(Error::Database)?;
+            }
+
+            // 只复制两边都存在的列，兼容新旧版本的表结构
+            let backup_columns = Self::table_columns(&tx, "backup", &name)?;
+            let main_columns = Self::table_columns(&tx, "main", &name)?;
+            let columns: Vec<String> = backup_columns
+                .into_iter()
+                .filter(|c| main_columns.contains(c))
+                .map(|c| quote_identifier(&c))
+                .collect();
+            if columns.is_empty() {
+                continue;
+            }
+
+            let columns = columns.join(", ");
+            let table = quote_identifier(&name);
+            tx.execute(
+                &format!("INSERT OR REPLACE INTO main.{table} ({columns}) SELECT {columns} FROM backup.{table}"),
+                [],
+            )
+            .map_err(Error::Database)?;
+        }
+
+        tx.commit().map_err(Error::Database)
+    }
+
+    /// 获取表的列名
+    fn table_columns(conn: &Connection, schema: &str, table: &str) -> Result<Vec<String>> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA {schema}.table_info({})", quote_identifier(table)))
             .map_err(Error::Database)?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(1))
+            .map_err(Error::Database)?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(Error::Database)
+    }
+
+    /// 在单个事务中执行一组写操作
+    ///
+    /// 任一操作失败时整个事务回滚。
+    pub fn execute_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.writer()?;
+        let tx = conn.transaction().map_err(Error::Database)?;
+
+        for op in &ops {
+            Self::apply(&tx, op)?;
+        }
+
+        tx.commit().map_err(Error::Database)?;
 
         Ok(())
     }
+
+    /// 在同一事务中执行多个相互独立的写操作，返回每个操作的结果
+    ///
+    /// 每个操作使用单独的保存点，失败时只回滚该操作本身，不影响同一批次中的其他操作。
+    pub fn execute_each(&self, ops: &[WriteOp]) -> Result<Vec<Result<()>>> {
+        let mut conn = self.pool.writer()?;
+        let mut tx = conn.transaction().map_err(Error::Database)?;
+        let mut results = Vec::with_capacity(ops.len());
+
+        for op in ops {
+            // 保存点未提交时在离开作用域时回滚
+            let savepoint = tx.savepoint().map_err(Error::Database)?;
+            let result = Self::apply(&savepoint, op);
+            if result.is_ok() {
+                savepoint.commit().map_err(Error::Database)?;
+            }
+            results.push(result);
+        }
+
+        tx.commit().map_err(Error::Database)?;
+
+        Ok(results)
+    }
+
+    /// 执行单个写操作
+    fn apply(conn: &Connection, op: &WriteOp) -> Result<()> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        match op {
+            WriteOp::SaveDevice(device) => {
+                let device_type = match device.device_type {
+                    DeviceType::Desktop => 0,
+                    DeviceType::Mobile => 1,
+                    DeviceType::Unknown => 2,
+                };
+
+                // 保留验证状态，除非设备的身份公钥发生变化
+                conn.execute(
+                    "INSERT INTO devices (id, name, device_type, public_key, last_seen, verify_key)
+                     VALUES (?, ?, ?, ?, ?, ?)
+                     ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        device_type = excluded.device_type,
+                        last_seen = excluded.last_seen,
+                        verified = CASE
+                            WHEN devices.public_key = excluded.public_key
+                             AND devices.verify_key = excluded.verify_key
+                            THEN devices.verified ELSE 0 END,
+                        public_key = excluded.public_key,
+                        verify_key = excluded.verify_key",
+                    params![
+                        device.id,
+                        device.name,
+                        device_type,
+                        device.public_key,
+                        timestamp,
+                        device.verify_key
+                    ],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::SetDeviceVerified { device_id, verified } => {
+                conn.execute(
+                    "UPDATE devices SET verified = ? WHERE id = ?",
+                    params![verified, device_id],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::RevokeDevice {
+                device_id,
+                public_key,
+            } => {
+                conn.execute("DELETE FROM keys WHERE device_id = ?", params![device_id])
+                    .map_err(Error::Database)?;
+
+                conn.execute("DELETE FROM devices WHERE id = ?", params![device_id])
+                    .map_err(Error::Database)?;
+
+                conn.execute(
+                    "INSERT OR REPLACE INTO revoked_devices (device_id, public_key, revoked_at)
+                     VALUES (?, ?, ?)",
+                    params![device_id, public_key, timestamp],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::SaveSyncGroup(group) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO sync_groups (id, name, epoch, group_key, members)
+                     VALUES (?, ?, ?, ?, ?)",
+                    params![group.id, group.name, group.epoch as i64, group.group_key, group.members],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::DeleteSyncGroup(group_id) => {
+                conn.execute("DELETE FROM sync_groups WHERE id = ?", params![group_id])
+                    .map_err(Error::Database)?;
+            }
+            WriteOp::SavePinnedKey(record) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO pinned_keys
+                     (device_id, public_key, verify_key, pinned_at, changed_public_key, changed_verify_key)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    params![
+                        record.device_id,
+                        record.public_key,
+                        record.verify_key,
+                        record.pinned_at as i64,
+                        record.changed_public_key,
+                        record.changed_verify_key
+                    ],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::SaveManualDevice(record) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO manual_devices (device_id, device, added_at) VALUES (?, ?, ?)",
+                    params![record.device.id, serde_json::to_string(&record.device)?, record.added_at as i64],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::DeleteManualDevice(device_id) => {
+                conn.execute("DELETE FROM manual_devices WHERE device_id = ?", params![device_id])
+                    .map_err(Error::Database)?;
+            }
+            WriteOp::SaveSetting { key, value } => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO local_settings (key, value) VALUES (?, ?)",
+                    params![key, value],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::DeleteDevice(device_id) => {
+                conn.execute("DELETE FROM keys WHERE device_id = ?", params![device_id])
+                    .map_err(Error::Database)?;
+
+                conn.execute("DELETE FROM devices WHERE id = ?", params![device_id])
+                    .map_err(Error::Database)?;
+            }
+            WriteOp::SaveSharedKey {
+                device_id,
+                key_data,
+            } => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO keys (device_id, shared_key, created_at)
+                     VALUES (?, ?, ?)",
+                    params![device_id, key_data, timestamp],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::AddHistory {
+                device_id,
+                content_type,
+                content_hash,
+                metadata,
+            } => {
+                conn.execute(
+                    "INSERT INTO history (device_id, content_type, content_hash, metadata, timestamp)
+                     VALUES (?, ?, ?, ?, ?)",
+                    params![device_id, content_type, content_hash, metadata, timestamp],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::ClearHistory => {
+                conn.execute("DELETE FROM history", [])
+                    .map_err(Error::Database)?;
+            }
+            WriteOp::SaveClipboardEntry(record) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO clipboard_history
+                     (id, content, content_type, timestamp, tags, is_favorite, source_device_id, deleted_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                    params![
+                        record.id,
+                        record.content,
+                        record.content_type,
+                        record.timestamp as i64,
+                        serde_json::to_string(&record.tags)?,
+                        record.is_favorite,
+                        record.source_device_id,
+                        record.deleted_at.map(|ts| ts as i64)
+                    ],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::DeleteClipboardEntry(id) => {
+                conn.execute("DELETE FROM clipboard_history WHERE id = ?", params![id])
+                    .map_err(Error::Database)?;
+            }
+            WriteOp::SetClipboardTags { id, tags } => {
+                conn.execute(
+                    "UPDATE clipboard_history SET tags = ? WHERE id = ?",
+                    params![serde_json::to_string(tags)?, id],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::TrashClipboardEntries { deleted_at } => {
+                conn.execute(
+                    "UPDATE clipboard_history SET deleted_at = ? WHERE deleted_at IS NULL",
+                    params![*deleted_at as i64],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::RestoreClipboardEntries { deleted_at } => {
+                conn.execute(
+                    "UPDATE clipboard_history SET deleted_at = NULL WHERE deleted_at = ?",
+                    params![*deleted_at as i64],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::SaveCollection(record) => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO smart_collections (id, name, query, created_at)
+                     VALUES (?, ?, ?, ?)",
+                    params![record.id, record.name, record.query, record.created_at as i64],
+                )
+                .map_err(Error::Database)?;
+            }
+            WriteOp::DeleteCollection(id) => {
+                conn.execute("DELETE FROM smart_collections WHERE id = ?", params![id])
+                    .map_err(Error::Database)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// 为SQL标识符加引号
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// 仅当前用户可读写的临时数据库文件，离开作用域时删除
+///
+/// 快照是未加密的完整数据库，因此放在数据库所在的配置目录中；内存数据库没有配置目录，
+/// 改用系统临时目录下仅当前用户可访问的子目录。
+struct PrivateDbFile {
+    path: std::path::PathBuf,
+    /// 为内存数据库创建的私有目录
+    dir: Option<std::path::PathBuf>,
+}
+
+impl PrivateDbFile {
+    fn create(db_path: &str, data: &[u8]) -> Result<Self> {
+        use std::io::Write;
+
+        let name = format!(".pasteall_snapshot_{}.db", uuid::Uuid::new_v4());
+        let (parent, dir) = if db_path == ":memory:" {
+            let dir = std::env::temp_dir().join(format!("pasteall_{}", uuid::Uuid::new_v4()));
+            create_private_dir(&dir)?;
+            (dir.clone(), Some(dir))
+        } else {
+            let parent = std::path::Path::new(db_path)
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| std::path::Path::new("."));
+            (parent.to_path_buf(), None)
+        };
+
+        let temp = Self {
+            path: parent.join(name),
+            dir,
+        };
+        let mut file = open_private_new(&temp.path)?;
+        file.write_all(data).map_err(Error::Io)?;
+
+        Ok(temp)
+    }
+
+    fn path_str(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for PrivateDbFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// 创建仅当前用户可读写的新文件，文件已存在时失败
+#[cfg(unix)]
+fn open_private_new(path: &std::path::Path) -> Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(Error::Io)
+}
+
+/// 创建仅当前用户可读写的新文件，文件已存在时失败
+#[cfg(not(unix))]
+fn open_private_new(path: &std::path::Path) -> Result<std::fs::File> {
+    // 非Unix平台依赖用户配置目录的默认访问控制
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(Error::Io)
+}
+
+/// 创建仅当前用户可访问的目录
+#[cfg(unix)]
+fn create_private_dir(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    std::fs::DirBuilder::new().mode(0o700).create(path).map_err(Error::Io)
+}
+
+/// 创建仅当前用户可访问的目录
+#[cfg(not(unix))]
+fn create_private_dir(path: &std::path::Path) -> Result<()> {
+    std::fs::create_dir(path).map_err(Error::Io)
+}
+
+/// 已吊销的设备
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevokedDevice {
+    /// 设备ID
+    pub device_id: String,
+    /// 被吊销的加密公钥（Base64编码）
+    pub public_key: String,
+    /// 吊销时间（Unix时间戳，秒）
+    pub revoked_at: u64,
+}
+
+/// 固定的设备公钥
+///
+/// 设备ID首次出现时的公钥被固定，之后出现的不同公钥记录为待确认的变更。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedKeyRecord {
+    /// 设备ID
+    pub device_id: String,
+    /// 固定的加密公钥（Base64编码）
+    pub public_key: String,
+    /// 固定的签名公钥（Base64编码），旧版本设备为空
+    pub verify_key: String,
+    /// 固定时间（Unix时间戳，秒）
+    pub pinned_at: u64,
+    /// 检测到的新加密公钥，存在时该设备被阻止同步
+    pub changed_public_key: Option<String>,
+    /// 检测到的新签名公钥
+    pub changed_verify_key: Option<String>,
+}
+
+/// 手动添加的设备
+///
+/// 通过地址或邀请码添加，重启后恢复到设备发现管理器中。
+#[derive(Debug, Clone)]
+pub struct ManualDeviceRecord {
+    /// 设备信息，包含添加时观察到的地址和端口
+    pub device: DeviceInfo,
+    /// 添加时间（Unix时间戳，秒）
+    pub added_at: u64,
+}
+
+/// 同步组记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncGroupRecord {
+    /// 同步组ID
+    pub id: String,
+    /// 同步组名称
+    pub name: String,
+    /// 组密钥版本
+    pub epoch: u64,
+    /// 组密钥
+    pub group_key: Vec<u8>,
+    /// 成员列表（JSON格式）
+    pub members: String,
+}
+
+/// 剪贴板历史记录
+///
+/// 内容按类型编码保存：文本为UTF-8，图片为原始数据，文件列表为JSON数组。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardRecord {
+    /// 条目ID
+    pub id: String,
+    /// 编码后的内容
+    pub content: Vec<u8>,
+    /// 内容类型：0为文本，1为图片，2为文件列表，3为空
+    pub content_type: i32,
+    /// 创建时间（Unix时间戳，毫秒）
+    pub timestamp: u64,
+    /// 标签
+    pub tags: Vec<String>,
+    /// 是否标记为收藏
+    pub is_favorite: bool,
+    /// 来源设备ID，本机复制的内容为None
+    pub source_device_id: Option<String>,
+    /// 移入回收站的时间（Unix时间戳，毫秒），None表示未删除
+    pub deleted_at: Option<u64>,
+}
+
+/// 智能收藏夹记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionRecord {
+    /// 收藏夹ID
+    pub id: String,
+    /// 收藏夹名称
+    pub name: String,
+    /// 查询条件（JSON格式）
+    pub query: String,
+    /// 创建时间
+    pub created_at: u64,
 }
 
 /// 历史记录条目
@@ -424,6 +1365,31 @@ mod tests {
         let temp_file = NamedTempFile::new().unwrap();
         let storage = Storage::new(temp_file.path().to_str().unwrap());
         assert!(storage.is_ok());
+
+        // 本机设置在重新打开数据库后依然存在
+        let storage = storage.unwrap();
+        assert_eq!(storage.get_setting("device_id").unwrap(), None);
+        storage.save_setting("device_id", "old-device").unwrap();
+        let reopened = Storage::new(temp_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(reopened.get_setting("device_id").unwrap().as_deref(), Some("old-device"));
+    }
+
+    #[test]
+    fn test_init_pools_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let first_path = dir.path().join("first.db");
+        let second_path = dir.path().join("second.db");
+
+        let first = init(first_path.to_str().unwrap()).unwrap();
+        let second = init(second_path.to_str().unwrap()).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.db_path(), second_path.to_str().unwrap());
+        assert!(Arc::ptr_eq(&first, &init(first_path.to_str().unwrap()).unwrap()));
+
+        // 写入一个数据库不影响另一个
+        Storage::with_pool(first).unwrap().save_setting("device_id", "first").unwrap();
+        let second = Storage::with_pool(second).unwrap();
+        assert_eq!(second.get_setting("device_id").unwrap(), None);
     }
 
     #[test]
@@ -436,8 +1402,11 @@ mod tests {
             name: "Test Device".to_string(),
             device_type: DeviceType::Desktop,
             public_key: "test_key".to_string(),
+            verify_key: "test_verify_key".to_string(),
             online: true,
             ip_address: None,
+            pairing_port: None,
+            transfer_port: None,
             system_version: Some("1.0".to_string()),
             app_version: Some("1.0.0".to_string()),
             capabilities: crate::types::DeviceCapabilities::default(),
@@ -445,6 +1414,9 @@ mod tests {
             pairing_status: crate::types::PairingStatus::Unpaired,
             description: Some("Test Description".to_string()),
             trusted: false,
+            announcement_verified: false,
+            addresses: Vec::new(),
+            namespace: None,
         };
 
         // 保存设备
@@ -456,14 +1428,120 @@ mod tests {
         let retrieved = result.unwrap();
         assert_eq!(retrieved.id, "test_id");
         assert_eq!(retrieved.name, "Test Device");
+        assert_eq!(retrieved.verify_key, "test_verify_key");
 
         // 获取所有设备
         let devices = storage.get_all_devices().unwrap();
         assert_eq!(devices.len(), 1);
 
+        // 验证状态在重新保存后保留，公钥变化后失效
+        assert!(!retrieved.trusted);
+        storage.set_device_verified("test_id", true).unwrap();
+        storage.save_device(&device).unwrap();
+        assert!(storage.get_device("test_id").unwrap().unwrap().trusted);
+        let rekeyed = DeviceInfo {
+            verify_key: "other_verify_key".to_string(),
+            ..device.clone()
+        };
+        storage.save_device(&rekeyed).unwrap();
+        assert!(!storage.get_all_devices().unwrap()[0].trusted);
+        assert!(storage.set_device_verified("missing", true).is_err());
+
         // 删除设备
         assert!(storage.delete_device("test_id").is_ok());
         let result = storage.get_device("test_id").unwrap();
         assert!(result.is_none());
+
+        // 损坏的手动设备记录被跳过，不影响其他记录
+        storage
+            .save_manual_device(&ManualDeviceRecord { device: device.clone(), added_at: 1 })
+            .unwrap();
+        storage
+            .pool()
+            .writer()
+            .unwrap()
+            .execute("INSERT INTO manual_devices (device_id, device, added_at) VALUES ('bad', '{', 2)", [])
+            .unwrap();
+        let manual = storage.get_manual_devices().unwrap();
+        assert_eq!(manual.len(), 1);
+        assert_eq!(manual[0].device.id, "test_id");
+    }
+
+    #[test]
+    fn test_execute_batch_rolls_back_on_error() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let storage = Storage::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let device = DeviceInfo::new("Batch Device", DeviceType::Mobile, "key");
+        storage
+            .execute_batch(vec![
+                WriteOp::SaveDevice(device.clone()),
+                WriteOp::SaveSharedKey {
+                    device_id: device.id.clone(),
+                    key_data: vec![1, 2, 3],
+                },
+            ])
+            .unwrap();
+        assert!(storage.get_device(&device.id).unwrap().is_some());
+        assert_eq!(storage.get_shared_key(&device.id).unwrap(), Some(vec![1, 2, 3]));
+
+        // 破坏表结构后，同一批次内之前的写入也应回滚
+        storage
+            .pool()
+            .writer()
+            .unwrap()
+            .execute_batch("DROP TABLE history")
+            .unwrap();
+        let other = DeviceInfo::new("Other Device", DeviceType::Desktop, "key");
+        let result = storage.execute_batch(vec![
+            WriteOp::SaveDevice(other.clone()),
+            WriteOp::ClearHistory,
+        ]);
+        assert!(result.is_err());
+        assert!(storage.get_device(&other.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_import() {
+        let source = Storage::new(":memory:").unwrap();
+        let device = DeviceInfo::new("Snapshot Device", DeviceType::Mobile, "key");
+        source.save_device(&device).unwrap();
+        source.save_shared_key(&device.id, &[4, 5, 6]).unwrap();
+        source
+            .pool()
+            .writer()
+            .unwrap()
+            .execute_batch("CREATE TABLE extra (id TEXT PRIMARY KEY, data BLOB); INSERT INTO extra VALUES ('a', x'0102');")
+            .unwrap();
+
+        let snapshot = source.snapshot().unwrap();
+
+        let target = Storage::new(":memory:").unwrap();
+        target.import_snapshot(&snapshot).unwrap();
+        assert_eq!(target.get_device(&device.id).unwrap().unwrap().name, "Snapshot Device");
+        assert_eq!(target.get_shared_key(&device.id).unwrap(), Some(vec![4, 5, 6]));
+
+        // 目标库中不存在的表也会被恢复
+        let data: Vec<u8> = target
+            .pool()
+            .reader()
+            .unwrap()
+            .query_row("SELECT data FROM extra WHERE id = 'a'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(data, vec![1, 2]);
+
+        assert!(target.import_snapshot(b"not a database").is_err());
+
+        // 磁盘数据库的快照文件写在配置目录中，用完即删除
+        let dir = tempfile::tempdir().unwrap();
+        let on_disk = Storage::new(dir.path().join("pasteall.db").to_str().unwrap()).unwrap();
+        on_disk.import_snapshot(&snapshot).unwrap();
+        assert!(!on_disk.snapshot().unwrap().is_empty());
+        let leftovers = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(".pasteall_snapshot_"))
+            .count();
+        assert_eq!(leftovers, 0);
     }
 }
@@ -0,0 +1,189 @@
+//! 数据库连接池，提供WAL模式下的多读单写连接管理
+
+use crate::error::{Error, Result};
+use log::{debug, error, warn};
+use rusqlite::{Connection, OpenFlags};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// 默认读连接数量
+pub const DEFAULT_READER_COUNT: usize = 4;
+
+/// 数据库忙等待超时
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 数据库连接池
+///
+/// 写操作全部经由唯一的写连接串行执行，读操作分散到多个只读连接上，
+/// 在WAL模式下读写互不阻塞。
+pub struct ConnectionPool {
+    /// 写连接
+    writer: Mutex<Connection>,
+    /// 只读连接
+    readers: Vec<Mutex<Connection>>,
+    /// 下一个读连接的索引（轮询）
+    next_reader: AtomicUsize,
+    /// 数据库路径
+    db_path: String,
+}
+
+impl ConnectionPool {
+    /// 打开连接池
+    ///
+    /// `db_path` 为 `:memory:` 时使用共享缓存的内存数据库，
+    /// 保证所有连接访问的是同一个库。
+    pub fn open(db_path: &str, reader_count: usize) -> Result<Self> {
+        let (path, flags) = Self::resolve_path(db_path);
+
+        let writer = Self::open_connection(&path, flags)?;
+        Self::configure_writer(&writer)?;
+
+        let mut readers = Vec::with_capacity(reader_count.max(1));
+        for _ in 0..reader_count.max(1) {
+            let reader = Self::open_connection(&path, flags)?;
+            Self::configure_reader(&reader)?;
+            readers.push(Mutex::new(reader));
+        }
+
+        debug!("数据库连接池已打开: {db_path}（{} 个读连接）", readers.len());
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            readers,
+            next_reader: AtomicUsize::new(0),
+            db_path: db_path.to_string(),
+        })
+    }
+
+    /// 获取数据库路径
+    pub fn db_path(&self) -> &str {
+        &self.db_path
+    }
+
+    /// 获取读连接数量
+    pub fn reader_count(&self) -> usize {
+        self.readers.len()
+    }
+
+    /// 获取写连接
+    pub fn writer(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.writer.lock().map_err(|e| {
+            error!("获取数据库写连接锁失败: {e:?}");
+            Error::Storage("获取数据库写连接锁失败".to_string())
+        })
+    }
+
+    /// 获取读连接
+    ///
+    /// 优先选择当前空闲的连接，全部繁忙时按轮询顺序等待。
+    pub fn reader(&self) -> Result<MutexGuard<'_, Connection>> {
+        let count = self.readers.len();
+        let start = self.next_reader.fetch_add(1, Ordering::Relaxed) % count;
+
+        for offset in 0..count {
+            if let Ok(guard) = self.readers[(start + offset) % count].try_lock() {
+                return Ok(guard);
+            }
+        }
+
+        self.readers[start].lock().map_err(|e| {
+            error!("获取数据库读连接锁失败: {e:?}");
+            Error::Storage("获取数据库读连接锁失败".to_string())
+        })
+    }
+
+    /// 解析数据库路径和打开标志
+    fn resolve_path(db_path: &str) -> (String, OpenFlags) {
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+
+        if db_path == ":memory:" {
+            let name = uuid::Uuid::new_v4().simple().to_string();
+            (
+                format!("file:pasteall_{name}?mode=memory&cache=shared"),
+                flags | OpenFlags::SQLITE_OPEN_URI,
+            )
+        } else {
+            (db_path.to_string(), flags)
+        }
+    }
+
+    /// 打开单个连接
+    fn open_connection(path: &str, flags: OpenFlags) -> Result<Connection> {
+        let conn = Connection::open_with_flags(path, flags).map_err(|e| {
+            error!("打开数据库失败: {e:?}");
+            Error::Storage(format!("打开数据库失败: {e}"))
+        })?;
+
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(Error::Database)?;
+
+        Ok(conn)
+    }
+
+    /// 配置写连接：启用WAL模式
+    fn configure_writer(conn: &Connection) -> Result<()> {
+        let mode: String = conn
+            .query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))
+            .map_err(Error::Database)?;
+
+        // 内存数据库不支持WAL，会返回memory
+        if !mode.eq_ignore_ascii_case("wal") {
+            warn!("数据库未能启用WAL模式，当前模式: {mode}");
+        }
+
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")
+            .map_err(Error::Database)?;
+
+        Ok(())
+    }
+
+    /// 配置读连接：只读
+    fn configure_reader(conn: &Connection) -> Result<()> {
+        conn.execute_batch("PRAGMA query_only = ON;")
+            .map_err(Error::Database)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_pool_wal_mode() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = ConnectionPool::open(temp_file.path().to_str().unwrap(), 2).unwrap();
+
+        let mode: String = pool
+            .writer()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode.to_lowercase(), "wal");
+        assert_eq!(pool.reader_count(), 2);
+    }
+
+    #[test]
+    fn test_pool_readers_see_writes() {
+        let pool = ConnectionPool::open(":memory:", 2).unwrap();
+
+        pool.writer()
+            .unwrap()
+            .execute_batch("CREATE TABLE t (v INTEGER); INSERT INTO t VALUES (42);")
+            .unwrap();
+
+        let value: i64 = pool
+            .reader()
+            .unwrap()
+            .query_row("SELECT v FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, 42);
+
+        // 读连接不允许写入
+        let result = pool.reader().unwrap().execute("INSERT INTO t VALUES (1)", []);
+        assert!(result.is_err());
+    }
+}
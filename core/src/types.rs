@@ -9,7 +9,7 @@
 //! - 配对相关：`PairingStatus`, `ConnectionStatus`, `AuthRequestPacket`
 //! - 传输相关：`TransferStatus`, `TransferProgress`, `FileTransfer`
 //! - 发现相关：`DiscoveryPacket`
-//! - 配置相关：`Config`, `ConfigOptions`, `SecurityPolicy`
+//! - 配置相关：`Config`, `ConfigOptions`, `SecurityPolicy`, `DiscoveryBackend`, `InterfaceFilter`
 //! - 通知相关：`NotificationType`, `Notification`, `NotificationAction`
 //! - 消息相关：`Message`, `MessageType`
 //!
@@ -78,6 +78,12 @@ pub enum ContentType {
     },
 }
 
+/// 每台设备最多记录的地址数量
+pub const MAX_DEVICE_ADDRESSES: usize = 8;
+
+/// 内容包的默认有效期（秒）
+pub const CONTENT_PACKET_TTL_SECS: u64 = 120;
+
 /// 设备信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceInfo {
@@ -89,10 +95,19 @@ pub struct DeviceInfo {
     pub device_type: DeviceType,
     /// 公钥（Base64编码）
     pub public_key: String,
+    /// 签名验证公钥（Ed25519，Base64编码），用于验证该设备签名的数据包
+    #[serde(default)]
+    pub verify_key: String,
     /// 设备是否在线
     pub online: bool,
     /// 设备IP地址
     pub ip_address: Option<String>,
+    /// 配对监听端口，由设备发现获得
+    #[serde(default)]
+    pub pairing_port: Option<u16>,
+    /// 文件传输端口，由设备发现获得
+    #[serde(default)]
+    pub transfer_port: Option<u16>,
     /// 设备系统版本
     pub system_version: Option<String>,
     /// 设备应用版本
@@ -110,6 +125,15 @@ pub struct DeviceInfo {
     /// 是否为受信任设备
     #[serde(default)]
     pub trusted: bool,
+    /// 最近一次发现广播的签名是否验证通过，未签名的广播按安全策略接受时为false
+    #[serde(default)]
+    pub announcement_verified: bool,
+    /// 观察到的所有地址，IPv6链路本地地址带有接口索引（如 `fe80::1%2`）
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// 设备发现命名空间的哈希，未配置命名空间时为None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
 }
 
 impl DeviceInfo {
@@ -120,8 +144,11 @@ impl DeviceInfo {
             name: name.to_string(),
             device_type,
             public_key: public_key.to_string(),
+            verify_key: String::new(),
             online: true,
             ip_address: None,
+            pairing_port: None,
+            transfer_port: None,
             system_version: None,
             app_version: None,
             capabilities: DeviceCapabilities::default(),
@@ -134,6 +161,9 @@ impl DeviceInfo {
             pairing_status: PairingStatus::Unpaired,
             description: None,
             trusted: false,
+            announcement_verified: false,
+            addresses: Vec::new(),
+            namespace: None,
         }
     }
 
@@ -152,8 +182,11 @@ impl DeviceInfo {
             name: name.to_string(),
             device_type,
             public_key: public_key.to_string(),
+            verify_key: String::new(),
             online: true,
             ip_address,
+            pairing_port: None,
+            transfer_port: None,
             system_version,
             app_version,
             capabilities,
@@ -166,6 +199,9 @@ impl DeviceInfo {
             pairing_status: PairingStatus::Unpaired,
             description: None,
             trusted: false,
+            announcement_verified: false,
+            addresses: Vec::new(),
+            namespace: None,
         }
     }
 
@@ -182,6 +218,34 @@ impl DeviceInfo {
         }
     }
 
+    /// 记录观察到的地址并更新首选地址
+    ///
+    /// 已有的地址不重复记录，超出上限时丢弃最早的地址。首选地址为最近观察到的IPv4地址，
+    /// 没有IPv4地址时使用IPv6地址，设备在IPv4和IPv6之间交替广播时首选地址保持不变。
+    pub fn add_address(&mut self, address: &str) {
+        if !self.addresses.iter().any(|a| a == address) {
+            if self.addresses.len() >= MAX_DEVICE_ADDRESSES {
+                self.addresses.remove(0);
+            }
+            self.addresses.push(address.to_string());
+        }
+
+        let is_ipv4 = |a: &str| !a.contains(':');
+        if is_ipv4(address) || !self.ip_address.as_deref().is_some_and(is_ipv4) {
+            self.ip_address = Some(address.to_string());
+        }
+    }
+
+    /// 生成首选地址上指定端口的连接地址，IPv6地址加方括号
+    pub fn socket_address(&self, port: u16) -> Option<String> {
+        let ip = self.ip_address.as_deref()?;
+        if ip.contains(':') {
+            Some(format!("[{ip}]:{port}"))
+        } else {
+            Some(format!("{ip}:{port}"))
+        }
+    }
+
     /// 更新设备信息
     pub fn update_from(&mut self, other: &DeviceInfo) {
         self.name = other.name.clone();
@@ -192,6 +256,10 @@ impl DeviceInfo {
             self.ip_address = Some(ip.clone());
         }
 
+        for address in &other.addresses {
+            self.add_address(address);
+        }
+
         if let Some(sv) = &other.system_version {
             self.system_version = Some(sv.clone());
         }
@@ -201,6 +269,7 @@ impl DeviceInfo {
         }
 
         self.capabilities = other.capabilities;
+        self.namespace = other.namespace.clone();
 
         if let Some(ls) = other.last_seen {
             self.last_seen = Some(ls);
@@ -350,13 +419,19 @@ pub struct DiscoveryPacket {
     pub device_name: String,
     /// 设备公钥
     pub public_key: String,
+    /// 签名验证公钥（Ed25519，Base64编码）
+    #[serde(default)]
+    pub verify_key: String,
     /// 时间戳
     pub timestamp: u64,
     /// 设备类型
     pub device_type: DeviceType,
-    /// 监听端口
+    /// 配对监听端口
     pub port: u16,
-    /// IP地址（可选）
+    /// 文件传输端口
+    #[serde(default)]
+    pub transfer_port: Option<u16>,
+    /// IP地址（可选），接收方以数据包的来源地址为准
     pub ip_address: Option<String>,
     /// 设备能力
     #[serde(default)]
@@ -367,6 +442,41 @@ pub struct DiscoveryPacket {
     pub system_version: Option<String>,
     /// 协议版本
     pub protocol_version: String,
+    /// 设备签名私钥对 [`DiscoveryPacket::signing_payload`] 的签名（Base64编码）
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// 设备发现命名空间的哈希
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl DiscoveryPacket {
+    /// 待签名的内容
+    ///
+    /// 覆盖设备身份、公钥、端口、时间戳和命名空间。IP地址由接收方按来源地址确定，不在签名范围内。
+    /// 未设置命名空间时与旧版本的签名内容相同。
+    pub fn signing_payload(&self) -> String {
+        let device_type = serde_json::to_string(&self.device_type).unwrap_or_default();
+        let transfer_port = self.transfer_port.map(|p| p.to_string()).unwrap_or_default();
+        let mut payload = [
+            self.r#type.as_str(),
+            self.protocol_version.as_str(),
+            self.device_id.as_str(),
+            self.device_name.as_str(),
+            device_type.as_str(),
+            self.public_key.as_str(),
+            self.verify_key.as_str(),
+            &self.port.to_string(),
+            transfer_port.as_str(),
+            &self.timestamp.to_string(),
+        ]
+        .join("\n");
+        if let Some(namespace) = &self.namespace {
+            payload.push('\n');
+            payload.push_str(namespace);
+        }
+        payload
+    }
 }
 
 /// 认证请求包
@@ -378,10 +488,43 @@ pub struct AuthRequestPacket {
     pub device_id: String,
     /// 随机数（用于防重放攻击）
     pub nonce: String,
+    /// 发送时间（Unix时间戳，秒）
+    #[serde(default)]
+    pub timestamp: u64,
+    /// 发送方的签名验证公钥（Ed25519，Base64编码）
+    #[serde(default)]
+    pub verify_key: String,
+    /// 发送方设备名称（旧版本设备不携带）
+    #[serde(default)]
+    pub device_name: String,
+    /// 发送方设备类型
+    #[serde(default)]
+    pub device_type: DeviceType,
     /// 签名
     pub signature: String,
 }
 
+impl AuthRequestPacket {
+    /// 获取参与签名的内容
+    ///
+    /// 旧版本设备不携带设备名称，此时签名内容保持原格式。
+    pub fn signing_payload(&self) -> String {
+        let mut payload = format!(
+            "{}|{}|{}|{}|{}",
+            self.r#type, self.device_id, self.nonce, self.timestamp, self.verify_key
+        );
+        if !self.device_name.is_empty() {
+            let device_type = match self.device_type {
+                DeviceType::Desktop => "desktop",
+                DeviceType::Mobile => "mobile",
+                DeviceType::Unknown => "unknown",
+            };
+            payload.push_str(&format!("|{}|{}", self.device_name, device_type));
+        }
+        payload
+    }
+}
+
 /// 内容传输包
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContentPacket {
@@ -395,8 +538,89 @@ pub struct ContentPacket {
     pub content: String,
     /// 元数据
     pub metadata: ContentMetadata,
-    /// 时间戳
+    /// 时间戳（Unix时间戳，秒）
     pub timestamp: u64,
+    /// 随机数（用于防重放攻击）
+    #[serde(default)]
+    pub nonce: String,
+    /// 过期时间（Unix时间戳，秒，0表示使用默认有效期）
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
+impl ContentPacket {
+    /// 创建内容包，填入发送时间、随机数和过期时间
+    ///
+    /// 接收方的防重放检查会拒绝缺少随机数的包，发送内容时应使用此方法创建。
+    pub fn new(device_id: &str, content_type: &str, content: &[u8], metadata: ContentMetadata) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Self {
+            r#type: "content".to_string(),
+            device_id: device_id.to_string(),
+            content_type: content_type.to_string(),
+            content: base64::encode(content),
+            metadata,
+            timestamp,
+            nonce: Uuid::new_v4().to_string(),
+            expires_at: timestamp + CONTENT_PACKET_TTL_SECS,
+        }
+    }
+}
+
+/// 同步组加密的内容包
+///
+/// 内容包只需使用组密钥加密一次，即可发送给组内所有成员。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupContentPacket {
+    /// 同步组ID
+    pub group_id: String,
+    /// 组密钥版本
+    pub epoch: u64,
+    /// 发送方设备ID
+    pub sender_id: String,
+    /// 加密后的内容包（Base64编码）
+    pub data: String,
+}
+
+/// 同步组成员
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupMember {
+    /// 设备ID
+    pub device_id: String,
+    /// 加密公钥（Base64编码）
+    pub public_key: String,
+    /// 最近已知的IP地址，用于向未直接配对的成员分发组密钥
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    /// 最近已知的配对监听端口
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pairing_port: Option<u16>,
+}
+
+impl GroupMember {
+    /// 由设备信息创建成员
+    pub fn from_device(device: &DeviceInfo) -> Self {
+        Self {
+            device_id: device.id.clone(),
+            public_key: device.public_key.clone(),
+            ip_address: device.ip_address.clone(),
+            pairing_port: device.pairing_port,
+        }
+    }
+
+    /// 用于连接该成员的设备信息，名称等未知字段取默认值
+    pub fn to_device(&self) -> DeviceInfo {
+        let mut device = DeviceInfo::new(&self.device_id, DeviceType::Unknown, &self.public_key);
+        device.id = self.device_id.clone();
+        device.ip_address = self.ip_address.clone();
+        device.pairing_port = self.pairing_port;
+        device.online = false;
+        device
+    }
 }
 
 /// 内容元数据
@@ -522,6 +746,42 @@ pub enum MessageType {
         /// 传输唯一标识符
         transfer_id: String,
     },
+    /// 历史记录删除标记
+    HistoryTombstone {
+        /// 被删除内容的哈希
+        content_hash: String,
+        /// 删除时间（Unix时间戳，毫秒）
+        deleted_at: u64,
+    },
+    /// 同步组密钥更新
+    GroupKeyUpdate {
+        /// 同步组ID
+        group_id: String,
+        /// 同步组名称
+        name: String,
+        /// 组密钥版本
+        epoch: u64,
+        /// 当前成员列表
+        members: Vec<GroupMember>,
+        /// 使用接收方公钥加密的组密钥（Base64编码）
+        sealed_key: String,
+    },
+    /// 设备吊销通知
+    DeviceRevoked {
+        /// 被吊销的设备ID
+        device_id: String,
+        /// 被吊销的加密公钥（Base64编码）
+        public_key: String,
+    },
+    /// 身份密钥轮换通知
+    KeyRotation {
+        /// 新的加密公钥（Base64编码）
+        public_key: String,
+        /// 新的签名公钥（Base64编码）
+        verify_key: String,
+        /// 旧签名私钥对通知内容的签名（Base64编码）
+        signature: String,
+    },
     /// 心跳包
     Heartbeat,
     /// 错误消息
@@ -670,6 +930,43 @@ impl Default for SecurityPolicy {
     }
 }
 
+/// 局域网设备发现方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DiscoveryBackend {
+    /// 仅使用UDP广播
+    Broadcast,
+    /// 仅使用mDNS/DNS-SD，适用于屏蔽广播的网络
+    Mdns,
+    /// 同时使用UDP广播和mDNS
+    #[default]
+    Both,
+}
+
+impl DiscoveryBackend {
+    /// 是否启用UDP广播
+    pub fn uses_broadcast(self) -> bool {
+        matches!(self, Self::Broadcast | Self::Both)
+    }
+
+    /// 是否启用mDNS
+    pub fn uses_mdns(self) -> bool {
+        matches!(self, Self::Mdns | Self::Both)
+    }
+}
+
+/// 设备发现的网络接口过滤规则
+///
+/// 每条规则为接口名称（支持以 `*` 结尾的前缀匹配，如 `docker*`）、IP地址或网段（如 `10.0.0.0/8`）。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterfaceFilter {
+    /// 允许列表，为空时允许所有未被禁止的接口
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 禁止列表，优先于允许列表
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
 /// 配置附加选项
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigOptions {
@@ -685,6 +982,37 @@ pub struct ConfigOptions {
     pub auto_start: bool,
     /// 开机自启动
     pub start_on_boot: bool,
+    /// 回收站条目保留天数
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    /// 将历史记录的删除操作同步到其他设备
+    #[serde(default)]
+    pub propagate_history_deletes: bool,
+    /// 局域网设备发现方式
+    #[serde(default)]
+    pub discovery_backend: DiscoveryBackend,
+    /// 连续错过多少次广播后将设备标记为离线
+    #[serde(default = "default_offline_after_missed")]
+    pub offline_after_missed: u32,
+    /// 设备发现使用的网络接口和网段
+    #[serde(default)]
+    pub discovery_interfaces: InterfaceFilter,
+    /// 设备发现命名空间（如团队名或同步组ID），只显示命名空间相同的设备
+    #[serde(default)]
+    pub discovery_namespace: Option<String>,
+    /// 开放发现，显示所有命名空间的设备
+    #[serde(default)]
+    pub open_discovery: bool,
+}
+
+/// 默认回收站保留天数
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+/// 默认离线判定的错过广播次数
+fn default_offline_after_missed() -> u32 {
+    3
 }
 
 impl Default for ConfigOptions {
@@ -696,6 +1024,13 @@ impl Default for ConfigOptions {
             enable_notifications: true,
             auto_start: true,
             start_on_boot: false,
+            trash_retention_days: default_trash_retention_days(),
+            propagate_history_deletes: false,
+            discovery_backend: DiscoveryBackend::default(),
+            offline_after_missed: default_offline_after_missed(),
+            discovery_interfaces: InterfaceFilter::default(),
+            discovery_namespace: None,
+            open_discovery: false,
         }
     }
 }
@@ -905,6 +1240,8 @@ pub enum NotificationType {
     Error,
     /// 系统通知
     System,
+    /// 安全警告
+    SecurityWarning,
 }
 
 /// 应用通知
@@ -1105,6 +1442,23 @@ mod tests {
         assert!(device.online);
     }
 
+    #[test]
+    fn test_device_addresses() {
+        let mut device = DeviceInfo::new("测试设备", DeviceType::Desktop, "key");
+        device.add_address("fe80::1%2");
+        assert_eq!(device.socket_address(45680).as_deref(), Some("[fe80::1%2]:45680"));
+
+        // 同时有IPv4地址时优先使用IPv4，重复的地址不再记录
+        device.add_address("192.168.1.20");
+        device.add_address("fe80::1%2");
+        assert_eq!(device.addresses, vec!["fe80::1%2", "192.168.1.20"]);
+        assert_eq!(device.socket_address(45680).as_deref(), Some("192.168.1.20:45680"));
+
+        device.add_address("192.168.1.30");
+        device.add_address("192.168.1.20");
+        assert_eq!(device.ip_address.as_deref(), Some("192.168.1.20"));
+    }
+
     #[test]
     fn test_config_default() {
         let config = Config::default();